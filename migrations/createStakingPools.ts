@@ -39,10 +39,15 @@ export const createStakingPool = async (
   const rewardDuration = new anchor.BN(86400 * 30);
 
   await stakingProgram.rpc.initializePool(
-    nonce,
     rewardDuration,
     lockPeriod,
     noTier,
+    false,
+    false,
+    new anchor.BN(0),
+    false,
+    anchor.web3.PublicKey.default,
+    new anchor.BN(0),
     {
       accounts: {
         authority: wallet.publicKey,
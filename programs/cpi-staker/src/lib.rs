@@ -0,0 +1,270 @@
+//! Example caller that stakes into the staking program through CPI. Each
+//! depositor's position is owned by a PDA of this program, which signs every
+//! staking instruction while the depositor only pays rent.
+
+use anchor_lang::prelude::*;
+use staking::cpi::accounts::{ClaimReward, CloseUser, CreateUser, Stake};
+use staking::program::Staking;
+
+declare_id!("MFoGwBLJ4WGqrMvaTcFGubLDFUiWuQcoL3TrfqNG1Zo");
+
+pub const VAULT_AUTHORITY_SEED: &[u8] = b"vault_authority";
+
+#[program]
+pub mod cpi_staker {
+    use super::*;
+
+    /// Creates the staking position owned by the depositor's vault authority.
+    pub fn create_position(ctx: Context<CreatePosition>) -> Result<()> {
+        let depositor = ctx.accounts.depositor.key();
+        let bump = *ctx.bumps.get("vault_authority").unwrap();
+        let seeds = &[VAULT_AUTHORITY_SEED, depositor.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            CreateUser {
+                pool: ctx.accounts.pool.to_account_info(),
+                user: ctx.accounts.user.to_account_info(),
+                owner: ctx.accounts.vault_authority.to_account_info(),
+                payer: ctx.accounts.depositor.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+            signer,
+        );
+        staking::cpi::create_user(cpi_ctx)
+    }
+
+    /// Stakes `amount` from the vault authority's token account. `day_index`
+    /// is forwarded to the staking program, which checks it against the clock.
+    pub fn stake(ctx: Context<MovePosition>, day_index: u64, amount: u64) -> Result<()> {
+        let depositor = ctx.accounts.depositor.key();
+        let bump = *ctx.bumps.get("vault_authority").unwrap();
+        let seeds = &[VAULT_AUTHORITY_SEED, depositor.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            ctx.accounts.stake_accounts(),
+            signer,
+        );
+        staking::cpi::stake(cpi_ctx, day_index, amount, 0, 0)
+    }
+
+    /// Unstakes `amount` back to the vault authority's token account.
+    pub fn unstake(ctx: Context<MovePosition>, day_index: u64, amount: u64) -> Result<()> {
+        let depositor = ctx.accounts.depositor.key();
+        let bump = *ctx.bumps.get("vault_authority").unwrap();
+        let seeds = &[VAULT_AUTHORITY_SEED, depositor.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            ctx.accounts.stake_accounts(),
+            signer,
+        );
+        staking::cpi::unstake(cpi_ctx, day_index, amount, 0, String::new())
+    }
+
+    /// Claims rewards into the vault authority's reward token account.
+    pub fn claim(ctx: Context<ClaimPosition>, day_index: u64) -> Result<()> {
+        let depositor = ctx.accounts.depositor.key();
+        let bump = *ctx.bumps.get("vault_authority").unwrap();
+        let seeds = &[VAULT_AUTHORITY_SEED, depositor.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            ClaimReward {
+                pool: ctx.accounts.pool.to_account_info(),
+                staking_vault: ctx.accounts.staking_vault.to_account_info(),
+                reward_vault: ctx.accounts.reward_vault.to_account_info(),
+                user: ctx.accounts.user.to_account_info(),
+                owner: ctx.accounts.vault_authority.to_account_info(),
+                reward_account: ctx.accounts.reward_account.to_account_info(),
+                pool_signer: ctx.accounts.pool_signer.to_account_info(),
+                daily_stats: ctx.accounts.daily_stats.to_account_info(),
+                payer: ctx.accounts.depositor.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                reward_mint: ctx.accounts.reward_mint.to_account_info(),
+                memo_program: ctx.accounts.memo_program.to_account_info(),
+                associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+                rent: ctx.accounts.rent.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                program: ctx.accounts.staking_program.to_account_info(),
+            },
+            signer,
+        );
+        staking::cpi::claim(cpi_ctx, day_index, 0, String::new())
+    }
+
+    /// Closes the emptied position, refunding its rent to the depositor who
+    /// paid it.
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        let depositor = ctx.accounts.depositor.key();
+        let bump = *ctx.bumps.get("vault_authority").unwrap();
+        let seeds = &[VAULT_AUTHORITY_SEED, depositor.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            CloseUser {
+                pool: ctx.accounts.pool.to_account_info(),
+                user: ctx.accounts.user.to_account_info(),
+                owner: ctx.accounts.vault_authority.to_account_info(),
+                rent_payer: ctx.accounts.depositor.to_account_info(),
+            },
+            signer,
+        );
+        staking::cpi::close_user(cpi_ctx)
+    }
+}
+
+#[derive(Accounts)]
+pub struct CreatePosition<'info> {
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub pool: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: created by the staking program.
+    pub user: AccountInfo<'info>,
+    #[account(
+        seeds = [VAULT_AUTHORITY_SEED, depositor.key().as_ref()],
+        bump,
+    )]
+    /// CHECK: PDA owning the position.
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub staking_program: Program<'info, Staking>,
+}
+
+#[derive(Accounts)]
+pub struct MovePosition<'info> {
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub pool: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub user: AccountInfo<'info>,
+    #[account(
+        seeds = [VAULT_AUTHORITY_SEED, depositor.key().as_ref()],
+        bump,
+    )]
+    /// CHECK: PDA owning the position.
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub vault_token_account: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub pool_signer: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub daily_stats: AccountInfo<'info>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    /// CHECK: checked by the staking program.
+    pub token_program: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub staking_program: Program<'info, Staking>,
+    /// CHECK: checked by the staking program.
+    pub staking_mint: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub price_feed: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub memo_program: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub event_authority: AccountInfo<'info>,
+}
+
+impl<'info> MovePosition<'info> {
+    fn stake_accounts(&self) -> Stake<'info> {
+        Stake {
+            pool: self.pool.to_account_info(),
+            staking_vault: self.staking_vault.to_account_info(),
+            user: self.user.to_account_info(),
+            owner: self.vault_authority.to_account_info(),
+            stake_from_account: self.vault_token_account.to_account_info(),
+            pool_signer: self.pool_signer.to_account_info(),
+            daily_stats: self.daily_stats.to_account_info(),
+            payer: self.depositor.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+            system_program: self.system_program.to_account_info(),
+            staking_mint: self.staking_mint.to_account_info(),
+            price_feed: self.price_feed.to_account_info(),
+            memo_program: self.memo_program.to_account_info(),
+            event_authority: self.event_authority.to_account_info(),
+            program: self.staking_program.to_account_info(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct ClaimPosition<'info> {
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub pool: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub reward_vault: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub user: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [VAULT_AUTHORITY_SEED, depositor.key().as_ref()],
+        bump,
+    )]
+    /// CHECK: PDA owning the position.
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub reward_account: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub pool_signer: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub daily_stats: AccountInfo<'info>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    /// CHECK: checked by the staking program.
+    pub token_program: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub staking_program: Program<'info, Staking>,
+    /// CHECK: checked by the staking program.
+    pub reward_mint: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub memo_program: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub associated_token_program: AccountInfo<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    /// CHECK: checked by the staking program.
+    pub event_authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub pool: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: closed by the staking program.
+    pub user: AccountInfo<'info>,
+    #[account(
+        seeds = [VAULT_AUTHORITY_SEED, depositor.key().as_ref()],
+        bump,
+    )]
+    /// CHECK: PDA owning the position.
+    pub vault_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub staking_program: Program<'info, Staking>,
+}
@@ -0,0 +1,72 @@
+//! Minimal caller program used by the tests to exercise `claim_via_cpi`.
+
+use anchor_lang::prelude::*;
+use staking::cpi::accounts::ClaimViaCpi;
+use staking::program::Staking;
+
+declare_id!("H4M6HwfRCaYtEFy3k7rrqQpPtdANCtBZUY1vchujVW2x");
+
+#[program]
+pub mod mock_claimer {
+    use super::*;
+
+    /// Claims the user's rewards, signing as this program's claim authority.
+    pub fn claim(ctx: Context<Claim>) -> Result<()> {
+        let user = ctx.accounts.user.key();
+        let bump = *ctx.bumps.get("claim_authority").unwrap();
+        let seeds = &[b"claim_authority".as_ref(), user.as_ref(), &[bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            ClaimViaCpi {
+                pool: ctx.accounts.pool.to_account_info(),
+                staking_vault: ctx.accounts.staking_vault.to_account_info(),
+                reward_vault: ctx.accounts.reward_vault.to_account_info(),
+                user: ctx.accounts.user.to_account_info(),
+                owner: ctx.accounts.owner.to_account_info(),
+                claim_authority: ctx.accounts.claim_authority.to_account_info(),
+                reward_account: ctx.accounts.reward_account.to_account_info(),
+                pool_signer: ctx.accounts.pool_signer.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+                reward_mint: ctx.accounts.reward_mint.to_account_info(),
+            },
+            signer,
+        );
+        staking::cpi::claim_via_cpi(cpi_ctx)
+    }
+}
+
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub pool: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub reward_vault: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub user: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub owner: AccountInfo<'info>,
+    #[account(
+        seeds = [b"claim_authority".as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    /// CHECK: PDA signing the claim.
+    pub claim_authority: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: checked by the staking program.
+    pub reward_account: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub pool_signer: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub token_program: AccountInfo<'info>,
+    /// CHECK: checked by the staking program.
+    pub reward_mint: AccountInfo<'info>,
+    pub staking_program: Program<'info, Staking>,
+}
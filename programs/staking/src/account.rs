@@ -1,3 +1,8 @@
+//! Program-owned account layouts. Fields are only ever appended (before
+//! `reserved`), so external readers can rely on existing offsets.
+
+use crate::constants::{ACTION_CLAIM, ACTION_STAKE, ACTION_UNSTAKE, LEADERBOARD_SIZE};
+use crate::utils::day_index;
 use anchor_lang::prelude::*;
 
 #[account]
@@ -38,10 +43,135 @@ pub struct Pool {
     /// [] because short size, fixed account size, and ease of use on
     /// client due to auto generated account size property
     pub funders: [Pubkey; 5],
+    /// Seconds after a lock starts in which top-ups keep the current maturity.
+    pub topup_grace_seconds: u64,
+    /// Top-ups at or below this share (bps) of the staked balance keep the current maturity.
+    pub topup_grace_bps: u16,
+    /// Penalty (bps) charged on an unstake right after staking, decaying to zero at maturity.
+    pub max_penalty_bps: u16,
+    /// Vault receiving early-unstake penalties.
+    pub penalty_vault: Pubkey,
+    /// Whether the authority may slash user stakes. Fixed at initialization.
+    pub slashing_enabled: bool,
+    /// Slots that must pass after a stake before the user can unstake.
+    pub min_unstake_delay_slots: u64,
+    /// Maximum a single user may deposit per deposit window. Zero disables the limit.
+    pub max_per_window: u64,
+    /// Verified NFT collection whose holders are exempt from early-unstake penalties.
+    pub penalty_waiver_collection: Pubkey,
+    /// Vault accumulating insurance funds to cover reward shortfalls.
+    pub insurance_vault: Pubkey,
+    /// Share (bps) of penalties routed to the insurance vault.
+    pub insurance_share_bps: u16,
+    /// Total amount routed into the insurance vault.
+    pub insurance_collected: u64,
+    /// Total amount moved from the insurance vault into the reward vault.
+    pub insurance_used: u64,
+    /// Pending rewards a user may leave behind when closing their account.
+    pub dust_threshold: u64,
+    /// Dust forgiven on close, added to the next funding.
+    pub forgiven_dust: u64,
+    /// Share of each auto-compounded reward paid to the cranker.
+    pub compound_tip_bps: u16,
+    /// Sum of the users' effective weights.
+    pub total_weight: u128,
+    /// Key that countersigns user attestations, default if none.
+    pub attestor: Pubkey,
+    /// Whether staking needs an attestation on the user account.
+    pub require_attestation: bool,
+    /// Whether the authority may extend user maturity. Fixed at initialization.
+    pub maturity_enforcement_enabled: bool,
+    /// Cap on the total maturity extension imposed on a single user, in seconds.
+    pub max_maturity_extension: u64,
+    /// Checkpointed rewards still owed to users.
+    pub aggregate_pending: u64,
+    /// Set when an invariant check fails; blocks staking, funding and claims.
+    pub halted: bool,
+    /// Set while the pool winds down: no new users or stakes, exits stay open.
+    pub closing: bool,
+    /// Set once both vaults moved to the migration's new pool; `migrate_stake`
+    /// then re-homes users without moving tokens.
+    pub vaults_migrated: bool,
+    /// Whether staking needs a reward period in progress.
+    pub require_active_rewards: bool,
+    /// Whether the staking mint has a freeze authority that could trap the vault.
+    pub staking_mint_freezable: bool,
+    /// Whether the reward mint has a freeze authority.
+    pub reward_mint_freezable: bool,
+    /// Every user-facing instruction fails before this timestamp.
+    pub halted_until: i64,
+    /// Funding from non-authority funders that may lower the reward rate.
+    /// Zero means they can never lower it.
+    pub min_fund_amount: u64,
+    /// Whether the staking mint doubles as the reward mint.
+    pub shared_mint: bool,
+    /// Lifetime funding per `funders` slot, with the authority last.
+    pub funder_contributions: [u64; 6],
+    /// Funding by funders since deauthorized or renounced.
+    pub removed_funder_contributions: u64,
+    /// Program version, `[major, minor, patch]`, that initialized the pool.
+    /// Zero for pools created before it was recorded.
+    pub created_with_version: [u16; 3],
+    /// Set while the reward vault can't cover pending rewards plus the rest of
+    /// the current period's emission.
+    pub underfunded: bool,
+    /// Lifetime deposit fees collected.
+    pub total_deposit_fees: u64,
+    /// Lifetime claim fees collected.
+    pub total_claim_fees: u64,
+    /// Lifetime early-unstake penalties collected, insurance share included.
+    pub total_penalties: u64,
+    /// Whether voter weight is boosted by `TIER_VOTE_MULTIPLIER_BPS`.
+    pub voter_weight_tier_boost: bool,
+    /// Pyth price account tiers are valued with; default for token tiers.
+    pub price_feed: Pubkey,
+    /// USD tier thresholds, with `USD_DECIMALS` decimals, used instead of
+    /// `TIER_INFO` when `price_feed` is set.
+    pub usd_tier_thresholds: [u64; 7],
+    /// Verified Metaplex collection staked by NFT pools; default for pools
+    /// staking `staking_mint`. Fixed at initialization.
+    pub nft_collection: Pubkey,
+    /// Stake each NFT counts as in NFT pools.
+    pub nft_weight: u64,
+    /// Oracle valuing `staking_mint` in its underlying asset, so that tiers of
+    /// interest-bearing mints follow the accrued value; default for tiers on
+    /// the raw amount.
+    pub exchange_rate_oracle: Pubkey,
+    /// How `exchange_rate_oracle` is read, one of the `EXCHANGE_RATE_*` values.
+    pub exchange_rate_source: u8,
+    /// Flat reward amount a keeper takes from each `auto_claim` it runs.
+    pub auto_claim_fee: u64,
+    /// Set by `assert_invariants` while the staking vault is frozen by the
+    /// staking mint's freeze authority, and cleared once it is thawed.
+    pub vault_frozen: bool,
+    /// Spare space for future fields, so existing pools need no resize.
+    /// Shrink it by the size of every field added above; the tests pin the
+    /// account size.
+    pub reserved: [u8; 55],
+}
+
+impl Pool {
+    /// Account tiers are valued through: the USD price feed, else the
+    /// exchange-rate oracle, else the default key for plain tiers.
+    pub fn tier_oracle(&self) -> Pubkey {
+        if self.price_feed != Pubkey::default() {
+            self.price_feed
+        } else {
+            self.exchange_rate_oracle
+        }
+    }
+
+    /// Account size of the current layout, discriminator included.
+    pub fn space() -> usize {
+        // Pool can't derive Default (its arrays are too long), so measure one
+        // decoded from zeroes instead.
+        let zeroes = vec![0u8; 4096];
+        let pool = Pool::deserialize(&mut &zeroes[..]).unwrap();
+        8 + pool.try_to_vec().unwrap().len()
+    }
 }
 
 #[account]
-#[derive(Default)]
 pub struct User {
     /// Pool the this user belongs to.
     pub pool: Pubkey,
@@ -59,4 +189,336 @@ pub struct User {
     pub tier: u8,
     /// Signer nonce.
     pub nonce: u8,
+    /// Slot of the last stake.
+    pub last_stake_slot: u64,
+    /// Start of the current deposit window.
+    pub window_start: u64,
+    /// Amount deposited in the current deposit window.
+    pub window_deposited: u64,
+    /// Account layout version.
+    pub version: u8,
+    /// Time the account was created.
+    pub created_at: i64,
+    /// Time of the last stake.
+    pub last_stake_time: i64,
+    /// Time of the last unstake.
+    pub last_unstake_time: i64,
+    /// Rewards actually paid out over the life of the position.
+    pub total_rewards_claimed: u128,
+    /// Most recent actions, oldest overwritten first.
+    pub history: [UserAction; 8],
+    /// Index of the next history entry to write.
+    pub history_head: u8,
+    /// Account allowed to unstake on the owner's behalf.
+    pub unstake_spender: Pubkey,
+    /// Amount the spender may still unstake.
+    pub unstake_allowance: u64,
+    /// Token account spender unstakes are paid into.
+    pub unstake_destination: Pubkey,
+    /// Whether anyone may compound this user's rewards.
+    pub auto_compound: bool,
+    /// Secondary key that can unfreeze the position.
+    pub guardian: Pubkey,
+    /// Frozen positions can't unstake or claim.
+    pub frozen: bool,
+    /// Wallet whose reward token account receives claims.
+    pub beneficiary: Pubkey,
+    /// Beneficiary taking over once `beneficiary_change_at` has passed.
+    pub next_beneficiary: Pubkey,
+    /// Time the pending beneficiary change takes effect, zero if none.
+    pub beneficiary_change_at: i64,
+    /// Account that paid the rent and gets it back on close.
+    pub rent_payer: Pubkey,
+    /// Hot wallet allowed to stake and unstake for the owner.
+    pub operations_delegate: Pubkey,
+    /// Time claimed rewards start vesting, zero for instant claims.
+    pub vesting_cliff: i64,
+    /// Seconds after the cliff until escrowed rewards are fully vested.
+    pub vesting_duration: u64,
+    /// Rewards claimed into the vesting escrow.
+    pub vesting_total: u64,
+    /// Rewards released from the vesting escrow.
+    pub vesting_released: u64,
+    /// Vesting escrow nonce.
+    pub vesting_nonce: u8,
+    /// Reward weight last used for this user.
+    pub effective_weight: u128,
+    /// Time of the last claim that paid out rewards.
+    pub last_claim_time: i64,
+    /// Owner a pending recovery moves the position to.
+    pub recovery_new_owner: Pubkey,
+    /// Time recovery was requested, zero if none is pending.
+    pub recovery_requested_at: i64,
+    /// Commitment to an off-chain attestation, zero if none.
+    pub attestation: [u8; 32],
+    /// Unstakes of at least this amount need the cosigner.
+    pub withdraw_threshold: u64,
+    /// Second signer for large unstakes, default if no policy.
+    pub withdraw_cosigner: Pubkey,
+    /// Minimum tier granted by the authority.
+    pub tier_override: u8,
+    /// Unix timestamp the tier override lapses at.
+    pub tier_override_expires_at: i64,
+    /// Skips the early-unstake penalty.
+    pub penalty_exempt: bool,
+    /// Seconds of maturity extension imposed by the authority so far.
+    pub maturity_extended: u64,
+    /// Program allowed to claim for the user via CPI, default if none.
+    pub claim_program: Pubkey,
+    /// Whether anyone may claim this user's rewards to the owner.
+    pub auto_claim: bool,
+    /// Pending rewards `auto_claim` must exceed to run.
+    pub auto_claim_min: u64,
+    /// Spare space for future fields, so migrated users need no resize.
+    /// Shrink it by the size of every field added above, like
+    /// `Pool::reserved`.
+    pub reserved: [u8; 64],
+}
+
+impl Default for User {
+    fn default() -> Self {
+        // `reserved` is too long to derive `Default`; decode zeroes instead,
+        // like `Pool::space`.
+        let zeroes = vec![0u8; 4096];
+        User::deserialize(&mut &zeroes[..]).unwrap()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct UserAction {
+    /// One of the `ACTION_*` constants.
+    pub action: u8,
+    /// Token amount the action moved.
+    pub amount: u64,
+    /// Time of the action.
+    pub timestamp: i64,
+}
+
+impl User {
+    /// Account size of the current layout, discriminator included.
+    pub fn space() -> usize {
+        8 + User::default().try_to_vec().unwrap().len()
+    }
+
+    /// Account refunded on close. Accounts created before the rent payer
+    /// was recorded refund the owner.
+    pub fn rent_refundee(&self) -> Pubkey {
+        if self.rent_payer == Pubkey::default() {
+            self.owner
+        } else {
+            self.rent_payer
+        }
+    }
+
+    /// Beneficiary in force at `now`, including a pending change whose
+    /// timelock has passed.
+    pub fn beneficiary_at(&self, now: i64) -> Pubkey {
+        if self.beneficiary_change_at != 0 && now >= self.beneficiary_change_at {
+            self.next_beneficiary
+        } else {
+            self.beneficiary
+        }
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct MigrationConfig {
+    /// Pool stakes are migrated from.
+    pub old_pool: Pubkey,
+    /// Pool stakes are migrated to.
+    pub new_pool: Pubkey,
+    /// Pay pending rewards from the old pool instead of carrying them over.
+    pub pay_pending_rewards: bool,
+    /// Signer nonce.
+    pub nonce: u8,
+    /// New pool's `reward_per_token_stored` when the vaults moved. Stakes
+    /// re-homed later earn the new pool's accrual from here on.
+    pub reward_per_token_start: u128,
+}
+
+/// Activity totals of one pool over one UTC day.
+#[account]
+#[derive(Default)]
+pub struct DailyStats {
+    /// Pool the figures belong to.
+    pub pool: Pubkey,
+    /// Days since the unix epoch.
+    pub day_index: u64,
+    /// Number of stakes.
+    pub stakes: u64,
+    /// Tokens staked.
+    pub stake_volume: u64,
+    /// Number of unstakes.
+    pub unstakes: u64,
+    /// Tokens unstaked.
+    pub unstake_volume: u64,
+    /// Number of claims.
+    pub claims: u64,
+    /// Rewards claimed.
+    pub claim_volume: u64,
+}
+
+impl DailyStats {
+    /// Counts one action of `amount`. Saturates rather than failing the
+    /// instruction over analytics.
+    pub fn record(&mut self, pool: Pubkey, action: u8, amount: u64, now: i64) {
+        if self.pool == Pubkey::default() {
+            self.pool = pool;
+            self.day_index = day_index(now);
+        }
+
+        let (count, volume) = match action {
+            ACTION_STAKE => (&mut self.stakes, &mut self.stake_volume),
+            ACTION_UNSTAKE => (&mut self.unstakes, &mut self.unstake_volume),
+            ACTION_CLAIM => (&mut self.claims, &mut self.claim_volume),
+            _ => return,
+        };
+        *count = count.saturating_add(1);
+        *volume = volume.saturating_add(amount);
+    }
+}
+
+/// spl-governance voter weight addin record. The layout, discriminator
+/// included, matches `spl-governance-addin-api` so realms using this program
+/// as their voter weight addin can read it.
+#[account]
+#[derive(Default)]
+pub struct VoterWeightRecord {
+    /// Realm the record votes in.
+    pub realm: Pubkey,
+    /// Governing token mint, the pool's staking mint.
+    pub governing_token_mint: Pubkey,
+    /// Owner of the staking position.
+    pub governing_token_owner: Pubkey,
+    /// Weight from the last `update_voter_weight_record`.
+    pub voter_weight: u64,
+    /// Slot after which the weight is stale.
+    pub voter_weight_expiry: Option<u64>,
+    /// Action the weight is restricted to, any when `None`.
+    pub weight_action: Option<VoterWeightAction>,
+    /// Target the weight is restricted to, any when `None`.
+    pub weight_action_target: Option<Pubkey>,
+    pub reserved: [u8; 8],
+}
+
+impl VoterWeightRecord {
+    /// Account size with every optional field set, discriminator included.
+    pub fn space() -> usize {
+        let record = VoterWeightRecord {
+            voter_weight_expiry: Some(0),
+            weight_action: Some(VoterWeightAction::CastVote),
+            weight_action_target: Some(Pubkey::default()),
+            ..VoterWeightRecord::default()
+        };
+        8 + record.try_to_vec().unwrap().len()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum VoterWeightAction {
+    CastVote,
+    CommentProposal,
+    CreateGovernance,
+    CreateProposal,
+    SignOffProposal,
+}
+
+#[account]
+#[derive(Default)]
+pub struct Leaderboard {
+    /// Pool the leaderboard ranks.
+    pub pool: Pubkey,
+    /// Top stakers by balance, highest first. Empty slots trail with a
+    /// default owner.
+    pub entries: [LeaderboardEntry; LEADERBOARD_SIZE],
+    /// Signer nonce.
+    pub nonce: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct LeaderboardEntry {
+    pub owner: Pubkey,
+    pub balance: u64,
+}
+
+impl Leaderboard {
+    /// Moves `owner` to the position `balance` earns, evicting the last entry
+    /// if the board is full. A zero balance drops the owner. Equal balances
+    /// are ordered by owner key.
+    pub fn update(&mut self, owner: Pubkey, balance: u64) {
+        if let Some(index) = self.entries.iter().position(|e| e.owner == owner) {
+            self.entries.copy_within(index + 1.., index);
+            self.entries[LEADERBOARD_SIZE - 1] = LeaderboardEntry::default();
+        }
+        if balance == 0 {
+            return;
+        }
+
+        let ranks_above = |e: &LeaderboardEntry| {
+            e.owner == Pubkey::default()
+                || balance > e.balance
+                || (balance == e.balance && owner < e.owner)
+        };
+        if let Some(index) = self.entries.iter().position(ranks_above) {
+            self.entries
+                .copy_within(index..LEADERBOARD_SIZE - 1, index + 1);
+            self.entries[index] = LeaderboardEntry { owner, balance };
+        }
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct LotteryRound {
+    /// Pool whose stakers the lottery draws from.
+    pub pool: Pubkey,
+    /// Reward-mint token account holding the prizes.
+    pub lottery_vault: Pubkey,
+    /// Minimum seconds between two draws.
+    pub draw_interval: u64,
+    /// Earliest time the next draw may be requested.
+    pub next_draw_at: i64,
+    /// Number of the current round, starting at 1.
+    pub round: u64,
+    /// Prize of the current round, rounds nobody won included.
+    pub prize: u64,
+    /// Switchboard randomness account of the pending draw, default if none.
+    pub randomness: Pubkey,
+    /// Slot the pending draw's randomness was committed at.
+    pub seed_slot: u64,
+    /// Leaderboard at the time the pending draw was requested.
+    pub entries: [LeaderboardEntry; LEADERBOARD_SIZE],
+    /// Winner of the last settled round, default if it rolled over.
+    pub last_winner: Pubkey,
+    /// Signer nonce.
+    pub nonce: u8,
+}
+
+impl LotteryRound {
+    /// Account size, discriminator included.
+    pub fn space() -> usize {
+        8 + LotteryRound::default().try_to_vec().unwrap().len()
+    }
+
+    pub fn draw_pending(&self) -> bool {
+        self.randomness != Pubkey::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // New fields take their bytes from `reserved`, so these sizes stay put; a
+    // change means existing accounts would need migrating.
+    #[test]
+    fn pool_size_is_fixed() {
+        assert_eq!(Pool::space(), 962);
+    }
+
+    #[test]
+    fn user_size_is_fixed() {
+        assert_eq!(User::space(), 850);
+    }
 }
@@ -7,3 +7,219 @@ pub const TIER_INFO: [u64; 7] = [
   100_000_000_000, // Legend
   200_000_000_000, // Immortal
 ];
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Voting weight per tier (index = tier) when the pool boosts voter weight by
+/// tier, in basis points of the staked balance.
+pub const TIER_VOTE_MULTIPLIER_BPS: [u64; 8] = [
+  10_000, 11_000, 12_000, 13_000, 14_000, 15_000, 16_000, 17_000,
+];
+
+/// Accounts per pool in `batch_unstake` remaining accounts:
+/// pool, staking vault, user, destination, pool signer, staking mint,
+/// price feed.
+pub const BATCH_UNSTAKE_ACCOUNTS: usize = 7;
+/// Largest batch tested to fit in one transaction's size and compute limits,
+/// with the pools sharing a staking mint and valuing tiers without an oracle.
+pub const MAX_BATCH_UNSTAKE_POOLS: usize = 5;
+/// Most positions `get_user_summary` accepts; keeps account deserialization
+/// well inside the compute budget and the transaction account limit.
+pub const MAX_SUMMARY_POSITIONS: usize = 20;
+
+pub const DEPOSIT_WINDOW_SECONDS: u64 = 86_400;
+
+/// Seed prefix of the per-pool `DailyStats` accounts.
+pub const DAILY_STATS_SEED: &[u8] = b"daily_stats";
+
+/// Oldest Pyth price, in seconds, still used for USD tiers.
+pub const PRICE_MAX_AGE_SECONDS: i64 = 60;
+/// Widest Pyth confidence interval, in basis points of the price, still used
+/// for USD tiers.
+pub const PRICE_MAX_CONFIDENCE_BPS: u64 = 200;
+/// Decimals of the USD tier thresholds.
+pub const USD_DECIMALS: u32 = 6;
+/// `Pool::exchange_rate_source` values: no oracle, a Pyth price of the
+/// staking mint in its underlying asset, or the SPL stake pool minting it.
+pub const EXCHANGE_RATE_NONE: u8 = 0;
+pub const EXCHANGE_RATE_PYTH: u8 = 1;
+pub const EXCHANGE_RATE_STAKE_POOL: u8 = 2;
+pub const VOTER_WEIGHT_RECORD_SEED: &[u8] = b"voter-weight-record";
+/// Seed prefix of the per-NFT escrow token accounts of NFT pools.
+pub const NFT_ESCROW_SEED: &[u8] = b"nft_escrow";
+
+/// Longest memo, in bytes, `claim` and `unstake` attach to their transfers.
+pub const MAX_MEMO_LEN: usize = 256;
+
+/// Switchboard on-demand program, owner of the randomness accounts lottery
+/// draws read.
+pub mod switchboard_on_demand {
+  use anchor_lang::prelude::*;
+
+  declare_id!("SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv");
+}
+
+/// Jupiter aggregator (v6), the swap program of `claim_and_swap`.
+pub mod jupiter {
+  use anchor_lang::prelude::*;
+
+  declare_id!("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4");
+}
+
+/// SPL Memo program (v2).
+pub mod memo_program {
+  use anchor_lang::prelude::*;
+
+  declare_id!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+}
+/// Length of a `DailyStats` day; days start at midnight UTC.
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+/// `FeeCollectedEvent::kind` values.
+pub const FEE_KIND_DEPOSIT: u8 = 0;
+pub const FEE_KIND_CLAIM: u8 = 1;
+pub const FEE_KIND_PENALTY: u8 = 2;
+
+/// Layout version of the `PoolConfig` returned by `get_config`.
+pub const POOL_CONFIG_VERSION: u8 = 1;
+
+/// Year length `get_pool_stats` annualizes the reward rate over.
+pub const SECONDS_PER_YEAR: u64 = 365 * 86_400;
+
+/// Layout version written to every new or migrated user account.
+pub const USER_VERSION: u8 = 23;
+/// Size of a user account created before versioning (discriminator included).
+pub const USER_V1_SIZE: usize = 114;
+
+/// Entries kept in `User::history`.
+pub const USER_HISTORY_LEN: usize = 8;
+pub const ACTION_STAKE: u8 = 1;
+pub const ACTION_UNSTAKE: u8 = 2;
+pub const ACTION_CLAIM: u8 = 3;
+pub const ACTION_COMPOUND: u8 = 4;
+
+/// Seed prefix of the per-user reward vesting escrows.
+pub const VESTING_SEED: &[u8] = b"vesting";
+/// Seed prefix of the per-pool `Leaderboard` accounts.
+pub const LEADERBOARD_SEED: &[u8] = b"leaderboard";
+/// Seed prefix of the per-pool `MigrationConfig` accounts.
+pub const MIGRATION_SEED: &[u8] = b"migration";
+
+/// Seed prefix of the per-pool `LotteryRound` accounts.
+pub const LOTTERY_SEED: &[u8] = b"lottery";
+/// Seed prefix of the per-pool lottery prize vaults.
+pub const LOTTERY_VAULT_SEED: &[u8] = b"lottery_vault";
+/// Oldest randomness commitment, in slots, a lottery draw accepts.
+pub const LOTTERY_MAX_COMMIT_AGE_SLOTS: u64 = 150;
+/// Slots after its commitment an unrevealed draw can be cancelled, about an
+/// hour.
+pub const LOTTERY_REVEAL_TIMEOUT_SLOTS: u64 = 9_000;
+
+/// Seed of the PDA an approved claim program signs `claim_via_cpi` with,
+/// derived under that program together with the user account key.
+pub const CLAIM_AUTHORITY_SEED: &[u8] = b"claim_authority";
+
+/// Seed prefix of the transient token accounts `claim_and_swap` claims into.
+pub const SWAP_ACCOUNT_SEED: &[u8] = b"swap";
+/// Seed prefix of the PDAs owning those accounts and signing the swap.
+pub const SWAP_AUTHORITY_SEED: &[u8] = b"swap_authority";
+
+/// Seed of the PDA signing the `log_event` self-CPI, the same as Anchor's
+/// `emit_cpi!` so indexers written for it find the events.
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+
+/// Discriminator of Jupiter's `route`, the only instruction `claim_and_swap`
+/// forwards.
+pub const JUPITER_ROUTE_DISCRIMINATOR: [u8; 8] = [229, 23, 203, 151, 122, 227, 173, 42];
+/// Positions of the transfer authority and the source token account among the
+/// accounts of Jupiter's `route`.
+pub const JUPITER_ROUTE_AUTHORITY_INDEX: usize = 1;
+pub const JUPITER_ROUTE_SOURCE_INDEX: usize = 2;
+
+/// Entries kept in `Leaderboard::entries`.
+pub const LEADERBOARD_SIZE: usize = 20;
+
+/// Delay before a new beneficiary starts receiving claims.
+pub const BENEFICIARY_TIMELOCK_SECONDS: i64 = 86_400;
+/// Time after the last reward period ends until a pool with unclaimed
+/// rewards may be closed anyway.
+pub const POOL_SUNSET_GRACE_SECONDS: u64 = 365 * 86_400;
+
+/// Delay between requesting and executing a position recovery, during which
+/// the original owner can cancel.
+pub const RECOVERY_DELAY_SECONDS: i64 = 7 * 86_400;
+
+/// Longest circuit-breaker halt the authority can impose at once.
+pub const MAX_HALT_SECONDS: u64 = 72 * 3_600;
+
+/// Crate version as `[major, minor, patch]`, parsed from Cargo at build time.
+pub const PROGRAM_VERSION: [u16; 3] = parse_version(env!("CARGO_PKG_VERSION"));
+
+/// Feature bits reported by `get_version`. Add a bit with every new
+/// instruction or behavior clients need to detect, and include it in
+/// `PROGRAM_FEATURES`.
+pub const FEATURE_EVENTS: u64 = 1 << 0;
+pub const FEATURE_STATS_VIEWS: u64 = 1 << 1;
+pub const FEATURE_FUNDER_CONTRIBUTIONS: u64 = 1 << 2;
+pub const FEATURE_CRANK_UPDATE: u64 = 1 << 3;
+pub const FEATURE_DAILY_STATS: u64 = 1 << 4;
+pub const FEATURE_VERSION_QUERY: u64 = 1 << 5;
+pub const FEATURE_CONFIG_VIEW: u64 = 1 << 6;
+pub const FEATURE_FEE_COUNTERS: u64 = 1 << 7;
+pub const FEATURE_CRANK_EVENTS: u64 = 1 << 8;
+pub const FEATURE_VOTER_WEIGHT: u64 = 1 << 9;
+pub const FEATURE_USD_TIERS: u64 = 1 << 10;
+pub const FEATURE_NFT_STAKING: u64 = 1 << 11;
+pub const FEATURE_EXCHANGE_RATE_TIERS: u64 = 1 << 12;
+pub const FEATURE_AUTO_CLAIM: u64 = 1 << 13;
+pub const FEATURE_LOTTERY: u64 = 1 << 14;
+/// Only set in builds with the `jupiter-swap` feature.
+pub const FEATURE_CLAIM_AND_SWAP: u64 = 1 << 15;
+/// Only set in builds with the `event-cpi` feature.
+pub const FEATURE_EVENT_CPI: u64 = 1 << 16;
+pub const PROGRAM_FEATURES: u64 = FEATURE_EVENTS
+  | FEATURE_STATS_VIEWS
+  | FEATURE_FUNDER_CONTRIBUTIONS
+  | FEATURE_CRANK_UPDATE
+  | FEATURE_DAILY_STATS
+  | FEATURE_VERSION_QUERY
+  | FEATURE_CONFIG_VIEW
+  | FEATURE_FEE_COUNTERS
+  | FEATURE_CRANK_EVENTS
+  | FEATURE_VOTER_WEIGHT
+  | FEATURE_USD_TIERS
+  | FEATURE_NFT_STAKING
+  | FEATURE_EXCHANGE_RATE_TIERS
+  | FEATURE_AUTO_CLAIM
+  | FEATURE_LOTTERY
+  | if cfg!(feature = "jupiter-swap") {
+    FEATURE_CLAIM_AND_SWAP
+  } else {
+    0
+  }
+  | if cfg!(feature = "event-cpi") {
+    FEATURE_EVENT_CPI
+  } else {
+    0
+  };
+
+/// Parses the leading `major.minor.patch` of a semver string; anything after
+/// the patch number, such as a pre-release tag, is ignored.
+const fn parse_version(version: &str) -> [u16; 3] {
+  let bytes = version.as_bytes();
+  let mut parts = [0u16; 3];
+  let mut part = 0;
+  let mut i = 0;
+  while i < bytes.len() && part < 3 {
+    let b = bytes[i];
+    if b == b'.' {
+      part += 1;
+    } else if b >= b'0' && b <= b'9' {
+      parts[part] = parts[part] * 10 + (b - b'0') as u16;
+    } else {
+      break;
+    }
+    i += 1;
+  }
+  parts
+}
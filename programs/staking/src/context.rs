@@ -1,32 +1,45 @@
+//! Account structs of every instruction. Other programs build their CPIs from
+//! the `staking::cpi::accounts` mirrors of these, so the field order is part
+//! of the public interface: append new accounts, never reorder or remove.
+
 use crate::account::*;
+use crate::constants::{
+    jupiter, memo_program, DAILY_STATS_SEED, EVENT_AUTHORITY_SEED, LEADERBOARD_SEED, LOTTERY_SEED,
+    LOTTERY_VAULT_SEED, MIGRATION_SEED, NFT_ESCROW_SEED, SWAP_ACCOUNT_SEED, SWAP_AUTHORITY_SEED,
+    USER_VERSION, VESTING_SEED, VOTER_WEIGHT_RECORD_SEED,
+};
 use crate::error::ErrorCode;
-use anchor_lang::solana_program::{clock, program_option::COption, sysvar};
+use crate::utils::is_claim_authority;
+use anchor_lang::solana_program::program_option::COption;
 use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token::{Mint, Token, TokenAccount};
-use std::convert::TryInto;
 
 #[derive(Accounts)]
-#[instruction(pool_nonce: u8)]
 pub struct InitializePool<'info> {
     /// CHECK: nothing to check.
     pub authority: AccountInfo<'info>,
 
-    pub staking_mint: Box<Account<'info, Mint>>,
-    #[account(
-        constraint = staking_vault.mint == staking_mint.key(),
-        constraint = staking_vault.owner == pool_signer.key(),
-        //strangely, spl maintains this on owner reassignment for non-native accounts
-        //we don't want to be given an account that someone else could close when empty
-        //because in our "pool close" operation we want to assert it is still open
-        constraint = staking_vault.close_authority == COption::None,
-    )]
-    pub staking_vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: a mint of either token program, checked by the handler.
+    pub staking_mint: AccountInfo<'info>,
+    // Checked by the handler like the reward vault below, as it may belong to
+    // Token-2022.
+    /// CHECK: see above.
+    pub staking_vault: AccountInfo<'info>,
 
     pub reward_mint: Box<Account<'info, Mint>>,
     #[account(
         constraint = reward_vault.mint == reward_mint.key(),
         constraint = reward_vault.owner == pool_signer.key(),
+        //strangely, spl maintains this on owner reassignment for non-native accounts
+        //we don't want to be given an account that someone else could close when empty
+        //because in our "pool close" operation we want to assert it is still open
         constraint = reward_vault.close_authority == COption::None,
+        //a pre-approved delegate could move vault funds outside the program
+        constraint = reward_vault.delegate == COption::None @ ErrorCode::VaultHasDelegate,
+        //pools staking their reward mint still keep principal and rewards in
+        //separate vaults, so claims (capped at the reward vault) can't pay out stake
+        constraint = reward_vault.key() != staking_vault.key() @ ErrorCode::IdenticalVaults,
     )]
     pub reward_vault: Box<Account<'info, TokenAccount>>,
 
@@ -34,7 +47,7 @@ pub struct InitializePool<'info> {
         seeds = [
             pool.to_account_info().key.as_ref()
         ],
-        bump = pool_nonce,
+        bump,
     )]
     /// CHECK: nothing to check.
     pub pool_signer: AccountInfo<'info>,
@@ -53,12 +66,13 @@ pub struct CreateUser<'info> {
     #[account(
         mut,
         constraint = !pool.paused @ ErrorCode::PoolPaused,
+        constraint = !pool.closing @ ErrorCode::PoolClosing,
     )]
     pub pool: Box<Account<'info, Pool>>,
     // Member.
     #[account(
         init,
-        payer=owner,
+        payer=payer,
         seeds = [
             owner.key.as_ref(), 
             pool.to_account_info().key.as_ref()
@@ -66,19 +80,186 @@ pub struct CreateUser<'info> {
         bump
     )]
     pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+    // Pays the rent, refunded on close.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    // Misc.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(day_index: u64)]
+pub struct CreateUserAndStake<'info> {
+    #[account(
+        mut,
+        has_one = staking_vault,
+        constraint = !pool.paused @ ErrorCode::PoolPaused,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump
+    )]
+    pub user: Box<Account<'info, User>>,
     #[account(mut)]
     pub owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = stake_from_account.mint == pool.staking_mint @ ErrorCode::InvalidMint,
+        constraint = !stake_from_account.is_frozen() @ ErrorCode::SourceAccountFrozen,
+    )]
+    pub stake_from_account: Box<Account<'info, TokenAccount>>,
+
     // Misc.
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    // Daily statistics of day `day_index`, which the handler checks against
+    // the clock, created by the payer on the day's first action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+
+    // Pyth price account or exchange-rate oracle of pools valuing tiers
+    // through one; any account otherwise.
+    #[account(
+        constraint = pool.tier_oracle() == Pubkey::default()
+            || price_feed.key() == pool.tier_oracle() @ ErrorCode::InvalidPriceFeed,
+    )]
+    /// CHECK: parsed by `refresh_oracle_tier`.
+    pub price_feed: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUser<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: may still use an older layout, checked in the handler.
+    pub user: AccountInfo<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    #[account(mut, owner = crate::ID)]
+    /// CHECK: may still use a shorter layout, checked in the handler.
+    pub pool: AccountInfo<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DowngradeUser<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: rewritten to the old layout, checked in the handler.
+    pub user: AccountInfo<'info>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CorruptPool<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(day_index: u64)]
+pub struct RecordDailyStats<'info> {
+    #[account(has_one = authority)]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct WritePriceFeed<'info> {
+    #[account(mut, owner = crate::ID)]
+    /// CHECK: test-only account laid out like a Pyth price account, claimed
+    /// for `authority` in the handler.
+    pub price_feed: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WriteRandomness<'info> {
+    #[account(mut, owner = crate::ID)]
+    /// CHECK: test-only account laid out like a Switchboard randomness account,
+    /// claimed for `authority` in the handler.
+    pub randomness: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct Pause<'info> {
     #[account(
         mut, 
         has_one = authority,
         constraint = !pool.paused @ ErrorCode::PoolPaused,
-        constraint = pool.reward_duration_end < clock::Clock::get().unwrap().unix_timestamp.try_into().unwrap(),
     )]
     pub pool: Box<Account<'info, Pool>>,
     pub authority: Signer<'info>,
@@ -96,6 +277,7 @@ pub struct Unpause<'info> {
 }
 
 #[derive(Accounts)]
+#[instruction(day_index: u64)]
 pub struct Stake<'info> {
     // Global accounts for the staking instance.
     #[account(
@@ -103,11 +285,11 @@ pub struct Stake<'info> {
         has_one = staking_vault,
     )]
     pub pool: Box<Account<'info, Pool>>,
-    #[account(
-        mut,
-        constraint = staking_vault.owner == *pool_signer.key,
-    )]
-    pub staking_vault: Box<Account<'info, TokenAccount>>,
+    // Token accounts of either token program, checked by
+    // `check_stake_accounts`.
+    #[account(mut)]
+    /// CHECK: see above.
+    pub staking_vault: AccountInfo<'info>,
 
     // User.
     #[account(
@@ -119,11 +301,13 @@ pub struct Stake<'info> {
             pool.to_account_info().key.as_ref()
         ],
         bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
     )]
     pub user: Box<Account<'info, User>>,
     pub owner: Signer<'info>,
     #[account(mut)]
-    pub stake_from_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: see `staking_vault`.
+    pub stake_from_account: AccountInfo<'info>,
 
     // Program signers.
     #[account(
@@ -135,39 +319,171 @@ pub struct Stake<'info> {
     /// CHECK: nothing to check.
     pub pool_signer: AccountInfo<'info>,
 
+    // Daily statistics of day `day_index`, which the handler checks against
+    // the clock, created by the payer on the day's first action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
     // Misc.
-    pub token_program: Program<'info, Token>,
+    /// CHECK: the staking mint's token program, checked on `staking_mint`.
+    pub token_program: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+        constraint = *staking_mint.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: a mint of either token program, read by `check_stake_accounts`.
+    pub staking_mint: AccountInfo<'info>,
+
+    // Pyth price account or exchange-rate oracle of pools valuing tiers
+    // through one; any account otherwise.
+    #[account(
+        constraint = pool.tier_oracle() == Pubkey::default()
+            || price_feed.key() == pool.tier_oracle() @ ErrorCode::InvalidPriceFeed,
+    )]
+    /// CHECK: parsed by `refresh_oracle_tier`.
+    pub price_feed: AccountInfo<'info>,
+
+    // SPL Memo program, invoked when a memo is given.
+    #[account(
+        address = memo_program::ID,
+    )]
+    /// CHECK: address checked.
+    pub memo_program: AccountInfo<'info>,
+
+    // Events over CPI, see `utils::emit_cpi`.
+    #[account(
+        seeds = [EVENT_AUTHORITY_SEED],
+        bump,
+    )]
+    /// CHECK: PDA signing `log_event`.
+    pub event_authority: AccountInfo<'info>,
+    #[account(
+        address = crate::ID,
+    )]
+    /// CHECK: address checked.
+    pub program: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct FunderChange<'info> {
+pub struct StakeNft<'info> {
     // Global accounts for the staking instance.
+    #[account(mut)]
+    pub pool: Box<Account<'info, Pool>>,
+
+    // User.
     #[account(
         mut, 
-        has_one = authority,
+        has_one = owner, 
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
     )]
-    pub pool: Box<Account<'info, Pool>>,
-    pub authority: Signer<'info>,
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+
+    // NFT, checked against the pool's collection by `holds_collection_nft`.
+    #[account(
+        constraint = nft_mint.decimals == 0 && nft_mint.supply == 1 @ ErrorCode::InvalidNft,
+    )]
+    pub nft_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = nft_from_account.mint == nft_mint.key() @ ErrorCode::InvalidMint,
+        constraint = !nft_from_account.is_frozen() @ ErrorCode::SourceAccountFrozen,
+    )]
+    pub nft_from_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: parsed by `holds_collection_nft`.
+    pub nft_metadata: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            NFT_ESCROW_SEED,
+            user.to_account_info().key.as_ref(),
+            nft_mint.to_account_info().key.as_ref()
+        ],
+        bump,
+        token::mint = nft_mint,
+        token::authority = pool_signer,
+    )]
+    pub nft_escrow: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct Fund<'info> {
+pub struct UnstakeNft<'info> {
     // Global accounts for the staking instance.
+    #[account(mut)]
+    pub pool: Box<Account<'info, Pool>>,
+
+    // User.
     #[account(
-        mut,
-        has_one = reward_vault,
-        constraint = !pool.paused @ ErrorCode::PoolPaused,
+        mut, 
+        has_one = owner, 
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
     )]
-    pub pool: Box<Account<'info, Pool>>,
+    pub user: Box<Account<'info, User>>,
     #[account(mut)]
-    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    pub owner: Signer<'info>,
+
+    // NFT, escrowed per user so only its staker can take it back.
+    pub nft_mint: Box<Account<'info, Mint>>,
     #[account(
-        //require signed funder auth - otherwise constant micro fund could hold funds hostage
-        constraint = funder.key() == pool.authority || pool.funders.iter().any(|x| *x == funder.key()),
+        mut,
+        constraint = nft_to_account.mint == nft_mint.key() @ ErrorCode::InvalidMint,
+        constraint = nft_to_account.owner == owner.key() @ ErrorCode::InvalidWithdrawDestination,
     )]
-    pub funder: Signer<'info>,
-    #[account(mut)]
-    pub from: Box<Account<'info, TokenAccount>>,
+    pub nft_to_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [
+            NFT_ESCROW_SEED,
+            user.to_account_info().key.as_ref(),
+            nft_mint.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    pub nft_escrow: Box<Account<'info, TokenAccount>>,
 
     // Program signers.
     #[account(
@@ -184,17 +500,27 @@ pub struct Fund<'info> {
 }
 
 #[derive(Accounts)]
-pub struct ClaimReward<'info> {
+#[instruction(day_index: u64)]
+pub struct UnstakeAndClaim<'info> {
     // Global accounts for the staking instance.
     #[account(
-        mut, 
+        mut,
         has_one = staking_vault,
         has_one = reward_vault,
+        constraint = !pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
     )]
     pub pool: Box<Account<'info, Pool>>,
-    #[account(mut)]
-    pub staking_vault: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
     pub reward_vault: Box<Account<'info, TokenAccount>>,
 
     // User.
@@ -203,13 +529,24 @@ pub struct ClaimReward<'info> {
         has_one = owner,
         has_one = pool,
         seeds = [
-            owner.key.as_ref(), 
+            owner.key.as_ref(),
             pool.to_account_info().key.as_ref()
         ],
         bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+        constraint = user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
     )]
     pub user: Box<Account<'info, User>>,
     pub owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = stake_from_account.mint == pool.staking_mint @ ErrorCode::InvalidMint,
+        constraint = !stake_from_account.is_frozen() @ ErrorCode::SourceAccountFrozen,
+        constraint = stake_from_account.owner == owner.key() @ ErrorCode::InvalidWithdrawDestination,
+    )]
+    pub stake_from_account: Box<Account<'info, TokenAccount>>,
+    // Checked by the handler like a `claim` destination.
     #[account(mut)]
     pub reward_account: Box<Account<'info, TokenAccount>>,
 
@@ -225,37 +562,1579 @@ pub struct ClaimReward<'info> {
 
     // Misc.
     pub token_program: Program<'info, Token>,
+
+    // Daily statistics of day `day_index`, which the handler checks against
+    // the clock, created by the payer on the day's first action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
 }
 
 #[derive(Accounts)]
-pub struct CloseUser<'info> {
-    #[account(mut)]
+pub struct ApproveUnstake<'info> {
     pub pool: Box<Account<'info, Pool>>,
     #[account(
         mut,
-        close = owner,
         has_one = owner,
         has_one = pool,
-        constraint = user.balance_staked == 0,
-        constraint = user.reward_per_token_pending == 0,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
         seeds = [
-            owner.key.as_ref(), 
+            owner.key.as_ref(),
             pool.to_account_info().key.as_ref()
         ],
         bump = user.nonce,
     )]
-    pub user: Account<'info, User>,
-    #[account(mut)]
+    pub user: Box<Account<'info, User>>,
     pub owner: Signer<'info>,
+    #[account(
+        constraint = destination.mint == pool.staking_mint,
+        constraint = destination.owner == owner.key(),
+    )]
+    pub destination: Box<Account<'info, TokenAccount>>,
 }
 
 #[derive(Accounts)]
-pub struct ClosePool<'info> {
-    #[account(mut)]
-    /// CHECK: nothing to check.
-    pub refundee: AccountInfo<'info>,
-    #[account(mut)]
-    pub staking_refundee: Box<Account<'info, TokenAccount>>,
+pub struct RevokeUnstakeAllowance<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeBySpender<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = staking_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked.
+    pub staking_vault: AccountInfo<'info>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = user.unstake_spender == spender.key(),
+        constraint = user.unstake_destination == destination.key(),
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub spender: Signer<'info>,
+    #[account(mut)]
+    pub destination: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutoCompound<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CrankCompound<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = staking_vault,
+        has_one = reward_vault,
+        constraint = !pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
+        constraint = pool.staking_mint == pool.reward_mint @ ErrorCode::AutoCompoundUnsupported,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = user.auto_compound @ ErrorCode::AutoCompoundDisabled,
+        constraint = user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    // Cranker.
+    pub cranker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = tip_account.mint == pool.reward_mint,
+    )]
+    pub tip_account: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnfreezeAccount<'info> {
+    #[account(
+        mut,
+        has_one = guardian,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub guardian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetBeneficiary<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetUserConfig<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(day_index: u64)]
+pub struct DelegateStake<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = staking_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked.
+    pub staking_vault: AccountInfo<'info>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = user.operations_delegate != Pubkey::default(),
+        constraint = user.operations_delegate == delegate.key(),
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub delegate: Signer<'info>,
+    #[account(
+        mut,
+        constraint = stake_from_account.mint == pool.staking_mint @ ErrorCode::InvalidMint,
+        constraint = !stake_from_account.is_frozen() @ ErrorCode::SourceAccountFrozen,
+    )]
+    pub stake_from_account: Box<Account<'info, TokenAccount>>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+
+    // Daily statistics of day `day_index`, which the handler checks against
+    // the clock, created by the payer on the day's first action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+
+    // Pyth price account or exchange-rate oracle of pools valuing tiers
+    // through one; any account otherwise.
+    #[account(
+        constraint = pool.tier_oracle() == Pubkey::default()
+            || price_feed.key() == pool.tier_oracle() @ ErrorCode::InvalidPriceFeed,
+    )]
+    /// CHECK: parsed by `refresh_oracle_tier`.
+    pub price_feed: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(day_index: u64)]
+pub struct DelegateUnstake<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = staking_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked.
+    pub staking_vault: AccountInfo<'info>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+        constraint = user.operations_delegate != Pubkey::default(),
+        constraint = user.operations_delegate == delegate.key(),
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub delegate: Signer<'info>,
+    // Principal only ever goes back to the owner.
+    #[account(
+        mut,
+        constraint = destination.owner == user.owner @ ErrorCode::InvalidWithdrawDestination,
+        constraint = destination.mint == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub destination: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+
+    // Daily statistics of day `day_index`, which the handler checks against
+    // the clock, created by the payer on the day's first action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+
+    // Pyth price account or exchange-rate oracle of pools valuing tiers
+    // through one; any account otherwise.
+    #[account(
+        constraint = pool.tier_oracle() == Pubkey::default()
+            || price_feed.key() == pool.tier_oracle() @ ErrorCode::InvalidPriceFeed,
+    )]
+    /// CHECK: parsed by `refresh_oracle_tier`.
+    pub price_feed: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetUserVesting<'info> {
+    #[account(
+        has_one = authority,
+        has_one = reward_mint,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub reward_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [
+            VESTING_SEED,
+            user.to_account_info().key.as_ref()
+        ],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool_signer,
+    )]
+    pub vesting_escrow: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseVested<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            VESTING_SEED,
+            user.to_account_info().key.as_ref()
+        ],
+        bump = user.vesting_nonce,
+    )]
+    pub vesting_escrow: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = !reward_account.is_frozen() @ ErrorCode::AccountFrozen,
+    )]
+    /// Checked against the reward destinations by the handler.
+    pub reward_account: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct RequestRecovery<'info> {
+    #[account(has_one = authority)]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteRecovery<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        close = rent_payer,
+        has_one = pool,
+        constraint = old_user.rent_refundee() == rent_payer.key(),
+        constraint = old_user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = old_user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
+        constraint = old_user.recovery_new_owner == new_owner.key() @ ErrorCode::NoRecoveryPending,
+    )]
+    pub old_user: Box<Account<'info, User>>,
+    #[account(
+        init,
+        payer = new_owner,
+        seeds = [
+            new_owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    pub new_user: Box<Account<'info, User>>,
+    #[account(mut)]
+    pub new_owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    /// CHECK: checked against the old user's recorded rent payer.
+    pub rent_payer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateLeaderboard<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            LEADERBOARD_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    pub leaderboard: Box<Account<'info, Leaderboard>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLeaderboard<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = pool,
+        seeds = [
+            LEADERBOARD_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = leaderboard.nonce,
+    )]
+    pub leaderboard: Box<Account<'info, Leaderboard>>,
+    #[account(
+        has_one = pool,
+        seeds = [
+            user.owner.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+pub struct SyncUser<'info> {
+    #[account(mut)]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+#[instruction(realm: Pubkey)]
+pub struct CreateVoterWeightRecord<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        has_one = owner,
+        has_one = pool,
+    )]
+    pub user: Box<Account<'info, User>>,
+    #[account(
+        init,
+        payer = payer,
+        space = VoterWeightRecord::space(),
+        seeds = [
+            VOTER_WEIGHT_RECORD_SEED,
+            realm.as_ref(),
+            pool.staking_mint.as_ref(),
+            owner.key.as_ref()
+        ],
+        bump,
+    )]
+    pub voter_weight_record: Box<Account<'info, VoterWeightRecord>>,
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateVoterWeightRecord<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+    #[account(
+        mut,
+        constraint = voter_weight_record.governing_token_owner == user.owner
+            @ ErrorCode::VoterWeightRecordMismatch,
+        constraint = voter_weight_record.governing_token_mint == pool.staking_mint
+            @ ErrorCode::VoterWeightRecordMismatch,
+    )]
+    pub voter_weight_record: Box<Account<'info, VoterWeightRecord>>,
+}
+
+#[derive(Accounts)]
+pub struct CrankUpdate<'info> {
+    #[account(
+        mut,
+        has_one = reward_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct AssertInvariants<'info> {
+    #[account(
+        mut,
+        has_one = staking_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    /// CHECK: may belong to Token-2022, read by the handler.
+    pub staking_vault: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClearHalt<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        constraint = pool.halted,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SyncTotalStaked<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = staking_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct BatchUnstake<'info> {
+    pub owner: Signer<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SimulateUnstake<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+pub struct GetUserSummary<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+}
+
+#[derive(Accounts)]
+pub struct GetVersion {}
+
+#[derive(Accounts)]
+pub struct GetConfig<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+}
+
+#[derive(Accounts)]
+pub struct GetUserStats<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolStats<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+}
+
+#[derive(Accounts)]
+#[instruction(day_index: u64)]
+pub struct StakeSplit<'info> {
+    // First pool.
+    #[account(mut)]
+    pub first_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = first_staking_vault.key() == first_pool.staking_vault,
+    )]
+    pub first_staking_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        has_one = owner,
+        constraint = first_user.pool == first_pool.key(),
+        seeds = [
+            owner.key.as_ref(),
+            first_pool.to_account_info().key.as_ref()
+        ],
+        bump = first_user.nonce,
+        constraint = first_user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub first_user: Box<Account<'info, User>>,
+
+    // Second pool.
+    #[account(
+        mut,
+        constraint = second_pool.key() != first_pool.key(),
+        constraint = second_pool.staking_mint == first_pool.staking_mint,
+    )]
+    pub second_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = second_staking_vault.key() == second_pool.staking_vault,
+    )]
+    pub second_staking_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        has_one = owner,
+        constraint = second_user.pool == second_pool.key(),
+        seeds = [
+            owner.key.as_ref(),
+            second_pool.to_account_info().key.as_ref()
+        ],
+        bump = second_user.nonce,
+        constraint = second_user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub second_user: Box<Account<'info, User>>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub stake_from_account: Box<Account<'info, TokenAccount>>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Daily statistics of each pool for day `day_index`, which the handler
+    // checks against the clock, created by the payer on the day's first
+    // action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            first_pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub first_daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            second_pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub second_daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == first_pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+
+    // Pyth price accounts or exchange-rate oracles of pools valuing tiers
+    // through one; any account otherwise.
+    #[account(
+        constraint = first_pool.tier_oracle() == Pubkey::default()
+            || first_price_feed.key() == first_pool.tier_oracle() @ ErrorCode::InvalidPriceFeed,
+    )]
+    /// CHECK: parsed by `refresh_oracle_tier`.
+    pub first_price_feed: AccountInfo<'info>,
+    #[account(
+        constraint = second_pool.tier_oracle() == Pubkey::default()
+            || second_price_feed.key() == second_pool.tier_oracle() @ ErrorCode::InvalidPriceFeed,
+    )]
+    /// CHECK: parsed by `refresh_oracle_tier`.
+    pub second_price_feed: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateMigration<'info> {
+    #[account(
+        init,
+        payer = old_authority,
+        seeds = [
+            MIGRATION_SEED,
+            old_pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    pub migration: Box<Account<'info, MigrationConfig>>,
+    #[account(
+        constraint = old_pool.authority == old_authority.key(),
+    )]
+    pub old_pool: Box<Account<'info, Pool>>,
+    #[account(
+        constraint = new_pool.authority == new_authority.key(),
+        constraint = new_pool.key() != old_pool.key(),
+        constraint = new_pool.staking_mint == old_pool.staking_mint,
+    )]
+    pub new_pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    pub old_authority: Signer<'info>,
+    pub new_authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePoolVaults<'info> {
+    #[account(
+        mut,
+        has_one = old_pool,
+        has_one = new_pool,
+        seeds = [
+            MIGRATION_SEED,
+            old_pool.to_account_info().key.as_ref()
+        ],
+        bump = migration.nonce,
+    )]
+    pub migration: Box<Account<'info, MigrationConfig>>,
+
+    // Pool migrated from.
+    #[account(
+        mut,
+        constraint = old_pool.authority == old_authority.key(),
+        constraint = old_pool.paused @ ErrorCode::PoolNotPaused,
+        constraint = !old_pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
+    )]
+    pub old_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = old_staking_vault.key() == old_pool.staking_vault,
+    )]
+    pub old_staking_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = old_reward_vault.key() == old_pool.reward_vault,
+    )]
+    pub old_reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [
+            old_pool.to_account_info().key.as_ref()
+        ],
+        bump = old_pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub old_pool_signer: AccountInfo<'info>,
+    pub old_authority: Signer<'info>,
+
+    // Pool migrated to.
+    #[account(
+        mut,
+        constraint = new_pool.authority == new_authority.key(),
+        constraint = new_pool.paused @ ErrorCode::PoolNotPaused,
+        constraint = new_pool.total_staked == 0 @ ErrorCode::PoolNotEmpty,
+        constraint = new_pool.user_stake_count == 0 @ ErrorCode::PoolNotEmpty,
+        constraint = new_pool.reward_mint == old_pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub new_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = new_staking_vault.key() == new_pool.staking_vault,
+    )]
+    pub new_staking_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = new_reward_vault.key() == new_pool.reward_vault,
+    )]
+    pub new_reward_vault: Box<Account<'info, TokenAccount>>,
+    pub new_authority: Signer<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == old_pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = reward_mint.key() == old_pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateStake<'info> {
+    #[account(
+        has_one = old_pool,
+        has_one = new_pool,
+        seeds = [
+            MIGRATION_SEED,
+            old_pool.to_account_info().key.as_ref()
+        ],
+        bump = migration.nonce,
+    )]
+    pub migration: Box<Account<'info, MigrationConfig>>,
+
+    // Pool migrated from.
+    #[account(mut)]
+    pub old_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = old_staking_vault.key() == old_pool.staking_vault,
+    )]
+    pub old_staking_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = old_reward_vault.key() == old_pool.reward_vault,
+    )]
+    pub old_reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        close = rent_payer,
+        has_one = owner,
+        constraint = old_user.pool == old_pool.key(),
+        constraint = old_user.rent_refundee() == rent_payer.key(),
+        seeds = [
+            owner.key.as_ref(),
+            old_pool.to_account_info().key.as_ref()
+        ],
+        bump = old_user.nonce,
+        constraint = old_user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !old_user.frozen @ ErrorCode::PositionFrozen,
+        constraint = old_user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
+    )]
+    pub old_user: Box<Account<'info, User>>,
+    #[account(
+        seeds = [
+            old_pool.to_account_info().key.as_ref()
+        ],
+        bump = old_pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub old_pool_signer: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = reward_account.mint == old_pool.reward_mint,
+    )]
+    /// Checked by the handler when pending rewards are paid out.
+    pub reward_account: Box<Account<'info, TokenAccount>>,
+
+    // Pool migrated to.
+    #[account(
+        mut,
+        constraint = !new_pool.paused @ ErrorCode::PoolPaused,
+        constraint = !new_pool.closing @ ErrorCode::PoolClosing,
+    )]
+    pub new_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = new_staking_vault.key() == new_pool.staking_vault,
+    )]
+    pub new_staking_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [
+            owner.key.as_ref(),
+            new_pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    pub new_user: Box<Account<'info, User>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == old_pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = reward_mint.key() == old_pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    /// CHECK: checked against the old user's recorded rent payer.
+    pub rent_payer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Slash<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = staking_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    // Transfers go through the legacy token program, so Token-2022 vaults fail.
+    #[account(
+        mut,
+        constraint = *staking_vault.owner == token_program.key() @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: owner checked.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+    #[account(
+        mut,
+        constraint = slash_destination.mint == pool.staking_mint,
+    )]
+    pub slash_destination: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    pub staking_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct SetUserByAuthority<'info> {
+    #[account(
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+pub struct FunderChange<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut, 
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetClosing<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RenounceFunder<'info> {
+    #[account(mut)]
+    pub pool: Box<Account<'info, Pool>>,
+    pub funder: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolConfig<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetUnstakePenalty<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        constraint = penalty_vault.mint == pool.staking_mint,
+        constraint = penalty_vault.key() != pool.staking_vault,
+    )]
+    pub penalty_vault: Box<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct SetInsurance<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        constraint = insurance_vault.mint == pool.reward_mint,
+        constraint = insurance_vault.owner == pool_signer.key(),
+        constraint = insurance_vault.close_authority == COption::None,
+        constraint = insurance_vault.key() != pool.reward_vault,
+        constraint = insurance_vault.key() != pool.staking_vault,
+    )]
+    pub insurance_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CoverShortfall<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        has_one = insurance_vault,
+        has_one = reward_vault,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub insurance_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct Fund<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = reward_vault,
+        constraint = !pool.paused @ ErrorCode::PoolPaused,
+        constraint = !pool.halted @ ErrorCode::PoolHalted,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        //require signed funder auth - otherwise constant micro fund could hold funds hostage
+        constraint = funder.key() == pool.authority || pool.funders.iter().any(|x| *x == funder.key()),
+    )]
+    /// CHECK: a signer, or an SPL Token multisig whose signers follow in the
+    /// remaining accounts; checked by `multisig_signers`.
+    pub funder: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = from.mint == pool.reward_mint @ ErrorCode::InvalidFundingMint,
+        constraint = !from.is_frozen() @ ErrorCode::SourceAccountFrozen,
+        constraint = from.owner == funder.key()
+            || from.delegate == COption::Some(funder.key())
+            @ ErrorCode::InvalidFundingSource,
+    )]
+    pub from: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+#[instruction(day_index: u64)]
+pub struct ClaimReward<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut, 
+        has_one = staking_vault,
+        has_one = reward_vault,
+        constraint = !pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    /// CHECK: may belong to Token-2022; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+    )]
+    pub user: Box<Account<'info, User>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    /// CHECK: the owner's associated token account is created when missing,
+    /// then checked by `load_reward_account`.
+    pub reward_account: AccountInfo<'info>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Daily statistics of day `day_index`, which the handler checks against
+    // the clock, created by the payer on the day's first action.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            DAILY_STATS_SEED,
+            pool.to_account_info().key.as_ref(),
+            &day_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub daily_stats: Box<Account<'info, DailyStats>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    // SPL Memo program, invoked when a memo is given.
+    #[account(
+        address = memo_program::ID,
+    )]
+    /// CHECK: address checked.
+    pub memo_program: AccountInfo<'info>,
+
+    // Creates a missing associated reward account.
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+
+    // Events over CPI, see `utils::emit_cpi`.
+    #[account(
+        seeds = [EVENT_AUTHORITY_SEED],
+        bump,
+    )]
+    /// CHECK: PDA signing `log_event`.
+    pub event_authority: AccountInfo<'info>,
+    #[account(
+        address = crate::ID,
+    )]
+    /// CHECK: address checked.
+    pub program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimViaCpi<'info> {
+    #[account(
+        mut,
+        has_one = staking_vault,
+        has_one = reward_vault,
+        constraint = !pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    /// CHECK: may belong to Token-2022; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+        constraint = user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
+    )]
+    pub user: Box<Account<'info, User>>,
+    /// CHECK: matched against the user's owner.
+    pub owner: AccountInfo<'info>,
+    #[account(
+        constraint = is_claim_authority(
+            &user,
+            &user.key(),
+            &claim_authority.key(),
+        ) @ ErrorCode::ClaimProgramNotApproved,
+    )]
+    pub claim_authority: Signer<'info>,
+    // Checked against the owner's reward account by the handler.
+    #[account(mut)]
+    pub reward_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct AutoClaim<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = staking_vault,
+        has_one = reward_vault,
+        constraint = !pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    /// CHECK: may belong to Token-2022; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = pool,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = user.auto_claim @ ErrorCode::AutoClaimDisabled,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+        constraint = user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
+    )]
+    pub user: Box<Account<'info, User>>,
+    // Checked against the owner's reward account by the handler.
+    #[account(mut)]
+    pub reward_account: Box<Account<'info, TokenAccount>>,
+
+    // Keeper.
+    pub cranker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = tip_account.mint == pool.reward_mint,
+    )]
+    pub tip_account: Box<Account<'info, TokenAccount>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUser<'info> {
+    #[account(mut)]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        close = rent_payer,
+        has_one = owner,
+        has_one = pool,
+        constraint = user.rent_refundee() == rent_payer.key(),
+        constraint = user.balance_staked == 0,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+    )]
+    pub user: Box<Account<'info, User>>,
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    /// CHECK: checked against the user's recorded rent payer.
+    pub rent_payer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    #[account(mut)]
+    /// CHECK: nothing to check.
+    pub refundee: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: a staking mint account, checked by the token program.
+    pub staking_refundee: AccountInfo<'info>,
     #[account(mut)]
     pub reward_refundee: Box<Account<'info, TokenAccount>>,
     #[account(
@@ -264,18 +2143,284 @@ pub struct ClosePool<'info> {
         has_one = authority,
         has_one = staking_vault,
         has_one = reward_vault,
-        constraint = pool.paused,
-        constraint = pool.reward_duration_end > 0,
-        constraint = pool.reward_duration_end < sysvar::clock::Clock::get().unwrap().unix_timestamp.try_into().unwrap(),
-        constraint = pool.user_stake_count == 0,
-        constraint = pool.total_staked == 0,
+        constraint = pool.closing @ ErrorCode::PoolNotClosing,
     )]
     pub pool: Account<'info, Pool>,
     pub authority: Signer<'info>,
     #[account(mut)]
-    pub staking_vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: may belong to Token-2022; the handler checks its delegate.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = reward_vault.delegate == COption::None @ ErrorCode::VaultHasDelegate,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+
+    // Mints, checked by transfer_checked.
+    #[account(
+        mut,
+        constraint = staking_mint.key() == pool.staking_mint @ ErrorCode::InvalidMint,
+    )]
+    /// CHECK: a mint of either token program; mutable to harvest withheld
+    /// transfer fees.
+    pub staking_mint: AccountInfo<'info>,
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    // Token program of the staking mint; `token_program` moves the rewards.
+    #[account(
+        constraint = staking_token_program.key() == *staking_mint.owner
+            @ ErrorCode::InvalidTokenProgram,
+    )]
+    /// CHECK: checked against the staking mint's owner.
+    pub staking_token_program: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct CreateLottery<'info> {
+    #[account(
+        has_one = authority,
+        has_one = reward_mint,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = LotteryRound::space(),
+        seeds = [
+            LOTTERY_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+    )]
+    pub lottery: Box<Account<'info, LotteryRound>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            LOTTERY_VAULT_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool_signer,
+    )]
+    pub lottery_vault: Box<Account<'info, TokenAccount>>,
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct FundLottery<'info> {
+    #[account(
+        has_one = authority,
+        has_one = reward_mint,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = lottery_vault,
+        seeds = [
+            LOTTERY_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = lottery.nonce,
+    )]
+    pub lottery: Box<Account<'info, LotteryRound>>,
+    #[account(mut)]
+    pub lottery_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = from.mint == pool.reward_mint @ ErrorCode::InvalidFundingMint,
+    )]
+    pub from: Box<Account<'info, TokenAccount>>,
+    pub reward_mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RequestDraw<'info> {
+    #[account(
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        seeds = [
+            LOTTERY_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = lottery.nonce,
+    )]
+    pub lottery: Box<Account<'info, LotteryRound>>,
+    #[account(
+        has_one = pool,
+        seeds = [
+            LEADERBOARD_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = leaderboard.nonce,
+    )]
+    pub leaderboard: Box<Account<'info, Leaderboard>>,
+    /// CHECK: parsed by `read_randomness`.
+    pub randomness: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelDraw<'info> {
+    #[account(
+        has_one = authority,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = randomness,
+        seeds = [
+            LOTTERY_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = lottery.nonce,
+        constraint = lottery.draw_pending() @ ErrorCode::NoLotteryDrawPending,
+    )]
+    pub lottery: Box<Account<'info, LotteryRound>>,
+    /// CHECK: parsed by `read_randomness`.
+    pub randomness: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleDraw<'info> {
+    #[account(
+        has_one = reward_mint,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = lottery_vault,
+        seeds = [
+            LOTTERY_SEED,
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = lottery.nonce,
+        constraint = lottery.draw_pending() @ ErrorCode::NoLotteryDrawPending,
+        constraint = lottery.randomness == randomness.key() @ ErrorCode::InvalidRandomness,
+    )]
+    pub lottery: Box<Account<'info, LotteryRound>>,
+    #[account(mut)]
+    pub lottery_vault: Box<Account<'info, TokenAccount>>,
+    /// CHECK: parsed by `read_randomness`.
+    pub randomness: AccountInfo<'info>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: nothing to check.
+    pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    pub token_program: Program<'info, Token>,
+    pub reward_mint: Box<Account<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAndSwap<'info> {
+    // Global accounts for the staking instance.
+    #[account(
+        mut,
+        has_one = staking_vault,
+        has_one = reward_vault,
+        constraint = !pool.vaults_migrated @ ErrorCode::VaultsAlreadyMigrated,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
     #[account(mut)]
+    /// CHECK: may belong to Token-2022; the handler checks it isn't frozen.
+    pub staking_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = !reward_vault.is_frozen() @ ErrorCode::VaultFrozen,
+    )]
     pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    // User.
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(),
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = user.nonce,
+        constraint = user.version == USER_VERSION @ ErrorCode::AccountNeedsMigration,
+        constraint = !user.frozen @ ErrorCode::PositionFrozen,
+        constraint = user.vesting_cliff == 0 @ ErrorCode::RewardsVesting,
+    )]
+    pub user: Box<Account<'info, User>>,
+    // Pays the swap account's rent and gets it back when it closes.
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    // Checked against the recipient's associated token account in the handler.
+    #[account(
+        mut,
+        constraint = !destination.is_frozen() @ ErrorCode::AccountFrozen,
+    )]
+    pub destination: Box<Account<'info, TokenAccount>>,
+
+    // Transient account the rewards are claimed into, closed again before the
+    // instruction returns.
+    #[account(
+        init,
+        payer = owner,
+        seeds = [SWAP_ACCOUNT_SEED, user.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = swap_authority,
+    )]
+    pub swap_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [SWAP_AUTHORITY_SEED, user.key().as_ref()],
+        bump,
+    )]
+    /// CHECK: PDA owning `swap_account` and signing the swap.
+    pub swap_authority: AccountInfo<'info>,
+
+    // Program signers.
     #[account(
         seeds = [
             pool.to_account_info().key.as_ref()
@@ -284,5 +2429,25 @@ pub struct ClosePool<'info> {
     )]
     /// CHECK: nothing to check.
     pub pool_signer: AccountInfo<'info>,
+
+    // Misc.
+    #[account(address = jupiter::ID)]
+    /// CHECK: checked against the program ID.
+    pub jupiter_program: AccountInfo<'info>,
     pub token_program: Program<'info, Token>,
-}
\ No newline at end of file
+    pub system_program: Program<'info, System>,
+    #[account(
+        constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub reward_mint: Box<Account<'info, Mint>>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct LogEvent<'info> {
+    #[account(
+        seeds = [EVENT_AUTHORITY_SEED],
+        bump,
+    )]
+    pub event_authority: Signer<'info>,
+}
@@ -1,3 +1,7 @@
+//! Program errors. Codes are part of the public interface: new variants are
+//! only ever appended.
+
+use crate::math::MathError;
 use anchor_lang::prelude::*;
 
 #[error_code]
@@ -20,4 +24,224 @@ pub enum ErrorCode {
     CannotDeauthorizeMissingAuthority,
     #[msg("Need to wait until maturity time to stake or claim.")]
     CannotStakeOrClaimBeforeMaturity,
+    #[msg("Basis points cannot exceed 10000.")]
+    InvalidBasisPoints,
+    #[msg("Penalty vault missing or does not match the pool.")]
+    InvalidPenaltyVault,
+    #[msg("Slashing is not enabled for this pool.")]
+    SlashingDisabled,
+    #[msg("Slash amount exceeds the staked balance.")]
+    SlashAmountExceedsBalance,
+    #[msg("User account does not belong to this owner and pool.")]
+    UserAccountMismatch,
+    #[msg("Batch must contain between one and the maximum number of pools.")]
+    InvalidBatchSize,
+    #[msg("Batch accounts are missing or do not match.")]
+    InvalidBatchAccounts,
+    #[msg("Cannot unstake this soon after staking.")]
+    StakeTooRecent,
+    #[msg("Deposit exceeds the per-wallet limit for the current window.")]
+    DepositLimitExceeded,
+    #[msg("Insurance vault missing or does not match the pool.")]
+    InvalidInsuranceVault,
+    #[msg("Routing penalties to insurance requires matching staking and reward mints.")]
+    InsuranceMintMismatch,
+    #[msg("Insurance vault balance is too low.")]
+    InsufficientInsurance,
+    #[msg("User account uses an old layout and must be migrated.")]
+    AccountNeedsMigration,
+    #[msg("User account is already on the current layout.")]
+    AccountAlreadyMigrated,
+    #[msg("Only available in local-testing builds.")]
+    LocalTestingOnly,
+    #[msg("Unstake exceeds the spender allowance.")]
+    UnstakeAllowanceExceeded,
+    #[msg("Pending rewards are above the dust threshold, claim them first.")]
+    PendingRewardsAboveDust,
+    #[msg("Auto-compound needs the same staking and reward mint.")]
+    AutoCompoundUnsupported,
+    #[msg("User has not opted in to auto-compound.")]
+    AutoCompoundDisabled,
+    #[msg("Position is frozen by its owner.")]
+    PositionFrozen,
+    #[msg("A guardian must be set before freezing.")]
+    GuardianNotSet,
+    #[msg("Changing the guardian requires the current guardian's signature.")]
+    GuardianSignatureRequired,
+    #[msg("Rewards must be paid to the beneficiary's token account.")]
+    InvalidBeneficiaryAccount,
+    #[msg("Vesting cliff must be set.")]
+    InvalidVestingSchedule,
+    #[msg("Vesting escrow account is missing or invalid.")]
+    InvalidVestingEscrow,
+    #[msg("Rewards of this user vest and must go through claim.")]
+    RewardsVesting,
+    #[msg("Recovery must move the position to a different owner.")]
+    InvalidRecoveryOwner,
+    #[msg("No recovery is pending for this user.")]
+    NoRecoveryPending,
+    #[msg("Recovery delay has not passed yet.")]
+    RecoveryTimelockActive,
+    #[msg("The pool attestor must countersign.")]
+    AttestorSignatureRequired,
+    #[msg("This pool requires an attestation before staking.")]
+    AttestationRequired,
+    #[msg("The withdraw policy cosigner must sign.")]
+    CosignerRequired,
+    #[msg("The rent recipient is not authorized.")]
+    UnauthorizedRentRecipient,
+    #[msg("Invalid tier.")]
+    InvalidTier,
+    #[msg("Maturity enforcement is disabled for this pool.")]
+    MaturityEnforcementDisabled,
+    #[msg("Maturity extension exceeds the pool maximum.")]
+    MaturityExtensionTooLong,
+    #[msg("The signer is not the approved claim program's authority.")]
+    ClaimProgramNotApproved,
+    #[msg("Math operation overflowed.")]
+    MathOverflow,
+    #[msg("Math operation underflowed.")]
+    ArithmeticUnderflow,
+    #[msg("The staking vault holds less than the total staked.")]
+    AccountingMismatch,
+    #[msg("The token account has the wrong mint.")]
+    InvalidMint,
+    #[msg("Withdrawals must go to a token account of the owner.")]
+    InvalidWithdrawDestination,
+    #[msg("The reward account must be the owner's account of the reward mint.")]
+    InvalidRewardAccount,
+    #[msg("The funding account must hold the reward mint.")]
+    InvalidFundingMint,
+    #[msg("The funder must own or be delegated the funding account.")]
+    InvalidFundingSource,
+    #[msg("The staking and reward vaults must differ.")]
+    IdenticalVaults,
+    #[msg("Duration cannot be longer than ten years.")]
+    DurationTooLong,
+    #[msg("Lock period cannot be longer than ten years.")]
+    LockPeriodTooLong,
+    #[msg("Users still have unclaimed rewards.")]
+    OutstandingRewards,
+    #[msg("Stake would lock tokens past the requested maturity.")]
+    MaturityWouldExceedLimit,
+    #[msg("Pool is halted pending an invariant review.")]
+    PoolHalted,
+    #[msg("Surplus destination is missing or invalid.")]
+    InvalidSyncTarget,
+    #[msg("Pool is closing.")]
+    PoolClosing,
+    #[msg("Pool is not closing.")]
+    PoolNotClosing,
+    #[msg("Pool vaults were already migrated.")]
+    VaultsAlreadyMigrated,
+    #[msg("Target pool must not have any stake or users.")]
+    PoolNotEmpty,
+    #[msg("Pool is not paused.")]
+    PoolNotPaused,
+    #[msg("No reward period is active.")]
+    NoActiveRewardPeriod,
+    #[msg("Vault must not have a delegate.")]
+    VaultHasDelegate,
+    #[msg("Staking mint has a freeze authority.")]
+    FreezableMint,
+    #[msg("Token account is frozen.")]
+    AccountFrozen,
+    #[msg("User stake count would overflow.")]
+    StakeCountOverflow,
+    #[msg("User stake count would underflow.")]
+    StakeCountUnderflow,
+    #[msg("Program is halted by the pool authority.")]
+    ProgramHalted,
+    #[msg("Halt cannot be longer than 72 hours.")]
+    HaltTooLong,
+    #[msg("Reward period has not ended yet.")]
+    RewardPeriodStillActive,
+    #[msg("Users still hold stake in the pool.")]
+    UsersStillStaked,
+    #[msg("Funding would lower the current reward rate.")]
+    FundingWouldDiluteRate,
+    #[msg("Pool authority cannot be the default public key.")]
+    InvalidAuthority,
+    #[msg("Position has not matured yet.")]
+    NotMatured,
+    #[msg("Amount exceeds the staked balance.")]
+    InsufficientStakedBalance,
+    #[msg("Account is not a pool of this program.")]
+    PoolAccountMismatch,
+    #[msg("Voter weight record belongs to another owner or mint.")]
+    VoterWeightRecordMismatch,
+    #[msg("Price feed does not match the pool.")]
+    InvalidPriceFeed,
+    #[msg("Tier thresholds must be increasing.")]
+    InvalidTierThresholds,
+    #[msg("Pool only stakes NFTs.")]
+    NftPoolOnly,
+    #[msg("Token is not an NFT of the pool's collection.")]
+    InvalidNft,
+    #[msg("Memo is too long.")]
+    MemoTooLong,
+    #[msg("Funder must sign, directly or through its multisig signers.")]
+    FunderNotSigned,
+    #[msg("Exchange-rate oracle does not match its source.")]
+    InvalidExchangeRateOracle,
+    #[msg("Pool already values tiers through another oracle.")]
+    TierOracleConflict,
+    #[msg("User has not opted in to auto-claim.")]
+    AutoClaimDisabled,
+    #[msg("Pending rewards do not exceed the auto-claim minimum.")]
+    AutoClaimBelowMinimum,
+    #[msg("Next lottery draw is not due yet.")]
+    LotteryDrawTooEarly,
+    #[msg("A lottery draw is already pending.")]
+    LotteryDrawPending,
+    #[msg("No lottery draw is pending.")]
+    NoLotteryDrawPending,
+    #[msg("Randomness account is invalid or not freshly committed.")]
+    InvalidRandomness,
+    #[msg("Randomness has not been revealed.")]
+    RandomnessNotRevealed,
+    #[msg("Winner accounts do not match the drawn staker.")]
+    InvalidLotteryWinner,
+    #[msg("Source token account is frozen by the mint's freeze authority.")]
+    SourceAccountFrozen,
+    #[msg("Pool vault is frozen by the mint's freeze authority.")]
+    VaultFrozen,
+    #[msg("Swaps are not enabled in this build.")]
+    SwapNotEnabled,
+    #[msg("Swap route must spend the claimed rewards from the swap account.")]
+    InvalidSwapRoute,
+    #[msg("Swap returned less than the minimum output.")]
+    SwapSlippageExceeded,
+    #[msg("Swap left claimed rewards unspent.")]
+    SwapIncomplete,
+    #[msg("Account is not a test account writable by this signer.")]
+    InvalidTestAccount,
+    #[msg("Token accounts must belong to the staking mint's token program.")]
+    InvalidTokenProgram,
+    #[msg("Staking mint allows confidential transfers, which hide amounts from the pool.")]
+    ConfidentialTransfersUnsupported,
+    #[msg("Staking mint has a Token-2022 extension pools don't support.")]
+    UnsupportedMintExtension,
+    #[msg(
+        "Staking vault must hold the staking mint for the pool signer, with no close authority."
+    )]
+    InvalidStakingVault,
+    #[msg("The day index isn't the current day of the clock; rebuild the transaction.")]
+    DayIndexMismatch,
+}
+
+impl From<MathError> for ErrorCode {
+    fn from(error: MathError) -> Self {
+        match error {
+            MathError::Overflow => ErrorCode::MathOverflow,
+            MathError::Underflow => ErrorCode::ArithmeticUnderflow,
+        }
+    }
+}
+
+/// Logs `error` together with the values that caused it, then returns it.
+/// Keeps the context lines of all call sites in one format.
+pub fn error_with_context(error: ErrorCode, context: std::fmt::Arguments) -> Error {
+    msg!("{:?}: {}", error, context);
+    error.into()
 }
@@ -0,0 +1,286 @@
+use anchor_lang::prelude::*;
+
+#[event]
+pub struct UserCreatedEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub created_at: i64,
+}
+
+#[event]
+pub struct StakeEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub balance_staked: u64,
+    pub last_stake_time: i64,
+    pub effective_weight: u128,
+    pub total_weight: u128,
+    pub client_ref: u64,
+}
+
+#[event]
+pub struct UnstakeEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub balance_staked: u64,
+    pub last_unstake_time: i64,
+    pub effective_weight: u128,
+    pub total_weight: u128,
+    pub client_ref: u64,
+}
+
+#[event]
+pub struct ClaimEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub total_rewards_claimed: u128,
+    pub last_claim_time: i64,
+    pub client_ref: u64,
+}
+
+#[event]
+pub struct TierChangedEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub old_tier: u8,
+    pub new_tier: u8,
+    pub balance: u64,
+}
+
+#[event]
+pub struct MaturitySetEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub old_maturity: u64,
+    pub new_maturity: u64,
+}
+
+#[event]
+pub struct FundEvent {
+    pub pool: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub reward_rate: u64,
+    pub reward_duration_end: u64,
+    pub funder_contribution: u64,
+    pub funded_at: i64,
+}
+
+#[event]
+pub struct PauseEvent {
+    pub pool: Pubkey,
+    pub paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserClosedEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub forgiven_dust: u64,
+    pub user_stake_count: u32,
+    pub closed_at: i64,
+}
+
+#[event]
+pub struct RecoveryRequestedEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct RecoveryCancelledEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+}
+
+#[event]
+pub struct RecoveryExecutedEvent {
+    pub pool: Pubkey,
+    pub old_user: Pubkey,
+    pub new_user: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct TierOverrideEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub tier_override: u8,
+    pub expires_at: i64,
+    pub tier: u8,
+}
+
+#[event]
+pub struct PenaltyExemptEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub penalty_exempt: bool,
+}
+
+#[event]
+pub struct PoolVaultsMigratedEvent {
+    pub old_pool: Pubkey,
+    pub new_pool: Pubkey,
+    pub staked_amount: u64,
+    pub reward_amount: u64,
+    pub total_staked: u64,
+    pub user_stake_count: u32,
+}
+
+#[event]
+pub struct HaltEvent {
+    pub pool: Pubkey,
+    pub halted_until: i64,
+}
+
+#[event]
+pub struct ClockSkewEvent {
+    pub pool: Pubkey,
+    pub last_update_time: u64,
+    pub last_time_reward_applicable: u64,
+}
+
+#[event]
+pub struct StakeCountRepairedEvent {
+    pub pool: Pubkey,
+    pub previous: u32,
+    pub user_stake_count: u32,
+}
+
+#[event]
+pub struct PoolUpdatedEvent {
+    pub pool: Pubkey,
+    pub reward_per_token_stored: u128,
+    pub last_update_time: u64,
+}
+
+#[event]
+pub struct CrankProgressEvent {
+    pub user: Pubkey,
+    pub action: u8,
+    pub amount: u64,
+}
+
+#[event]
+pub struct CrankCompletedEvent {
+    pub processed: u32,
+    pub skipped: u32,
+}
+
+#[event]
+pub struct FeeCollectedEvent {
+    pub pool: Pubkey,
+    pub kind: u8,
+    pub amount: u64,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct FundingRequiredEvent {
+    pub pool: Pubkey,
+    pub shortfall: u64,
+    pub projected_exhaustion_time: i64,
+}
+
+#[event]
+pub struct FunderRenouncedEvent {
+    pub pool: Pubkey,
+    pub funder: Pubkey,
+}
+
+#[event]
+pub struct SurplusSyncedEvent {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub destination: Pubkey,
+    pub credited_user: bool,
+}
+
+#[event]
+pub struct MaturityExtendedEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub additional_seconds: u64,
+    pub maturity_time: u64,
+    pub reason_code: u16,
+}
+
+#[event]
+pub struct UnstakePenaltyEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub penalty: u64,
+    pub penalty_bps: u16,
+}
+
+#[event]
+pub struct SlashEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub reason_code: u16,
+}
+
+#[event]
+pub struct MigrateStakeEvent {
+    pub old_pool: Pubkey,
+    pub new_pool: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub rewards_paid: u64,
+    pub rewards_carried: u64,
+    pub maturity_time: u64,
+}
+
+#[event]
+pub struct NftStakeEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub staked: bool,
+}
+
+#[event]
+pub struct LotteryDrawEvent {
+    pub pool: Pubkey,
+    pub round: u64,
+    /// Default when nobody won and the prize rolled over.
+    pub winner: Pubkey,
+    pub prize: u64,
+}
+
+#[event]
+pub struct ClaimSwappedEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub output_mint: Pubkey,
+    pub amount_out: u64,
+}
+
+#[event]
+pub struct VaultFrozenEvent {
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+}
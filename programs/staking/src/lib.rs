@@ -1,31 +1,137 @@
+//! FCON staking program.
+//!
+//! Off-chain services and other programs can depend on this crate for its
+//! account types, errors and instruction builders:
+//!
+//! - `no-entrypoint` leaves out the BPF entrypoint, so the crate links into
+//!   host binaries and other programs;
+//! - `cpi` adds the `staking::cpi` instruction builders on top of it;
+//! - `local-testing` compiles the test-only instructions; only the test
+//!   script (`yarn test`) turns it on;
+//! - `jupiter-swap`, on by default, enables `claim_and_swap`; minimal
+//!   deployments turn it off with `default-features = false`;
+//! - `event-cpi`, on by default, also emits the stake, unstake and claim
+//!   events through a `log_event` self-CPI, which survives log truncation;
+//!   turning it off saves the CPI's compute.
+//!
+//! `account::{Pool, User}`, `error::ErrorCode` and the `context` structs are
+//! the stable interface: fields, variants and accounts are only appended.
+//! Accounts created with an older layout decode once migrated with
+//! `migrate_pool` or `migrate_user`.
+//!
+//! `yarn check:interface` checks the crate as dependents build it: a host
+//! build without default features, the example below, and a BPF build.
+//!
+//! ```no_run
+//! use anchor_lang::AccountDeserialize;
+//! use staking::account::Pool;
+//!
+//! fn decode_pool(data: &[u8]) -> anchor_lang::Result<Pool> {
+//!     Pool::try_deserialize(&mut &data[..])
+//! }
+//! ```
+
 pub mod account;
 pub mod constants;
 pub mod context;
 pub mod error;
+pub mod event;
+pub mod math;
+pub mod pda;
 pub mod utils;
+pub mod view;
+
+#[cfg(feature = "local-testing")]
+mod testing;
+// Builds without `local-testing` keep the test-only instructions in the
+// interface but reject them, without compiling their bodies.
+#[cfg(not(feature = "local-testing"))]
+mod testing {
+    use crate::context::*;
+    use crate::error::ErrorCode;
+    use anchor_lang::prelude::*;
+
+    pub fn downgrade_user(_ctx: Context<DowngradeUser>) -> Result<()> {
+        Err(ErrorCode::LocalTestingOnly.into())
+    }
+
+    pub fn corrupt_pool(_ctx: Context<CorruptPool>, _total_staked: u64) -> Result<()> {
+        Err(ErrorCode::LocalTestingOnly.into())
+    }
+
+    pub fn rewind_pool(_ctx: Context<CorruptPool>, _seconds: u64) -> Result<()> {
+        Err(ErrorCode::LocalTestingOnly.into())
+    }
+
+    pub fn record_daily_stats(
+        _ctx: Context<RecordDailyStats>,
+        _day_index: u64,
+        _amount: u64,
+    ) -> Result<()> {
+        Err(ErrorCode::LocalTestingOnly.into())
+    }
+
+    pub fn write_price_feed(
+        _ctx: Context<WritePriceFeed>,
+        _price: i64,
+        _conf: u64,
+        _expo: i32,
+        _timestamp: i64,
+    ) -> Result<()> {
+        Err(ErrorCode::LocalTestingOnly.into())
+    }
+
+    pub fn write_randomness(
+        _ctx: Context<WriteRandomness>,
+        _seed_slot: u64,
+        _reveal_slot: u64,
+        _value: [u8; 32],
+    ) -> Result<()> {
+        Err(ErrorCode::LocalTestingOnly.into())
+    }
+}
 
 use account::*;
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::clock;
-use anchor_spl::token::{self};
+use anchor_lang::solana_program::program::{invoke, set_return_data};
+use anchor_lang::solana_program::system_instruction;
+use anchor_lang::Discriminator;
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token::{Mint, TokenAccount};
+use constants::{
+    ACTION_CLAIM, ACTION_COMPOUND, ACTION_STAKE, ACTION_UNSTAKE, BATCH_UNSTAKE_ACCOUNTS,
+    BENEFICIARY_TIMELOCK_SECONDS, BPS_DENOMINATOR, DEPOSIT_WINDOW_SECONDS, EXCHANGE_RATE_NONE,
+    EXCHANGE_RATE_PYTH, EXCHANGE_RATE_STAKE_POOL, FEE_KIND_PENALTY, LEADERBOARD_SIZE,
+    LOTTERY_MAX_COMMIT_AGE_SLOTS, LOTTERY_REVEAL_TIMEOUT_SLOTS, MAX_BATCH_UNSTAKE_POOLS,
+    MAX_HALT_SECONDS, MAX_SUMMARY_POSITIONS, POOL_CONFIG_VERSION, POOL_SUNSET_GRACE_SECONDS,
+    PROGRAM_FEATURES, PROGRAM_VERSION, RECOVERY_DELAY_SECONDS, SECONDS_PER_YEAR,
+    SWAP_AUTHORITY_SEED, TIER_INFO, TIER_VOTE_MULTIPLIER_BPS, USER_HISTORY_LEN, USER_V1_SIZE,
+    USER_VERSION, VESTING_SEED,
+};
 use context::*;
-use error::ErrorCode;
+use error::{error_with_context, ErrorCode};
+use event::*;
+use math::last_time_reward_applicable;
 use std::convert::Into;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use utils::*;
+use view::*;
 
 declare_id!("5dAQP2JtgJ3vFKMi3McnXkut51PXfHuyXRJhFCofd13J");
 
-pub const PRECISION: u128 = u64::MAX as u128;
 pub const MIN_DURATION: u64 = 86400;
+pub const MAX_DURATION: u64 = 10 * 365 * 86400;
+pub const MAX_LOCK_PERIOD: u64 = 10 * 365 * 86400;
 
 pub fn update_rewards(
     pool: &mut Account<Pool>,
     user: Option<&mut Box<Account<User>>>,
     total_staked: u64,
 ) -> Result<()> {
-    let clock = clock::Clock::get().unwrap();
+    let clock = clock::Clock::get()?;
+    check_not_halted(pool, clock.unix_timestamp)?;
     let last_time_reward_applicable =
         last_time_reward_applicable(pool.reward_duration_end, clock.unix_timestamp);
 
@@ -35,364 +141,4482 @@ pub fn update_rewards(
         last_time_reward_applicable,
         pool.last_update_time,
         pool.reward_rate,
-    );
+    )?;
 
-    pool.last_update_time = last_time_reward_applicable;
+    if last_time_reward_applicable < pool.last_update_time {
+        emit!(ClockSkewEvent {
+            pool: pool.key(),
+            last_update_time: pool.last_update_time,
+            last_time_reward_applicable,
+        });
+    } else {
+        pool.last_update_time = last_time_reward_applicable;
+    }
 
     if let Some(u) = user {
-        u.reward_per_token_pending = earned(
+        let pending = earned(
             u.balance_staked,
             pool.reward_per_token_stored,
             u.reward_per_token_complete,
             u.reward_per_token_pending,
-        );
+        )?;
+        pool.aggregate_pending = pool
+            .aggregate_pending
+            .checked_add(pending - u.reward_per_token_pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        u.reward_per_token_pending = pending;
         u.reward_per_token_complete = pool.reward_per_token_stored;
+        refresh_weight(pool, u)?;
     }
     Ok(())
 }
 
-pub fn last_time_reward_applicable(reward_duration_end: u64, unix_timestamp: i64) -> u64 {
-    return std::cmp::min(unix_timestamp.try_into().unwrap(), reward_duration_end);
+/// Recomputes the user's tier from their balance, raised to any unexpired
+/// authority override. Pools without tiers leave it untouched.
+pub fn refresh_tier(pool: &Pool, user: &mut Account<User>, now: i64) {
+    // Oracle-valued tiers need the oracle account, see `refresh_oracle_tier`.
+    if pool.no_tier || pool.tier_oracle() != Pubkey::default() {
+        return;
+    }
+
+    set_tier(user, get_tier(user.balance_staked), now);
+}
+
+/// Recomputes the tier of a pool valuing tiers through an oracle: USD tiers
+/// from its price feed, or token tiers on the underlying value of an
+/// interest-bearing staking mint. A stale or unusable oracle keeps the last
+/// computed tier instead of failing.
+pub fn refresh_oracle_tier(
+    pool: &Pool,
+    user: &mut Account<User>,
+    oracle: &AccountInfo,
+    decimals: u8,
+    clock: &clock::Clock,
+) {
+    if pool.no_tier || pool.tier_oracle() == Pubkey::default() {
+        return;
+    }
+
+    let tier = if pool.price_feed != Pubkey::default() {
+        read_pyth_price(oracle, clock.unix_timestamp).map(|price| {
+            let value = usd_value(user.balance_staked, decimals, &price);
+            get_usd_tier(value, &pool.usd_tier_thresholds)
+        })
+    } else {
+        underlying_value(
+            user.balance_staked,
+            pool.exchange_rate_source,
+            oracle,
+            &pool.staking_mint,
+            clock,
+        )
+        .map(get_tier)
+    };
+    match tier {
+        Some(tier) => set_tier(user, tier, clock.unix_timestamp),
+        None => msg!("Tier oracle unusable, keeping tier {}", user.tier),
+    }
+}
+
+/// Applies a balance-derived tier, raised to any unexpired authority
+/// override, announcing it when it actually changes.
+fn set_tier(user: &mut Account<User>, balance_tier: u8, now: i64) {
+    let mut tier = balance_tier;
+    if user.tier_override_expires_at > now {
+        tier = std::cmp::max(tier, user.tier_override);
+    }
+    if tier == user.tier {
+        return;
+    }
+
+    emit!(TierChangedEvent {
+        pool: user.pool,
+        user: user.key(),
+        old_tier: user.tier,
+        new_tier: tier,
+        balance: user.balance_staked,
+    });
+    user.tier = tier;
+}
+
+/// Moves the user's maturity, announcing it when it actually changes.
+pub fn set_maturity(user: &mut Account<User>, maturity_time: u64) {
+    if maturity_time == user.maturity_time {
+        return;
+    }
+
+    emit!(MaturitySetEvent {
+        pool: user.pool,
+        user: user.key(),
+        old_maturity: user.maturity_time,
+        new_maturity: maturity_time,
+    });
+    user.maturity_time = maturity_time;
+}
+
+/// Weight the user earns rewards with. Rewards are currently pro rata to the
+/// staked balance, so this is the balance itself.
+pub fn user_weight(user: &User) -> u128 {
+    user.balance_staked as u128
+}
+
+/// Recomputes the user's effective weight and carries the change into the
+/// pool total. Call after every change to the user's balance.
+pub fn refresh_weight(pool: &mut Pool, user: &mut User) -> Result<()> {
+    let weight = user_weight(user);
+    pool.total_weight = pool
+        .total_weight
+        .checked_sub(user.effective_weight)
+        .ok_or(ErrorCode::ArithmeticUnderflow)?
+        .checked_add(weight)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user.effective_weight = weight;
+    Ok(())
 }
 
+/// `math::reward_per_token`, logging the inputs when it fails.
 pub fn reward_per_token(
     total_staked: u64,
     reward_per_token_stored: u128,
     last_time_reward_applicable: u64,
     last_update_time: u64,
     reward_rate: u64,
-) -> u128 {
-    if total_staked == 0 {
-        return reward_per_token_stored;
-    }
-
-    return reward_per_token_stored
-        .checked_add(
-            (last_time_reward_applicable as u128)
-                .checked_sub(last_update_time as u128)
-                .unwrap()
-                .checked_mul(reward_rate as u128)
-                .unwrap()
-                .checked_mul(PRECISION)
-                .unwrap()
-                .checked_div(total_staked as u128)
-                .unwrap(),
+) -> Result<u128> {
+    // A last update ahead of the applicable time accrues nothing rather than
+    // aborting every instruction on the pool.
+    if last_time_reward_applicable < last_update_time {
+        msg!(
+            "reward_per_token: last update {} is ahead of {}, no accrual",
+            last_update_time,
+            last_time_reward_applicable
+        );
+    }
+
+    math::reward_per_token(
+        total_staked,
+        reward_per_token_stored,
+        last_time_reward_applicable,
+        last_update_time,
+        reward_rate,
+    )
+    .map_err(|err| {
+        error_with_context(
+            err.into(),
+            format_args!(
+                "reward_per_token: {} staked at {} from {} to {} on {}",
+                total_staked,
+                reward_rate,
+                last_update_time,
+                last_time_reward_applicable,
+                reward_per_token_stored
+            ),
         )
-        .unwrap();
+    })
 }
 
+/// `math::earned`, logging the inputs when it fails.
 pub fn earned(
     balance_staked: u64,
     reward_per_token: u128,
     user_reward_per_token_paid: u128,
     user_reward_pending: u64,
-) -> u64 {
-    return (balance_staked as u128)
-        .checked_mul(
-            (reward_per_token as u128)
-                .checked_sub(user_reward_per_token_paid as u128)
-                .unwrap(),
+) -> Result<u64> {
+    math::earned(
+        balance_staked,
+        reward_per_token,
+        user_reward_per_token_paid,
+        user_reward_pending,
+    )
+    .map_err(|err| {
+        error_with_context(
+            err.into(),
+            format_args!(
+                "earned: {} * ({} - {}) + {}",
+                balance_staked, reward_per_token, user_reward_per_token_paid, user_reward_pending
+            ),
         )
-        .unwrap()
-        .checked_div(PRECISION)
-        .unwrap()
-        .checked_add(user_reward_pending as u128)
-        .unwrap()
-        .try_into()
-        .unwrap();
+    })
+}
+
+/// Computes the user's pending rewards as of `unix_timestamp` without
+/// persisting anything.
+pub fn pending_rewards(pool: &Pool, user: &User, unix_timestamp: i64) -> Result<u64> {
+    let last_time_reward_applicable =
+        last_time_reward_applicable(pool.reward_duration_end, unix_timestamp);
+    let reward_per_token = reward_per_token(
+        pool.total_staked,
+        pool.reward_per_token_stored,
+        last_time_reward_applicable,
+        pool.last_update_time,
+        pool.reward_rate,
+    )?;
+
+    return earned(
+        user.balance_staked,
+        reward_per_token,
+        user.reward_per_token_complete,
+        user.reward_per_token_pending,
+    );
+}
+
+pub fn is_grace_topup(
+    pool: &Pool,
+    balance_staked: u64,
+    maturity_time: u64,
+    amount: u64,
+    now: u64,
+) -> Result<bool> {
+    if balance_staked == 0 {
+        return Ok(false);
+    }
+
+    let lock_start = maturity_time.saturating_sub(pool.lock_period);
+    let grace_end = lock_start.saturating_add(pool.topup_grace_seconds);
+    if pool.topup_grace_seconds > 0 && now <= grace_end {
+        return Ok(true);
+    }
+
+    let max_topup = (balance_staked as u128)
+        .checked_mul(pool.topup_grace_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    return Ok(pool.topup_grace_bps > 0 && (amount as u128) <= max_topup);
+}
+
+/// Sets up a freshly created user account and counts it on the pool.
+pub fn init_user(
+    pool: &mut Account<Pool>,
+    user: &mut Account<User>,
+    owner: Pubkey,
+    rent_payer: Pubkey,
+    nonce: u8,
+) -> Result<()> {
+    user.pool = pool.key();
+    user.owner = owner;
+    user.reward_per_token_complete = 0;
+    user.reward_per_token_pending = 0;
+    user.balance_staked = 0;
+    user.maturity_time = 0;
+    user.tier = 0;
+    user.nonce = nonce;
+    user.last_stake_slot = 0;
+    user.window_start = 0;
+    user.window_deposited = 0;
+    user.version = USER_VERSION;
+    user.created_at = clock::Clock::get()?.unix_timestamp;
+    user.last_stake_time = 0;
+    user.last_unstake_time = 0;
+    user.history = [UserAction::default(); USER_HISTORY_LEN];
+    user.history_head = 0;
+    user.unstake_spender = Pubkey::default();
+    user.unstake_allowance = 0;
+    user.unstake_destination = Pubkey::default();
+    user.auto_compound = false;
+    user.guardian = Pubkey::default();
+    user.frozen = false;
+    user.beneficiary = Pubkey::default();
+    user.next_beneficiary = Pubkey::default();
+    user.beneficiary_change_at = 0;
+    user.rent_payer = rent_payer;
+    user.operations_delegate = Pubkey::default();
+    user.vesting_cliff = 0;
+    user.vesting_duration = 0;
+    user.vesting_total = 0;
+    user.vesting_released = 0;
+    user.vesting_nonce = 0;
+    user.effective_weight = 0;
+    user.last_claim_time = 0;
+    user.recovery_new_owner = Pubkey::default();
+    user.recovery_requested_at = 0;
+    user.attestation = [0u8; 32];
+    user.withdraw_threshold = 0;
+    user.withdraw_cosigner = Pubkey::default();
+    user.tier_override = 0;
+    user.tier_override_expires_at = 0;
+    user.penalty_exempt = false;
+    user.maturity_extended = 0;
+    user.claim_program = Pubkey::default();
+    user.auto_claim = false;
+    user.auto_claim_min = 0;
+
+    pool.user_stake_count = pool
+        .user_stake_count
+        .checked_add(1)
+        .ok_or(ErrorCode::StakeCountOverflow)?;
+
+    emit!(UserCreatedEvent {
+        pool: pool.key(),
+        user: user.key(),
+        owner,
+        created_at: user.created_at,
+    });
+
+    Ok(())
+}
+
+/// `credit_stake` for the fungible paths, which NFT pools don't take.
+pub fn apply_stake(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    amount: u64,
+    clock: &clock::Clock,
+    client_ref: u64,
+) -> Result<StakeEvent> {
+    if pool.nft_collection != Pubkey::default() {
+        return Err(ErrorCode::NftPoolOnly.into());
+    }
+    credit_stake(pool, user, amount, clock, client_ref)
+}
+
+/// Refreshes an oracle-valued tier after `apply_stake` and enforces the
+/// caller's `max_maturity`, where 0 means no limit.
+pub fn check_stake_limits(
+    pool: &Pool,
+    user: &mut Account<User>,
+    price_feed: &AccountInfo,
+    decimals: u8,
+    max_maturity: u64,
+    clock: &clock::Clock,
+) -> Result<()> {
+    refresh_oracle_tier(pool, user, price_feed, decimals, clock);
+    if max_maturity != 0 && user.maturity_time > max_maturity {
+        return Err(error_with_context(
+            ErrorCode::MaturityWouldExceedLimit,
+            format_args!("matures at {}, limit {}", user.maturity_time, max_maturity),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checkpoints rewards and credits `amount` to the user's position. Token
+/// movement is left to the caller. Returns the emitted `StakeEvent` for
+/// handlers that also emit it over CPI.
+pub fn credit_stake(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    amount: u64,
+    clock: &clock::Clock,
+    client_ref: u64,
+) -> Result<StakeEvent> {
+    if pool.paused {
+        return Err(ErrorCode::PoolPaused.into());
+    }
+    if pool.halted {
+        return Err(ErrorCode::PoolHalted.into());
+    }
+    if pool.closing {
+        return Err(ErrorCode::PoolClosing.into());
+    }
+
+    if pool.require_attestation && user.attestation == [0u8; 32] {
+        return Err(ErrorCode::AttestationRequired.into());
+    }
+
+    let now = unix_timestamp_to_u64(clock.unix_timestamp);
+    if pool.require_active_rewards && now >= pool.reward_duration_end {
+        return Err(ErrorCode::NoActiveRewardPeriod.into());
+    }
+
+    if pool.max_per_window > 0 {
+        let window_end = user.window_start.saturating_add(DEPOSIT_WINDOW_SECONDS);
+        if now >= window_end {
+            user.window_start = now;
+            user.window_deposited = 0;
+        }
+
+        let window_deposited = user
+            .window_deposited
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if window_deposited > pool.max_per_window {
+            return Err(error_with_context(
+                ErrorCode::DepositLimitExceeded,
+                format_args!(
+                    "deposited {} of {}, window resets at {}",
+                    user.window_deposited,
+                    pool.max_per_window,
+                    user.window_start.saturating_add(DEPOSIT_WINDOW_SECONDS)
+                ),
+            ));
+        }
+        user.window_deposited = window_deposited;
+    }
+
+    let total_staked = pool.total_staked;
+    update_rewards(pool, Some(&mut *user), total_staked)?;
+
+    let grace_topup = is_grace_topup(pool, user.balance_staked, user.maturity_time, amount, now)?;
+    user.balance_staked = user
+        .balance_staked
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if !grace_topup {
+        // Never shorten a lock, e.g. one extended by the authority.
+        let maturity_time = std::cmp::max(
+            user.maturity_time,
+            now.checked_add(pool.lock_period)
+                .ok_or(ErrorCode::MathOverflow)?,
+        );
+        set_maturity(user, maturity_time);
+    }
+
+    refresh_tier(pool, user, clock.unix_timestamp);
+
+    user.last_stake_slot = clock.slot;
+    user.last_stake_time = clock.unix_timestamp;
+    record_action(user, ACTION_STAKE, amount, clock.unix_timestamp);
+    pool.total_staked = pool
+        .total_staked
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    refresh_weight(pool, user)?;
+
+    let event = StakeEvent {
+        pool: pool.key(),
+        user: user.key(),
+        owner: user.owner,
+        amount,
+        balance_staked: user.balance_staked,
+        last_stake_time: user.last_stake_time,
+        effective_weight: user.effective_weight,
+        total_weight: pool.total_weight,
+        client_ref,
+    };
+    emit!(event);
+
+    Ok(event)
+}
+
+/// `debit_stake` for the fungible paths, which NFT pools don't take.
+pub fn apply_unstake(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    amount: u64,
+    client_ref: u64,
+) -> Result<UnstakeEvent> {
+    if pool.nft_collection != Pubkey::default() {
+        return Err(ErrorCode::NftPoolOnly.into());
+    }
+    debit_stake(pool, user, amount, client_ref)
+}
+
+/// Checkpoints rewards and debits `amount` from the user's position. Token
+/// movement is left to the caller. Returns the emitted `UnstakeEvent`, like
+/// `credit_stake`.
+pub fn debit_stake(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    amount: u64,
+    client_ref: u64,
+) -> Result<UnstakeEvent> {
+    let total_staked = pool.total_staked;
+    update_rewards(pool, Some(&mut *user), total_staked)?;
+    user.balance_staked = user
+        .balance_staked
+        .checked_sub(amount)
+        .ok_or(ErrorCode::ArithmeticUnderflow)?;
+
+    let now = clock::Clock::get()?.unix_timestamp;
+    refresh_tier(pool, user, now);
+
+    user.last_unstake_time = now;
+    record_action(user, ACTION_UNSTAKE, amount, now);
+    pool.total_staked = pool
+        .total_staked
+        .checked_sub(amount)
+        .ok_or(ErrorCode::ArithmeticUnderflow)?;
+    refresh_weight(pool, user)?;
+
+    let event = UnstakeEvent {
+        pool: pool.key(),
+        user: user.key(),
+        owner: user.owner,
+        amount,
+        balance_staked: user.balance_staked,
+        last_unstake_time: user.last_unstake_time,
+        effective_weight: user.effective_weight,
+        total_weight: pool.total_weight,
+        client_ref,
+    };
+    emit!(event);
+
+    Ok(event)
+}
+
+/// Writes an entry into the user's history ring buffer, overwriting the
+/// oldest one.
+pub fn record_action(user: &mut User, action: u8, amount: u64, timestamp: i64) {
+    let head = user.history_head as usize % USER_HISTORY_LEN;
+    user.history[head] = UserAction {
+        action,
+        amount,
+        timestamp,
+    };
+    user.history_head = ((head + 1) % USER_HISTORY_LEN) as u8;
+}
+
+/// Takes the user's pending rewards for payout, capped at the reward vault
+/// balance. Whatever the vault can't cover is forfeited, and only the amount
+/// actually paid out counts towards `total_rewards_claimed`.
+pub fn take_pending_rewards(pool: &mut Pool, user: &mut User, vault_balance: u64) -> Result<u64> {
+    if pool.halted {
+        return Err(ErrorCode::PoolHalted.into());
+    }
+
+    let reward_amount = std::cmp::min(user.reward_per_token_pending, vault_balance);
+    pool.aggregate_pending = pool
+        .aggregate_pending
+        .checked_sub(user.reward_per_token_pending)
+        .ok_or(ErrorCode::ArithmeticUnderflow)?;
+    user.reward_per_token_pending = 0;
+    user.total_rewards_claimed = user
+        .total_rewards_claimed
+        .checked_add(reward_amount as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    return Ok(reward_amount);
+}
+
+/// Portion of the user's escrowed rewards vested at `now`: nothing before the
+/// cliff, then linearly over `vesting_duration`.
+pub fn vested_amount(user: &User, now: i64) -> Result<u64> {
+    if now < user.vesting_cliff {
+        return Ok(0);
+    }
+
+    let elapsed = now
+        .checked_sub(user.vesting_cliff)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    if user.vesting_duration == 0 || elapsed >= user.vesting_duration {
+        return Ok(user.vesting_total);
+    }
+
+    let vested = (user.vesting_total as u128)
+        .checked_mul(elapsed as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(user.vesting_duration as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    return u64::try_from(vested).map_err(|_| ErrorCode::MathOverflow.into());
+}
+
+/// Reloads the staking vault after a transfer and checks it still covers
+/// every stake. Donations may push it above `total_staked`, never below.
+pub fn check_vault_balance(
+    staking_vault: &mut Account<TokenAccount>,
+    total_staked: u64,
+) -> Result<()> {
+    staking_vault.reload()?;
+    check_vault_amount(staking_vault.amount, total_staked)
+}
+
+/// Fails with `VaultFrozen` once the staking vault, of either token program,
+/// is frozen.
+pub fn check_vault_not_frozen(staking_vault: &AccountInfo) -> Result<()> {
+    if load_token_account(staking_vault)?.is_frozen() {
+        return Err(ErrorCode::VaultFrozen.into());
+    }
+
+    Ok(())
+}
+
+/// `check_vault_balance` for staking vaults of either token program.
+pub fn check_interface_vault_balance(staking_vault: &AccountInfo, total_staked: u64) -> Result<()> {
+    check_vault_amount(load_token_account(staking_vault)?.amount, total_staked)
+}
+
+fn check_vault_amount(amount: u64, total_staked: u64) -> Result<()> {
+    if amount < total_staked {
+        msg!(
+            "Staking vault holds {} but {} is staked",
+            amount,
+            total_staked
+        );
+        return Err(ErrorCode::AccountingMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Checks the token accounts of `Stake`, plain `AccountInfo`s so pools of
+/// Token-2022 staking mints can pass them, and returns the staking mint's
+/// decimals together with the source account.
+pub fn check_stake_accounts(accounts: &Stake) -> Result<(u8, spl_token_2022::state::Account)> {
+    let staking_mint = load_mint(&accounts.staking_mint)?;
+    let staking_vault = load_token_account(&accounts.staking_vault)?;
+    if staking_vault.owner != accounts.pool_signer.key() {
+        return Err(ErrorCode::InvalidStakingVault.into());
+    }
+    if staking_vault.is_frozen() {
+        return Err(ErrorCode::VaultFrozen.into());
+    }
+    let stake_from_account = load_token_account(&accounts.stake_from_account)?;
+    if stake_from_account.mint != accounts.pool.staking_mint {
+        return Err(ErrorCode::InvalidMint.into());
+    }
+    if stake_from_account.is_frozen() {
+        return Err(ErrorCode::SourceAccountFrozen.into());
+    }
+
+    Ok((staking_mint.decimals, stake_from_account))
+}
+
+/// Compares the reward vault with what the pool still owes: checkpointed
+/// pending rewards plus the rest of the current period's emission. Flags the
+/// pool and emits `FundingRequiredEvent` on a shortfall, and clears the flag
+/// once the vault covers it again.
+pub fn check_reward_funding(
+    pool: &mut Account<Pool>,
+    reward_vault: &mut Account<TokenAccount>,
+    now: i64,
+) -> Result<()> {
+    reward_vault.reload()?;
+    let remaining = pool
+        .reward_duration_end
+        .saturating_sub(unix_timestamp_to_u64(now));
+    let obligations = (pool.reward_rate as u128)
+        .checked_mul(remaining as u128)
+        .and_then(|v| v.checked_add(pool.aggregate_pending as u128))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let balance = reward_vault.amount as u128;
+
+    pool.underfunded = balance < obligations;
+    if pool.underfunded {
+        // The vault first pays what is already owed, then lasts as long as
+        // the rest buys at the current rate.
+        let runway = match pool.reward_rate {
+            0 => 0,
+            rate => balance.saturating_sub(pool.aggregate_pending as u128) / rate as u128,
+        };
+        emit!(FundingRequiredEvent {
+            pool: pool.key(),
+            shortfall: u64::try_from(obligations - balance).unwrap_or(u64::MAX),
+            projected_exhaustion_time: now
+                .saturating_add(i64::try_from(runway).unwrap_or(i64::MAX)),
+        });
+    }
+
+    Ok(())
+}
+
+/// Loads the reward account of a claim, first creating the owner's associated
+/// token account, at the payer's expense, when it doesn't exist yet. The owner
+/// may be a PDA of a calling program that holds no lamports.
+fn load_reward_account<'info>(
+    accounts: &ClaimReward<'info>,
+    now: i64,
+) -> Result<Account<'info, TokenAccount>> {
+    let info = &accounts.reward_account;
+    if info.data_is_empty() && *info.owner == System::id() {
+        let address = get_associated_token_address(accounts.owner.key, &accounts.pool.reward_mint);
+        if info.key() != address {
+            return Err(ErrorCode::InvalidRewardAccount.into());
+        }
+        associated_token::create(CpiContext::new(
+            accounts.associated_token_program.to_account_info(),
+            associated_token::Create {
+                payer: accounts.payer.to_account_info(),
+                associated_token: info.clone(),
+                authority: accounts.owner.to_account_info(),
+                mint: accounts.reward_mint.to_account_info(),
+                system_program: accounts.system_program.to_account_info(),
+                token_program: accounts.token_program.to_account_info(),
+                rent: accounts.rent.to_account_info(),
+            },
+        ))?;
+    }
+
+    let reward_account: Account<TokenAccount> = Account::try_from(info)?;
+    check_reward_account(&reward_account, &accounts.pool, &accounts.user, now)?;
+
+    Ok(reward_account)
+}
+
+/// Checks a reward account the owner signed for: any account of the owner,
+/// or the beneficiary's associated token account once one applies.
+fn check_reward_account(
+    reward_account: &Account<TokenAccount>,
+    pool: &Pool,
+    user: &User,
+    now: i64,
+) -> Result<()> {
+    // Beneficiary payouts are pinned by `is_reward_destination` instead.
+    if reward_account.mint != pool.reward_mint
+        || (reward_account.owner != user.owner && user.beneficiary_at(now) == Pubkey::default())
+    {
+        return Err(ErrorCode::InvalidRewardAccount.into());
+    }
+    if reward_account.is_frozen() {
+        return Err(ErrorCode::AccountFrozen.into());
+    }
+    if !is_reward_destination(user, &reward_account.key(), &pool.reward_mint, now) {
+        return Err(ErrorCode::InvalidBeneficiaryAccount.into());
+    }
+
+    Ok(())
+}
+
+/// Returns the first accounting invariant the pool breaks, if any, given the
+/// staking vault balance and the current time.
+pub fn invariant_violation(pool: &Pool, staked_balance: u64, now: u64) -> Option<&'static str> {
+    // NFT pools count NFTs, not vault tokens, towards `total_staked`.
+    if pool.nft_collection == Pubkey::default() && staked_balance < pool.total_staked {
+        return Some("staking vault holds less than total_staked");
+    }
+    if pool.reward_duration_end == 0 && pool.reward_rate != 0 {
+        return Some("reward_rate is set on a pool that was never funded");
+    }
+    if pool.reward_duration_end != 0 && pool.last_update_time > pool.reward_duration_end {
+        return Some("last_update_time is past reward_duration_end");
+    }
+    if pool.last_update_time > now {
+        return Some("last_update_time is in the future");
+    }
+    if pool.user_stake_count == 0 && pool.total_staked != 0 {
+        return Some("total_staked is non-zero without any users");
+    }
+
+    None
+}
+
+/// Index of `funder` in `Pool::funder_contributions`: its `funders` slot, or
+/// the last entry for the authority.
+pub fn funder_slot(pool: &Pool, funder: Pubkey) -> Option<usize> {
+    if funder == pool.authority {
+        return Some(pool.funders.len());
+    }
+    pool.funders.iter().position(|x| *x == funder)
+}
+
+/// Frees a `funders` slot, moving its contributions to the removed bucket so
+/// the figures keep summing to lifetime funding.
+pub fn remove_funder(pool: &mut Pool, idx: usize) -> Result<()> {
+    pool.funders[idx] = Pubkey::default();
+    pool.removed_funder_contributions = pool
+        .removed_funder_contributions
+        .checked_add(pool.funder_contributions[idx])
+        .ok_or(ErrorCode::MathOverflow)?;
+    pool.funder_contributions[idx] = 0;
+    Ok(())
+}
+
+/// Fails with `DayIndexMismatch` unless `day` is the clock's current day, the
+/// one whose `DailyStats` account the instruction was built for.
+pub fn check_day_index(day: u64, now: i64) -> Result<()> {
+    let today = day_index(now);
+    if day != today {
+        return Err(error_with_context(
+            ErrorCode::DayIndexMismatch,
+            format_args!("day {}, clock day {}", day, today),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails with `NotMatured`, logging the time left, before `maturity_time`.
+pub fn check_matured(maturity_time: u64, now: u64) -> Result<()> {
+    if maturity_time > now {
+        return Err(error_with_context(
+            ErrorCode::NotMatured,
+            format_args!(
+                "matures at {}, {} seconds left",
+                maturity_time,
+                maturity_time - now
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails with `InsufficientStakedBalance`, logging the balance, when `amount`
+/// exceeds what the user has staked.
+pub fn check_staked_balance(balance_staked: u64, amount: u64) -> Result<()> {
+    if balance_staked < amount {
+        return Err(error_with_context(
+            ErrorCode::InsufficientStakedBalance,
+            format_args!("requested {}, staked {}", amount, balance_staked),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails while an authority circuit-breaker halt is in effect.
+pub fn check_not_halted(pool: &Pool, now: i64) -> Result<()> {
+    if now < pool.halted_until {
+        msg!("Pool is halted until {}", pool.halted_until);
+        return Err(ErrorCode::ProgramHalted.into());
+    }
+
+    Ok(())
+}
+
+/// Early-unstake penalty and its rate for unstaking `amount`, waived for
+/// exempt users and for holders of the pool's waiver collection. Their NFT
+/// token account and metadata account follow the penalty vault, and the
+/// insurance vault when insurance takes a share, in `remaining_accounts`.
+pub fn unstake_penalty(
+    pool: &Pool,
+    user: &User,
+    amount: u64,
+    now: u64,
+    remaining_accounts: &[AccountInfo],
+) -> Result<(u64, u16)> {
+    let (penalty, penalty_bps) = early_unstake_penalty(
+        amount,
+        pool.max_penalty_bps,
+        user.maturity_time,
+        pool.lock_period,
+        now,
+    )?;
+    let proof_offset = if pool.insurance_share_bps > 0 { 2 } else { 1 };
+    if penalty > 0
+        && (user.penalty_exempt
+            || (remaining_accounts.len() >= proof_offset + 2
+                && holds_collection_nft(
+                    &user.owner,
+                    &pool.penalty_waiver_collection,
+                    &remaining_accounts[proof_offset],
+                    &remaining_accounts[proof_offset + 1],
+                )))
+    {
+        return Ok((0, 0));
+    }
+
+    Ok((penalty, penalty_bps))
+}
+
+/// Requires the user's withdraw cosigner to have signed, anywhere among
+/// `signers`, when `amount` reaches the policy threshold.
+pub fn check_withdraw_policy(user: &User, amount: u64, signers: &[AccountInfo]) -> Result<()> {
+    if user.withdraw_cosigner == Pubkey::default() || amount < user.withdraw_threshold {
+        return Ok(());
+    }
+
+    if !signers
+        .iter()
+        .any(|info| info.is_signer && info.key() == user.withdraw_cosigner)
+    {
+        return Err(ErrorCode::CosignerRequired.into());
+    }
+
+    Ok(())
 }
 
-#[program]
-pub mod staking {
-    use super::*;
+/// Rejects unstaking within `min_unstake_delay_slots` of the user's last stake
+/// so a flash-borrowed stake can't be unwound in the same transaction.
+pub fn check_unstake_delay(pool: &Pool, user: &User, slot: u64) -> Result<()> {
+    if pool.no_tier || pool.min_unstake_delay_slots == 0 {
+        return Ok(());
+    }
+
+    let earliest_slot = user
+        .last_stake_slot
+        .saturating_add(pool.min_unstake_delay_slots);
+    if slot < earliest_slot {
+        return Err(ErrorCode::StakeTooRecent.into());
+    }
+
+    Ok(())
+}
+
+/// Returns the penalty charged on an unstake of `amount` before maturity and
+/// the effective bps it was derived from. Both round up so the pool never
+/// under-collects.
+pub fn early_unstake_penalty(
+    amount: u64,
+    max_penalty_bps: u16,
+    maturity_time: u64,
+    lock_period: u64,
+    now: u64,
+) -> Result<(u64, u16)> {
+    if now >= maturity_time || lock_period == 0 || max_penalty_bps == 0 {
+        return Ok((0, 0));
+    }
+
+    let remaining_lock = std::cmp::min(maturity_time - now, lock_period) as u128;
+    let penalty_bps = (max_penalty_bps as u128)
+        .checked_mul(remaining_lock)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(lock_period as u128 - 1)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(lock_period as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let penalty = (amount as u128)
+        .checked_mul(penalty_bps)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(BPS_DENOMINATOR as u128 - 1)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    return Ok((
+        u64::try_from(penalty).map_err(|_| ErrorCode::MathOverflow)?,
+        u16::try_from(penalty_bps).map_err(|_| ErrorCode::MathOverflow)?,
+    ));
+}
+
+#[program]
+pub mod staking {
+    use super::*;
+
+    /// Creates a pool. Its staking mint may belong to Token-2022 when its
+    /// extensions pass `check_mint_extensions`; `stake`, `unstake`, `claim`
+    /// and `close_pool` serve such pools, while the other staking-side
+    /// instructions still take legacy token accounts. Rewards stay on the
+    /// legacy token program.
+    pub fn initialize_pool(
+        ctx: Context<InitializePool>,
+        reward_duration: u64,
+        lock_period: u64,
+        no_tier: bool,
+        slashing_enabled: bool,
+        maturity_enforcement_enabled: bool,
+        max_maturity_extension: u64,
+        strict_mint: bool,
+        nft_collection: Pubkey,
+        nft_weight: u64,
+    ) -> Result<()> {
+        if ctx.accounts.authority.key() == Pubkey::default() {
+            return Err(ErrorCode::InvalidAuthority.into());
+        }
+        // The staking side may belong to Token-2022; rewards stay on the
+        // legacy token program.
+        let staking_mint = load_mint(&ctx.accounts.staking_mint)?;
+        check_mint_extensions(&ctx.accounts.staking_mint)?;
+        let staking_vault = load_token_account(&ctx.accounts.staking_vault)?;
+        if ctx.accounts.staking_vault.owner != ctx.accounts.staking_mint.owner
+            || staking_vault.mint != ctx.accounts.staking_mint.key()
+            || staking_vault.owner != ctx.accounts.pool_signer.key()
+            || staking_vault.close_authority.is_some()
+        {
+            return Err(ErrorCode::InvalidStakingVault.into());
+        }
+        if staking_vault.delegate.is_some() {
+            return Err(ErrorCode::VaultHasDelegate.into());
+        }
+        let staking_mint_freezable = staking_mint.freeze_authority.is_some();
+        if strict_mint && staking_mint_freezable {
+            return Err(ErrorCode::FreezableMint.into());
+        }
+        if reward_duration < MIN_DURATION {
+            return Err(ErrorCode::DurationTooShort.into());
+        }
+        if reward_duration > MAX_DURATION {
+            return Err(ErrorCode::DurationTooLong.into());
+        }
+        if lock_period > MAX_LOCK_PERIOD {
+            return Err(ErrorCode::LockPeriodTooLong.into());
+        }
+        if nft_collection != Pubkey::default() && nft_weight == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+
+        pool.authority = ctx.accounts.authority.key();
+        // Only the canonical bump is accepted; pools created before keep
+        // whatever bump they stored.
+        pool.nonce = *ctx.bumps.get("pool_signer").unwrap();
+        pool.paused = false;
+        pool.staking_mint = ctx.accounts.staking_mint.key();
+        pool.staking_vault = ctx.accounts.staking_vault.key();
+        pool.reward_mint = ctx.accounts.reward_mint.key();
+        pool.reward_vault = ctx.accounts.reward_vault.key();
+        pool.reward_duration = reward_duration;
+        pool.reward_duration_end = 0;
+        pool.lock_period = lock_period;
+        pool.last_update_time = 0;
+        pool.reward_rate = 0;
+        pool.reward_per_token_stored = 0;
+        pool.user_stake_count = 0;
+        pool.total_staked = 0;
+        pool.no_tier = no_tier;
+        pool.funders = [Pubkey::default(); 5];
+        pool.topup_grace_seconds = 0;
+        pool.topup_grace_bps = 0;
+        pool.max_penalty_bps = 0;
+        pool.penalty_vault = Pubkey::default();
+        pool.slashing_enabled = slashing_enabled;
+        pool.min_unstake_delay_slots = 0;
+        pool.max_per_window = 0;
+        pool.penalty_waiver_collection = Pubkey::default();
+        pool.insurance_vault = Pubkey::default();
+        pool.insurance_share_bps = 0;
+        pool.insurance_collected = 0;
+        pool.insurance_used = 0;
+        pool.dust_threshold = 0;
+        pool.forgiven_dust = 0;
+        pool.compound_tip_bps = 0;
+        pool.total_weight = 0;
+        pool.attestor = Pubkey::default();
+        pool.require_attestation = false;
+        pool.maturity_enforcement_enabled = maturity_enforcement_enabled;
+        pool.max_maturity_extension = max_maturity_extension;
+        pool.aggregate_pending = 0;
+        pool.halted = false;
+        pool.closing = false;
+        pool.vaults_migrated = false;
+        pool.require_active_rewards = false;
+        pool.staking_mint_freezable = staking_mint_freezable;
+        pool.reward_mint_freezable = ctx.accounts.reward_mint.freeze_authority.is_some();
+        pool.halted_until = 0;
+        pool.min_fund_amount = 0;
+        pool.shared_mint = pool.staking_mint == pool.reward_mint;
+        pool.funder_contributions = [0; 6];
+        pool.removed_funder_contributions = 0;
+        pool.created_with_version = PROGRAM_VERSION;
+        pool.underfunded = false;
+        pool.total_deposit_fees = 0;
+        pool.total_claim_fees = 0;
+        pool.total_penalties = 0;
+        pool.voter_weight_tier_boost = false;
+        pool.price_feed = Pubkey::default();
+        pool.usd_tier_thresholds = [0; 7];
+        pool.nft_collection = nft_collection;
+        pool.nft_weight = nft_weight;
+        pool.exchange_rate_oracle = Pubkey::default();
+        pool.exchange_rate_source = EXCHANGE_RATE_NONE;
+        pool.auto_claim_fee = 0;
+        pool.vault_frozen = false;
+        pool.reserved = [0u8; 55];
+        if pool.shared_mint {
+            msg!("Warning: pool stakes and rewards the same mint");
+        }
+
+        Ok(())
+    }
+
+    pub fn create_user(ctx: Context<CreateUser>) -> Result<()> {
+        check_not_halted(&ctx.accounts.pool, clock::Clock::get()?.unix_timestamp)?;
+        let owner = ctx.accounts.owner.key();
+        let nonce = *ctx.bumps.get("user").unwrap();
+        let payer = ctx.accounts.payer.key();
+        init_user(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            owner,
+            payer,
+            nonce,
+        )?;
+
+        Ok(())
+    }
+
+    /// Creates the user account and stakes `amount` in a single instruction,
+    /// with the same `max_maturity` and `client_ref` handling as `stake`.
+    pub fn create_user_and_stake(
+        ctx: Context<CreateUserAndStake>,
+        day_index: u64,
+        amount: u64,
+        max_maturity: u64,
+        client_ref: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+
+        let owner = ctx.accounts.owner.key();
+        let nonce = *ctx.bumps.get("user").unwrap();
+        init_user(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            owner,
+            owner,
+            nonce,
+        )?;
+
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        apply_stake(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            amount,
+            &clock,
+            client_ref,
+        )?;
+        check_stake_limits(
+            &ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            &ctx.accounts.price_feed,
+            ctx.accounts.staking_mint.decimals,
+            max_maturity,
+            &clock,
+        )?;
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_STAKE,
+            amount,
+            clock.unix_timestamp,
+        );
+
+        // Transfer tokens into the stake vault.
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.stake_from_account.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.staking_vault.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            &[],
+            amount,
+        )?;
+        check_interface_vault_balance(&ctx.accounts.staking_vault, ctx.accounts.pool.total_staked)?;
+
+        Ok(())
+    }
+
+    /// Brings a user account created with an older layout up to the current
+    /// one. The owner pays for any extra rent and new fields start zeroed.
+    pub fn migrate_user(ctx: Context<MigrateUser>) -> Result<()> {
+        let user_info = ctx.accounts.user.to_account_info();
+        {
+            let data = user_info.try_borrow_data()?;
+            if data.len() < USER_V1_SIZE || data[..8] != User::discriminator() {
+                return Err(ErrorCode::UserAccountMismatch.into());
+            }
+        }
+
+        let space = User::space();
+        if user_info.data_len() < space {
+            let rent = Rent::get()?.minimum_balance(space);
+            let lamports = user_info.lamports();
+            if rent > lamports {
+                invoke(
+                    &system_instruction::transfer(
+                        ctx.accounts.owner.key,
+                        user_info.key,
+                        rent - lamports,
+                    ),
+                    &[
+                        ctx.accounts.owner.to_account_info(),
+                        user_info.clone(),
+                        ctx.accounts.system_program.to_account_info(),
+                    ],
+                )?;
+            }
+            user_info.realloc(space, true)?;
+        }
+
+        let mut user: Account<User> = Account::try_from(&user_info)?;
+        if user.version == USER_VERSION {
+            return Err(ErrorCode::AccountAlreadyMigrated.into());
+        }
+        user.version = USER_VERSION;
+        user.exit(ctx.program_id)?;
+
+        Ok(())
+    }
+
+    /// Grows a pool account created with a shorter layout to the current one.
+    /// The authority pays for the extra rent and new fields start zeroed.
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        let pool_info = ctx.accounts.pool.to_account_info();
+        {
+            let data = pool_info.try_borrow_data()?;
+            if data.len() < 40 || data[..8] != Pool::discriminator() {
+                return Err(ErrorCode::PoolAccountMismatch.into());
+            }
+            if data[8..40] != ctx.accounts.authority.key.to_bytes() {
+                return Err(ErrorCode::InvalidAuthority.into());
+            }
+        }
+
+        let space = Pool::space();
+        if pool_info.data_len() < space {
+            let rent = Rent::get()?.minimum_balance(space);
+            let lamports = pool_info.lamports();
+            if rent > lamports {
+                invoke(
+                    &system_instruction::transfer(
+                        ctx.accounts.authority.key,
+                        pool_info.key,
+                        rent - lamports,
+                    ),
+                    &[
+                        ctx.accounts.authority.to_account_info(),
+                        pool_info.clone(),
+                        ctx.accounts.system_program.to_account_info(),
+                    ],
+                )?;
+            }
+            pool_info.realloc(space, true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Truncates a user account back to the unversioned layout so migrations
+    /// can be exercised against a local validator.
+    pub fn downgrade_user_for_testing(ctx: Context<DowngradeUser>) -> Result<()> {
+        testing::downgrade_user(ctx)
+    }
+
+    /// Overwrites `total_staked` so invariant checks can be exercised against
+    /// a local validator.
+    pub fn corrupt_pool_for_testing(ctx: Context<CorruptPool>, total_staked: u64) -> Result<()> {
+        testing::corrupt_pool(ctx, total_staked)
+    }
+
+    /// Moves `last_update_time` back by `seconds` so accrual over gaps longer
+    /// than a reward period can be exercised against a local validator.
+    pub fn rewind_pool_for_testing(ctx: Context<CorruptPool>, seconds: u64) -> Result<()> {
+        testing::rewind_pool(ctx, seconds)
+    }
+
+    /// Records a stake of `amount` into the `DailyStats` of `day_index` as of
+    /// that day's last second, so day boundaries can be exercised against a
+    /// local validator.
+    pub fn record_daily_stats_for_testing(
+        ctx: Context<RecordDailyStats>,
+        day_index: u64,
+        amount: u64,
+    ) -> Result<()> {
+        testing::record_daily_stats(ctx, day_index, amount)
+    }
+
+    /// Writes a Pyth v2 price account layout into `price_feed` so USD tiers
+    /// can be exercised against a local validator. A zeroed program-owned
+    /// account is claimed for `authority` on the first write.
+    pub fn write_price_feed_for_testing(
+        ctx: Context<WritePriceFeed>,
+        price: i64,
+        conf: u64,
+        expo: i32,
+        timestamp: i64,
+    ) -> Result<()> {
+        testing::write_price_feed(ctx, price, conf, expo, timestamp)
+    }
+
+    /// Writes a Switchboard randomness account layout into `randomness` so
+    /// lottery draws can be exercised against a local validator. A zeroed
+    /// program-owned account is claimed for `authority` on the first write.
+    pub fn write_randomness_for_testing(
+        ctx: Context<WriteRandomness>,
+        seed_slot: u64,
+        reveal_slot: u64,
+        value: [u8; 32],
+    ) -> Result<()> {
+        testing::write_randomness(ctx, seed_slot, reveal_slot, value)
+    }
+
+    pub fn pause(ctx: Context<Pause>) -> Result<()> {
+        let timestamp = clock::Clock::get()?.unix_timestamp;
+        let now = unix_timestamp_to_u64(timestamp);
+        let pool = &mut ctx.accounts.pool;
+        if now <= pool.reward_duration_end {
+            return Err(ErrorCode::RewardPeriodStillActive.into());
+        }
+        pool.paused = true;
+
+        emit!(PauseEvent {
+            pool: pool.key(),
+            paused: true,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn unpause(ctx: Context<Unpause>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.paused = false;
+
+        emit!(PauseEvent {
+            pool: pool.key(),
+            paused: false,
+            timestamp: clock::Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Stakes `amount` tokens. When `max_maturity` is non-zero the instruction
+    /// fails if the resulting lock would end after it. `client_ref` is only
+    /// echoed in `StakeEvent`; pass 0 when unused. `day_index` must be the
+    /// clock's current day, as in every instruction recording `DailyStats`.
+    pub fn stake(
+        ctx: Context<Stake>,
+        day_index: u64,
+        amount: u64,
+        max_maturity: u64,
+        client_ref: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let (decimals, _) = check_stake_accounts(ctx.accounts)?;
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        // Token-2022 transfer fees are withheld from what reaches the vault,
+        // so only the net amount is staked.
+        let fee = transfer_fee(&ctx.accounts.staking_mint, clock.epoch, amount)?;
+        let staked = amount
+            .checked_sub(fee)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+        if staked == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+        let event = apply_stake(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            staked,
+            &clock,
+            client_ref,
+        )?;
+        check_stake_limits(
+            &ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            &ctx.accounts.price_feed,
+            decimals,
+            max_maturity,
+            &clock,
+        )?;
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_STAKE,
+            staked,
+            clock.unix_timestamp,
+        );
+
+        // Transfer tokens into the stake vault.
+        transfer_checked_interface(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.stake_from_account.to_account_info(),
+            ctx.accounts.staking_mint.to_account_info(),
+            decimals,
+            ctx.accounts.staking_vault.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            &[],
+            amount,
+        )?;
+        check_interface_vault_balance(&ctx.accounts.staking_vault, ctx.accounts.pool.total_staked)?;
+        emit_cpi(
+            &event,
+            &ctx.accounts.event_authority,
+            &ctx.accounts.program,
+            *ctx.bumps.get("event_authority").unwrap(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Splits `total_amount` across two pools sharing a staking mint, sending
+    /// `first_pool_bps` of it to the first pool and the rest to the second.
+    /// `max_maturity` and `client_ref` apply to both stakes as in `stake`.
+    pub fn stake_split(
+        ctx: Context<StakeSplit>,
+        day_index: u64,
+        total_amount: u64,
+        first_pool_bps: u16,
+        max_maturity: u64,
+        client_ref: u64,
+    ) -> Result<()> {
+        if first_pool_bps as u64 > BPS_DENOMINATOR {
+            return Err(ErrorCode::InvalidBasisPoints.into());
+        }
+
+        let first_amount: u64 = (total_amount as u128)
+            .checked_mul(first_pool_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+        let second_amount = total_amount
+            .checked_sub(first_amount)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+        if first_amount == 0 || second_amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        apply_stake(
+            &mut ctx.accounts.first_pool,
+            &mut ctx.accounts.first_user,
+            first_amount,
+            &clock,
+            client_ref,
+        )?;
+        apply_stake(
+            &mut ctx.accounts.second_pool,
+            &mut ctx.accounts.second_user,
+            second_amount,
+            &clock,
+            client_ref,
+        )?;
+        check_stake_limits(
+            &ctx.accounts.first_pool,
+            &mut ctx.accounts.first_user,
+            &ctx.accounts.first_price_feed,
+            ctx.accounts.staking_mint.decimals,
+            max_maturity,
+            &clock,
+        )?;
+        check_stake_limits(
+            &ctx.accounts.second_pool,
+            &mut ctx.accounts.second_user,
+            &ctx.accounts.second_price_feed,
+            ctx.accounts.staking_mint.decimals,
+            max_maturity,
+            &clock,
+        )?;
+        ctx.accounts.first_daily_stats.record(
+            ctx.accounts.first_pool.key(),
+            ACTION_STAKE,
+            first_amount,
+            clock.unix_timestamp,
+        );
+        ctx.accounts.second_daily_stats.record(
+            ctx.accounts.second_pool.key(),
+            ACTION_STAKE,
+            second_amount,
+            clock.unix_timestamp,
+        );
+
+        // Transfer tokens into both stake vaults.
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.stake_from_account.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.first_staking_vault.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            &[],
+            first_amount,
+        )?;
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.stake_from_account.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.second_staking_vault.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            &[],
+            second_amount,
+        )?;
+        check_vault_balance(
+            &mut ctx.accounts.first_staking_vault,
+            ctx.accounts.first_pool.total_staked,
+        )?;
+        check_vault_balance(
+            &mut ctx.accounts.second_staking_vault,
+            ctx.accounts.second_pool.total_staked,
+        )?;
+
+        Ok(())
+    }
+
+    /// Unstakes `spt_amount`. Before maturity this is only allowed when the
+    /// pool charges an early-unstake penalty, in which case the pool's penalty
+    /// vault must be passed as the first remaining account, followed by the
+    /// insurance vault when the pool routes a share of penalties to insurance.
+    /// Holders of the pool's waiver collection may append their NFT token
+    /// account and metadata account to skip the penalty. `client_ref` is only
+    /// echoed in `UnstakeEvent`; pass 0 when unused. A non-empty `memo` is
+    /// attached to the withdrawal through the SPL Memo program.
+    pub fn unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, Stake<'info>>,
+        day_index: u64,
+        spt_amount: u64,
+        client_ref: u64,
+        memo: String,
+    ) -> Result<()> {
+        if spt_amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        if ctx.accounts.user.frozen {
+            return Err(ErrorCode::PositionFrozen.into());
+        }
+
+        // `Stake` is shared with `stake`, where the owner authorizes the
+        // transfer anyway; withdrawals must go back to the owner.
+        let (decimals, stake_from_account) = check_stake_accounts(ctx.accounts)?;
+        if stake_from_account.owner != ctx.accounts.owner.key() {
+            return Err(ErrorCode::InvalidWithdrawDestination.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        let now = unix_timestamp_to_u64(clock.unix_timestamp);
+        if ctx.accounts.pool.max_penalty_bps == 0 {
+            check_matured(ctx.accounts.user.maturity_time, now)?;
+        }
+        check_staked_balance(ctx.accounts.user.balance_staked, spt_amount)?;
+
+        check_unstake_delay(&ctx.accounts.pool, &ctx.accounts.user, clock.slot)?;
+        check_withdraw_policy(&ctx.accounts.user, spt_amount, ctx.remaining_accounts)?;
+
+        let (penalty, penalty_bps) = unstake_penalty(
+            &ctx.accounts.pool,
+            &ctx.accounts.user,
+            spt_amount,
+            now,
+            ctx.remaining_accounts,
+        )?;
+
+        let insurance_amount: u64 = (penalty as u128)
+            .checked_mul(ctx.accounts.pool.insurance_share_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+
+        let event = apply_unstake(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            spt_amount,
+            client_ref,
+        )?;
+        refresh_oracle_tier(
+            &ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            &ctx.accounts.price_feed,
+            decimals,
+            &clock,
+        );
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_UNSTAKE,
+            spt_amount,
+            clock.unix_timestamp,
+        );
+        ctx.accounts.pool.insurance_collected = ctx
+            .accounts
+            .pool
+            .insurance_collected
+            .checked_add(insurance_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let pool = &ctx.accounts.pool;
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        // Transfer tokens from the pool vault to user vault.
+        write_memo(&ctx.accounts.memo_program, &memo)?;
+        transfer_checked_interface(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.staking_vault.to_account_info(),
+            ctx.accounts.staking_mint.to_account_info(),
+            decimals,
+            ctx.accounts.stake_from_account.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            spt_amount
+                .checked_sub(penalty)
+                .ok_or(ErrorCode::ArithmeticUnderflow)?,
+        )?;
+
+        // Transfer the early-unstake penalty to the penalty and insurance vaults.
+        if penalty > 0 {
+            let penalty_vault = match ctx.remaining_accounts.get(0) {
+                Some(vault) if vault.key() == pool.penalty_vault => vault.clone(),
+                _ => return Err(ErrorCode::InvalidPenaltyVault.into()),
+            };
+
+            transfer_checked_interface(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.staking_vault.to_account_info(),
+                ctx.accounts.staking_mint.to_account_info(),
+                decimals,
+                penalty_vault,
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                penalty
+                    .checked_sub(insurance_amount)
+                    .ok_or(ErrorCode::ArithmeticUnderflow)?,
+            )?;
+
+            if insurance_amount > 0 {
+                let insurance_vault = match ctx.remaining_accounts.get(1) {
+                    Some(vault) if vault.key() == pool.insurance_vault => vault.clone(),
+                    _ => return Err(ErrorCode::InvalidInsuranceVault.into()),
+                };
+
+                transfer_checked_interface(
+                    ctx.accounts.token_program.to_account_info(),
+                    ctx.accounts.staking_vault.to_account_info(),
+                    ctx.accounts.staking_mint.to_account_info(),
+                    decimals,
+                    insurance_vault,
+                    ctx.accounts.pool_signer.to_account_info(),
+                    pool_signer,
+                    insurance_amount,
+                )?;
+            }
+
+            ctx.accounts.pool.total_penalties = ctx
+                .accounts
+                .pool
+                .total_penalties
+                .checked_add(penalty)
+                .ok_or(ErrorCode::MathOverflow)?;
+            emit!(FeeCollectedEvent {
+                pool: ctx.accounts.pool.key(),
+                kind: FEE_KIND_PENALTY,
+                amount: penalty,
+                payer: ctx.accounts.owner.key(),
+            });
+            emit!(UnstakePenaltyEvent {
+                pool: ctx.accounts.pool.key(),
+                user: ctx.accounts.user.key(),
+                amount: spt_amount,
+                penalty,
+                penalty_bps,
+            });
+        }
+        check_interface_vault_balance(&ctx.accounts.staking_vault, ctx.accounts.pool.total_staked)?;
+        emit_cpi(
+            &event,
+            &ctx.accounts.event_authority,
+            &ctx.accounts.program,
+            *ctx.bumps.get("event_authority").unwrap(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Stakes one NFT of the pool's collection as `nft_weight`, moving it into
+    /// an escrow token account owned by the pool signer.
+    pub fn stake_nft(ctx: Context<StakeNft>) -> Result<()> {
+        if !holds_collection_nft(
+            ctx.accounts.owner.key,
+            &ctx.accounts.pool.nft_collection,
+            &ctx.accounts.nft_from_account.to_account_info(),
+            &ctx.accounts.nft_metadata,
+        ) {
+            return Err(ErrorCode::InvalidNft.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        let weight = ctx.accounts.pool.nft_weight;
+        credit_stake(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            weight,
+            &clock,
+            0,
+        )?;
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.nft_from_account.to_account_info(),
+            &ctx.accounts.nft_mint,
+            ctx.accounts.nft_escrow.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            &[],
+            1,
+        )?;
+
+        emit!(NftStakeEvent {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.owner.key(),
+            mint: ctx.accounts.nft_mint.key(),
+            staked: true,
+        });
+
+        Ok(())
+    }
+
+    /// Returns a staked NFT to its owner once the position has matured and
+    /// closes its escrow, refunding the rent to the owner.
+    pub fn unstake_nft(ctx: Context<UnstakeNft>) -> Result<()> {
+        if ctx.accounts.user.frozen {
+            return Err(ErrorCode::PositionFrozen.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        let now = unix_timestamp_to_u64(clock.unix_timestamp);
+        check_matured(ctx.accounts.user.maturity_time, now)?;
+        check_unstake_delay(&ctx.accounts.pool, &ctx.accounts.user, clock.slot)?;
+
+        let weight = ctx.accounts.pool.nft_weight;
+        debit_stake(&mut ctx.accounts.pool, &mut ctx.accounts.user, weight, 0)?;
+
+        let pool = &ctx.accounts.pool;
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.nft_escrow.to_account_info(),
+            &ctx.accounts.nft_mint,
+            ctx.accounts.nft_to_account.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            1,
+        )?;
+
+        let ix = spl_token::instruction::close_account(
+            &spl_token::ID,
+            ctx.accounts.nft_escrow.to_account_info().key,
+            ctx.accounts.owner.key,
+            ctx.accounts.pool_signer.key,
+            &[ctx.accounts.pool_signer.key],
+        )?;
+        solana_program::program::invoke_signed(
+            &ix,
+            &[
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.nft_escrow.to_account_info(),
+                ctx.accounts.owner.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+            ],
+            pool_signer,
+        )?;
+
+        emit!(NftStakeEvent {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.owner.key(),
+            mint: ctx.accounts.nft_mint.key(),
+            staked: false,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_migration(
+        ctx: Context<CreateMigration>,
+        pay_pending_rewards: bool,
+    ) -> Result<()> {
+        let migration = &mut ctx.accounts.migration;
+        migration.old_pool = ctx.accounts.old_pool.key();
+        migration.new_pool = ctx.accounts.new_pool.key();
+        migration.pay_pending_rewards = pay_pending_rewards;
+        migration.nonce = *ctx.bumps.get("migration").unwrap();
+
+        Ok(())
+    }
+
+    /// Moves both vault balances of a paused pool to the empty, paused new pool
+    /// of its migration link and hands over the stake bookkeeping. Users are
+    /// re-homed afterwards through `migrate_stake`; the old pool stops accruing
+    /// and claims on it are refused, so nothing is paid out of its empty vault.
+    pub fn migrate_pool_vaults(ctx: Context<MigratePoolVaults>) -> Result<()> {
+        let now = clock::Clock::get()?.unix_timestamp;
+        let old_pool = &mut ctx.accounts.old_pool;
+        let total_staked = old_pool.total_staked;
+        update_rewards(old_pool, None, total_staked)?;
+        // The stake earns in the new pool from here on, not in both.
+        old_pool.reward_duration_end =
+            std::cmp::min(old_pool.reward_duration_end, unix_timestamp_to_u64(now));
+
+        let staked_amount = ctx.accounts.old_staking_vault.amount;
+        let reward_amount = ctx.accounts.old_reward_vault.amount;
+
+        let seeds = &[old_pool.to_account_info().key.as_ref(), &[old_pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+        if staked_amount > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.old_staking_vault.to_account_info(),
+                &ctx.accounts.staking_mint,
+                ctx.accounts.new_staking_vault.to_account_info(),
+                ctx.accounts.old_pool_signer.to_account_info(),
+                pool_signer,
+                staked_amount,
+            )?;
+        }
+        if reward_amount > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.old_reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.new_reward_vault.to_account_info(),
+                ctx.accounts.old_pool_signer.to_account_info(),
+                pool_signer,
+                reward_amount,
+            )?;
+        }
+
+        let new_pool = &mut ctx.accounts.new_pool;
+        update_rewards(new_pool, None, 0)?;
+        new_pool.total_staked = old_pool.total_staked;
+        new_pool.user_stake_count = old_pool.user_stake_count;
+        ctx.accounts.migration.reward_per_token_start = new_pool.reward_per_token_stored;
+        old_pool.vaults_migrated = true;
+
+        emit!(PoolVaultsMigratedEvent {
+            old_pool: old_pool.key(),
+            new_pool: new_pool.key(),
+            staked_amount,
+            reward_amount,
+            total_staked: new_pool.total_staked,
+            user_stake_count: new_pool.user_stake_count,
+        });
+
+        Ok(())
+    }
+
+    /// Moves the owner's whole position from the old pool of a migration link
+    /// to the new one, keeping the later of both maturity times. Once the pool
+    /// vaults were migrated only the accounts move and pending rewards are
+    /// always carried over. The withdraw policy applies as in `unstake`, with
+    /// the cosigner in the remaining accounts, and moves to the new position.
+    /// The old account's rent goes back to whoever paid it.
+    pub fn migrate_stake(ctx: Context<MigrateStake>) -> Result<()> {
+        let owner = ctx.accounts.owner.key();
+        let new_pool_key = ctx.accounts.new_pool.key();
+        if ctx.accounts.new_user.pool == Pubkey::default() {
+            let nonce = *ctx.bumps.get("new_user").unwrap();
+            init_user(
+                &mut ctx.accounts.new_pool,
+                &mut ctx.accounts.new_user,
+                owner,
+                owner,
+                nonce,
+            )?;
+        } else if ctx.accounts.new_user.pool != new_pool_key || ctx.accounts.new_user.owner != owner
+        {
+            return Err(ErrorCode::UserAccountMismatch.into());
+        } else if ctx.accounts.new_user.version != USER_VERSION {
+            return Err(ErrorCode::AccountNeedsMigration.into());
+        }
+
+        let amount = ctx.accounts.old_user.balance_staked;
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+        check_withdraw_policy(&ctx.accounts.old_user, amount, ctx.remaining_accounts)?;
+
+        let old_pool = &mut ctx.accounts.old_pool;
+        let total_staked = old_pool.total_staked;
+        update_rewards(old_pool, Some(&mut ctx.accounts.old_user), total_staked)?;
+
+        let new_pool = &mut ctx.accounts.new_pool;
+        let total_staked = new_pool.total_staked;
+        update_rewards(new_pool, Some(&mut ctx.accounts.new_user), total_staked)?;
+
+        // Pay or carry over pending rewards from the old pool.
+        let pending = ctx.accounts.old_user.reward_per_token_pending;
+        ctx.accounts.old_user.reward_per_token_pending = 0;
+        old_pool.aggregate_pending = old_pool
+            .aggregate_pending
+            .checked_sub(pending)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+
+        let seeds = &[old_pool.to_account_info().key.as_ref(), &[old_pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+        let vaults_migrated = old_pool.vaults_migrated;
+        let mut rewards_paid = 0;
+        let mut rewards_carried = 0;
+        if ctx.accounts.migration.pay_pending_rewards && !vaults_migrated {
+            let now = clock::Clock::get()?.unix_timestamp;
+            if !is_owner_reward_account(
+                &ctx.accounts.old_user,
+                &ctx.accounts.reward_account.key(),
+                &old_pool.reward_mint,
+                now,
+            ) {
+                return Err(ErrorCode::InvalidBeneficiaryAccount.into());
+            }
+            rewards_paid = std::cmp::min(pending, ctx.accounts.old_reward_vault.amount);
+            if rewards_paid > 0 {
+                transfer_checked(
+                    ctx.accounts.token_program.to_account_info(),
+                    ctx.accounts.old_reward_vault.to_account_info(),
+                    &ctx.accounts.reward_mint,
+                    ctx.accounts.reward_account.to_account_info(),
+                    ctx.accounts.old_pool_signer.to_account_info(),
+                    pool_signer,
+                    rewards_paid,
+                )?;
+            }
+        } else {
+            rewards_carried = pending;
+            if vaults_migrated {
+                // The new pool accrued on this stake since the vaults moved,
+                // before the user held it there.
+                let accrued = earned(
+                    amount,
+                    new_pool.reward_per_token_stored,
+                    ctx.accounts.migration.reward_per_token_start,
+                    0,
+                )?;
+                rewards_carried = rewards_carried
+                    .checked_add(accrued)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            new_pool.aggregate_pending = new_pool
+                .aggregate_pending
+                .checked_add(rewards_carried)
+                .ok_or(ErrorCode::MathOverflow)?;
+            ctx.accounts.new_user.reward_per_token_pending = ctx
+                .accounts
+                .new_user
+                .reward_per_token_pending
+                .checked_add(rewards_carried)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        // Transfer staked tokens from the old pool vault to the new one.
+        if !vaults_migrated {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.old_staking_vault.to_account_info(),
+                &ctx.accounts.staking_mint,
+                ctx.accounts.new_staking_vault.to_account_info(),
+                ctx.accounts.old_pool_signer.to_account_info(),
+                pool_signer,
+                amount,
+            )?;
+        }
+
+        ctx.accounts.old_user.balance_staked = 0;
+        refresh_weight(old_pool, &mut ctx.accounts.old_user)?;
+        old_pool.total_staked = old_pool
+            .total_staked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+        old_pool.user_stake_count = old_pool
+            .user_stake_count
+            .checked_sub(1)
+            .ok_or(ErrorCode::StakeCountUnderflow)?;
+
+        let new_user = &mut ctx.accounts.new_user;
+        new_user.balance_staked = new_user
+            .balance_staked
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let maturity_time =
+            std::cmp::max(new_user.maturity_time, ctx.accounts.old_user.maturity_time);
+        set_maturity(new_user, maturity_time);
+        // The policy moves with the stake. A policy of the new position stays,
+        // since changing it needs that position's cosigner.
+        if new_user.withdraw_cosigner == Pubkey::default() {
+            new_user.withdraw_threshold = ctx.accounts.old_user.withdraw_threshold;
+            new_user.withdraw_cosigner = ctx.accounts.old_user.withdraw_cosigner;
+        }
+        refresh_tier(new_pool, new_user, clock::Clock::get()?.unix_timestamp);
+        refresh_weight(new_pool, new_user)?;
+        if vaults_migrated {
+            // The new pool already counts this stake and user.
+            new_pool.user_stake_count = new_pool
+                .user_stake_count
+                .checked_sub(1)
+                .ok_or(ErrorCode::StakeCountUnderflow)?;
+        } else {
+            new_pool.total_staked = new_pool
+                .total_staked
+                .checked_add(amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        emit!(MigrateStakeEvent {
+            old_pool: old_pool.key(),
+            new_pool: new_pool.key(),
+            owner,
+            amount,
+            rewards_paid,
+            rewards_carried,
+            maturity_time: new_user.maturity_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn slash(ctx: Context<Slash>, amount: u64, reason_code: u16) -> Result<()> {
+        if !ctx.accounts.pool.slashing_enabled {
+            return Err(ErrorCode::SlashingDisabled.into());
+        }
+
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        if ctx.accounts.user.balance_staked < amount {
+            return Err(ErrorCode::SlashAmountExceedsBalance.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+
+        let user_opt = Some(&mut ctx.accounts.user);
+        update_rewards(pool, user_opt, total_staked)?;
+        ctx.accounts.user.balance_staked = ctx
+            .accounts
+            .user
+            .balance_staked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+
+        let now = clock::Clock::get()?.unix_timestamp;
+        refresh_tier(pool, &mut ctx.accounts.user, now);
+        refresh_weight(pool, &mut ctx.accounts.user)?;
+
+        pool.total_staked = pool
+            .total_staked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+
+        // Transfer slashed tokens from the pool vault to the slash destination.
+        {
+            let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+            let pool_signer = &[&seeds[..]];
+
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.staking_vault.to_account_info(),
+                &ctx.accounts.staking_mint,
+                ctx.accounts.slash_destination.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                amount,
+            )?;
+        }
+
+        emit!(SlashEvent {
+            pool: pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.user.owner,
+            amount,
+            reason_code,
+        });
+
+        Ok(())
+    }
+
+    /// Unstakes matured positions from several pools at once. For every amount,
+    /// `remaining_accounts` must hold, in order: pool, staking vault, user,
+    /// destination token account, pool signer, staking mint and price feed
+    /// (any account for pools without a tier oracle), with pool and user
+    /// writable. Every pool is checked exactly like `unstake` and any failure
+    /// reverts the whole batch. At most `MAX_BATCH_UNSTAKE_POOLS` pools fit in
+    /// one call. Each unstaked position emits a `CrankProgressEvent`, and the
+    /// batch ends with a `CrankCompletedEvent`.
+    pub fn batch_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, BatchUnstake<'info>>,
+        amounts: Vec<u64>,
+    ) -> Result<()> {
+        if amounts.is_empty() || amounts.len() > MAX_BATCH_UNSTAKE_POOLS {
+            return Err(ErrorCode::InvalidBatchSize.into());
+        }
+
+        // Withdraw policy cosigners may follow the per-pool accounts.
+        let batch_len = amounts.len() * BATCH_UNSTAKE_ACCOUNTS;
+        if ctx.remaining_accounts.len() < batch_len {
+            return Err(ErrorCode::InvalidBatchAccounts.into());
+        }
+        let (batch_accounts, cosigners) = ctx.remaining_accounts.split_at(batch_len);
+
+        let clock = clock::Clock::get()?;
+        let now = unix_timestamp_to_u64(clock.unix_timestamp);
+        let owner = ctx.accounts.owner.key();
+
+        for (accounts, &amount) in batch_accounts
+            .chunks(BATCH_UNSTAKE_ACCOUNTS)
+            .zip(amounts.iter())
+        {
+            if amount == 0 {
+                return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+            }
+
+            let mut pool: Account<Pool> = Account::try_from(&accounts[0])?;
+            let staking_vault: Account<TokenAccount> = Account::try_from(&accounts[1])?;
+            let mut user: Box<Account<User>> = Box::new(Account::try_from(&accounts[2])?);
+            let destination: Account<TokenAccount> = Account::try_from(&accounts[3])?;
+            let pool_signer = &accounts[4];
+            let staking_mint: Account<Mint> = Account::try_from(&accounts[5])?;
+            let price_feed = &accounts[6];
+
+            if staking_vault.key() != pool.staking_vault
+                || user.pool != pool.key()
+                || user.owner != owner
+            {
+                return Err(ErrorCode::InvalidBatchAccounts.into());
+            }
+            if destination.owner != owner || destination.mint != pool.staking_mint {
+                return Err(ErrorCode::InvalidWithdrawDestination.into());
+            }
+            if staking_mint.key() != pool.staking_mint {
+                return Err(ErrorCode::InvalidMint.into());
+            }
+            if pool.tier_oracle() != Pubkey::default() && price_feed.key() != pool.tier_oracle() {
+                return Err(ErrorCode::InvalidPriceFeed.into());
+            }
+            if user.version != USER_VERSION {
+                return Err(ErrorCode::AccountNeedsMigration.into());
+            }
+            if user.frozen {
+                return Err(ErrorCode::PositionFrozen.into());
+            }
+
+            let user_address = Pubkey::create_program_address(
+                &[owner.as_ref(), pool.key().as_ref(), &[user.nonce]],
+                ctx.program_id,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            let signer_address = Pubkey::create_program_address(
+                &[pool.key().as_ref(), &[pool.nonce]],
+                ctx.program_id,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            if user.key() != user_address || pool_signer.key() != signer_address {
+                return Err(ErrorCode::InvalidBatchAccounts.into());
+            }
+
+            check_matured(user.maturity_time, now)?;
+            check_staked_balance(user.balance_staked, amount)?;
+
+            check_unstake_delay(&pool, &user, clock.slot)?;
+            check_withdraw_policy(&user, amount, cosigners)?;
+
+            apply_unstake(&mut pool, &mut user, amount, 0)?;
+            refresh_oracle_tier(&pool, &mut user, price_feed, staking_mint.decimals, &clock);
+
+            // Transfer tokens from the pool vault to the destination.
+            {
+                let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+                let signer = &[&seeds[..]];
+
+                transfer_checked(
+                    ctx.accounts.token_program.to_account_info(),
+                    staking_vault.to_account_info(),
+                    &staking_mint,
+                    destination.to_account_info(),
+                    pool_signer.clone(),
+                    signer,
+                    amount,
+                )?;
+            }
+
+            emit!(CrankProgressEvent {
+                user: user.key(),
+                action: ACTION_UNSTAKE,
+                amount,
+            });
+
+            pool.exit(ctx.program_id)?;
+            user.exit(ctx.program_id)?;
+        }
+
+        // Any failing position reverts the batch, so none are ever skipped.
+        emit!(CrankCompletedEvent {
+            processed: amounts.len() as u32,
+            skipped: 0,
+        });
+
+        Ok(())
+    }
+
+    /// Previews `unstake` without mutating state, failing where it would. The
+    /// remaining accounts are those of `unstake`, so waiver proofs and the
+    /// withdraw cosigner count the same. The result is returned as a
+    /// borsh-serialized `UnstakeSimulation` via return data.
+    pub fn simulate_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, SimulateUnstake<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let pool = &ctx.accounts.pool;
+        let user = &ctx.accounts.user;
+        if user.frozen {
+            return Err(ErrorCode::PositionFrozen.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        let now = unix_timestamp_to_u64(clock.unix_timestamp);
+        let matured = user.maturity_time <= now;
+        if pool.max_penalty_bps == 0 {
+            check_matured(user.maturity_time, now)?;
+        }
+        check_staked_balance(user.balance_staked, amount)?;
+
+        check_unstake_delay(pool, user, clock.slot)?;
+        check_withdraw_policy(user, amount, ctx.remaining_accounts)?;
+
+        let (penalty, penalty_bps) =
+            unstake_penalty(pool, user, amount, now, ctx.remaining_accounts)?;
+
+        let simulation = UnstakeSimulation {
+            amount,
+            penalty,
+            penalty_bps,
+            net_amount: amount
+                .checked_sub(penalty)
+                .ok_or(ErrorCode::ArithmeticUnderflow)?,
+            pending_rewards: pending_rewards(pool, user, clock.unix_timestamp)?,
+            matured,
+            penalty_exempt: user.penalty_exempt,
+        };
+        set_return_data(&simulation.try_to_vec().unwrap());
+
+        Ok(())
+    }
+
+    /// Sums `owner`'s positions in the pool, passed as remaining accounts,
+    /// and returns a `UserSummary` as return data. Nothing is written.
+    pub fn get_user_summary<'info>(
+        ctx: Context<'_, '_, '_, 'info, GetUserSummary<'info>>,
+        owner: Pubkey,
+    ) -> Result<()> {
+        let positions = ctx.remaining_accounts;
+        if positions.is_empty() || positions.len() > MAX_SUMMARY_POSITIONS {
+            return Err(ErrorCode::InvalidBatchSize.into());
+        }
+
+        let pool = &ctx.accounts.pool;
+        let now = clock::Clock::get()?.unix_timestamp;
+        let mut summary = UserSummary {
+            owner,
+            positions: positions.len() as u8,
+            ..UserSummary::default()
+        };
+        for (i, info) in positions.iter().enumerate() {
+            if positions[..i].iter().any(|other| other.key == info.key) {
+                return Err(ErrorCode::InvalidBatchAccounts.into());
+            }
+
+            let user: Account<User> = Account::try_from(info)?;
+            if user.pool != pool.key() || user.owner != owner {
+                return Err(ErrorCode::UserAccountMismatch.into());
+            }
+
+            summary.total_staked = summary
+                .total_staked
+                .checked_add(user.balance_staked)
+                .ok_or(ErrorCode::MathOverflow)?;
+            summary.total_pending = summary
+                .total_pending
+                .checked_add(pending_rewards(pool, &user, now)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            if user.balance_staked > 0
+                && (summary.earliest_unlock == 0 || user.maturity_time < summary.earliest_unlock)
+            {
+                summary.earliest_unlock = user.maturity_time;
+            }
+        }
+        set_return_data(&summary.try_to_vec().unwrap());
+
+        Ok(())
+    }
+
+    /// Returns the program's `ProgramVersion` via return data, so clients can
+    /// tell which features a deployment supports.
+    pub fn get_version(_ctx: Context<GetVersion>) -> Result<()> {
+        let [major, minor, patch] = PROGRAM_VERSION;
+        let version = ProgramVersion {
+            major,
+            minor,
+            patch,
+            features: PROGRAM_FEATURES,
+        };
+        set_return_data(&version.try_to_vec().unwrap());
+
+        Ok(())
+    }
+
+    /// Returns the pool's settings as a `PoolConfig` via return data.
+    pub fn get_config(ctx: Context<GetConfig>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let config = PoolConfig {
+            version: POOL_CONFIG_VERSION,
+            authority: pool.authority,
+            funders: pool
+                .funders
+                .iter()
+                .filter(|x| **x != Pubkey::default())
+                .copied()
+                .collect(),
+            reward_duration: pool.reward_duration,
+            lock_period: pool.lock_period,
+            no_tier: pool.no_tier,
+            paused: pool.paused,
+            halted: pool.halted,
+            halted_until: pool.halted_until,
+            closing: pool.closing,
+            slashing_enabled: pool.slashing_enabled,
+            maturity_enforcement_enabled: pool.maturity_enforcement_enabled,
+            max_maturity_extension: pool.max_maturity_extension,
+            require_attestation: pool.require_attestation,
+            attestor: pool.attestor,
+            require_active_rewards: pool.require_active_rewards,
+            topup_grace_seconds: pool.topup_grace_seconds,
+            topup_grace_bps: pool.topup_grace_bps,
+            max_penalty_bps: pool.max_penalty_bps,
+            insurance_share_bps: pool.insurance_share_bps,
+            compound_tip_bps: pool.compound_tip_bps,
+            min_unstake_delay_slots: pool.min_unstake_delay_slots,
+            max_per_window: pool.max_per_window,
+            dust_threshold: pool.dust_threshold,
+            min_fund_amount: pool.min_fund_amount,
+            shared_mint: pool.shared_mint,
+            voter_weight_tier_boost: pool.voter_weight_tier_boost,
+            price_feed: pool.price_feed,
+            usd_tier_thresholds: pool.usd_tier_thresholds,
+            nft_collection: pool.nft_collection,
+            nft_weight: pool.nft_weight,
+            exchange_rate_oracle: pool.exchange_rate_oracle,
+            exchange_rate_source: pool.exchange_rate_source,
+            auto_claim_fee: pool.auto_claim_fee,
+        };
+        set_return_data(&config.try_to_vec().unwrap());
+
+        Ok(())
+    }
+
+    /// Returns a `UserStats` for any user of the pool via return data. No
+    /// signature is needed and nothing is written.
+    pub fn get_user_stats(ctx: Context<GetUserStats>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let user = &ctx.accounts.user;
+        let now = clock::Clock::get()?.unix_timestamp;
+
+        let stats = UserStats {
+            pending_rewards: pending_rewards(pool, user, now)?,
+            seconds_until_maturity: user
+                .maturity_time
+                .saturating_sub(unix_timestamp_to_u64(now)),
+            tier: user.tier,
+            balance_staked: user.balance_staked,
+        };
+        set_return_data(&stats.try_to_vec().unwrap());
+
+        Ok(())
+    }
+
+    /// Returns the pool's current figures as a `PoolStats` via return data.
+    /// Nothing is written, so it can be simulated against any RPC.
+    pub fn get_pool_stats(ctx: Context<GetPoolStats>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let now = unix_timestamp_to_u64(clock::Clock::get()?.unix_timestamp);
+        let seconds_remaining = pool.reward_duration_end.saturating_sub(now);
+        let reward_rate = if seconds_remaining > 0 {
+            pool.reward_rate
+        } else {
+            0
+        };
+
+        // Reward tokens per staked token and year; the two mints may be
+        // priced differently, so this is not a market APR.
+        let apr_bps = if pool.total_staked > 0 {
+            (reward_rate as u128)
+                .checked_mul(SECONDS_PER_YEAR as u128)
+                .and_then(|v| v.checked_mul(BPS_DENOMINATOR as u128))
+                .map(|v| v / pool.total_staked as u128)
+                .map(|v| u64::try_from(v).unwrap_or(u64::MAX))
+                .unwrap_or(u64::MAX)
+        } else {
+            0
+        };
+
+        let stats = PoolStats {
+            total_staked: pool.total_staked,
+            reward_rate,
+            seconds_remaining,
+            rewards_remaining: (reward_rate as u128)
+                .checked_mul(seconds_remaining as u128)
+                .and_then(|v| u64::try_from(v).ok())
+                .ok_or(ErrorCode::MathOverflow)?,
+            apr_bps,
+            user_stake_count: pool.user_stake_count,
+            paused: pool.paused,
+            halted: pool.halted,
+            closing: pool.closing,
+            funder_contributions: pool.funder_contributions,
+            removed_funder_contributions: pool.removed_funder_contributions,
+            total_deposit_fees: pool.total_deposit_fees,
+            total_claim_fees: pool.total_claim_fees,
+            total_penalties: pool.total_penalties,
+        };
+        set_return_data(&stats.try_to_vec().unwrap());
+
+        Ok(())
+    }
+
+    /// Unstakes a matured position and pays out its pending rewards in the same
+    /// instruction.
+    pub fn unstake_and_claim(
+        ctx: Context<UnstakeAndClaim>,
+        day_index: u64,
+        spt_amount: u64,
+    ) -> Result<()> {
+        if spt_amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        check_reward_account(
+            &ctx.accounts.reward_account,
+            &ctx.accounts.pool,
+            &ctx.accounts.user,
+            clock.unix_timestamp,
+        )?;
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
+        check_staked_balance(ctx.accounts.user.balance_staked, spt_amount)?;
+
+        check_unstake_delay(&ctx.accounts.pool, &ctx.accounts.user, clock.slot)?;
+        check_withdraw_policy(&ctx.accounts.user, spt_amount, ctx.remaining_accounts)?;
+
+        apply_unstake(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            spt_amount,
+            0,
+        )?;
+        let reward_amount = take_pending_rewards(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.reward_vault.amount,
+        )?;
+        record_action(
+            &mut ctx.accounts.user,
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_UNSTAKE,
+            spt_amount,
+            clock.unix_timestamp,
+        );
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
+
+        let pool = &ctx.accounts.pool;
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        // Transfer tokens from the pool vault to user vault.
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.staking_vault.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.stake_from_account.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            spt_amount,
+        )?;
+
+        // Transfer pending rewards from the reward vault to the user.
+        if reward_amount > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.reward_account.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                reward_amount,
+            )?;
+            ctx.accounts.user.last_claim_time = clock.unix_timestamp;
+        }
+        check_reward_funding(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.reward_vault,
+            clock.unix_timestamp,
+        )?;
+
+        emit!(ClaimEvent {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.owner.key(),
+            amount: reward_amount,
+            total_rewards_claimed: ctx.accounts.user.total_rewards_claimed,
+            last_claim_time: ctx.accounts.user.last_claim_time,
+            client_ref: 0,
+        });
+
+        Ok(())
+    }
+
+    /// Lets `spender` unstake up to `max_amount` from the position, paid only
+    /// into `destination`. Replaces any previous approval.
+    pub fn approve_unstake(
+        ctx: Context<ApproveUnstake>,
+        spender: Pubkey,
+        max_amount: u64,
+    ) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.unstake_spender = spender;
+        user.unstake_allowance = max_amount;
+        user.unstake_destination = ctx.accounts.destination.key();
+
+        Ok(())
+    }
+
+    pub fn revoke_unstake_allowance(ctx: Context<RevokeUnstakeAllowance>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.unstake_spender = Pubkey::default();
+        user.unstake_allowance = 0;
+        user.unstake_destination = Pubkey::default();
+
+        Ok(())
+    }
+
+    /// Unstakes `amount` on the owner's behalf against the spender allowance.
+    pub fn unstake_by_spender(ctx: Context<UnstakeBySpender>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        if amount > ctx.accounts.user.unstake_allowance {
+            return Err(ErrorCode::UnstakeAllowanceExceeded.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
+        check_staked_balance(ctx.accounts.user.balance_staked, amount)?;
+
+        check_unstake_delay(&ctx.accounts.pool, &ctx.accounts.user, clock.slot)?;
+        check_withdraw_policy(&ctx.accounts.user, amount, ctx.remaining_accounts)?;
+
+        apply_unstake(&mut ctx.accounts.pool, &mut ctx.accounts.user, amount, 0)?;
+        ctx.accounts.user.unstake_allowance -= amount;
+
+        let pool = &ctx.accounts.pool;
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.staking_vault.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.destination.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Opts the position in or out of permissionless reward compounding.
+    pub fn set_auto_compound(ctx: Context<SetAutoCompound>, enabled: bool) -> Result<()> {
+        if enabled && ctx.accounts.pool.staking_mint != ctx.accounts.pool.reward_mint {
+            return Err(ErrorCode::AutoCompoundUnsupported.into());
+        }
+        ctx.accounts.user.auto_compound = enabled;
+
+        Ok(())
+    }
+
+    /// Restakes an opted-in user's pending rewards. Anyone may call it; the
+    /// cranker receives `compound_tip_bps` of the compounded amount.
+    pub fn crank_compound(ctx: Context<CrankCompound>) -> Result<()> {
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+        let pool = &mut ctx.accounts.pool;
+        if pool.paused {
+            return Err(ErrorCode::PoolPaused.into());
+        }
+
+        let total_staked = pool.total_staked;
+        update_rewards(pool, Some(&mut ctx.accounts.user), total_staked)?;
+
+        let reward_amount = take_pending_rewards(
+            pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.reward_vault.amount,
+        )?;
+        if reward_amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+        let tip: u64 = (reward_amount as u128)
+            .checked_mul(pool.compound_tip_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+        let compounded = reward_amount - tip;
+
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.reward_mint,
+            ctx.accounts.staking_vault.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            compounded,
+        )?;
+        if tip > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.tip_account.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                tip,
+            )?;
+        }
+
+        // Rewards were already checkpointed above, so credit the stake
+        // directly without touching maturity or the deposit window.
+        let user = &mut ctx.accounts.user;
+        user.balance_staked = user
+            .balance_staked
+            .checked_add(compounded)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let now = clock::Clock::get()?.unix_timestamp;
+        refresh_tier(pool, user, now);
+        record_action(user, ACTION_COMPOUND, compounded, now);
+        refresh_weight(pool, user)?;
+        pool.total_staked = pool
+            .total_staked
+            .checked_add(compounded)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Registers the key that can unfreeze the position. Replacing or removing
+    /// an existing guardian also needs its signature as the first remaining
+    /// account.
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+        let current = ctx.accounts.user.guardian;
+        if current != Pubkey::default() {
+            let cosigned = ctx
+                .remaining_accounts
+                .first()
+                .map_or(false, |info| info.is_signer && info.key() == current);
+            if !cosigned {
+                return Err(ErrorCode::GuardianSignatureRequired.into());
+            }
+        }
+        ctx.accounts.user.guardian = guardian;
+
+        Ok(())
+    }
+
+    /// Blocks unstakes and claims until the guardian unfreezes the position.
+    pub fn freeze_account(ctx: Context<SetGuardian>) -> Result<()> {
+        if ctx.accounts.user.guardian == Pubkey::default() {
+            return Err(ErrorCode::GuardianNotSet.into());
+        }
+        ctx.accounts.user.frozen = true;
+
+        Ok(())
+    }
+
+    pub fn unfreeze_account(ctx: Context<UnfreezeAccount>) -> Result<()> {
+        ctx.accounts.user.frozen = false;
+
+        Ok(())
+    }
+
+    /// Schedules `beneficiary` to receive this position's claims after
+    /// `BENEFICIARY_TIMELOCK_SECONDS`. The default key removes it.
+    pub fn set_beneficiary(ctx: Context<SetBeneficiary>, beneficiary: Pubkey) -> Result<()> {
+        let now = clock::Clock::get()?.unix_timestamp;
+        let user = &mut ctx.accounts.user;
+        user.beneficiary = user.beneficiary_at(now);
+        user.next_beneficiary = beneficiary;
+        user.beneficiary_change_at = now
+            .checked_add(BENEFICIARY_TIMELOCK_SECONDS)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Lets `delegate` stake and unstake for the owner. Unstaked tokens still
+    /// only go to the owner's accounts. The default key removes it.
+    pub fn set_operations_delegate(ctx: Context<SetUserConfig>, delegate: Pubkey) -> Result<()> {
+        ctx.accounts.user.operations_delegate = delegate;
+
+        Ok(())
+    }
+
+    /// Stakes `amount` from a token account controlled by the delegate, with
+    /// the limits and bookkeeping of `stake`.
+    pub fn delegate_stake(
+        ctx: Context<DelegateStake>,
+        day_index: u64,
+        amount: u64,
+        max_maturity: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        apply_stake(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            amount,
+            &clock,
+            0,
+        )?;
+        check_stake_limits(
+            &ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            &ctx.accounts.price_feed,
+            ctx.accounts.staking_mint.decimals,
+            max_maturity,
+            &clock,
+        )?;
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_STAKE,
+            amount,
+            clock.unix_timestamp,
+        );
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.stake_from_account.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.staking_vault.to_account_info(),
+            ctx.accounts.delegate.to_account_info(),
+            &[],
+            amount,
+        )?;
+        check_interface_vault_balance(&ctx.accounts.staking_vault, ctx.accounts.pool.total_staked)?;
+
+        Ok(())
+    }
+
+    /// Unstakes `amount` of matured stake into a token account of the owner.
+    pub fn delegate_unstake(
+        ctx: Context<DelegateUnstake>,
+        day_index: u64,
+        amount: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
+        check_staked_balance(ctx.accounts.user.balance_staked, amount)?;
+
+        check_unstake_delay(&ctx.accounts.pool, &ctx.accounts.user, clock.slot)?;
+        check_withdraw_policy(&ctx.accounts.user, amount, ctx.remaining_accounts)?;
+
+        apply_unstake(&mut ctx.accounts.pool, &mut ctx.accounts.user, amount, 0)?;
+        refresh_oracle_tier(
+            &ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            &ctx.accounts.price_feed,
+            ctx.accounts.staking_mint.decimals,
+            &clock,
+        );
+        ctx.accounts.daily_stats.record(
+            ctx.accounts.pool.key(),
+            ACTION_UNSTAKE,
+            amount,
+            clock.unix_timestamp,
+        );
+
+        let pool = &ctx.accounts.pool;
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.staking_vault.to_account_info(),
+            &ctx.accounts.staking_mint,
+            ctx.accounts.destination.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Puts the user's future claims into a vesting escrow that unlocks
+    /// linearly over `vest_duration` seconds from `cliff_timestamp`.
+    pub fn set_user_vesting(
+        ctx: Context<SetUserVesting>,
+        cliff_timestamp: i64,
+        vest_duration: u64,
+    ) -> Result<()> {
+        if cliff_timestamp <= 0 {
+            return Err(ErrorCode::InvalidVestingSchedule.into());
+        }
+
+        let user = &mut ctx.accounts.user;
+        user.vesting_cliff = cliff_timestamp;
+        user.vesting_duration = vest_duration;
+        user.vesting_nonce = *ctx.bumps.get("vesting_escrow").unwrap();
+
+        Ok(())
+    }
+
+    pub fn release_vested(ctx: Context<ReleaseVested>) -> Result<()> {
+        let now = clock::Clock::get()?.unix_timestamp;
+        check_not_halted(&ctx.accounts.pool, now)?;
+        if !is_reward_destination(
+            &ctx.accounts.user,
+            &ctx.accounts.reward_account.key(),
+            &ctx.accounts.pool.reward_mint,
+            now,
+        ) {
+            return Err(ErrorCode::InvalidBeneficiaryAccount.into());
+        }
+        let user = &mut ctx.accounts.user;
+        let amount = vested_amount(user, now)?
+            .checked_sub(user.vesting_released)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+        user.vesting_released = user
+            .vesting_released
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let pool = &ctx.accounts.pool;
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vesting_escrow.to_account_info(),
+            &ctx.accounts.reward_mint,
+            ctx.accounts.reward_account.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Starts moving a position to `new_owner` after its owner lost control
+    /// of their wallet. The owner can cancel until `execute_recovery`.
+    pub fn request_recovery(ctx: Context<RequestRecovery>, new_owner: Pubkey) -> Result<()> {
+        if new_owner == Pubkey::default() || new_owner == ctx.accounts.user.owner {
+            return Err(ErrorCode::InvalidRecoveryOwner.into());
+        }
+
+        let now = clock::Clock::get()?.unix_timestamp;
+        let user = &mut ctx.accounts.user;
+        user.recovery_new_owner = new_owner;
+        user.recovery_requested_at = now;
+
+        emit!(RecoveryRequestedEvent {
+            pool: user.pool,
+            user: user.key(),
+            owner: user.owner,
+            new_owner,
+            executable_at: now
+                .checked_add(RECOVERY_DELAY_SECONDS)
+                .ok_or(ErrorCode::MathOverflow)?,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_recovery(ctx: Context<SetUserConfig>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        if user.recovery_requested_at == 0 {
+            return Err(ErrorCode::NoRecoveryPending.into());
+        }
+        user.recovery_new_owner = Pubkey::default();
+        user.recovery_requested_at = 0;
+
+        emit!(RecoveryCancelledEvent {
+            pool: user.pool,
+            user: user.key(),
+            owner: user.owner,
+        });
+
+        Ok(())
+    }
+
+    /// Re-homes the position to the new owner's user account once the
+    /// recovery delay has passed. Every permission granted by the old key is
+    /// dropped, and the old account's rent goes back to whoever paid it.
+    pub fn execute_recovery(ctx: Context<ExecuteRecovery>) -> Result<()> {
+        let now = clock::Clock::get()?.unix_timestamp;
+        let requested_at = ctx.accounts.old_user.recovery_requested_at;
+        if requested_at == 0 {
+            return Err(ErrorCode::NoRecoveryPending.into());
+        }
+        if now
+            < requested_at
+                .checked_add(RECOVERY_DELAY_SECONDS)
+                .ok_or(ErrorCode::MathOverflow)?
+        {
+            return Err(ErrorCode::RecoveryTimelockActive.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+        update_rewards(pool, Some(&mut ctx.accounts.old_user), total_staked)?;
+
+        let new_owner = ctx.accounts.new_owner.key();
+        let mut position = User::clone(&ctx.accounts.old_user);
+        position.owner = new_owner;
+        position.nonce = *ctx.bumps.get("new_user").unwrap();
+        position.rent_payer = new_owner;
+        position.unstake_spender = Pubkey::default();
+        position.unstake_allowance = 0;
+        position.unstake_destination = Pubkey::default();
+        position.guardian = Pubkey::default();
+        position.frozen = false;
+        position.beneficiary = Pubkey::default();
+        position.next_beneficiary = Pubkey::default();
+        position.beneficiary_change_at = 0;
+        position.operations_delegate = Pubkey::default();
+        position.claim_program = Pubkey::default();
+        position.auto_claim = false;
+        position.auto_claim_min = 0;
+        position.recovery_new_owner = Pubkey::default();
+        position.recovery_requested_at = 0;
+        let new_user: &mut User = &mut ctx.accounts.new_user;
+        *new_user = position;
+
+        emit!(RecoveryExecutedEvent {
+            pool: pool.key(),
+            old_user: ctx.accounts.old_user.key(),
+            new_user: ctx.accounts.new_user.key(),
+            old_owner: ctx.accounts.old_user.owner,
+            new_owner,
+            amount: ctx.accounts.new_user.balance_staked,
+        });
+
+        Ok(())
+    }
+
+    /// Anchors an off-chain attestation commitment to the position. With a
+    /// pool attestor configured it must countersign as the first remaining
+    /// account; clearing the commitment always needs the attestor.
+    pub fn set_attestation(ctx: Context<SetUserConfig>, commitment: [u8; 32]) -> Result<()> {
+        let attestor = ctx.accounts.pool.attestor;
+        let clearing = commitment == [0u8; 32];
+        if attestor != Pubkey::default() || clearing {
+            let countersigned = attestor != Pubkey::default()
+                && ctx
+                    .remaining_accounts
+                    .first()
+                    .map_or(false, |info| info.is_signer && info.key() == attestor);
+            if !countersigned {
+                return Err(ErrorCode::AttestorSignatureRequired.into());
+            }
+        }
+        ctx.accounts.user.attestation = commitment;
+
+        Ok(())
+    }
+
+    /// Requires `cosigner` to also sign unstakes of `threshold` or more. Once
+    /// set, changing or removing the policy needs the current cosigner as the
+    /// first remaining account.
+    pub fn set_withdraw_policy(
+        ctx: Context<SetUserConfig>,
+        threshold: u64,
+        cosigner: Pubkey,
+    ) -> Result<()> {
+        let current = ctx.accounts.user.withdraw_cosigner;
+        if current != Pubkey::default() {
+            let cosigned = ctx
+                .remaining_accounts
+                .first()
+                .map_or(false, |info| info.is_signer && info.key() == current);
+            if !cosigned {
+                return Err(ErrorCode::CosignerRequired.into());
+            }
+        }
+
+        let user = &mut ctx.accounts.user;
+        user.withdraw_threshold = threshold;
+        user.withdraw_cosigner = cosigner;
+
+        Ok(())
+    }
+
+    /// Grants the user at least `tier` until `expires_at`. An `expires_at` in
+    /// the past clears the override.
+    pub fn set_tier_override(
+        ctx: Context<SetUserByAuthority>,
+        tier: u8,
+        expires_at: i64,
+    ) -> Result<()> {
+        if usize::from(tier) > TIER_INFO.len() {
+            return Err(ErrorCode::InvalidTier.into());
+        }
+
+        let now = clock::Clock::get()?.unix_timestamp;
+        let pool = &ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        user.tier_override = tier;
+        user.tier_override_expires_at = expires_at;
+        refresh_tier(pool, user, now);
+
+        emit!(TierOverrideEvent {
+            pool: pool.key(),
+            user: user.key(),
+            owner: user.owner,
+            tier_override: tier,
+            expires_at,
+            tier: user.tier,
+        });
+
+        Ok(())
+    }
+
+    /// Exempts the user from the early-unstake penalty, e.g. for contractual
+    /// exit rights.
+    pub fn set_penalty_exempt(ctx: Context<SetUserByAuthority>, exempt: bool) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.penalty_exempt = exempt;
+
+        emit!(PenaltyExemptEvent {
+            pool: ctx.accounts.pool.key(),
+            user: user.key(),
+            owner: user.owner,
+            penalty_exempt: exempt,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_leaderboard(ctx: Context<CreateLeaderboard>) -> Result<()> {
+        let leaderboard = &mut ctx.accounts.leaderboard;
+        leaderboard.pool = ctx.accounts.pool.key();
+        leaderboard.nonce = *ctx.bumps.get("leaderboard").unwrap();
+
+        Ok(())
+    }
+
+    /// Re-ranks the user's owner by the live `balance_staked` of the user
+    /// account. Permissionless, so anyone can refresh a stale entry.
+    pub fn update_leaderboard(ctx: Context<UpdateLeaderboard>) -> Result<()> {
+        let user = &ctx.accounts.user;
+        ctx.accounts
+            .leaderboard
+            .update(user.owner, user.balance_staked);
+
+        Ok(())
+    }
+
+    /// Opens the pool's staker lottery, drawing at most every `draw_interval`
+    /// seconds. Prizes are paid in the reward mint from the lottery vault.
+    pub fn create_lottery(ctx: Context<CreateLottery>, draw_interval: u64) -> Result<()> {
+        if draw_interval == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let now = clock::Clock::get()?.unix_timestamp;
+        let lottery = &mut ctx.accounts.lottery;
+        lottery.pool = ctx.accounts.pool.key();
+        lottery.lottery_vault = ctx.accounts.lottery_vault.key();
+        lottery.draw_interval = draw_interval;
+        lottery.next_draw_at = now.saturating_add(draw_interval as i64);
+        lottery.round = 1;
+        lottery.prize = 0;
+        lottery.randomness = Pubkey::default();
+        lottery.seed_slot = 0;
+        lottery.entries = [LeaderboardEntry::default(); LEADERBOARD_SIZE];
+        lottery.last_winner = Pubkey::default();
+        lottery.nonce = *ctx.bumps.get("lottery").unwrap();
+
+        Ok(())
+    }
+
+    /// Adds `amount` to the prize of the current round.
+    pub fn fund_lottery(ctx: Context<FundLottery>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.from.to_account_info(),
+            &ctx.accounts.reward_mint,
+            ctx.accounts.lottery_vault.to_account_info(),
+            ctx.accounts.authority.to_account_info(),
+            &[],
+            amount,
+        )?;
+        let lottery = &mut ctx.accounts.lottery;
+        lottery.prize = lottery
+            .prize
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Starts the current round's draw on a freshly committed, unrevealed
+    /// Switchboard randomness account, snapshotting the leaderboard the winner
+    /// is picked from so later rank changes can't steer the outcome.
+    pub fn request_draw(ctx: Context<RequestDraw>) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.draw_pending() {
+            return Err(ErrorCode::LotteryDrawPending.into());
+        }
+        if clock.unix_timestamp < lottery.next_draw_at {
+            return Err(error_with_context(
+                ErrorCode::LotteryDrawTooEarly,
+                format_args!(
+                    "now {}, next draw at {}",
+                    clock.unix_timestamp, lottery.next_draw_at
+                ),
+            ));
+        }
+
+        let randomness =
+            read_randomness(&ctx.accounts.randomness).ok_or(ErrorCode::InvalidRandomness)?;
+        if randomness.reveal_slot >= randomness.seed_slot
+            || clock.slot.saturating_sub(randomness.seed_slot) > LOTTERY_MAX_COMMIT_AGE_SLOTS
+        {
+            return Err(error_with_context(
+                ErrorCode::InvalidRandomness,
+                format_args!(
+                    "committed at slot {}, revealed at {}, now {}",
+                    randomness.seed_slot, randomness.reveal_slot, clock.slot
+                ),
+            ));
+        }
+
+        lottery.randomness = ctx.accounts.randomness.key();
+        lottery.seed_slot = randomness.seed_slot;
+        lottery.entries = ctx.accounts.leaderboard.entries;
+        lottery.next_draw_at = clock
+            .unix_timestamp
+            .saturating_add(lottery.draw_interval as i64);
+
+        Ok(())
+    }
+
+    /// Drops a draw whose randomness was still not revealed
+    /// `LOTTERY_REVEAL_TIMEOUT_SLOTS` after its commitment, so the round can be
+    /// drawn again.
+    pub fn cancel_draw(ctx: Context<CancelDraw>) -> Result<()> {
+        let slot = clock::Clock::get()?.slot;
+        let lottery = &mut ctx.accounts.lottery;
+        let revealed = read_randomness(&ctx.accounts.randomness).map_or(false, |randomness| {
+            randomness.seed_slot == lottery.seed_slot
+                && randomness.reveal_slot > randomness.seed_slot
+        });
+        if revealed || slot.saturating_sub(lottery.seed_slot) <= LOTTERY_REVEAL_TIMEOUT_SLOTS {
+            return Err(ErrorCode::LotteryDrawPending.into());
+        }
+
+        lottery.randomness = Pubkey::default();
+        lottery.seed_slot = 0;
+        lottery.entries = [LeaderboardEntry::default(); LEADERBOARD_SIZE];
+
+        Ok(())
+    }
+
+    /// Settles the pending draw once its randomness is revealed, picking a
+    /// staker from the snapshot with odds proportional to their balance. The
+    /// winner's user account and reward account, their or their
+    /// beneficiary's associated token account, follow as remaining accounts.
+    /// Rounds nobody wins, including by a winner who has since fully
+    /// unstaked or whose reward account is missing, closed or frozen, roll
+    /// their prize over to the next round.
+    pub fn settle_draw<'info>(ctx: Context<'_, '_, '_, 'info, SettleDraw<'info>>) -> Result<()> {
+        let now = clock::Clock::get()?.unix_timestamp;
+        let randomness =
+            read_randomness(&ctx.accounts.randomness).ok_or(ErrorCode::InvalidRandomness)?;
+        // A new commitment on the same account would be a different draw.
+        if randomness.seed_slot != ctx.accounts.lottery.seed_slot {
+            return Err(ErrorCode::InvalidRandomness.into());
+        }
+        if randomness.reveal_slot <= randomness.seed_slot {
+            return Err(ErrorCode::RandomnessNotRevealed.into());
+        }
+
+        let pool_key = ctx.accounts.pool.key();
+        let random = u128::from_le_bytes(randomness.value[..16].try_into().unwrap());
+        let mut winner = None;
+        if let Some(index) = weighted_pick(&ctx.accounts.lottery.entries, random) {
+            let owner = ctx.accounts.lottery.entries[index].owner;
+            let (user_info, reward_account) = match ctx.remaining_accounts {
+                [user_info, reward_account, ..] => (user_info, reward_account),
+                _ => return Err(ErrorCode::InvalidLotteryWinner.into()),
+            };
+            if user_info.key() != pda::user_address(&owner, &pool_key).0 {
+                return Err(ErrorCode::InvalidLotteryWinner.into());
+            }
+
+            let staked = user_info.owner == ctx.program_id && !user_info.data_is_empty();
+            if staked {
+                let user: Account<User> = Account::try_from(user_info)?;
+                if user.balance_staked > 0 {
+                    if !is_owner_reward_account(
+                        &user,
+                        &reward_account.key(),
+                        &ctx.accounts.pool.reward_mint,
+                        now,
+                    ) {
+                        return Err(ErrorCode::InvalidLotteryWinner.into());
+                    }
+                    // Failing the transfer would leave the draw pending, and
+                    // revealed draws can't be cancelled.
+                    let usable = *reward_account.owner == ctx.accounts.token_program.key()
+                        && load_token_account(reward_account)
+                            .map_or(false, |account| !account.is_frozen());
+                    if usable {
+                        winner = Some((owner, reward_account.clone()));
+                    }
+                }
+            }
+        }
+
+        let mut paid = 0;
+        if let Some((_, reward_account)) = &winner {
+            paid = std::cmp::min(
+                ctx.accounts.lottery.prize,
+                ctx.accounts.lottery_vault.amount,
+            );
+            let seeds = &[pool_key.as_ref(), &[ctx.accounts.pool.nonce]];
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.lottery_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                reward_account.clone(),
+                ctx.accounts.pool_signer.to_account_info(),
+                &[&seeds[..]],
+                paid,
+            )?;
+        }
+
+        let winner = winner.map_or(Pubkey::default(), |(owner, _)| owner);
+        let lottery = &mut ctx.accounts.lottery;
+        emit!(LotteryDrawEvent {
+            pool: pool_key,
+            round: lottery.round,
+            winner,
+            prize: paid,
+        });
+        lottery.prize = lottery
+            .prize
+            .checked_sub(paid)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+        lottery.round = lottery
+            .round
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        lottery.randomness = Pubkey::default();
+        lottery.seed_slot = 0;
+        lottery.entries = [LeaderboardEntry::default(); LEADERBOARD_SIZE];
+        lottery.last_winner = winner;
+
+        Ok(())
+    }
+
+    /// Pushes the user's maturity back by `additional_seconds`, counted from
+    /// now if already matured. Only pools created with maturity enforcement
+    /// allow this, and the user's total extension is capped by the pool.
+    pub fn extend_user_maturity(
+        ctx: Context<SetUserByAuthority>,
+        additional_seconds: u64,
+        reason_code: u16,
+    ) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        if !pool.maturity_enforcement_enabled {
+            return Err(ErrorCode::MaturityEnforcementDisabled.into());
+        }
+
+        let user = &mut ctx.accounts.user;
+        let maturity_extended = user
+            .maturity_extended
+            .checked_add(additional_seconds)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if maturity_extended > pool.max_maturity_extension {
+            return Err(ErrorCode::MaturityExtensionTooLong.into());
+        }
+
+        let now = unix_timestamp_to_u64(clock::Clock::get()?.unix_timestamp);
+        let maturity_time = std::cmp::max(user.maturity_time, now)
+            .checked_add(additional_seconds)
+            .ok_or(ErrorCode::MathOverflow)?;
+        set_maturity(user, maturity_time);
+        user.maturity_extended = maturity_extended;
+
+        emit!(MaturityExtendedEvent {
+            pool: pool.key(),
+            user: user.key(),
+            owner: user.owner,
+            additional_seconds,
+            maturity_time: user.maturity_time,
+            reason_code,
+        });
+
+        Ok(())
+    }
+
+    /// Checkpoints the user's pending rewards. Permissionless and moves no
+    /// tokens, so cranks may call it freely.
+    pub fn sync_user(ctx: Context<SyncUser>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+        update_rewards(pool, Some(&mut ctx.accounts.user), total_staked)?;
+
+        Ok(())
+    }
+
+    /// Creates the owner's spl-governance voter weight record for `realm`. The
+    /// realm must name this program as its voter weight addin.
+    pub fn create_voter_weight_record(
+        ctx: Context<CreateVoterWeightRecord>,
+        realm: Pubkey,
+    ) -> Result<()> {
+        let record = &mut ctx.accounts.voter_weight_record;
+        record.realm = realm;
+        record.governing_token_mint = ctx.accounts.pool.staking_mint;
+        record.governing_token_owner = ctx.accounts.owner.key();
+        record.voter_weight = 0;
+        record.voter_weight_expiry = Some(0);
+
+        Ok(())
+    }
+
+    /// Refreshes the voter weight from the user's staked balance, boosted by
+    /// tier if the pool enables it. The weight expires at the current slot,
+    /// so governance only accepts it within the same transaction.
+    /// Permissionless, as it only mirrors on-chain state.
+    pub fn update_voter_weight_record(ctx: Context<UpdateVoterWeightRecord>) -> Result<()> {
+        let user = &ctx.accounts.user;
+        let multiplier_bps = if ctx.accounts.pool.voter_weight_tier_boost {
+            TIER_VOTE_MULTIPLIER_BPS[usize::from(user.tier).min(TIER_VOTE_MULTIPLIER_BPS.len() - 1)]
+        } else {
+            BPS_DENOMINATOR
+        };
+        let voter_weight: u64 = (user.balance_staked as u128)
+            .checked_mul(multiplier_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+
+        let record = &mut ctx.accounts.voter_weight_record;
+        record.voter_weight = voter_weight;
+        record.voter_weight_expiry = Some(clock::Clock::get()?.slot);
+        record.weight_action = None;
+        record.weight_action_target = None;
+
+        Ok(())
+    }
+
+    /// Switches the pool to USD tiers valued with the Pyth `price_feed`, or
+    /// back to token tiers with the default key. Tiers only move to USD
+    /// thresholds as users next stake or unstake.
+    pub fn set_price_feed(
+        ctx: Context<SetPoolConfig>,
+        price_feed: Pubkey,
+        usd_tier_thresholds: [u64; 7],
+    ) -> Result<()> {
+        if price_feed != Pubkey::default()
+            && usd_tier_thresholds
+                .windows(2)
+                .any(|pair| pair[0] >= pair[1])
+        {
+            return Err(ErrorCode::InvalidTierThresholds.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        if price_feed != Pubkey::default() && pool.exchange_rate_oracle != Pubkey::default() {
+            return Err(ErrorCode::TierOracleConflict.into());
+        }
+        pool.price_feed = price_feed;
+        pool.usd_tier_thresholds = usd_tier_thresholds;
+
+        Ok(())
+    }
+
+    /// Values token tiers of an interest-bearing staking mint at its
+    /// underlying amount, read from `oracle` as `source`, or back on the raw
+    /// amount with the default key and `EXCHANGE_RATE_NONE`. Tiers only move
+    /// as users next stake or unstake.
+    pub fn set_exchange_rate_oracle(
+        ctx: Context<SetPoolConfig>,
+        oracle: Pubkey,
+        source: u8,
+    ) -> Result<()> {
+        let valid = match source {
+            EXCHANGE_RATE_NONE => oracle == Pubkey::default(),
+            EXCHANGE_RATE_PYTH | EXCHANGE_RATE_STAKE_POOL => oracle != Pubkey::default(),
+            _ => false,
+        };
+        if !valid {
+            return Err(ErrorCode::InvalidExchangeRateOracle.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        if oracle != Pubkey::default() && pool.price_feed != Pubkey::default() {
+            return Err(ErrorCode::TierOracleConflict.into());
+        }
+        pool.exchange_rate_oracle = oracle;
+        pool.exchange_rate_source = source;
+
+        Ok(())
+    }
+
+    pub fn set_voter_weight_tier_boost(ctx: Context<SetPoolConfig>, enabled: bool) -> Result<()> {
+        ctx.accounts.pool.voter_weight_tier_boost = enabled;
+
+        Ok(())
+    }
+
+    /// Checkpoints the pool's reward accounting. Permissionless and moves no
+    /// tokens, so keepers may call it as often as they like.
+    pub fn crank_update(ctx: Context<CrankUpdate>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+        update_rewards(pool, None, total_staked)?;
+        check_reward_funding(
+            pool,
+            &mut ctx.accounts.reward_vault,
+            clock::Clock::get()?.unix_timestamp,
+        )?;
+
+        emit!(PoolUpdatedEvent {
+            pool: pool.key(),
+            reward_per_token_stored: pool.reward_per_token_stored,
+            last_update_time: pool.last_update_time,
+        });
+
+        Ok(())
+    }
+
+    /// Lets `program_id` claim for the user via CPI, signing with its
+    /// `CLAIM_AUTHORITY_SEED` PDA. The default key revokes the approval.
+    pub fn approve_claim_program(ctx: Context<SetUserConfig>, program_id: Pubkey) -> Result<()> {
+        ctx.accounts.user.claim_program = program_id;
+
+        Ok(())
+    }
+
+    /// Lets keepers claim for the user once pending rewards exceed
+    /// `min_amount`, paying only to the owner's or beneficiary's associated
+    /// token account.
+    pub fn set_auto_claim(
+        ctx: Context<SetUserConfig>,
+        enabled: bool,
+        min_amount: u64,
+    ) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.auto_claim = enabled;
+        user.auto_claim_min = min_amount;
+
+        Ok(())
+    }
+
+    /// `claim` for opted-in users that anyone may run. The keeper receives
+    /// the pool's flat `auto_claim_fee` out of the claimed amount.
+    pub fn auto_claim(ctx: Context<AutoClaim>) -> Result<()> {
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+
+        let clock = clock::Clock::get()?;
+        if !is_owner_reward_account(
+            &ctx.accounts.user,
+            &ctx.accounts.reward_account.key(),
+            &pool.reward_mint,
+            clock.unix_timestamp,
+        ) {
+            return Err(ErrorCode::InvalidBeneficiaryAccount.into());
+        }
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
+
+        update_rewards(pool, Some(&mut ctx.accounts.user), total_staked)?;
+        let pending = ctx.accounts.user.reward_per_token_pending;
+        if pending <= ctx.accounts.user.auto_claim_min {
+            return Err(error_with_context(
+                ErrorCode::AutoClaimBelowMinimum,
+                format_args!(
+                    "pending {}, minimum {}",
+                    pending, ctx.accounts.user.auto_claim_min
+                ),
+            ));
+        }
+        let reward_amount = take_pending_rewards(
+            pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.reward_vault.amount,
+        )?;
+        let fee = std::cmp::min(pool.auto_claim_fee, reward_amount);
+        let paid = reward_amount - fee;
+
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        record_action(
+            &mut ctx.accounts.user,
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
+        if paid > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.reward_account.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                paid,
+            )?;
+        }
+        if fee > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.tip_account.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                fee,
+            )?;
+        }
+        if reward_amount > 0 {
+            ctx.accounts.user.last_claim_time = clock.unix_timestamp;
+        }
+        check_reward_funding(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.reward_vault,
+            clock.unix_timestamp,
+        )?;
+
+        emit!(ClaimEvent {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.user.owner,
+            amount: reward_amount,
+            total_rewards_claimed: ctx.accounts.user.total_rewards_claimed,
+            last_claim_time: ctx.accounts.user.last_claim_time,
+            client_ref: 0,
+        });
+
+        Ok(())
+    }
+
+    /// `claim` for approved claim programs. Rewards only go to the owner's or
+    /// beneficiary's associated token account, and vesting users are not
+    /// supported.
+    pub fn claim_via_cpi(ctx: Context<ClaimViaCpi>) -> Result<()> {
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+
+        let clock = clock::Clock::get()?;
+        if !is_owner_reward_account(
+            &ctx.accounts.user,
+            &ctx.accounts.reward_account.key(),
+            &pool.reward_mint,
+            clock.unix_timestamp,
+        ) {
+            return Err(ErrorCode::InvalidBeneficiaryAccount.into());
+        }
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
+
+        update_rewards(pool, Some(&mut ctx.accounts.user), total_staked)?;
+        let reward_amount = take_pending_rewards(
+            pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.reward_vault.amount,
+        )?;
+
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        record_action(
+            &mut ctx.accounts.user,
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
+        if reward_amount > 0 {
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.reward_account.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                reward_amount,
+            )?;
+            ctx.accounts.user.last_claim_time = clock.unix_timestamp;
+        }
+        check_reward_funding(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.reward_vault,
+            clock.unix_timestamp,
+        )?;
+
+        emit!(ClaimEvent {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.owner.key(),
+            amount: reward_amount,
+            total_rewards_claimed: ctx.accounts.user.total_rewards_claimed,
+            last_claim_time: ctx.accounts.user.last_claim_time,
+            client_ref: 0,
+        });
+
+        Ok(())
+    }
+
+    /// Claims into a transient swap account and swaps the rewards through
+    /// Jupiter, delivering at least `min_out` of the output mint to the
+    /// recipient's associated token account. `route_data` and the remaining
+    /// accounts form a Jupiter `route` with `swap_authority` as transfer
+    /// authority and `swap_account` as source. A failed swap reverts the claim
+    /// with it. Needs a build with the `jupiter-swap` feature.
+    pub fn claim_and_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimAndSwap<'info>>,
+        min_out: u64,
+        route_data: Vec<u8>,
+    ) -> Result<()> {
+        if !cfg!(feature = "jupiter-swap") {
+            return Err(ErrorCode::SwapNotEnabled.into());
+        }
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+
+        let clock = clock::Clock::get()?;
+        let user = &ctx.accounts.user;
+        let recipient = match user.beneficiary_at(clock.unix_timestamp) {
+            beneficiary if beneficiary == Pubkey::default() => user.owner,
+            beneficiary => beneficiary,
+        };
+        let destination = &ctx.accounts.destination;
+        if destination.key() != get_associated_token_address(&recipient, &destination.mint) {
+            return Err(ErrorCode::InvalidBeneficiaryAccount.into());
+        }
+        let swap_authority = ctx.accounts.swap_authority.key();
+        if !is_jupiter_route(
+            ctx.remaining_accounts,
+            &route_data,
+            &swap_authority,
+            &ctx.accounts.swap_account.key(),
+        ) {
+            return Err(ErrorCode::InvalidSwapRoute.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        let total_staked = pool.total_staked;
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
+        update_rewards(pool, Some(&mut ctx.accounts.user), total_staked)?;
+        let reward_amount = take_pending_rewards(
+            pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.reward_vault.amount,
+        )?;
+        if reward_amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+        }
+
+        let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+        let pool_signer = &[&seeds[..]];
+
+        record_action(
+            &mut ctx.accounts.user,
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
+        transfer_checked(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.reward_mint,
+            ctx.accounts.swap_account.to_account_info(),
+            ctx.accounts.pool_signer.to_account_info(),
+            pool_signer,
+            reward_amount,
+        )?;
+        ctx.accounts.user.last_claim_time = clock.unix_timestamp;
+
+        let user_key = ctx.accounts.user.key();
+        let bump = *ctx.bumps.get("swap_authority").unwrap();
+        let seeds = &[SWAP_AUTHORITY_SEED, user_key.as_ref(), &[bump]];
+        let swap_signer = &[&seeds[..]];
+
+        let balance_before = ctx.accounts.destination.amount;
+        invoke_jupiter_route(
+            &ctx.accounts.jupiter_program,
+            ctx.remaining_accounts,
+            route_data,
+            &swap_authority,
+            swap_signer,
+        )?;
+        ctx.accounts.swap_account.reload()?;
+        if ctx.accounts.swap_account.amount != 0 {
+            return Err(error_with_context(
+                ErrorCode::SwapIncomplete,
+                format_args!(
+                    "claimed {}, unspent {}",
+                    reward_amount, ctx.accounts.swap_account.amount
+                ),
+            ));
+        }
+        ctx.accounts.destination.reload()?;
+        let amount_out = ctx
+            .accounts
+            .destination
+            .amount
+            .saturating_sub(balance_before);
+        if amount_out < min_out {
+            return Err(error_with_context(
+                ErrorCode::SwapSlippageExceeded,
+                format_args!("output {}, minimum {}", amount_out, min_out),
+            ));
+        }
+
+        // Hand the swap account's rent back to the owner.
+        let ix = spl_token::instruction::close_account(
+            &spl_token::ID,
+            ctx.accounts.swap_account.to_account_info().key,
+            ctx.accounts.owner.key,
+            &swap_authority,
+            &[&swap_authority],
+        )?;
+        solana_program::program::invoke_signed(
+            &ix,
+            &[
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.swap_account.to_account_info(),
+                ctx.accounts.owner.to_account_info(),
+                ctx.accounts.swap_authority.to_account_info(),
+            ],
+            swap_signer,
+        )?;
+        check_reward_funding(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.reward_vault,
+            clock.unix_timestamp,
+        )?;
+
+        emit!(ClaimEvent {
+            pool: ctx.accounts.pool.key(),
+            user: user_key,
+            owner: ctx.accounts.owner.key(),
+            amount: reward_amount,
+            total_rewards_claimed: ctx.accounts.user.total_rewards_claimed,
+            last_claim_time: ctx.accounts.user.last_claim_time,
+            client_ref: 0,
+        });
+        emit!(ClaimSwappedEvent {
+            pool: ctx.accounts.pool.key(),
+            user: user_key,
+            amount_in: reward_amount,
+            output_mint: ctx.accounts.destination.mint,
+            amount_out,
+        });
+
+        Ok(())
+    }
+
+    /// Checks the pool's accounting invariants. Anyone may call it; a failed
+    /// check halts the pool instead of returning an error so the latch sticks.
+    /// It also tracks whether the staking vault is frozen: instructions touching
+    /// a frozen vault fail with `VaultFrozen`, which can't record anything.
+    pub fn assert_invariants(ctx: Context<AssertInvariants>) -> Result<()> {
+        let now = unix_timestamp_to_u64(clock::Clock::get()?.unix_timestamp);
+        let pool = &mut ctx.accounts.pool;
+        // The staking vault may belong to Token-2022, so it isn't typed.
+        let staking_vault = load_token_account(&ctx.accounts.staking_vault)?;
+        let vault_frozen = staking_vault.is_frozen();
+        if vault_frozen && !pool.vault_frozen {
+            msg!("Staking vault is frozen");
+            emit!(VaultFrozenEvent {
+                pool: pool.key(),
+                vault: pool.staking_vault,
+            });
+        }
+        pool.vault_frozen = vault_frozen;
+        if let Some(reason) = invariant_violation(pool, staking_vault.amount, now) {
+            msg!("Invariant violated: {}", reason);
+            pool.halted = true;
+        }
+
+        Ok(())
+    }
+
+    /// Lifts an invariant halt once the authority has investigated.
+    pub fn clear_halt(ctx: Context<ClearHalt>) -> Result<()> {
+        ctx.accounts.pool.halted = false;
+        Ok(())
+    }
+
+    /// Reconciles tokens sent straight to the staking vault. The surplus over
+    /// `total_staked` goes to the recovery token account in remaining[0], or,
+    /// with `credit_user`, is staked for the user in remaining[0] whose owner
+    /// signs as remaining[1].
+    pub fn sync_total_staked<'info>(
+        ctx: Context<'_, '_, '_, 'info, SyncTotalStaked<'info>>,
+        credit_user: bool,
+    ) -> Result<()> {
+        let surplus = load_token_account(&ctx.accounts.staking_vault)?
+            .amount
+            .checked_sub(ctx.accounts.pool.total_staked)
+            .ok_or(ErrorCode::AccountingMismatch)?;
+        if surplus == 0 {
+            msg!("Staking vault has no surplus");
+            return Ok(());
+        }
+
+        let destination = ctx
+            .remaining_accounts
+            .first()
+            .ok_or(ErrorCode::InvalidSyncTarget)?;
+        if credit_user {
+            let owner = ctx
+                .remaining_accounts
+                .get(1)
+                .ok_or(ErrorCode::InvalidSyncTarget)?;
+            let mut user: Box<Account<User>> = Box::new(Account::try_from(destination)?);
+            if user.pool != ctx.accounts.pool.key() || user.owner != owner.key() || !owner.is_signer
+            {
+                return Err(ErrorCode::InvalidSyncTarget.into());
+            }
+            if user.version != USER_VERSION {
+                return Err(ErrorCode::AccountNeedsMigration.into());
+            }
+
+            let clock = clock::Clock::get()?;
+            apply_stake(&mut ctx.accounts.pool, &mut user, surplus, &clock, 0)?;
+            user.exit(ctx.program_id)?;
+        } else {
+            let recovery_account: Account<TokenAccount> = Account::try_from(destination)?;
+            if recovery_account.mint != ctx.accounts.pool.staking_mint {
+                return Err(ErrorCode::InvalidSyncTarget.into());
+            }
+
+            let pool = &ctx.accounts.pool;
+            let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
+            let pool_signer = &[&seeds[..]];
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.staking_vault.to_account_info(),
+                &ctx.accounts.staking_mint,
+                destination.clone(),
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                surplus,
+            )?;
+        }
+
+        let total_staked = ctx.accounts.pool.total_staked;
+        check_interface_vault_balance(&ctx.accounts.staking_vault, total_staked)?;
+
+        emit!(SurplusSyncedEvent {
+            pool: ctx.accounts.pool.key(),
+            amount: surplus,
+            destination: destination.key(),
+            credited_user: credit_user,
+        });
+
+        Ok(())
+    }
+
+    pub fn authorize_funder(ctx: Context<FunderChange>, funder_to_add: Pubkey) -> Result<()> {
+        if funder_to_add == ctx.accounts.pool.authority {
+            return Err(ErrorCode::FunderAlreadyAuthorized.into());
+        }
+        let funders = &mut ctx.accounts.pool.funders;
+        if funders.iter().any(|x| *x == funder_to_add) {
+            return Err(ErrorCode::FunderAlreadyAuthorized.into());
+        }
+        let default_pubkey = Pubkey::default();
+        if let Some(idx) = funders.iter().position(|x| *x == default_pubkey) {
+            funders[idx] = funder_to_add;
+        } else {
+            return Err(ErrorCode::MaxFunders.into());
+        }
+        Ok(())
+    }
+
+    pub fn deauthorize_funder(ctx: Context<FunderChange>, funder_to_remove: Pubkey) -> Result<()> {
+        if funder_to_remove == ctx.accounts.pool.authority {
+            return Err(ErrorCode::CannotDeauthorizePoolAuthority.into());
+        }
+        let pool = &mut ctx.accounts.pool;
+        if let Some(idx) = pool.funders.iter().position(|x| *x == funder_to_remove) {
+            remove_funder(pool, idx)?;
+        } else {
+            return Err(ErrorCode::CannotDeauthorizeMissingAuthority.into());
+        }
+        Ok(())
+    }
+
+    /// Starts winding the pool down. New users and stakes are rejected while
+    /// unstakes and claims keep working; `close_pool` requires this state.
+    pub fn begin_close(ctx: Context<SetClosing>) -> Result<()> {
+        if ctx.accounts.pool.closing {
+            return Err(ErrorCode::PoolClosing.into());
+        }
+        ctx.accounts.pool.closing = true;
+        Ok(())
+    }
+
+    /// Reopens a closing pool.
+    pub fn cancel_close(ctx: Context<SetClosing>) -> Result<()> {
+        if !ctx.accounts.pool.closing {
+            return Err(ErrorCode::PoolNotClosing.into());
+        }
+        ctx.accounts.pool.closing = false;
+        Ok(())
+    }
+
+    /// Lets an authorized funder remove themselves from `pool.funders`.
+    pub fn renounce_funder(ctx: Context<RenounceFunder>) -> Result<()> {
+        let funder = ctx.accounts.funder.key();
+        let pool = &mut ctx.accounts.pool;
+        if let Some(idx) = pool.funders.iter().position(|x| *x == funder) {
+            remove_funder(pool, idx)?;
+        } else {
+            return Err(ErrorCode::CannotDeauthorizeMissingAuthority.into());
+        }
+
+        emit!(FunderRenouncedEvent {
+            pool: ctx.accounts.pool.key(),
+            funder,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_topup_grace(
+        ctx: Context<SetPoolConfig>,
+        grace_seconds: u64,
+        grace_bps: u16,
+    ) -> Result<()> {
+        if grace_bps as u64 > BPS_DENOMINATOR {
+            return Err(ErrorCode::InvalidBasisPoints.into());
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        pool.topup_grace_seconds = grace_seconds;
+        pool.topup_grace_bps = grace_bps;
+
+        Ok(())
+    }
+
+    pub fn set_unstake_delay(
+        ctx: Context<SetPoolConfig>,
+        min_unstake_delay_slots: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.min_unstake_delay_slots = min_unstake_delay_slots;
+
+        Ok(())
+    }
+
+    pub fn set_deposit_limit(ctx: Context<SetPoolConfig>, max_per_window: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.max_per_window = max_per_window;
 
-    pub fn initialize_pool(
-        ctx: Context<InitializePool>,
-        pool_nonce: u8,
-        reward_duration: u64,
-        lock_period: u64,
-        no_tier: bool,
+        Ok(())
+    }
+
+    pub fn set_require_active_rewards(
+        ctx: Context<SetPoolConfig>,
+        require_active_rewards: bool,
     ) -> Result<()> {
-        if reward_duration < MIN_DURATION {
-            return Err(ErrorCode::DurationTooShort.into());
+        let pool = &mut ctx.accounts.pool;
+        pool.require_active_rewards = require_active_rewards;
+
+        Ok(())
+    }
+
+    /// Stops every user-facing instruction for `duration_seconds`, capped at
+    /// `MAX_HALT_SECONDS`. The halt lapses on its own; zero lifts it early.
+    pub fn halt(ctx: Context<SetPoolConfig>, duration_seconds: u64) -> Result<()> {
+        if duration_seconds > MAX_HALT_SECONDS {
+            return Err(ErrorCode::HaltTooLong.into());
         }
 
+        let now = clock::Clock::get()?.unix_timestamp;
         let pool = &mut ctx.accounts.pool;
+        pool.halted_until = now
+            .checked_add(duration_seconds as i64)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-        pool.authority = ctx.accounts.authority.key();
-        pool.nonce = pool_nonce;
-        pool.paused = false;
-        pool.staking_mint = ctx.accounts.staking_mint.key();
-        pool.staking_vault = ctx.accounts.staking_vault.key();
-        pool.reward_mint = ctx.accounts.reward_mint.key();
-        pool.reward_vault = ctx.accounts.reward_vault.key();
-        pool.reward_duration = reward_duration;
-        pool.reward_duration_end = 0;
-        pool.lock_period = lock_period;
-        pool.last_update_time = 0;
-        pool.reward_rate = 0;
-        pool.reward_per_token_stored = 0;
-        pool.user_stake_count = 0;
-        pool.total_staked = 0;
-        pool.no_tier = no_tier;
+        emit!(HaltEvent {
+            pool: pool.key(),
+            halted_until: pool.halted_until,
+        });
 
         Ok(())
     }
 
-    pub fn create_user(ctx: Context<CreateUser>) -> Result<()> {
-        let user = &mut ctx.accounts.user;
-        user.pool = *ctx.accounts.pool.to_account_info().key;
-        user.owner = *ctx.accounts.owner.key;
-        user.reward_per_token_complete = 0;
-        user.reward_per_token_pending = 0;
-        user.balance_staked = 0;
-        user.maturity_time = 0;
-        user.tier = 0;
-        user.nonce = *ctx.bumps.get("user").unwrap();
-
+    /// Overwrites a drifted `user_stake_count`, logging the correction.
+    pub fn repair_stake_count(ctx: Context<SetPoolConfig>, user_stake_count: u32) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
-        pool.user_stake_count = pool.user_stake_count.checked_add(1).unwrap();
+        msg!(
+            "Repairing user_stake_count from {} to {}",
+            pool.user_stake_count,
+            user_stake_count
+        );
+        emit!(StakeCountRepairedEvent {
+            pool: pool.key(),
+            previous: pool.user_stake_count,
+            user_stake_count,
+        });
+        pool.user_stake_count = user_stake_count;
 
         Ok(())
     }
 
-    pub fn pause(ctx: Context<Pause>) -> Result<()> {
+    pub fn set_min_fund_amount(ctx: Context<SetPoolConfig>, min_fund_amount: u64) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
-        pool.paused = true;
+        pool.min_fund_amount = min_fund_amount;
 
         Ok(())
     }
 
-    pub fn unpause(ctx: Context<Unpause>) -> Result<()> {
+    pub fn set_dust_threshold(ctx: Context<SetPoolConfig>, dust_threshold: u64) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
-        pool.paused = false;
+        pool.dust_threshold = dust_threshold;
+
         Ok(())
     }
 
-    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
-        if amount == 0 {
-            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+    pub fn set_compound_tip(ctx: Context<SetPoolConfig>, compound_tip_bps: u16) -> Result<()> {
+        if compound_tip_bps as u64 > BPS_DENOMINATOR {
+            return Err(ErrorCode::InvalidBasisPoints.into());
         }
 
         let pool = &mut ctx.accounts.pool;
-        if pool.paused {
-            return Err(ErrorCode::PoolPaused.into());
-        }
+        pool.compound_tip_bps = compound_tip_bps;
 
-        let total_staked = pool.total_staked;
+        Ok(())
+    }
 
-        let user_opt = Some(&mut ctx.accounts.user);
-        update_rewards(pool, user_opt, total_staked).unwrap();
-        let clock = clock::Clock::get().unwrap();
-        ctx.accounts.user.balance_staked = ctx
-            .accounts
-            .user
-            .balance_staked
-            .checked_add(amount)
-            .unwrap();
-        ctx.accounts.user.maturity_time = u64::try_from(clock.unix_timestamp)
-            .unwrap()
-            .checked_add(pool.lock_period)
-            .unwrap();
+    /// Flat reward amount keepers take from each `auto_claim`, capped at the
+    /// claimed amount.
+    pub fn set_auto_claim_fee(ctx: Context<SetPoolConfig>, auto_claim_fee: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.auto_claim_fee = auto_claim_fee;
 
-        if pool.no_tier == false {
-            ctx.accounts.user.tier = get_tier(ctx.accounts.user.balance_staked);
-        }
+        Ok(())
+    }
 
-        // Transfer tokens into the stake vault.
-        {
-            let cpi_ctx = CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.stake_from_account.to_account_info(),
-                    to: ctx.accounts.staking_vault.to_account_info(),
-                    authority: ctx.accounts.owner.to_account_info(),
-                },
-            );
-            token::transfer(cpi_ctx, amount)?;
-        }
+    pub fn set_attestation_policy(
+        ctx: Context<SetPoolConfig>,
+        attestor: Pubkey,
+        require_attestation: bool,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.attestor = attestor;
+        pool.require_attestation = require_attestation;
 
-        pool.total_staked += amount;
+        Ok(())
+    }
+
+    pub fn set_penalty_waiver_collection(
+        ctx: Context<SetPoolConfig>,
+        collection: Pubkey,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.penalty_waiver_collection = collection;
 
         Ok(())
     }
 
-    pub fn unstake(ctx: Context<Stake>, spt_amount: u64) -> Result<()> {
-        if spt_amount == 0 {
-            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
+    pub fn set_unstake_penalty(
+        ctx: Context<SetUnstakePenalty>,
+        max_penalty_bps: u16,
+    ) -> Result<()> {
+        if max_penalty_bps as u64 > BPS_DENOMINATOR {
+            return Err(ErrorCode::InvalidBasisPoints.into());
         }
 
-        let clock = clock::Clock::get().unwrap();
-        if ctx.accounts.user.maturity_time > u64::try_from(clock.unix_timestamp).unwrap() {
-            return Err(ErrorCode::CannotStakeOrClaimBeforeMaturity.into());
-        }
+        let pool = &mut ctx.accounts.pool;
+        pool.max_penalty_bps = max_penalty_bps;
+        pool.penalty_vault = ctx.accounts.penalty_vault.key();
+
+        Ok(())
+    }
 
-        if ctx.accounts.user.balance_staked < spt_amount {
-            return Err(ErrorCode::InsufficientFundUnstake.into());
+    /// Sets the insurance vault and the share of penalties routed into it.
+    /// Penalties are paid in the staking mint while insurance covers rewards,
+    /// so a non-zero share requires both mints to match.
+    pub fn set_insurance(ctx: Context<SetInsurance>, insurance_share_bps: u16) -> Result<()> {
+        if insurance_share_bps as u64 > BPS_DENOMINATOR {
+            return Err(ErrorCode::InvalidBasisPoints.into());
         }
 
         let pool = &mut ctx.accounts.pool;
-        let total_staked = pool.total_staked;
+        if insurance_share_bps > 0 && pool.staking_mint != pool.reward_mint {
+            return Err(ErrorCode::InsuranceMintMismatch.into());
+        }
 
-        let user_opt = Some(&mut ctx.accounts.user);
-        update_rewards(pool, user_opt, total_staked).unwrap();
-        ctx.accounts.user.balance_staked = ctx
-            .accounts
-            .user
-            .balance_staked
-            .checked_sub(spt_amount)
-            .unwrap();
+        pool.insurance_vault = ctx.accounts.insurance_vault.key();
+        pool.insurance_share_bps = insurance_share_bps;
 
-        if pool.no_tier == false {
-            ctx.accounts.user.tier = get_tier(ctx.accounts.user.balance_staked);
+        Ok(())
+    }
+
+    /// Moves insurance funds into the reward vault. The insurance vault is
+    /// owned by the pool signer and this is its only outflow, so insurance can
+    /// never leave the pool while users have rewards pending.
+    pub fn cover_shortfall(ctx: Context<CoverShortfall>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::AmountMustBeGreaterThanZero.into());
         }
 
-        pool.total_staked -= spt_amount;
+        if ctx.accounts.insurance_vault.amount < amount {
+            return Err(ErrorCode::InsufficientInsurance.into());
+        }
 
-        // Transfer tokens from the pool vault to user vault.
+        let pool = &mut ctx.accounts.pool;
+        pool.insurance_used = pool
+            .insurance_used
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Transfer insurance funds into the reward vault.
         {
             let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
             let pool_signer = &[&seeds[..]];
 
-            let cpi_ctx = CpiContext::new_with_signer(
+            transfer_checked(
                 ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.staking_vault.to_account_info(),
-                    to: ctx.accounts.stake_from_account.to_account_info(),
-                    authority: ctx.accounts.pool_signer.to_account_info(),
-                },
+                ctx.accounts.insurance_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.reward_vault.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
                 pool_signer,
-            );
-            token::transfer(cpi_ctx, spt_amount.try_into().unwrap())?;
-        }
-
-        Ok(())
-    }
-
-    pub fn authorize_funder(ctx: Context<FunderChange>, funder_to_add: Pubkey) -> Result<()> {
-        if funder_to_add == ctx.accounts.pool.authority {
-            return Err(ErrorCode::FunderAlreadyAuthorized.into());
-        }
-        let funders = &mut ctx.accounts.pool.funders;
-        if funders.iter().any(|x| *x == funder_to_add) {
-            return Err(ErrorCode::FunderAlreadyAuthorized.into());
-        }
-        let default_pubkey = Pubkey::default();
-        if let Some(idx) = funders.iter().position(|x| *x == default_pubkey) {
-            funders[idx] = funder_to_add;
-        } else {
-            return Err(ErrorCode::MaxFunders.into());
+                amount,
+            )?;
         }
-        Ok(())
-    }
 
-    pub fn deauthorize_funder(ctx: Context<FunderChange>, funder_to_remove: Pubkey) -> Result<()> {
-        if funder_to_remove == ctx.accounts.pool.authority {
-            return Err(ErrorCode::CannotDeauthorizePoolAuthority.into());
-        }
-        let funders = &mut ctx.accounts.pool.funders;
-        if let Some(idx) = funders.iter().position(|x| *x == funder_to_remove) {
-            funders[idx] = Pubkey::default();
-        } else {
-            return Err(ErrorCode::CannotDeauthorizeMissingAuthority.into());
-        }
         Ok(())
     }
 
-    pub fn fund(ctx: Context<Fund>, amount: u64) -> Result<()> {
+    /// Funds the next reward period. A `funder` that is an SPL Token multisig
+    /// signs through enough of its signers passed as remaining accounts.
+    pub fn fund<'info>(ctx: Context<'_, '_, '_, 'info, Fund<'info>>, amount: u64) -> Result<()> {
+        let multisig_signers = multisig_signers(&ctx.accounts.funder, ctx.remaining_accounts)
+            .ok_or(ErrorCode::FunderNotSigned)?;
         let pool = &mut ctx.accounts.pool;
         let total_staked = pool.total_staked;
 
-        update_rewards(pool, None, total_staked).unwrap();
+        update_rewards(pool, None, total_staked)?;
 
-        let current_time = clock::Clock::get()
-            .unwrap()
-            .unix_timestamp
-            .try_into()
-            .unwrap();
+        // Dust forgiven on user close is redistributed with this funding.
+        let distributed = amount
+            .checked_add(pool.forgiven_dust)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pool.forgiven_dust = 0;
+
+        let current_time = unix_timestamp_to_u64(clock::Clock::get()?.unix_timestamp);
         let reward_period_end = pool.reward_duration_end;
+        let previous_rate = if current_time >= reward_period_end {
+            0
+        } else {
+            pool.reward_rate
+        };
 
         if current_time >= reward_period_end {
-            pool.reward_rate = amount.checked_div(pool.reward_duration).unwrap();
+            pool.reward_rate = distributed
+                .checked_div(pool.reward_duration)
+                .ok_or(ErrorCode::MathOverflow)?;
         } else {
-            let remaining = pool.reward_duration_end.checked_sub(current_time).unwrap();
-            let leftover = remaining.checked_mul(pool.reward_rate).unwrap();
+            let remaining = pool
+                .reward_duration_end
+                .checked_sub(current_time)
+                .ok_or(ErrorCode::ArithmeticUnderflow)?;
+            let leftover = remaining
+                .checked_mul(pool.reward_rate)
+                .ok_or(ErrorCode::MathOverflow)?;
 
-            pool.reward_rate = amount
+            pool.reward_rate = distributed
                 .checked_add(leftover)
-                .unwrap()
+                .ok_or(ErrorCode::MathOverflow)?
                 .checked_div(pool.reward_duration)
-                .unwrap();
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        // Other funders can't stretch a running period at a lower rate unless
+        // they bring at least the pool's minimum funding.
+        if ctx.accounts.funder.key() != pool.authority
+            && pool.reward_rate < previous_rate
+            && (pool.min_fund_amount == 0 || amount < pool.min_fund_amount)
+        {
+            return Err(error_with_context(
+                ErrorCode::FundingWouldDiluteRate,
+                format_args!(
+                    "rate {} below {}, minimum funding {}",
+                    pool.reward_rate, previous_rate, pool.min_fund_amount
+                ),
+            ));
         }
 
         // Transfer reward A tokens into the A vault.
         if amount > 0 {
-            let cpi_ctx = CpiContext::new(
+            transfer_checked_multisig(
                 ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.from.to_account_info(),
-                    to: ctx.accounts.reward_vault.to_account_info(),
-                    authority: ctx.accounts.funder.to_account_info(),
-                },
-            );
-
-            token::transfer(cpi_ctx, amount)?;
+                ctx.accounts.from.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.reward_vault.to_account_info(),
+                ctx.accounts.funder.to_account_info(),
+                &multisig_signers,
+                amount,
+            )?;
         }
 
+        let slot =
+            funder_slot(pool, ctx.accounts.funder.key()).ok_or(ErrorCode::InvalidFundingSource)?;
+        pool.funder_contributions[slot] = pool.funder_contributions[slot]
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
         pool.last_update_time = current_time;
-        pool.reward_duration_end = current_time.checked_add(pool.reward_duration).unwrap();
+        pool.reward_duration_end = current_time
+            .checked_add(pool.reward_duration)
+            .ok_or(ErrorCode::MathOverflow)?;
+        check_reward_funding(pool, &mut ctx.accounts.reward_vault, current_time as i64)?;
+
+        emit!(FundEvent {
+            pool: pool.key(),
+            funder: ctx.accounts.funder.key(),
+            amount,
+            reward_rate: pool.reward_rate,
+            reward_duration_end: pool.reward_duration_end,
+            funder_contribution: pool.funder_contributions[slot],
+            funded_at: current_time as i64,
+        });
 
         Ok(())
     }
 
-    pub fn claim(ctx: Context<ClaimReward>) -> Result<()> {
+    /// Pays pending rewards to `reward_account`, or for vesting users into
+    /// their vesting escrow passed as the first remaining account. `client_ref`
+    /// is only echoed in `ClaimEvent`; pass 0 when unused. A non-empty `memo`
+    /// is attached to the payout through the SPL Memo program.
+    pub fn claim<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimReward<'info>>,
+        day_index: u64,
+        client_ref: u64,
+        memo: String,
+    ) -> Result<()> {
+        // The staking vault may belong to Token-2022, so it isn't typed.
+        check_vault_not_frozen(&ctx.accounts.staking_vault)?;
+        let clock = clock::Clock::get()?;
+        check_day_index(day_index, clock.unix_timestamp)?;
+        load_reward_account(&ctx.accounts, clock.unix_timestamp)?;
+
         let pool = &mut ctx.accounts.pool;
         let total_staked = pool.total_staked;
 
-        let clock = clock::Clock::get().unwrap();
-        if ctx.accounts.user.maturity_time > u64::try_from(clock.unix_timestamp).unwrap() {
-            return Err(ErrorCode::CannotStakeOrClaimBeforeMaturity.into());
-        }
+        check_matured(
+            ctx.accounts.user.maturity_time,
+            unix_timestamp_to_u64(clock.unix_timestamp),
+        )?;
 
         let user_opt = Some(&mut ctx.accounts.user);
-        update_rewards(pool, user_opt, total_staked).unwrap();
+        update_rewards(pool, user_opt, total_staked)?;
+        let reward_amount = take_pending_rewards(
+            pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.reward_vault.amount,
+        )?;
 
         let seeds = &[pool.to_account_info().key.as_ref(), &[pool.nonce]];
         let pool_signer = &[&seeds[..]];
 
-        if ctx.accounts.user.reward_per_token_pending > 0 {
-            let mut reward_amount = ctx.accounts.user.reward_per_token_pending;
-            let vault_balance = ctx.accounts.reward_vault.amount;
-
-            ctx.accounts.user.reward_per_token_pending = 0;
-            if vault_balance < reward_amount {
-                reward_amount = vault_balance;
-            }
+        record_action(
+            &mut ctx.accounts.user,
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
+        ctx.accounts.daily_stats.record(
+            pool.key(),
+            ACTION_CLAIM,
+            reward_amount,
+            clock.unix_timestamp,
+        );
 
-            if reward_amount > 0 {
-                let cpi_ctx = CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    token::Transfer {
-                        from: ctx.accounts.reward_vault.to_account_info(),
-                        to: ctx.accounts.reward_account.to_account_info(),
-                        authority: ctx.accounts.pool_signer.to_account_info(),
-                    },
-                    pool_signer,
-                );
-                token::transfer(cpi_ctx, reward_amount)?;
+        let destination = if ctx.accounts.user.vesting_cliff != 0 {
+            let user = &mut ctx.accounts.user;
+            let escrow = ctx
+                .remaining_accounts
+                .first()
+                .ok_or(ErrorCode::InvalidVestingEscrow)?;
+            let escrow_address = Pubkey::create_program_address(
+                &[VESTING_SEED, user.key().as_ref(), &[user.vesting_nonce]],
+                ctx.program_id,
+            )
+            .map_err(|_| ErrorCode::InvalidVestingEscrow)?;
+            if escrow.key() != escrow_address {
+                return Err(ErrorCode::InvalidVestingEscrow.into());
             }
+            user.vesting_total = user
+                .vesting_total
+                .checked_add(reward_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            escrow.clone()
+        } else {
+            ctx.accounts.reward_account.to_account_info()
+        };
+        if reward_amount > 0 {
+            write_memo(&ctx.accounts.memo_program, &memo)?;
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                destination,
+                ctx.accounts.pool_signer.to_account_info(),
+                pool_signer,
+                reward_amount,
+            )?;
+            ctx.accounts.user.last_claim_time = clock.unix_timestamp;
         }
+        check_reward_funding(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.reward_vault,
+            clock.unix_timestamp,
+        )?;
+
+        let event = ClaimEvent {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.owner.key(),
+            amount: reward_amount,
+            total_rewards_claimed: ctx.accounts.user.total_rewards_claimed,
+            last_claim_time: ctx.accounts.user.last_claim_time,
+            client_ref,
+        };
+        emit!(event);
+        emit_cpi(
+            &event,
+            &ctx.accounts.event_authority,
+            &ctx.accounts.program,
+            *ctx.bumps.get("event_authority").unwrap(),
+        )?;
+
         Ok(())
     }
 
-    pub fn close_user(ctx: Context<CloseUser>) -> Result<()> {
+    /// An optional first remaining account redirects the rent refund. The
+    /// owner may redirect rent they paid themselves; a sponsor's rent only
+    /// moves with the sponsor's signature.
+    pub fn close_user<'info>(ctx: Context<'_, '_, '_, 'info, CloseUser<'info>>) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
-        pool.user_stake_count = pool.user_stake_count.checked_sub(1).unwrap();
+        let total_staked = pool.total_staked;
+        update_rewards(pool, Some(&mut ctx.accounts.user), total_staked)?;
+
+        // Rounding dust isn't worth a claim; hand it back to the pool instead.
+        let pending = ctx.accounts.user.reward_per_token_pending;
+        if pending > pool.dust_threshold {
+            return Err(error_with_context(
+                ErrorCode::PendingRewardsAboveDust,
+                format_args!(
+                    "pending {}, dust threshold {}",
+                    pending, pool.dust_threshold
+                ),
+            ));
+        }
+        pool.forgiven_dust = pool
+            .forgiven_dust
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pool.aggregate_pending = pool
+            .aggregate_pending
+            .checked_sub(pending)
+            .ok_or(ErrorCode::ArithmeticUnderflow)?;
+        ctx.accounts.user.reward_per_token_pending = 0;
+
+        pool.user_stake_count = pool
+            .user_stake_count
+            .checked_sub(1)
+            .ok_or(ErrorCode::StakeCountUnderflow)?;
+
+        if let Some(recipient) = ctx.remaining_accounts.first() {
+            let rent_payer = &ctx.accounts.rent_payer;
+            let authorized = recipient.key() == rent_payer.key()
+                || rent_payer.key() == ctx.accounts.owner.key()
+                || rent_payer.is_signer;
+            if !authorized || !recipient.is_writable {
+                return Err(ErrorCode::UnauthorizedRentRecipient.into());
+            }
+
+            // Anchor closes the now empty account into `rent_payer` on exit.
+            let user_info = ctx.accounts.user.to_account_info();
+            let lamports = recipient
+                .lamports()
+                .checked_add(user_info.lamports())
+                .ok_or(ErrorCode::MathOverflow)?;
+            **recipient.try_borrow_mut_lamports()? = lamports;
+            **user_info.try_borrow_mut_lamports()? = 0;
+        }
+
+        emit!(UserClosedEvent {
+            pool: pool.key(),
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.owner.key(),
+            forgiven_dust: pending,
+            user_stake_count: pool.user_stake_count,
+            closed_at: clock::Clock::get()?.unix_timestamp,
+        });
+
         Ok(())
     }
 
     pub fn close_pool<'info>(ctx: Context<ClosePool>) -> Result<()> {
         let pool = &ctx.accounts.pool;
+        let now = unix_timestamp_to_u64(clock::Clock::get()?.unix_timestamp);
+        if !pool.paused {
+            return Err(ErrorCode::PoolNotPaused.into());
+        }
+        // Never funded pools (reward_duration_end == 0) can be closed too.
+        if now <= pool.reward_duration_end {
+            return Err(ErrorCode::RewardPeriodStillActive.into());
+        }
+        if pool.user_stake_count != 0 || pool.total_staked != 0 {
+            return Err(ErrorCode::UsersStillStaked.into());
+        }
+
+        // Unclaimed rewards keep the pool open until the sunset grace period
+        // after the last reward period has passed.
+        if pool.aggregate_pending > 0 {
+            let sunset = pool
+                .reward_duration_end
+                .checked_add(POOL_SUNSET_GRACE_SECONDS)
+                .ok_or(ErrorCode::MathOverflow)?;
+            if now < sunset {
+                return Err(ErrorCode::OutstandingRewards.into());
+            }
+            msg!(
+                "Sweeping {} unclaimed rewards past the sunset",
+                pool.aggregate_pending
+            );
+        }
 
         let signer_seeds = &[
             pool.to_account_info().key.as_ref(),
@@ -403,41 +4627,53 @@ pub mod staking {
         //set_authority on them. it's not very ata clean, but it'd work
         //if size of tx is an issue, thats an approach
 
-        //close staking vault
-        let staking_vault_balance = ctx.accounts.staking_vault.amount;
+        //close staking vault, through the staking mint's token program
+        let staking_vault = load_token_account(&ctx.accounts.staking_vault)?;
+        if staking_vault.delegate.is_some() {
+            return Err(ErrorCode::VaultHasDelegate.into());
+        }
+        let staking_vault_balance = staking_vault.amount;
+        let staking_token_program = ctx.accounts.staking_token_program.key;
 
         if staking_vault_balance > 0 {
-            let ix = spl_token::instruction::transfer(
-                &spl_token::ID,
-                ctx.accounts.staking_vault.to_account_info().key,
-                ctx.accounts.staking_refundee.to_account_info().key,
-                ctx.accounts.pool_signer.key,
-                &[ctx.accounts.pool_signer.key],
-                staking_vault_balance,
-            )?;
-            solana_program::program::invoke_signed(
-                &ix,
-                &[
-                    ctx.accounts.token_program.to_account_info(),
-                    ctx.accounts.staking_vault.to_account_info(),
-                    ctx.accounts.staking_refundee.to_account_info(),
-                    ctx.accounts.pool_signer.to_account_info(),
-                ],
+            transfer_checked_interface(
+                ctx.accounts.staking_token_program.to_account_info(),
+                ctx.accounts.staking_vault.to_account_info(),
+                ctx.accounts.staking_mint.to_account_info(),
+                load_mint(&ctx.accounts.staking_mint)?.decimals,
+                ctx.accounts.staking_refundee.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
                 &[signer_seeds],
+                staking_vault_balance,
             )?;
         }
 
-        let ix = spl_token::instruction::close_account(
-            &spl_token::ID,
-            ctx.accounts.staking_vault.to_account_info().key,
-            ctx.accounts.refundee.key,
-            ctx.accounts.pool_signer.key,
-            &[ctx.accounts.pool_signer.key],
-        )?;
+        let ix = if *staking_token_program == spl_token_2022::ID {
+            harvest_withheld_fees(
+                ctx.accounts.staking_token_program.to_account_info(),
+                ctx.accounts.staking_mint.to_account_info(),
+                ctx.accounts.staking_vault.to_account_info(),
+            )?;
+            spl_token_2022::instruction::close_account(
+                staking_token_program,
+                ctx.accounts.staking_vault.key,
+                ctx.accounts.refundee.key,
+                ctx.accounts.pool_signer.key,
+                &[ctx.accounts.pool_signer.key],
+            )?
+        } else {
+            spl_token::instruction::close_account(
+                staking_token_program,
+                ctx.accounts.staking_vault.key,
+                ctx.accounts.refundee.key,
+                ctx.accounts.pool_signer.key,
+                &[ctx.accounts.pool_signer.key],
+            )?
+        };
         solana_program::program::invoke_signed(
             &ix,
             &[
-                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.staking_token_program.to_account_info(),
                 ctx.accounts.staking_vault.to_account_info(),
                 ctx.accounts.refundee.to_account_info(),
                 ctx.accounts.pool_signer.to_account_info(),
@@ -449,23 +4685,14 @@ pub mod staking {
         let reward_vault_balance = ctx.accounts.reward_vault.amount;
 
         if reward_vault_balance > 0 {
-            let ix = spl_token::instruction::transfer(
-                &spl_token::ID,
-                ctx.accounts.reward_vault.to_account_info().key,
-                ctx.accounts.reward_refundee.to_account_info().key,
-                ctx.accounts.pool_signer.key,
-                &[ctx.accounts.pool_signer.key],
-                reward_vault_balance,
-            )?;
-            solana_program::program::invoke_signed(
-                &ix,
-                &[
-                    ctx.accounts.token_program.to_account_info(),
-                    ctx.accounts.reward_vault.to_account_info(),
-                    ctx.accounts.reward_refundee.to_account_info(),
-                    ctx.accounts.pool_signer.to_account_info(),
-                ],
+            transfer_checked(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.reward_mint,
+                ctx.accounts.reward_refundee.to_account_info(),
+                ctx.accounts.pool_signer.to_account_info(),
                 &[signer_seeds],
+                reward_vault_balance,
             )?;
         }
         let ix = spl_token::instruction::close_account(
@@ -488,4 +4715,131 @@ pub mod staking {
 
         Ok(())
     }
+
+    /// Target of the self-CPI behind `utils::emit_cpi`, carrying a serialized
+    /// event, discriminator first, as instruction data for indexers. Only the
+    /// event authority, which this program alone signs for, may call it.
+    pub fn log_event(_ctx: Context<LogEvent>, _event: Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zeroed_pool() -> Pool {
+        Pool::deserialize(&mut &vec![0u8; 4096][..]).unwrap()
+    }
+
+    #[test]
+    fn reward_per_token_overflows_at_the_maximal_rate() {
+        assert_eq!(
+            reward_per_token(1, 0, u64::MAX, 0, u64::MAX).unwrap_err(),
+            ErrorCode::MathOverflow.into(),
+        );
+        assert_eq!(
+            reward_per_token(1, u128::MAX, 1, 0, 1).unwrap_err(),
+            ErrorCode::MathOverflow.into(),
+        );
+    }
+
+    #[test]
+    fn earned_overflows_past_u64_max() {
+        assert_eq!(earned(u64::MAX, math::PRECISION, 0, 0).unwrap(), u64::MAX);
+        assert_eq!(
+            earned(u64::MAX, math::PRECISION, 0, 1).unwrap_err(),
+            ErrorCode::MathOverflow.into(),
+        );
+        assert_eq!(
+            earned(u64::MAX, 2 * math::PRECISION, 0, 0).unwrap_err(),
+            ErrorCode::MathOverflow.into(),
+        );
+        assert_eq!(
+            earned(1, 0, 1, 0).unwrap_err(),
+            ErrorCode::ArithmeticUnderflow.into(),
+        );
+    }
+
+    #[test]
+    fn early_unstake_penalty_decays_linearly() {
+        // 10% over a 1000s lock maturing at 2000.
+        let penalty = |now| early_unstake_penalty(1_000_000, 1_000, 2_000, 1_000, now).unwrap();
+        assert_eq!(penalty(0), (100_000, 1_000));
+        assert_eq!(penalty(1_000), (100_000, 1_000));
+        assert_eq!(penalty(1_250), (75_000, 750));
+        assert_eq!(penalty(1_500), (50_000, 500));
+        assert_eq!(penalty(1_999), (100, 1));
+        assert_eq!(penalty(2_000), (0, 0));
+        assert_eq!(penalty(3_000), (0, 0));
+    }
+
+    #[test]
+    fn early_unstake_penalty_rounds_up() {
+        // 10 bps over 3s: 1s left is 3.33 bps, 2s left 6.67 bps.
+        assert_eq!(early_unstake_penalty(10_000, 10, 3, 3, 2).unwrap(), (4, 4));
+        assert_eq!(early_unstake_penalty(10_000, 10, 3, 3, 1).unwrap(), (7, 7));
+        assert_eq!(early_unstake_penalty(1, 1, 1, 1, 0).unwrap(), (1, 1));
+        assert_eq!(
+            early_unstake_penalty(0, 10_000, 1, 1, 0).unwrap(),
+            (0, 10_000)
+        );
+
+        assert_eq!(early_unstake_penalty(1, 10_000, 1, 0, 0).unwrap(), (0, 0));
+        assert_eq!(early_unstake_penalty(1, 0, 1, 1, 0).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn early_unstake_penalty_of_a_u64_max_stake() {
+        assert_eq!(
+            early_unstake_penalty(u64::MAX, 10_000, u64::MAX, u64::MAX, 0).unwrap(),
+            (u64::MAX, 10_000),
+        );
+        assert_eq!(
+            early_unstake_penalty(u64::MAX, u16::MAX, u64::MAX, u64::MAX, 0).unwrap_err(),
+            ErrorCode::MathOverflow.into(),
+        );
+    }
+
+    #[test]
+    fn grace_topup_of_a_u64_max_stake() {
+        let mut pool = zeroed_pool();
+        pool.topup_grace_bps = BPS_DENOMINATOR as u16;
+        assert!(is_grace_topup(&pool, u64::MAX, 0, u64::MAX, 0).unwrap());
+
+        pool.topup_grace_bps = BPS_DENOMINATOR as u16 / 2;
+        assert!(is_grace_topup(&pool, u64::MAX, 0, u64::MAX / 2, 0).unwrap());
+        assert!(!is_grace_topup(&pool, u64::MAX, 0, u64::MAX / 2 + 1, 0).unwrap());
+    }
+
+    #[test]
+    fn vested_amount_at_the_extremes() {
+        let mut user = User {
+            vesting_total: u64::MAX,
+            vesting_duration: u64::MAX,
+            ..User::default()
+        };
+        assert_eq!(vested_amount(&user, -1).unwrap(), 0);
+        assert_eq!(vested_amount(&user, i64::MAX).unwrap(), i64::MAX as u64);
+
+        user.vesting_duration = 1;
+        assert_eq!(vested_amount(&user, i64::MAX).unwrap(), u64::MAX);
+
+        user.vesting_cliff = i64::MIN;
+        assert_eq!(
+            vested_amount(&user, i64::MAX).unwrap_err(),
+            ErrorCode::MathOverflow.into(),
+        );
+    }
+
+    #[test]
+    fn day_index_across_midnight() {
+        let midnight = 19_000 * crate::constants::SECONDS_PER_DAY;
+        assert!(check_day_index(18_999, midnight - 1).is_ok());
+        assert_eq!(
+            check_day_index(18_999, midnight).unwrap_err(),
+            ErrorCode::DayIndexMismatch.into(),
+        );
+        assert!(check_day_index(19_000, midnight).is_ok());
+    }
 }
@@ -0,0 +1,264 @@
+//! Reward accrual math. Takes and returns plain integers and has no Solana
+//! dependencies, so clients built for the host or wasm compute exactly the
+//! numbers the program stores.
+
+use std::convert::TryFrom;
+
+/// Fixed-point scale of reward-per-token values.
+pub const PRECISION: u128 = u64::MAX as u128;
+
+/// Why a reward computation failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathError {
+    Overflow,
+    Underflow,
+}
+
+/// Latest time rewards accrue until: `unix_timestamp`, capped at the end of
+/// the reward period. Timestamps before the epoch read as 0.
+pub fn last_time_reward_applicable(reward_duration_end: u64, unix_timestamp: i64) -> u64 {
+    std::cmp::min(
+        u64::try_from(unix_timestamp).unwrap_or(0),
+        reward_duration_end,
+    )
+}
+
+/// Reward per staked token, scaled by `PRECISION`, after accruing
+/// `reward_rate` from `last_update_time` to `last_time_reward_applicable`.
+/// A last update ahead of the applicable time accrues nothing.
+pub fn reward_per_token(
+    total_staked: u64,
+    reward_per_token_stored: u128,
+    last_time_reward_applicable: u64,
+    last_update_time: u64,
+    reward_rate: u64,
+) -> Result<u128, MathError> {
+    if total_staked == 0 {
+        return Ok(reward_per_token_stored);
+    }
+
+    let elapsed = last_time_reward_applicable.saturating_sub(last_update_time);
+    // elapsed * reward_rate always fits in u128, so only the scaling by
+    // PRECISION needs care.
+    let emitted = elapsed as u128 * reward_rate as u128;
+    mul_precision_div(emitted, total_staked as u128)
+        .and_then(|accrued| reward_per_token_stored.checked_add(accrued))
+        .ok_or(MathError::Overflow)
+}
+
+/// Pending rewards of a balance whose last checkpoint stood at
+/// `user_reward_per_token_paid` with `user_reward_pending` owed.
+pub fn earned(
+    balance_staked: u64,
+    reward_per_token: u128,
+    user_reward_per_token_paid: u128,
+    user_reward_pending: u64,
+) -> Result<u64, MathError> {
+    let delta = reward_per_token
+        .checked_sub(user_reward_per_token_paid)
+        .ok_or(MathError::Underflow)?;
+
+    mul_div_precision(delta, balance_staked as u128)
+        .and_then(|x| x.checked_add(user_reward_pending as u128))
+        .and_then(|x| u64::try_from(x).ok())
+        .ok_or(MathError::Overflow)
+}
+
+/// Computes `value * PRECISION / divisor` for a non-zero `divisor` that fits
+/// in a u64. Dividing first and carrying the remainder keeps the intermediate
+/// product within u128 whenever the result does.
+pub fn mul_precision_div(value: u128, divisor: u128) -> Option<u128> {
+    let whole = (value / divisor).checked_mul(PRECISION)?;
+    let fraction = (value % divisor) * PRECISION / divisor;
+    whole.checked_add(fraction)
+}
+
+/// Computes `value * multiplier / PRECISION` for a `multiplier` that fits in a
+/// u64, splitting `value` so the intermediate product cannot overflow.
+pub fn mul_div_precision(value: u128, multiplier: u128) -> Option<u128> {
+    let whole = (value / PRECISION).checked_mul(multiplier)?;
+    let fraction = (value % PRECISION) * multiplier / PRECISION;
+    whole.checked_add(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN_DURATION: u64 = 86400;
+
+    /// Little-endian 64-bit limbs of a u256, for exact reference results.
+    type U256 = [u64; 4];
+
+    fn mul_u128(a: u128, b: u128) -> U256 {
+        let (a, b) = ([a as u64, (a >> 64) as u64], [b as u64, (b >> 64) as u64]);
+        let mut limbs = [0u64; 4];
+        for i in 0..2 {
+            let mut carry = 0u128;
+            for j in 0..2 {
+                let x = a[i] as u128 * b[j] as u128 + limbs[i + j] as u128 + carry;
+                limbs[i + j] = x as u64;
+                carry = x >> 64;
+            }
+            limbs[i + 2] = carry as u64;
+        }
+        limbs
+    }
+
+    fn div_u64(value: U256, divisor: u64) -> U256 {
+        let mut quotient = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let x = remainder << 64 | value[i] as u128;
+            quotient[i] = (x / divisor as u128) as u64;
+            remainder = x % divisor as u128;
+        }
+        quotient
+    }
+
+    fn to_u128(value: U256) -> Option<u128> {
+        if value[2] != 0 || value[3] != 0 {
+            return None;
+        }
+        Some((value[1] as u128) << 64 | value[0] as u128)
+    }
+
+    fn reference_reward_per_token(
+        total_staked: u64,
+        stored: u128,
+        applicable: u64,
+        last_update: u64,
+        rate: u64,
+    ) -> Result<u128, MathError> {
+        if total_staked == 0 {
+            return Ok(stored);
+        }
+        let emitted = applicable.saturating_sub(last_update) as u128 * rate as u128;
+        to_u128(div_u64(mul_u128(emitted, PRECISION), total_staked))
+            .and_then(|x| x.checked_add(stored))
+            .ok_or(MathError::Overflow)
+    }
+
+    fn reference_earned(
+        balance: u64,
+        rpt: u128,
+        paid: u128,
+        pending: u64,
+    ) -> Result<u64, MathError> {
+        let delta = rpt.checked_sub(paid).ok_or(MathError::Underflow)?;
+        to_u128(div_u64(mul_u128(delta, balance as u128), PRECISION as u64))
+            .and_then(|x| x.checked_add(pending as u128))
+            .and_then(|x| u64::try_from(x).ok())
+            .ok_or(MathError::Overflow)
+    }
+
+    /// Seeded 64-bit LCG, so failures reproduce.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0
+        }
+
+        /// A value of random bit length, so small and boundary values come up
+        /// as often as large ones.
+        fn u64(&mut self) -> u64 {
+            match self.next() % 8 {
+                0 => 0,
+                1 => u64::MAX,
+                _ => self.next() >> (self.next() % 64),
+            }
+        }
+
+        fn u128(&mut self) -> u128 {
+            if self.next() >> 63 == 0 {
+                self.u64() as u128
+            } else {
+                (self.u64() as u128) << 64 | self.u64() as u128
+            }
+        }
+    }
+
+    #[test]
+    fn reward_per_token_matches_the_u256_reference() {
+        let mut rng = Lcg(0x5eed);
+        for _ in 0..100_000 {
+            let (total, stored) = (rng.u64(), rng.u128());
+            let (applicable, last_update, rate) = (rng.u64(), rng.u64(), rng.u64());
+            assert_eq!(
+                reward_per_token(total, stored, applicable, last_update, rate),
+                reference_reward_per_token(total, stored, applicable, last_update, rate),
+                "reward_per_token({}, {}, {}, {}, {})",
+                total,
+                stored,
+                applicable,
+                last_update,
+                rate
+            );
+        }
+    }
+
+    #[test]
+    fn earned_matches_the_u256_reference() {
+        let mut rng = Lcg(0x5eed);
+        for _ in 0..100_000 {
+            let (balance, rpt, paid, pending) = (rng.u64(), rng.u128(), rng.u128(), rng.u64());
+            assert_eq!(
+                earned(balance, rpt, paid, pending),
+                reference_earned(balance, rpt, paid, pending),
+                "earned({}, {}, {}, {})",
+                balance,
+                rpt,
+                paid,
+                pending
+            );
+        }
+    }
+
+    #[test]
+    fn reward_per_token_at_the_largest_funded_rate() {
+        // Funding u64::MAX over the shortest period against a single token.
+        let rate = u64::MAX / MIN_DURATION;
+        let emitted = rate as u128 * MIN_DURATION as u128;
+        assert_eq!(
+            reward_per_token(1, 0, MIN_DURATION, 0, rate),
+            Ok(emitted * PRECISION)
+        );
+    }
+
+    #[test]
+    fn reward_per_token_over_a_long_gap() {
+        // `elapsed * rate * PRECISION` overflows u128 here, the scaled result
+        // doesn't.
+        let elapsed = 7 * MIN_DURATION;
+        let emitted = elapsed as u128 * u64::MAX as u128;
+        assert!(emitted.checked_mul(PRECISION).is_none());
+        assert_eq!(
+            reward_per_token(u64::MAX, 0, elapsed, 0, u64::MAX),
+            Ok(emitted)
+        );
+
+        let stored = reward_per_token(1_000_000, 0, 200_000_000, 0, 104_166_666_666).unwrap();
+        let emitted = 200_000_000u128 * 104_166_666_666;
+        assert_eq!(
+            stored,
+            emitted / 1_000_000 * PRECISION + emitted % 1_000_000 * PRECISION / 1_000_000
+        );
+    }
+
+    #[test]
+    fn reward_per_token_reports_overflow() {
+        assert_eq!(
+            reward_per_token(1, 0, u64::MAX, 0, u64::MAX),
+            Err(MathError::Overflow)
+        );
+        assert_eq!(
+            reward_per_token(1, u128::MAX, 1, 0, 1),
+            Err(MathError::Overflow)
+        );
+    }
+}
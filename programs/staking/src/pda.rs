@@ -0,0 +1,226 @@
+//! Addresses of the program's PDAs, for clients and for the program itself.
+//! The seeds match the `seeds = [...]` constraints in `context`, which build
+//! on the same constants.
+
+use crate::constants::{
+    CLAIM_AUTHORITY_SEED, DAILY_STATS_SEED, EVENT_AUTHORITY_SEED, LEADERBOARD_SEED, LOTTERY_SEED,
+    LOTTERY_VAULT_SEED, MIGRATION_SEED, NFT_ESCROW_SEED, SWAP_ACCOUNT_SEED, SWAP_AUTHORITY_SEED,
+    VESTING_SEED, VOTER_WEIGHT_RECORD_SEED,
+};
+use anchor_lang::prelude::*;
+
+/// `User` account of `owner` in `pool`.
+pub fn user_address(owner: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[owner.as_ref(), pool.as_ref()], &crate::ID)
+}
+
+/// Signer owning the pool's vaults.
+pub fn pool_signer_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[pool.as_ref()], &crate::ID)
+}
+
+/// `DailyStats` account of `pool` for the day numbered `day` since the Unix
+/// epoch, see `utils::day_index`.
+pub fn daily_stats_address(pool: &Pubkey, day: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[DAILY_STATS_SEED, pool.as_ref(), &day.to_le_bytes()],
+        &crate::ID,
+    )
+}
+
+/// Escrow token account vesting `user`'s claimed rewards.
+pub fn vesting_escrow_address(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VESTING_SEED, user.as_ref()], &crate::ID)
+}
+
+/// `Leaderboard` account of `pool`.
+pub fn leaderboard_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LEADERBOARD_SEED, pool.as_ref()], &crate::ID)
+}
+
+/// `LotteryRound` account of `pool`.
+pub fn lottery_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LOTTERY_SEED, pool.as_ref()], &crate::ID)
+}
+
+/// Token account holding the prizes of `pool`'s lottery.
+pub fn lottery_vault_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LOTTERY_VAULT_SEED, pool.as_ref()], &crate::ID)
+}
+
+/// `MigrationConfig` account of positions leaving `old_pool`.
+pub fn migration_address(old_pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MIGRATION_SEED, old_pool.as_ref()], &crate::ID)
+}
+
+/// Escrow token account holding `nft_mint` while `user` stakes it.
+pub fn nft_escrow_address(user: &Pubkey, nft_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[NFT_ESCROW_SEED, user.as_ref(), nft_mint.as_ref()],
+        &crate::ID,
+    )
+}
+
+/// Transient token account `claim_and_swap` claims `user`'s rewards into.
+pub fn swap_account_address(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SWAP_ACCOUNT_SEED, user.as_ref()], &crate::ID)
+}
+
+/// Owner of `user`'s swap account, signing the swap.
+pub fn swap_authority_address(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SWAP_AUTHORITY_SEED, user.as_ref()], &crate::ID)
+}
+
+/// Authority signing the `log_event` self-CPI.
+pub fn event_authority_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &crate::ID)
+}
+
+/// `VoterWeightRecord` of `owner` for `staking_mint` in the governance
+/// `realm`.
+pub fn voter_weight_record_address(
+    realm: &Pubkey,
+    staking_mint: &Pubkey,
+    owner: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            VOTER_WEIGHT_RECORD_SEED,
+            realm.as_ref(),
+            staking_mint.as_ref(),
+            owner.as_ref(),
+        ],
+        &crate::ID,
+    )
+}
+
+/// Authority `claim_program` signs `claim_via_cpi` with for `user`. Derived
+/// under the claim program, not this one.
+pub fn claim_authority_address(user: &Pubkey, claim_program: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CLAIM_AUTHORITY_SEED, user.as_ref()], claim_program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks `derived` like a `seeds = [...], bump = <stored bump>`
+    /// constraint does: by re-creating the address from the seeds, spelled
+    /// out as in `context` and the clients, and the bump.
+    fn assert_derived(derived: (Pubkey, u8), seeds: &[&[u8]], program_id: &Pubkey) {
+        let (address, bump) = derived;
+        let bump = [bump];
+        let mut seeds = seeds.to_vec();
+        seeds.push(&bump);
+        assert_eq!(
+            Pubkey::create_program_address(&seeds, program_id),
+            Ok(address)
+        );
+    }
+
+    #[test]
+    fn user_and_pool_signer() {
+        let (owner, pool) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert_derived(
+            user_address(&owner, &pool),
+            &[owner.as_ref(), pool.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(pool_signer_address(&pool), &[pool.as_ref()], &crate::ID);
+        assert_ne!(user_address(&owner, &pool), user_address(&pool, &owner));
+    }
+
+    #[test]
+    fn pool_accounts() {
+        let pool = Pubkey::new_unique();
+        let day = 19_000u64;
+        assert_derived(
+            daily_stats_address(&pool, day),
+            &[b"daily_stats", pool.as_ref(), &day.to_le_bytes()],
+            &crate::ID,
+        );
+        assert_derived(
+            leaderboard_address(&pool),
+            &[b"leaderboard", pool.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(
+            lottery_address(&pool),
+            &[b"lottery", pool.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(
+            lottery_vault_address(&pool),
+            &[b"lottery_vault", pool.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(
+            migration_address(&pool),
+            &[b"migration", pool.as_ref()],
+            &crate::ID,
+        );
+    }
+
+    #[test]
+    fn user_accounts() {
+        let (user, nft_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert_derived(
+            vesting_escrow_address(&user),
+            &[b"vesting", user.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(
+            nft_escrow_address(&user, &nft_mint),
+            &[b"nft_escrow", user.as_ref(), nft_mint.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(
+            swap_account_address(&user),
+            &[b"swap", user.as_ref()],
+            &crate::ID,
+        );
+        assert_derived(
+            swap_authority_address(&user),
+            &[b"swap_authority", user.as_ref()],
+            &crate::ID,
+        );
+    }
+
+    #[test]
+    fn event_authority() {
+        assert_derived(
+            event_authority_address(),
+            &[b"__event_authority"],
+            &crate::ID,
+        );
+    }
+
+    #[test]
+    fn voter_weight_record() {
+        let (realm, mint, owner) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        assert_derived(
+            voter_weight_record_address(&realm, &mint, &owner),
+            &[
+                b"voter-weight-record",
+                realm.as_ref(),
+                mint.as_ref(),
+                owner.as_ref(),
+            ],
+            &crate::ID,
+        );
+    }
+
+    #[test]
+    fn claim_authority_lives_under_the_claim_program() {
+        let (user, claim_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert_derived(
+            claim_authority_address(&user, &claim_program),
+            &[b"claim_authority", user.as_ref()],
+            &claim_program,
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Bodies of the `*_for_testing` instructions, compiled only with the
+//! `local-testing` feature.
+
+use crate::account::User;
+use crate::constants::{ACTION_STAKE, SECONDS_PER_DAY, USER_V1_SIZE};
+use crate::context::*;
+use crate::error::ErrorCode;
+use crate::utils::{
+    PYTH_AGG_CONF_OFFSET, PYTH_AGG_PRICE_OFFSET, PYTH_AGG_STATUS_OFFSET, PYTH_EXPO_OFFSET,
+    PYTH_MAGIC, PYTH_PRICE_ACCOUNT, PYTH_PRICE_MIN_LEN, PYTH_STATUS_TRADING, PYTH_TIMESTAMP_OFFSET,
+    SWITCHBOARD_RANDOMNESS_DISCRIMINATOR, SWITCHBOARD_RANDOMNESS_MIN_LEN,
+    SWITCHBOARD_REVEAL_SLOT_OFFSET, SWITCHBOARD_SEED_SLOT_OFFSET, SWITCHBOARD_VALUE_OFFSET,
+    TEST_ACCOUNT_DISCRIMINATOR, TEST_ACCOUNT_TAIL_LEN,
+};
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use std::convert::TryFrom;
+
+/// Lets `authority` write a `layout_len`-byte oracle layout into `account`. A
+/// zeroed account is claimed for `authority` by stamping the test-account
+/// tail; a claimed one must carry its key. Program accounts are never zeroed
+/// and never end in the tail, so they can't be overwritten.
+fn claim_test_account(account: &AccountInfo, authority: &Pubkey, layout_len: usize) -> Result<()> {
+    let mut data = account.try_borrow_mut_data()?;
+    if data.len() < layout_len + TEST_ACCOUNT_TAIL_LEN {
+        return Err(ErrorCode::InvalidTestAccount.into());
+    }
+    let tail = data.len() - TEST_ACCOUNT_TAIL_LEN;
+    if data.iter().all(|x| *x == 0) {
+        data[tail..tail + 8].copy_from_slice(&TEST_ACCOUNT_DISCRIMINATOR);
+        data[tail + 8..].copy_from_slice(authority.as_ref());
+    } else if data[tail..tail + 8] != TEST_ACCOUNT_DISCRIMINATOR
+        || data[tail + 8..] != authority.as_ref()[..]
+    {
+        return Err(ErrorCode::InvalidTestAccount.into());
+    }
+
+    Ok(())
+}
+
+pub fn downgrade_user(ctx: Context<DowngradeUser>) -> Result<()> {
+    let user_info = ctx.accounts.user.to_account_info();
+    if user_info.try_borrow_data()?[..8] != User::discriminator() {
+        return Err(ErrorCode::UserAccountMismatch.into());
+    }
+    user_info.realloc(USER_V1_SIZE, false)?;
+
+    Ok(())
+}
+
+pub fn corrupt_pool(ctx: Context<CorruptPool>, total_staked: u64) -> Result<()> {
+    ctx.accounts.pool.total_staked = total_staked;
+
+    Ok(())
+}
+
+pub fn rewind_pool(ctx: Context<CorruptPool>, seconds: u64) -> Result<()> {
+    let pool = &mut ctx.accounts.pool;
+    pool.last_update_time = pool
+        .last_update_time
+        .checked_sub(seconds)
+        .ok_or(ErrorCode::ArithmeticUnderflow)?;
+
+    Ok(())
+}
+
+pub fn record_daily_stats(
+    ctx: Context<RecordDailyStats>,
+    day_index: u64,
+    amount: u64,
+) -> Result<()> {
+    // The day's last second, when a stake landing just before midnight would
+    // have been recorded.
+    let now = day_index
+        .checked_add(1)
+        .and_then(|day| day.checked_mul(SECONDS_PER_DAY as u64))
+        .and_then(|end| i64::try_from(end - 1).ok())
+        .ok_or(ErrorCode::MathOverflow)?;
+    let pool = ctx.accounts.pool.key();
+    ctx.accounts
+        .daily_stats
+        .record(pool, ACTION_STAKE, amount, now);
+
+    Ok(())
+}
+
+pub fn write_price_feed(
+    ctx: Context<WritePriceFeed>,
+    price: i64,
+    conf: u64,
+    expo: i32,
+    timestamp: i64,
+) -> Result<()> {
+    let price_feed = &ctx.accounts.price_feed;
+    claim_test_account(price_feed, ctx.accounts.authority.key, PYTH_PRICE_MIN_LEN)?;
+
+    let mut data = price_feed.try_borrow_mut_data()?;
+    let mut write = |offset: usize, bytes: &[u8]| {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    write(0, &PYTH_MAGIC.to_le_bytes());
+    write(8, &PYTH_PRICE_ACCOUNT.to_le_bytes());
+    write(PYTH_EXPO_OFFSET, &expo.to_le_bytes());
+    write(PYTH_TIMESTAMP_OFFSET, &timestamp.to_le_bytes());
+    write(PYTH_AGG_PRICE_OFFSET, &price.to_le_bytes());
+    write(PYTH_AGG_CONF_OFFSET, &conf.to_le_bytes());
+    write(PYTH_AGG_STATUS_OFFSET, &PYTH_STATUS_TRADING.to_le_bytes());
+
+    Ok(())
+}
+
+pub fn write_randomness(
+    ctx: Context<WriteRandomness>,
+    seed_slot: u64,
+    reveal_slot: u64,
+    value: [u8; 32],
+) -> Result<()> {
+    let randomness = &ctx.accounts.randomness;
+    claim_test_account(
+        randomness,
+        ctx.accounts.authority.key,
+        SWITCHBOARD_RANDOMNESS_MIN_LEN,
+    )?;
+
+    let mut data = randomness.try_borrow_mut_data()?;
+    let mut write = |offset: usize, bytes: &[u8]| {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    write(0, &SWITCHBOARD_RANDOMNESS_DISCRIMINATOR);
+    write(SWITCHBOARD_SEED_SLOT_OFFSET, &seed_slot.to_le_bytes());
+    write(SWITCHBOARD_REVEAL_SLOT_OFFSET, &reveal_slot.to_le_bytes());
+    write(SWITCHBOARD_VALUE_OFFSET, &value);
+
+    Ok(())
+}
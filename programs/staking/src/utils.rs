@@ -1,4 +1,285 @@
-use crate::constants::TIER_INFO;
+use crate::account::{LeaderboardEntry, User};
+use crate::constants::{
+  jupiter, memo_program, switchboard_on_demand, EVENT_AUTHORITY_SEED, EXCHANGE_RATE_PYTH,
+  EXCHANGE_RATE_STAKE_POOL, JUPITER_ROUTE_AUTHORITY_INDEX, JUPITER_ROUTE_DISCRIMINATOR,
+  JUPITER_ROUTE_SOURCE_INDEX, MAX_MEMO_LEN, PRICE_MAX_AGE_SECONDS, PRICE_MAX_CONFIDENCE_BPS,
+  SECONDS_PER_DAY, TIER_INFO, USD_DECIMALS,
+};
+use crate::error::{error_with_context, ErrorCode};
+use crate::pda::claim_authority_address;
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::{invoke, invoke_signed};
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::{Event, InstructionData};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::{Mint, TokenAccount};
+use mpl_token_metadata::state::Metadata;
+use spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint;
+use spl_token_2022::extension::transfer_fee::TransferFeeConfig;
+use spl_token_2022::extension::{ExtensionType, StateWithExtensions};
+use std::convert::{TryFrom, TryInto};
+
+/// Clock time as an unsigned timestamp; a clock before the epoch reads as 0
+/// instead of panicking.
+pub fn unix_timestamp_to_u64(unix_timestamp: i64) -> u64 {
+  u64::try_from(unix_timestamp).unwrap_or(0)
+}
+
+/// Days since the unix epoch at `unix_timestamp`.
+pub fn day_index(unix_timestamp: i64) -> u64 {
+  unix_timestamp_to_u64(unix_timestamp) / SECONDS_PER_DAY as u64
+}
+
+/// Moves `amount` with `transfer_checked`, taking the decimals from `mint`.
+/// Shaped like a plain token CPI; `transfer_checked_interface` takes mints of
+/// either token program.
+pub fn transfer_checked<'info>(
+  token_program: AccountInfo<'info>,
+  from: AccountInfo<'info>,
+  mint: &Account<'info, Mint>,
+  to: AccountInfo<'info>,
+  authority: AccountInfo<'info>,
+  signer_seeds: &[&[&[u8]]],
+  amount: u64,
+) -> Result<()> {
+  transfer_checked_interface(
+    token_program,
+    from,
+    mint.to_account_info(),
+    mint.decimals,
+    to,
+    authority,
+    signer_seeds,
+    amount,
+  )
+}
+
+/// `transfer_checked` through the legacy token program or Token-2022,
+/// whichever `token_program` is.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_checked_interface<'info>(
+  token_program: AccountInfo<'info>,
+  from: AccountInfo<'info>,
+  mint: AccountInfo<'info>,
+  decimals: u8,
+  to: AccountInfo<'info>,
+  authority: AccountInfo<'info>,
+  signer_seeds: &[&[&[u8]]],
+  amount: u64,
+) -> Result<()> {
+  let ix = if *token_program.key == spl_token_2022::ID {
+    spl_token_2022::instruction::transfer_checked(
+      token_program.key,
+      from.key,
+      mint.key,
+      to.key,
+      authority.key,
+      &[],
+      amount,
+      decimals,
+    )?
+  } else {
+    spl_token::instruction::transfer_checked(
+      token_program.key,
+      from.key,
+      mint.key,
+      to.key,
+      authority.key,
+      &[],
+      amount,
+      decimals,
+    )?
+  };
+  invoke_signed(
+    &ix,
+    &[from, mint, to, authority, token_program],
+    signer_seeds,
+  )?;
+  Ok(())
+}
+
+/// Whether `program_id` is a token program staking mints may belong to.
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+  *program_id == spl_token::ID || *program_id == spl_token_2022::ID
+}
+
+/// Base state of a token account of either token program; extensions are
+/// skipped. Token-2022 extends the legacy layout, so both parse the same.
+pub fn load_token_account(info: &AccountInfo) -> Result<spl_token_2022::state::Account> {
+  if !is_token_program(info.owner) {
+    return Err(ErrorCode::InvalidTokenProgram.into());
+  }
+  let data = info.try_borrow_data()?;
+  Ok(StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?.base)
+}
+
+/// Base state of a mint of either token program.
+pub fn load_mint(info: &AccountInfo) -> Result<spl_token_2022::state::Mint> {
+  if !is_token_program(info.owner) {
+    return Err(ErrorCode::InvalidTokenProgram.into());
+  }
+  let data = info.try_borrow_data()?;
+  Ok(StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?.base)
+}
+
+/// Rejects staking mints whose Token-2022 extensions the pool's accounting
+/// can't follow. Legacy mints have none.
+pub fn check_mint_extensions(mint: &AccountInfo) -> Result<()> {
+  if *mint.owner != spl_token_2022::ID {
+    return Ok(());
+  }
+  let data = mint.try_borrow_data()?;
+  let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+  check_mint_extension_types(&state.get_extension_types()?)
+}
+
+/// Transfer fees are netted out of stakes and a close authority can only
+/// close an empty mint. Confidential transfers hide amounts from the vault
+/// checks; anything else is refused until reviewed.
+pub fn check_mint_extension_types(extension_types: &[ExtensionType]) -> Result<()> {
+  for extension_type in extension_types {
+    match extension_type {
+      ExtensionType::TransferFeeConfig | ExtensionType::MintCloseAuthority => {}
+      ExtensionType::ConfidentialTransferMint => {
+        return Err(ErrorCode::ConfidentialTransfersUnsupported.into());
+      }
+      _ => {
+        return Err(error_with_context(
+          ErrorCode::UnsupportedMintExtension,
+          format_args!("{:?}", extension_type),
+        ));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Transfer fee settings of a Token-2022 mint, if it charges one.
+fn transfer_fee_config(mint: &AccountInfo) -> Result<Option<TransferFeeConfig>> {
+  if *mint.owner != spl_token_2022::ID {
+    return Ok(None);
+  }
+  let data = mint.try_borrow_data()?;
+  let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+  Ok(state.get_extension::<TransferFeeConfig>().ok().copied())
+}
+
+/// Fee `mint` withholds from a transfer of `amount` in `epoch`; 0 for mints
+/// without a transfer fee.
+pub fn transfer_fee(mint: &AccountInfo, epoch: u64, amount: u64) -> Result<u64> {
+  match transfer_fee_config(mint)? {
+    Some(config) => Ok(
+      config
+        .calculate_epoch_fee(epoch, amount)
+        .ok_or(ErrorCode::MathOverflow)?,
+    ),
+    None => Ok(0),
+  }
+}
+
+/// Moves the transfer fees withheld in `account` to its mint, which
+/// Token-2022 requires before closing it. Permissionless, and a no-op for
+/// mints without a transfer fee.
+pub fn harvest_withheld_fees<'info>(
+  token_program: AccountInfo<'info>,
+  mint: AccountInfo<'info>,
+  account: AccountInfo<'info>,
+) -> Result<()> {
+  if transfer_fee_config(&mint)?.is_none() {
+    return Ok(());
+  }
+  let ix = harvest_withheld_tokens_to_mint(token_program.key, mint.key, &[account.key])?;
+  invoke(&ix, &[mint, account, token_program])?;
+  Ok(())
+}
+
+/// Emits `event` again as the data of a `log_event` self-CPI signed by the
+/// event authority. Indexers read it from the inner instruction, which log
+/// truncation can't drop. A no-op in builds without the `event-cpi` feature.
+pub fn emit_cpi<'info, E: Event>(
+  event: &E,
+  event_authority: &AccountInfo<'info>,
+  program: &AccountInfo<'info>,
+  bump: u8,
+) -> Result<()> {
+  if !cfg!(feature = "event-cpi") {
+    return Ok(());
+  }
+
+  let ix = Instruction {
+    program_id: crate::ID,
+    accounts: vec![AccountMeta::new_readonly(*event_authority.key, true)],
+    data: crate::instruction::LogEvent {
+      _event: event.data(),
+    }
+    .data(),
+  };
+  invoke_signed(
+    &ix,
+    &[event_authority.clone(), program.clone()],
+    &[&[EVENT_AUTHORITY_SEED, &[bump]]],
+  )?;
+  Ok(())
+}
+
+/// `transfer_checked` authorized by `multisig_signers` when `authority` is an
+/// SPL Token multisig; pass none for a plain signer.
+pub fn transfer_checked_multisig<'info>(
+  token_program: AccountInfo<'info>,
+  from: AccountInfo<'info>,
+  mint: &Account<'info, Mint>,
+  to: AccountInfo<'info>,
+  authority: AccountInfo<'info>,
+  multisig_signers: &[AccountInfo<'info>],
+  amount: u64,
+) -> Result<()> {
+  let signer_keys: Vec<&Pubkey> = multisig_signers.iter().map(|info| info.key).collect();
+  let ix = spl_token::instruction::transfer_checked(
+    token_program.key,
+    from.key,
+    &mint.key(),
+    to.key,
+    authority.key,
+    &signer_keys,
+    amount,
+    mint.decimals,
+  )?;
+  let mut infos = vec![from, mint.to_account_info(), to, authority, token_program];
+  infos.extend_from_slice(multisig_signers);
+  invoke(&ix, &infos)?;
+  Ok(())
+}
+
+/// Accounts among `remaining` that sign for `authority`: none when it signed
+/// itself, otherwise enough distinct signers of the SPL Token multisig it is.
+/// `None` when `authority` isn't authorized.
+pub fn multisig_signers<'info>(
+  authority: &AccountInfo<'info>,
+  remaining: &[AccountInfo<'info>],
+) -> Option<Vec<AccountInfo<'info>>> {
+  if authority.is_signer {
+    return Some(vec![]);
+  }
+  if *authority.owner != spl_token::ID {
+    return None;
+  }
+  let multisig = spl_token::state::Multisig::unpack(&authority.try_borrow_data().ok()?).ok()?;
+
+  let keys = &multisig.signers[..multisig.n as usize];
+  let mut matched = [false; spl_token::instruction::MAX_SIGNERS];
+  let mut signers = vec![];
+  for info in remaining.iter().filter(|info| info.is_signer) {
+    if let Some(position) = (0..keys.len()).find(|&i| keys[i] == *info.key && !matched[i]) {
+      matched[position] = true;
+      signers.push(info.clone());
+    }
+  }
+  if signers.len() < multisig.m as usize {
+    return None;
+  }
+  Some(signers)
+}
 
 pub fn get_tier(amount: u64) -> u8 {
   for (i, x) in TIER_INFO.iter().enumerate() {
@@ -9,3 +290,413 @@ pub fn get_tier(amount: u64) -> u8 {
 
   return TIER_INFO.len() as u8;
 }
+
+/// Tier of a USD value against the pool's thresholds.
+pub fn get_usd_tier(value: u64, thresholds: &[u64; 7]) -> u8 {
+  thresholds
+    .iter()
+    .position(|x| value < *x)
+    .unwrap_or(thresholds.len()) as u8
+}
+
+/// Aggregate price of a Pyth price account.
+pub struct PythPrice {
+  pub price: i64,
+  pub conf: u64,
+  pub expo: i32,
+}
+
+// Layout of a Pyth v2 price account.
+pub const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+pub const PYTH_PRICE_ACCOUNT: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+pub const PYTH_EXPO_OFFSET: usize = 20;
+pub const PYTH_TIMESTAMP_OFFSET: usize = 96;
+pub const PYTH_AGG_PRICE_OFFSET: usize = 208;
+pub const PYTH_AGG_CONF_OFFSET: usize = 216;
+pub const PYTH_AGG_STATUS_OFFSET: usize = 224;
+pub const PYTH_PRICE_MIN_LEN: usize = 240;
+
+/// Reads the aggregate price of a Pyth v2 price account, or `None` if the
+/// account isn't one, isn't trading, is older than `PRICE_MAX_AGE_SECONDS` or
+/// is less certain than `PRICE_MAX_CONFIDENCE_BPS`.
+pub fn read_pyth_price(feed: &AccountInfo, now: i64) -> Option<PythPrice> {
+  let data = feed.try_borrow_data().ok()?;
+  if data.len() < PYTH_PRICE_MIN_LEN {
+    return None;
+  }
+  let u32_at = |o: usize| u32::from_le_bytes(data[o..o + 4].try_into().unwrap());
+  let u64_at = |o: usize| u64::from_le_bytes(data[o..o + 8].try_into().unwrap());
+  if u32_at(0) != PYTH_MAGIC
+    || u32_at(8) != PYTH_PRICE_ACCOUNT
+    || u32_at(PYTH_AGG_STATUS_OFFSET) != PYTH_STATUS_TRADING
+  {
+    return None;
+  }
+
+  let timestamp = u64_at(PYTH_TIMESTAMP_OFFSET) as i64;
+  if now.saturating_sub(timestamp) > PRICE_MAX_AGE_SECONDS {
+    return None;
+  }
+  let price = PythPrice {
+    price: u64_at(PYTH_AGG_PRICE_OFFSET) as i64,
+    conf: u64_at(PYTH_AGG_CONF_OFFSET),
+    expo: u32_at(PYTH_EXPO_OFFSET) as i32,
+  };
+  if price.price <= 0
+    || (price.conf as u128) * 10_000 > (price.price as u128) * PRICE_MAX_CONFIDENCE_BPS as u128
+  {
+    return None;
+  }
+  Some(price)
+}
+
+/// USD value, with `USD_DECIMALS` decimals, of `amount` base units of a mint
+/// with `decimals`. Saturates instead of overflowing.
+pub fn usd_value(amount: u64, decimals: u8, price: &PythPrice) -> u64 {
+  scale_by_price(amount, price, USD_DECIMALS as i32 - decimals as i32)
+}
+
+/// `amount` times `price`, shifted by `10^shift` on top of the price's own
+/// exponent. Saturates instead of overflowing.
+fn scale_by_price(amount: u64, price: &PythPrice, shift: i32) -> u64 {
+  let value = (amount as u128).saturating_mul(price.price as u128);
+  let scale = price.expo + shift;
+  let value = if scale >= 0 {
+    10u128
+      .checked_pow(scale as u32)
+      .map_or(u128::MAX, |factor| value.saturating_mul(factor))
+  } else {
+    10u128
+      .checked_pow(scale.unsigned_abs())
+      .map_or(0, |divisor| value / divisor)
+  };
+  u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+// Layout of an SPL stake pool's `StakePool` account.
+pub const STAKE_POOL_ACCOUNT_TYPE: u8 = 1;
+pub const STAKE_POOL_MINT_OFFSET: usize = 162;
+pub const STAKE_POOL_TOTAL_LAMPORTS_OFFSET: usize = 258;
+pub const STAKE_POOL_TOKEN_SUPPLY_OFFSET: usize = 266;
+pub const STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET: usize = 274;
+
+/// Reads the total lamports and pool token supply of the SPL stake pool
+/// minting `pool_mint`, or `None` if the account isn't one or wasn't updated
+/// this `epoch`.
+pub fn read_stake_pool_rate(
+  stake_pool: &AccountInfo,
+  pool_mint: &Pubkey,
+  epoch: u64,
+) -> Option<(u64, u64)> {
+  let data = stake_pool.try_borrow_data().ok()?;
+  if data.len() < STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET + 8 || data[0] != STAKE_POOL_ACCOUNT_TYPE {
+    return None;
+  }
+  let u64_at = |o: usize| u64::from_le_bytes(data[o..o + 8].try_into().unwrap());
+  if data[STAKE_POOL_MINT_OFFSET..STAKE_POOL_MINT_OFFSET + 32] != pool_mint.to_bytes()
+    || u64_at(STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET) != epoch
+  {
+    return None;
+  }
+  Some((
+    u64_at(STAKE_POOL_TOTAL_LAMPORTS_OFFSET),
+    u64_at(STAKE_POOL_TOKEN_SUPPLY_OFFSET),
+  ))
+}
+
+/// Value of `amount` base units of an interest-bearing `staking_mint` in its
+/// underlying asset, read from `oracle` as `source`. `None` if the oracle is
+/// unusable or stale.
+pub fn underlying_value(
+  amount: u64,
+  source: u8,
+  oracle: &AccountInfo,
+  staking_mint: &Pubkey,
+  clock: &Clock,
+) -> Option<u64> {
+  match source {
+    EXCHANGE_RATE_PYTH => {
+      read_pyth_price(oracle, clock.unix_timestamp).map(|price| scale_by_price(amount, &price, 0))
+    }
+    EXCHANGE_RATE_STAKE_POOL => {
+      let (total_lamports, supply) = read_stake_pool_rate(oracle, staking_mint, clock.epoch)?;
+      let value = (amount as u128)
+        .checked_mul(total_lamports as u128)?
+        .checked_div(supply as u128)?;
+      Some(u64::try_from(value).unwrap_or(u64::MAX))
+    }
+    _ => None,
+  }
+}
+
+/// Commitment and, once revealed, value of a Switchboard randomness account.
+pub struct Randomness {
+  pub seed_slot: u64,
+  pub reveal_slot: u64,
+  pub value: [u8; 32],
+}
+
+// Layout of a Switchboard on-demand `RandomnessAccountData` account.
+pub const SWITCHBOARD_RANDOMNESS_DISCRIMINATOR: [u8; 8] = [10, 66, 229, 135, 220, 239, 217, 114];
+pub const SWITCHBOARD_SEED_SLOT_OFFSET: usize = 104;
+pub const SWITCHBOARD_REVEAL_SLOT_OFFSET: usize = 144;
+pub const SWITCHBOARD_VALUE_OFFSET: usize = 152;
+pub const SWITCHBOARD_RANDOMNESS_MIN_LEN: usize = 184;
+
+// Tail of the accounts the `*_for_testing` oracle instructions write: this
+// marker, then the key of the signer allowed to rewrite the account.
+pub const TEST_ACCOUNT_DISCRIMINATOR: [u8; 8] = [200, 208, 249, 117, 197, 42, 20, 255];
+pub const TEST_ACCOUNT_TAIL_LEN: usize = 40;
+
+/// Whether `account` is a program-owned test account claimed by a
+/// `*_for_testing` instruction. Program accounts never end in the marker.
+pub fn is_test_account(account: &AccountInfo) -> bool {
+  let data = match account.try_borrow_data() {
+    Ok(data) => data,
+    Err(_) => return false,
+  };
+  *account.owner == crate::ID
+    && data.len() >= TEST_ACCOUNT_TAIL_LEN
+    && data[data.len() - TEST_ACCOUNT_TAIL_LEN..][..8] == TEST_ACCOUNT_DISCRIMINATOR
+}
+
+/// Reads a Switchboard on-demand randomness account, or `None` if the account
+/// isn't one. Local-testing builds also accept test accounts written by
+/// `write_randomness_for_testing`.
+pub fn read_randomness(account: &AccountInfo) -> Option<Randomness> {
+  let trusted_owner = *account.owner == switchboard_on_demand::ID
+    || (cfg!(feature = "local-testing") && is_test_account(account));
+  if !trusted_owner {
+    return None;
+  }
+  let data = account.try_borrow_data().ok()?;
+  if data.len() < SWITCHBOARD_RANDOMNESS_MIN_LEN
+    || data[..8] != SWITCHBOARD_RANDOMNESS_DISCRIMINATOR
+  {
+    return None;
+  }
+  let u64_at = |o: usize| u64::from_le_bytes(data[o..o + 8].try_into().unwrap());
+  Some(Randomness {
+    seed_slot: u64_at(SWITCHBOARD_SEED_SLOT_OFFSET),
+    reveal_slot: u64_at(SWITCHBOARD_REVEAL_SLOT_OFFSET),
+    value: data[SWITCHBOARD_VALUE_OFFSET..SWITCHBOARD_VALUE_OFFSET + 32]
+      .try_into()
+      .unwrap(),
+  })
+}
+
+/// Index of the entry `random` lands on when each entry is weighted by its
+/// balance, or `None` if no entry has a balance.
+pub fn weighted_pick(entries: &[LeaderboardEntry], random: u128) -> Option<usize> {
+  let total: u128 = entries.iter().map(|e| e.balance as u128).sum();
+  if total == 0 {
+    return None;
+  }
+
+  let mut target = random % total;
+  for (index, entry) in entries.iter().enumerate() {
+    if target < entry.balance as u128 {
+      return Some(index);
+    }
+    target -= entry.balance as u128;
+  }
+  None
+}
+
+/// Whether `data` and `route` describe a Jupiter `route` that `authority`
+/// signs, spending from `source`. Routes from any other account, and so of any
+/// other input mint, are refused.
+pub fn is_jupiter_route(
+  route: &[AccountInfo],
+  data: &[u8],
+  authority: &Pubkey,
+  source: &Pubkey,
+) -> bool {
+  data.starts_with(&JUPITER_ROUTE_DISCRIMINATOR)
+    && route.get(JUPITER_ROUTE_AUTHORITY_INDEX).map(|x| x.key) == Some(authority)
+    && route.get(JUPITER_ROUTE_SOURCE_INDEX).map(|x| x.key) == Some(source)
+}
+
+/// Runs a Jupiter `route` over `route`, signing for `authority` with
+/// `signer_seeds`. The client can't sign for the PDA, so its meta is upgraded
+/// here.
+pub fn invoke_jupiter_route<'info>(
+  jupiter_program: &AccountInfo<'info>,
+  route: &[AccountInfo<'info>],
+  data: Vec<u8>,
+  authority: &Pubkey,
+  signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+  let ix = Instruction {
+    program_id: jupiter::ID,
+    accounts: route
+      .iter()
+      .map(|x| AccountMeta {
+        pubkey: *x.key,
+        is_signer: x.is_signer || x.key == authority,
+        is_writable: x.is_writable,
+      })
+      .collect(),
+    data,
+  };
+  let mut infos = route.to_vec();
+  infos.push(jupiter_program.clone());
+  invoke_signed(&ix, &infos, signer_seeds)?;
+  Ok(())
+}
+
+/// Logs `memo` through the SPL Memo program, so that exchanges crediting the
+/// following transfer see it. Empty memos are skipped.
+pub fn write_memo<'a>(memo_program: &AccountInfo<'a>, memo: &str) -> Result<()> {
+  if memo.is_empty() {
+    return Ok(());
+  }
+  if memo.len() > MAX_MEMO_LEN {
+    return Err(ErrorCode::MemoTooLong.into());
+  }
+
+  let ix = Instruction {
+    program_id: memo_program::ID,
+    accounts: vec![],
+    data: memo.as_bytes().to_vec(),
+  };
+  invoke(&ix, &[memo_program.clone()])?;
+  Ok(())
+}
+
+/// Returns whether rewards may be paid into `reward_account`: anything goes
+/// without a beneficiary, otherwise it must be the beneficiary's associated
+/// token account.
+pub fn is_reward_destination(
+  user: &User,
+  reward_account: &Pubkey,
+  reward_mint: &Pubkey,
+  now: i64,
+) -> bool {
+  let beneficiary = user.beneficiary_at(now);
+  if beneficiary == Pubkey::default() {
+    return true;
+  }
+
+  return *reward_account == get_associated_token_address(&beneficiary, reward_mint);
+}
+
+/// Stricter `is_reward_destination` for claims the owner doesn't sign: the
+/// beneficiary's associated token account or, without one, the owner's.
+pub fn is_owner_reward_account(
+  user: &User,
+  reward_account: &Pubkey,
+  reward_mint: &Pubkey,
+  now: i64,
+) -> bool {
+  let mut recipient = user.beneficiary_at(now);
+  if recipient == Pubkey::default() {
+    recipient = user.owner;
+  }
+
+  return *reward_account == get_associated_token_address(&recipient, reward_mint);
+}
+
+/// Returns whether `claim_authority` may claim for `user` via CPI: the PDA
+/// of the user's approved claim program, if any.
+pub fn is_claim_authority(user: &User, user_key: &Pubkey, claim_authority: &Pubkey) -> bool {
+  if user.claim_program == Pubkey::default() {
+    return false;
+  }
+
+  let (address, _) = claim_authority_address(user_key, &user.claim_program);
+  return *claim_authority == address;
+}
+
+/// Returns whether `owner` holds an NFT of the verified `collection`, proven by
+/// its token account and Metaplex metadata account. Any invalid proof is
+/// treated as not holding one.
+pub fn holds_collection_nft<'a>(
+  owner: &Pubkey,
+  collection: &Pubkey,
+  token_account: &AccountInfo<'a>,
+  metadata_account: &AccountInfo<'a>,
+) -> bool {
+  if *collection == Pubkey::default() {
+    return false;
+  }
+
+  let token_account: Account<TokenAccount> = match Account::try_from(token_account) {
+    Ok(account) => account,
+    Err(_) => return false,
+  };
+  if token_account.owner != *owner || token_account.amount == 0 {
+    return false;
+  }
+
+  let (metadata_address, _) = Pubkey::find_program_address(
+    &[
+      b"metadata",
+      mpl_token_metadata::ID.as_ref(),
+      token_account.mint.as_ref(),
+    ],
+    &mpl_token_metadata::ID,
+  );
+  if metadata_account.key() != metadata_address
+    || *metadata_account.owner != mpl_token_metadata::ID
+  {
+    return false;
+  }
+
+  return match Metadata::from_account_info(metadata_account) {
+    Ok(metadata) => {
+      metadata.mint == token_account.mint
+        && matches!(metadata.collection, Some(c) if c.verified && c.key == *collection)
+    }
+    Err(_) => false,
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn accepts_fee_and_close_authority_extensions() {
+    assert!(check_mint_extension_types(&[]).is_ok());
+    assert!(check_mint_extension_types(&[
+      ExtensionType::TransferFeeConfig,
+      ExtensionType::MintCloseAuthority,
+    ])
+    .is_ok());
+  }
+
+  #[test]
+  fn rejects_confidential_transfers() {
+    assert_eq!(
+      check_mint_extension_types(&[
+        ExtensionType::TransferFeeConfig,
+        ExtensionType::ConfidentialTransferMint,
+      ])
+      .unwrap_err(),
+      ErrorCode::ConfidentialTransfersUnsupported.into(),
+    );
+  }
+
+  #[test]
+  fn rejects_unreviewed_extensions() {
+    assert_eq!(
+      check_mint_extension_types(&[ExtensionType::DefaultAccountState]).unwrap_err(),
+      ErrorCode::UnsupportedMintExtension.into(),
+    );
+  }
+
+  #[test]
+  fn clocks_before_the_epoch_read_as_zero() {
+    assert_eq!(unix_timestamp_to_u64(-1), 0);
+    assert_eq!(unix_timestamp_to_u64(i64::MIN), 0);
+    assert_eq!(unix_timestamp_to_u64(0), 0);
+    assert_eq!(unix_timestamp_to_u64(1_700_000_000), 1_700_000_000);
+    assert_eq!(unix_timestamp_to_u64(i64::MAX), i64::MAX as u64);
+
+    assert_eq!(day_index(-SECONDS_PER_DAY), 0);
+    assert_eq!(day_index(SECONDS_PER_DAY - 1), 0);
+    assert_eq!(day_index(SECONDS_PER_DAY), 1);
+  }
+}
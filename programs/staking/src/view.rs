@@ -0,0 +1,134 @@
+use anchor_lang::prelude::*;
+
+/// Return data of `get_user_summary`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct UserSummary {
+    /// Owner the positions belong to.
+    pub owner: Pubkey,
+    /// Number of positions summed.
+    pub positions: u8,
+    /// Combined staked balance.
+    pub total_staked: u64,
+    /// Combined pending rewards as of now.
+    pub total_pending: u64,
+    /// Earliest maturity among positions with a balance, zero if none.
+    pub earliest_unlock: u64,
+}
+
+/// Return data of `simulate_unstake`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct UnstakeSimulation {
+    /// Amount requested to unstake.
+    pub amount: u64,
+    /// Early-unstake penalty that would be charged.
+    pub penalty: u64,
+    /// Effective penalty bps applied.
+    pub penalty_bps: u16,
+    /// Amount that would reach the user.
+    pub net_amount: u64,
+    /// Pending rewards as of now, kept by the user after unstaking.
+    pub pending_rewards: u64,
+    /// Whether the position has reached maturity.
+    pub matured: bool,
+    /// Whether the user is exempt from the early-unstake penalty.
+    pub penalty_exempt: bool,
+}
+
+/// Return data of `get_config`. Fields are only ever appended, with
+/// `version` bumped, so decoders of an older layout can read its prefix.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct PoolConfig {
+    /// `POOL_CONFIG_VERSION` the layout follows.
+    pub version: u8,
+    pub authority: Pubkey,
+    /// Authorized funders besides the authority.
+    pub funders: Vec<Pubkey>,
+    pub reward_duration: u64,
+    pub lock_period: u64,
+    pub no_tier: bool,
+    pub paused: bool,
+    pub halted: bool,
+    pub halted_until: i64,
+    pub closing: bool,
+    pub slashing_enabled: bool,
+    pub maturity_enforcement_enabled: bool,
+    pub max_maturity_extension: u64,
+    pub require_attestation: bool,
+    pub attestor: Pubkey,
+    pub require_active_rewards: bool,
+    pub topup_grace_seconds: u64,
+    pub topup_grace_bps: u16,
+    pub max_penalty_bps: u16,
+    pub insurance_share_bps: u16,
+    pub compound_tip_bps: u16,
+    pub min_unstake_delay_slots: u64,
+    pub max_per_window: u64,
+    pub dust_threshold: u64,
+    pub min_fund_amount: u64,
+    pub shared_mint: bool,
+    pub voter_weight_tier_boost: bool,
+    pub price_feed: Pubkey,
+    pub usd_tier_thresholds: [u64; 7],
+    pub nft_collection: Pubkey,
+    pub nft_weight: u64,
+    pub exchange_rate_oracle: Pubkey,
+    pub exchange_rate_source: u8,
+    pub auto_claim_fee: u64,
+}
+
+/// Return data of `get_version`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct ProgramVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+    /// `FEATURE_*` bits the program supports.
+    pub features: u64,
+}
+
+/// Return data of `get_user_stats`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct UserStats {
+    /// Pending rewards as of now.
+    pub pending_rewards: u64,
+    /// Seconds until the position matures, zero once it has.
+    pub seconds_until_maturity: u64,
+    /// Current tier.
+    pub tier: u8,
+    /// Staked balance.
+    pub balance_staked: u64,
+}
+
+/// Return data of `get_pool_stats`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct PoolStats {
+    /// Tokens staked in the pool.
+    pub total_staked: u64,
+    /// Reward tokens emitted per second, zero once the period has ended.
+    pub reward_rate: u64,
+    /// Seconds left in the current reward period.
+    pub seconds_remaining: u64,
+    /// Rewards still to be emitted in the current period.
+    pub rewards_remaining: u64,
+    /// Annualized reward tokens per staked token in bps, zero when nothing
+    /// is staked.
+    pub apr_bps: u64,
+    /// Users with an account in the pool.
+    pub user_stake_count: u32,
+    /// Whether staking is paused.
+    pub paused: bool,
+    /// Whether the circuit breaker is engaged.
+    pub halted: bool,
+    /// Whether the pool is winding down.
+    pub closing: bool,
+    /// Lifetime funding per `funders` slot, with the authority last.
+    pub funder_contributions: [u64; 6],
+    /// Funding by funders since removed.
+    pub removed_funder_contributions: u64,
+    /// Lifetime deposit fees collected.
+    pub total_deposit_fees: u64,
+    /// Lifetime claim fees collected.
+    pub total_claim_fees: u64,
+    /// Lifetime early-unstake penalties collected.
+    pub total_penalties: u64,
+}
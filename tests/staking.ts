@@ -1,15 +1,64 @@
 import * as anchor from '@project-serum/anchor';
 import { Program } from '@project-serum/anchor';
-import { TOKEN_PROGRAM_ID, Token } from '@solana/spl-token';
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  Token,
+} from '@solana/spl-token';
+import {
+  GovernanceConfig,
+  GoverningTokenConfigAccountArgs,
+  GoverningTokenType,
+  MintMaxVoteWeightSource,
+  Vote,
+  VoteRecord,
+  VoteThreshold,
+  VoteThresholdType,
+  VoteTipping,
+  VoteType,
+  YesNoVote,
+  getGovernanceAccount,
+  getGovernanceProgramVersion,
+  getVoteRecordAddress,
+  withCastVote,
+  withCreateGovernance,
+  withCreateProposal,
+  withCreateRealm,
+  withCreateTokenOwnerRecord,
+  withSignOffProposal,
+} from '@solana/spl-governance';
 import assert from 'assert';
+import { CpiStaker } from '../target/types/cpi_staker';
+import { MockClaimer } from '../target/types/mock_claimer';
 import { Staking } from '../target/types/staking';
-import { createMint } from './utils';
+import {
+  GOVERNANCE_PROGRAM_ID,
+  MEMO_PROGRAM_ID,
+  captureEvents,
+  clockDayIndex,
+  createMint,
+  createMint2022,
+  cpiEvents,
+  dailyStatsAddress,
+  defaultAccountStateExtension,
+  earned,
+  eventAuthorityAddress,
+  readU64,
+  rewardPerToken,
+  simulateReturnData,
+  sleep,
+  tokenBalance,
+  transferFeeExtension,
+  unitsConsumed,
+} from './utils';
 
 describe('staking', () => {
   const provider = anchor.Provider.env();
   anchor.setProvider(provider);
 
   const stakingProgram = anchor.workspace.Staking as Program<Staking>;
+  const mockClaimer = anchor.workspace.MockClaimer as Program<MockClaimer>;
+  const cpiStaker = anchor.workspace.CpiStaker as Program<CpiStaker>;
   let stakingMint: Token;
   let stakingVault: anchor.web3.PublicKey;
   let rewardMint: Token;
@@ -22,10 +71,12 @@ describe('staking', () => {
   const lockPeriod = new anchor.BN(0);
   const rewardDuration = new anchor.BN(86400 * 7);
   let wallet: anchor.Wallet = provider.wallet as anchor.Wallet;
+  let eventAuthority: anchor.web3.PublicKey;
 
   before(async () => {
     stakingMint = await createMint(provider, 4);
     rewardMint = await createMint(provider, 4);
+    eventAuthority = await eventAuthorityAddress(stakingProgram.programId);
   });
 
   beforeEach(async () => {
@@ -71,6 +122,196 @@ describe('staking', () => {
       assert.equal(poolAccount.userStakeCount.toString(), '0');
       assert.equal(poolAccount.funders.length, 5);
       assert.equal(poolAccount.noTier, false);
+      assert.equal(poolAccount.sharedMint, false);
+      assert.deepEqual(poolAccount.createdWithVersion, [0, 1, 0]);
+    });
+
+    it('rejects the default public key as authority', async () => {
+      await assert.rejects(
+        initializePool(false, { authority: anchor.web3.PublicKey.default }),
+      );
+    });
+
+    it('rejects a pool signer with a non-canonical bump', async () => {
+      for (let bump = nonce - 1; bump >= 0; bump--) {
+        try {
+          poolSigner = await anchor.web3.PublicKey.createProgramAddress(
+            [pool.publicKey.toBuffer(), Buffer.from([bump])],
+            stakingProgram.programId,
+          );
+          break;
+        } catch (e) {
+          // On the curve, try the next bump.
+        }
+      }
+      stakingVault = await stakingMint.createAccount(poolSigner);
+      rewardVault = await rewardMint.createAccount(poolSigner);
+
+      await assert.rejects(initializePool(false));
+    });
+  });
+
+  describe('initialization bounds', () => {
+    const tenYears = new anchor.BN(10 * 365 * 86400);
+
+    it('accepts a lock period of ten years', async () => {
+      await initializePool(false, { lockPeriod: tenYears });
+    });
+
+    it('rejects a lock period above ten years', async () => {
+      await assert.rejects(
+        initializePool(false, { lockPeriod: tenYears.addn(1) }),
+      );
+    });
+
+    it('accepts a reward duration of ten years', async () => {
+      await initializePool(false, { rewardDuration: tenYears });
+    });
+
+    it('rejects a reward duration above ten years', async () => {
+      await assert.rejects(
+        initializePool(false, { rewardDuration: tenYears.addn(1) }),
+      );
+    });
+  });
+
+  describe('freezable mints', () => {
+    let freezableMint: Token;
+
+    before(async () => {
+      freezableMint = await createMint(provider, 4, wallet.publicKey);
+    });
+
+    const stakeFrom = async (stakeFromAccount: anchor.web3.PublicKey) =>
+      stakingProgram.rpc.stake(
+        await today(),
+        new anchor.BN(2_000_000_000),
+        new anchor.BN(0),
+        new anchor.BN(0),
+        {
+          accounts: {
+            pool: pool.publicKey,
+            stakingVault,
+            user,
+            owner: wallet.publicKey,
+            stakeFromAccount,
+            poolSigner,
+            ...(await dailyStatsAccounts()),
+            tokenProgram: TOKEN_PROGRAM_ID,
+            stakingMint: freezableMint.publicKey,
+            priceFeed: pool.publicKey,
+            memoProgram: MEMO_PROGRAM_ID,
+            eventAuthority,
+            program: stakingProgram.programId,
+          },
+        },
+      );
+
+    it('records mints without a freeze authority', async () => {
+      await initializePool(false, { strictMint: true });
+
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.stakingMintFreezable, false);
+      assert.equal(poolAccount.rewardMintFreezable, false);
+    });
+
+    it('records freezable mints', async () => {
+      stakingVault = await freezableMint.createAccount(poolSigner);
+      rewardVault = await freezableMint.createAccount(poolSigner);
+      const options = { stakingMint: freezableMint, rewardMint: freezableMint };
+      await assert.rejects(
+        initializePool(false, { ...options, strictMint: true }),
+      );
+      await initializePool(false, options);
+
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.stakingMintFreezable, true);
+      assert.equal(poolAccount.rewardMintFreezable, true);
+    });
+
+    it('rejects stakes from a frozen account', async () => {
+      stakingVault = await freezableMint.createAccount(poolSigner);
+      await initializePool(false, { stakingMint: freezableMint });
+      await createUser();
+      const frozen = await freezableMint.createAccount(wallet.publicKey);
+      await freezableMint.mintTo(frozen, wallet.payer, [], 2_000_000_000);
+      await freezableMint.freezeAccount(frozen, wallet.payer, []);
+
+      await assert.rejects(
+        stakeFrom(frozen),
+        (err: any) =>
+          err.msg ===
+          "Source token account is frozen by the mint's freeze authority.",
+      );
+    });
+
+    it('rejects stakes into a frozen vault and flags the pool', async () => {
+      stakingVault = await freezableMint.createAccount(poolSigner);
+      await initializePool(false, { stakingMint: freezableMint });
+      await createUser();
+      const from = await freezableMint.createAccount(wallet.publicKey);
+      await freezableMint.mintTo(from, wallet.payer, [], 4_000_000_000);
+      await stakeFrom(from);
+
+      await freezableMint.freezeAccount(stakingVault, wallet.payer, []);
+      await assert.rejects(
+        stakeFrom(from),
+        (err: any) =>
+          err.msg === "Pool vault is frozen by the mint's freeze authority.",
+      );
+      await assertInvariants();
+      let poolAccount = await stakingProgram.account.pool.fetch(pool.publicKey);
+      assert.equal(poolAccount.vaultFrozen, true);
+
+      await freezableMint.thawAccount(stakingVault, wallet.payer, []);
+      await assertInvariants();
+      poolAccount = await stakingProgram.account.pool.fetch(pool.publicKey);
+      assert.equal(poolAccount.vaultFrozen, false);
+      await stakeFrom(from);
+    });
+  });
+
+  describe('same-mint pool', () => {
+    it('rejects a shared staking and reward vault', async () => {
+      rewardVault = stakingVault;
+      await assert.rejects(initializePool(false, { rewardMint: stakingMint }));
+    });
+
+    it('never pays staked principal out as rewards', async () => {
+      rewardVault = await stakingMint.createAccount(poolSigner);
+      await initializePool(false, { rewardMint: stakingMint });
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.sharedMint, true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const from = await stakingMint.createAccount(wallet.publicKey);
+      await stakingMint.mintTo(from, wallet.payer, [], 604_800);
+      await stakingProgram.rpc.fund(new anchor.BN(604_800), {
+        accounts: {
+          pool: pool.publicKey,
+          rewardVault,
+          funder: wallet.publicKey,
+          from,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: stakingMint.publicKey,
+        },
+      });
+      await sleep(2000);
+
+      const rewardAccount = await stakingMint.createAccount(wallet.publicKey);
+      await claim(rewardAccount);
+      const claimed = await stakingMint.getAccountInfo(rewardAccount);
+      assert.ok(claimed.amount.lten(604_800));
+      const vault = await stakingMint.getAccountInfo(stakingVault);
+      assert.equal(vault.amount.toString(), '2000000000');
     });
   });
 
@@ -81,88 +322,4997 @@ describe('staking', () => {
 
       const amount = new anchor.BN(2_000_000_000);
 
-      await stake(amount);
+      await stake(amount);
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.pool.toString(), pool.publicKey.toString());
+      assert.equal(userAccount.owner.toString(), wallet.publicKey.toString());
+      assert.equal(userAccount.rewardPerTokenComplete.toString(), '0');
+      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
+      assert.equal(userAccount.balanceStaked.toString(), amount.toString());
+      assert.equal(userAccount.tier.toString(), '1');
+    });
+
+    it('increase tier', async () => {
+      await initializePool(false);
+      await createUser();
+
+      const amount = new anchor.BN(2_000_000_000);
+
+      await stake(amount);
+      await stake(amount);
+
+      let userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier.toString(), '1');
+
+      await stake(amount);
+
+      userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier.toString(), '2');
+    });
+
+    it('do not update tier if no allocation', async () => {
+      await initializePool(true);
+      await createUser();
+
+      const amount = new anchor.BN(2_000_000_000);
+
+      await stake(amount);
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier.toString(), '0');
+    });
+
+    it('respects the caller maturity bound', async () => {
+      await initializePool(false, { lockPeriod: new anchor.BN(86400) });
+      await createUser();
+
+      const amount = new anchor.BN(2_000_000_000);
+
+      await stake(amount);
+      const maturity = (await stakingProgram.account.user.fetch(user))
+        .maturityTime;
+      await sleep(2000);
+
+      await assert.rejects(stake(amount, maturity));
+      await stake(amount);
+    });
+  });
+
+  describe('unstake and claim', () => {
+    const unstakeAndClaim = async (
+      amount: anchor.BN,
+      rewardAccount: anchor.web3.PublicKey,
+    ) =>
+      stakingProgram.rpc.unstakeAndClaim(await today(), amount, {
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          rewardVault,
+          user,
+          owner: wallet.publicKey,
+          stakeFromAccount: ownerTokenAccount,
+          rewardAccount,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          ...(await dailyStatsAccounts()),
+          stakingMint: stakingMint.publicKey,
+          rewardMint: rewardMint.publicKey,
+        },
+      });
+
+    it('pays pending rewards while unstaking', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      await unstakeAndClaim(new anchor.BN(2_000_000_000), rewardAccount);
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '0');
+      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+      assert.equal(
+        userAccount.totalRewardsClaimed.toString(),
+        rewards.amount.toString(),
+      );
+      const stats = await stakingProgram.account.dailyStats.fetch(
+        (await dailyStatsAccounts()).dailyStats,
+      );
+      assert.equal(stats.unstakeVolume.toString(), '2000000000');
+      assert.equal(stats.claimVolume.toString(), rewards.amount.toString());
+    });
+
+    it("rejects a reward account the owner doesn't hold", async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+
+      const stranger = anchor.web3.Keypair.generate();
+      const rewardAccount = await rewardMint.createAccount(stranger.publicKey);
+      await assert.rejects(
+        unstakeAndClaim(new anchor.BN(2_000_000_000), rewardAccount),
+        (err: any) =>
+          err.msg ===
+          "The reward account must be the owner's account of the reward mint.",
+      );
+    });
+  });
+
+  describe('insurance', () => {
+    let insuranceVault: anchor.web3.PublicKey;
+
+    beforeEach(async () => {
+      insuranceVault = await rewardMint.createAccount(poolSigner);
+    });
+
+    it('rejects a penalty share when mints differ', async () => {
+      await initializePool(false);
+
+      await assert.rejects(setInsurance(insuranceVault, 5000));
+    });
+
+    it('covers a shortfall from the insurance vault', async () => {
+      await initializePool(false);
+      await setInsurance(insuranceVault, 0);
+      await rewardMint.mintTo(insuranceVault, wallet.payer, [], 1_000_000);
+
+      await stakingProgram.rpc.coverShortfall(new anchor.BN(400_000), {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          insuranceVault,
+          rewardVault,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+        },
+      });
+
+      const vault = await rewardMint.getAccountInfo(rewardVault);
+      assert.equal(vault.amount.toString(), '400000');
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.insuranceUsed.toString(), '400000');
+    });
+  });
+
+  describe('unstake allowance', () => {
+    it('lets an approved spender unstake up to the allowance', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+
+      const spender = anchor.web3.Keypair.generate();
+      const destination = await stakingMint.createAccount(wallet.publicKey);
+      await stakingProgram.rpc.approveUnstake(
+        spender.publicKey,
+        new anchor.BN(2_000_000_000),
+        {
+          accounts: {
+            pool: pool.publicKey,
+            user,
+            owner: wallet.publicKey,
+            destination,
+          },
+        },
+      );
+
+      await unstakeBySpender(spender, destination, 1_500_000_000);
+      await assert.rejects(
+        unstakeBySpender(spender, destination, 1_000_000_000),
+      );
+
+      const received = await stakingMint.getAccountInfo(destination);
+      assert.equal(received.amount.toString(), '1500000000');
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.unstakeAllowance.toString(), '500000000');
+
+      await stakingProgram.rpc.revokeUnstakeAllowance({
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          owner: wallet.publicKey,
+        },
+      });
+      await assert.rejects(unstakeBySpender(spender, destination, 1));
+    });
+  });
+
+  describe('close user', () => {
+    const repairStakeCount = async (userStakeCount: number) => {
+      await stakingProgram.rpc.repairStakeCount(userStakeCount, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+    };
+
+    it('reports a drifted stake count until repaired', async () => {
+      await initializePool(false);
+      await createUser();
+      await repairStakeCount(0);
+
+      await assert.rejects(closeUser());
+
+      await repairStakeCount(1);
+      await closeUser();
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.userStakeCount, 0);
+    });
+
+    it('forgives pending rewards up to the dust threshold', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await unstake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(closeUser());
+
+      await stakingProgram.rpc.setDustThreshold(new anchor.BN(1_000_000_000), {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+        },
+      });
+      await closeUser();
+
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.ok(poolAccount.forgivenDust.gtn(0));
+      assert.equal(poolAccount.userStakeCount, 0);
+    });
+
+    it('fails until freshly accrued rewards are claimed', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await unstake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(closeUser());
+      await claim(await rewardMint.createAccount(wallet.publicKey));
+      await closeUser();
+    });
+
+    it('refunds rent to the sponsor that paid it', async () => {
+      const sponsor = anchor.web3.Keypair.generate();
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: wallet.publicKey,
+            toPubkey: sponsor.publicKey,
+            lamports: anchor.web3.LAMPORTS_PER_SOL,
+          }),
+        ),
+      );
+
+      await initializePool(false);
+      await createUser(sponsor);
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(
+        userAccount.rentPayer.toString(),
+        sponsor.publicKey.toString(),
+      );
+
+      await assert.rejects(closeUser());
+      const before = await provider.connection.getBalance(sponsor.publicKey);
+      await closeUser(sponsor.publicKey);
+      const after = await provider.connection.getBalance(sponsor.publicKey);
+      assert.ok(after > before);
+    });
+
+    it('redirects rent to an authorized recipient', async () => {
+      const recipient = anchor.web3.Keypair.generate().publicKey;
+      const redirect = [
+        { pubkey: recipient, isWritable: true, isSigner: false },
+      ];
+
+      await initializePool(false);
+      await createUser();
+      await closeUser(wallet.publicKey, redirect);
+      assert.ok((await provider.connection.getBalance(recipient)) > 0);
+
+      const sponsor = anchor.web3.Keypair.generate();
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: wallet.publicKey,
+            toPubkey: sponsor.publicKey,
+            lamports: anchor.web3.LAMPORTS_PER_SOL,
+          }),
+        ),
+      );
+      await createUser(sponsor);
+      await assert.rejects(closeUser(sponsor.publicKey, redirect));
+
+      const sponsorRedirect = [
+        ...redirect,
+        { pubkey: sponsor.publicKey, isWritable: true, isSigner: true },
+      ];
+      const before = await provider.connection.getBalance(recipient);
+      await closeUser(sponsor.publicKey, sponsorRedirect, [sponsor]);
+      assert.ok((await provider.connection.getBalance(recipient)) > before);
+    });
+  });
+
+  describe('auto-compound', () => {
+    it('compounds rewards for opted-in users only', async () => {
+      rewardVault = await stakingMint.createAccount(poolSigner);
+      await initializePool(false, { rewardMint: stakingMint });
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const from = await stakingMint.createAccount(wallet.publicKey);
+      await stakingMint.mintTo(from, wallet.payer, [], 604_800_000_000);
+      await stakingProgram.rpc.fund(new anchor.BN(604_800_000_000), {
+        accounts: {
+          pool: pool.publicKey,
+          rewardVault,
+          funder: wallet.publicKey,
+          from,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: stakingMint.publicKey,
+        },
+      });
+      await sleep(2000);
+
+      await assert.rejects(crankCompound());
+
+      await stakingProgram.rpc.setAutoCompound(true, {
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          owner: wallet.publicKey,
+        },
+      });
+      await crankCompound();
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.ok(userAccount.balanceStaked.gt(new anchor.BN(2_000_000_000)));
+      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
+    });
+  });
+
+  describe('auto-claim', () => {
+    const keeper = anchor.web3.Keypair.generate();
+
+    const setAutoClaim = (enabled: boolean, minAmount: anchor.BN) =>
+      stakingProgram.rpc.setAutoClaim(enabled, minAmount, {
+        accounts: { pool: pool.publicKey, user, owner: wallet.publicKey },
+      });
+    const autoClaim = async (tipAccount: anchor.web3.PublicKey) => {
+      const rewardAccount = (
+        await rewardMint.getOrCreateAssociatedAccountInfo(wallet.publicKey)
+      ).address;
+      await stakingProgram.rpc.autoClaim({
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          rewardVault,
+          user,
+          rewardAccount,
+          cranker: keeper.publicKey,
+          tipAccount,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+        },
+        signers: [keeper],
+      });
+      return rewardAccount;
+    };
+
+    it('claims to the owner for opted-in users only', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      const tipAccount = await rewardMint.createAccount(keeper.publicKey);
+
+      await assert.rejects(
+        autoClaim(tipAccount),
+        (err: any) => err.msg === 'User has not opted in to auto-claim.',
+      );
+
+      await setAutoClaim(true, new anchor.BN(0));
+      await stakingProgram.rpc.setAutoClaimFee(new anchor.BN(1_000), {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+      const before = (
+        await rewardMint.getOrCreateAssociatedAccountInfo(wallet.publicKey)
+      ).amount;
+      const rewardAccount = await autoClaim(tipAccount);
+
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gt(before));
+      const tip = await rewardMint.getAccountInfo(tipAccount);
+      assert.equal(tip.amount.toString(), '1000');
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
+    });
+
+    it('waits for pending rewards above the minimum', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await setAutoClaim(true, new anchor.BN(604_800_000_000));
+
+      await assert.rejects(
+        autoClaim(await rewardMint.createAccount(keeper.publicKey)),
+        (err: any) =>
+          err.msg === 'Pending rewards do not exceed the auto-claim minimum.',
+      );
+    });
+  });
+
+  describe('guardian freeze', () => {
+    it('blocks unstaking until the guardian unfreezes', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const guardian = anchor.web3.Keypair.generate();
+      const accounts = {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
+      };
+      await assert.rejects(stakingProgram.rpc.freezeAccount({ accounts }));
+      await stakingProgram.rpc.setGuardian(guardian.publicKey, { accounts });
+      await stakingProgram.rpc.freezeAccount({ accounts });
+
+      await assert.rejects(unstake(new anchor.BN(1_000_000_000)));
+      await stake(new anchor.BN(1_000_000_000));
+      await assert.rejects(
+        stakingProgram.rpc.setGuardian(anchor.web3.PublicKey.default, {
+          accounts,
+        }),
+      );
+
+      await stakingProgram.rpc.unfreezeAccount({
+        accounts: { user, guardian: guardian.publicKey },
+        signers: [guardian],
+      });
+      await unstake(new anchor.BN(1_000_000_000));
+    });
+  });
+
+  describe('beneficiary', () => {
+    it('applies a new beneficiary only after the timelock', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+
+      const beneficiary = anchor.web3.Keypair.generate();
+      await stakingProgram.rpc.setBeneficiary(beneficiary.publicKey, {
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          owner: wallet.publicKey,
+        },
+      });
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(
+        userAccount.nextBeneficiary.toString(),
+        beneficiary.publicKey.toString(),
+      );
+      assert.equal(
+        userAccount.beneficiary.toString(),
+        anchor.web3.PublicKey.default.toString(),
+      );
+
+      // Still inside the timelock, so the owner's account is accepted.
+      await sleep(1000);
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      await stakingProgram.rpc.claim(await today(), new anchor.BN(0), '', {
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          rewardVault,
+          user,
+          owner: wallet.publicKey,
+          rewardAccount,
+          poolSigner,
+          ...(await dailyStatsAccounts()),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+          memoProgram: MEMO_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          eventAuthority,
+          program: stakingProgram.programId,
+        },
+      });
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+    });
+  });
+
+  describe('operations delegate', () => {
+    let delegate: anchor.web3.Keypair;
+    let delegateTokenAccount: anchor.web3.PublicKey;
+
+    beforeEach(async () => {
+      await initializePool(false);
+      await createUser();
+
+      delegate = anchor.web3.Keypair.generate();
+      delegateTokenAccount = await stakingMint.createAccount(
+        delegate.publicKey,
+      );
+      await stakingMint.mintTo(
+        delegateTokenAccount,
+        wallet.payer,
+        [],
+        2_000_000_000,
+      );
+      await stakingProgram.rpc.setOperationsDelegate(delegate.publicKey, {
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          owner: wallet.publicKey,
+        },
+      });
+    });
+
+    it('stakes and unstakes back to the owner', async () => {
+      await stakingProgram.rpc.delegateStake(
+        await today(),
+        new anchor.BN(2_000_000_000),
+        new anchor.BN(0),
+        {
+          accounts: {
+            pool: pool.publicKey,
+            stakingVault,
+            user,
+            delegate: delegate.publicKey,
+            stakeFromAccount: delegateTokenAccount,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            stakingMint: stakingMint.publicKey,
+            ...(await dailyStatsAccounts()),
+            priceFeed: pool.publicKey,
+          },
+          signers: [delegate],
+        },
+      );
+
+      await delegateUnstake(delegate, ownerTokenAccount, 1_000_000_000);
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '1000000000');
+      assert.equal(userAccount.tier.toString(), '0');
+      const stats = await stakingProgram.account.dailyStats.fetch(
+        (await dailyStatsAccounts()).dailyStats,
+      );
+      assert.equal(stats.stakeVolume.toString(), '2000000000');
+      assert.equal(stats.unstakeVolume.toString(), '1000000000');
+    });
+
+    it('cannot unstake into its own account', async () => {
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(
+        delegateUnstake(delegate, delegateTokenAccount, 1_000_000_000),
+        (err: any) =>
+          err.msg === 'Withdrawals must go to a token account of the owner.',
+      );
+    });
+
+    it('cannot change the delegate', async () => {
+      await assert.rejects(
+        stakingProgram.rpc.setOperationsDelegate(delegate.publicKey, {
+          accounts: {
+            pool: pool.publicKey,
+            user,
+            owner: delegate.publicKey,
+          },
+          signers: [delegate],
+        }),
+        (err: any) => err.msg === 'A has_one constraint was violated',
+      );
+    });
+
+    it('cannot set a beneficiary', async () => {
+      await assert.rejects(
+        stakingProgram.rpc.setBeneficiary(delegate.publicKey, {
+          accounts: {
+            pool: pool.publicKey,
+            user,
+            owner: delegate.publicKey,
+          },
+          signers: [delegate],
+        }),
+        (err: any) => err.msg === 'A has_one constraint was violated',
+      );
+    });
+
+    it('cannot transfer the position', async () => {
+      await stake(new anchor.BN(2_000_000_000));
+      const second = await createSecondPool(false);
+      const [migration] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('migration'), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createMigration(false, {
+        accounts: {
+          migration,
+          oldPool: pool.publicKey,
+          newPool: second.pool,
+          oldAuthority: wallet.publicKey,
+          newAuthority: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      // Lets the delegate pay for a user account of its own.
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: wallet.publicKey,
+            toPubkey: delegate.publicKey,
+            lamports: anchor.web3.LAMPORTS_PER_SOL,
+          }),
+        ),
+      );
+      const [delegateUser] = await anchor.web3.PublicKey.findProgramAddress(
+        [delegate.publicKey.toBuffer(), second.pool.toBuffer()],
+        stakingProgram.programId,
+      );
+
+      await assert.rejects(
+        stakingProgram.rpc.migrateStake({
+          accounts: {
+            migration,
+            oldPool: pool.publicKey,
+            oldStakingVault: stakingVault,
+            oldRewardVault: rewardVault,
+            oldUser: user,
+            oldPoolSigner: poolSigner,
+            rewardAccount: await rewardMint.createAccount(delegate.publicKey),
+            newPool: second.pool,
+            newStakingVault: second.stakingVault,
+            newUser: delegateUser,
+            owner: delegate.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            stakingMint: stakingMint.publicKey,
+            rewardMint: rewardMint.publicKey,
+            rentPayer: wallet.publicKey,
+          },
+          signers: [delegate],
+        }),
+        (err: any) => err.msg === 'A has_one constraint was violated',
+      );
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '2000000000');
+    });
+  });
+
+  describe('user vesting', () => {
+    let vestingEscrow: anchor.web3.PublicKey;
+    let rewardAccount: anchor.web3.PublicKey;
+
+    beforeEach(async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+
+      [vestingEscrow] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('vesting'), user.toBuffer()],
+        stakingProgram.programId,
+      );
+      rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+    });
+
+    it('routes claims into the escrow until the cliff', async () => {
+      const now = Math.floor(Date.now() / 1000);
+      await setUserVesting(now + 86400, 86400);
+      await sleep(1000);
+
+      await assert.rejects(claim(rewardAccount));
+      await claim(rewardAccount, [
+        { pubkey: vestingEscrow, isWritable: true, isSigner: false },
+      ]);
+
+      const escrow = await rewardMint.getAccountInfo(vestingEscrow);
+      assert.ok(escrow.amount.gtn(0));
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.equal(rewards.amount.toString(), '0');
+      await assert.rejects(releaseVested(vestingEscrow, rewardAccount));
+    });
+
+    it('releases vested rewards after the cliff', async () => {
+      const now = Math.floor(Date.now() / 1000);
+      await setUserVesting(now - 10, 0);
+      await sleep(1000);
+
+      await claim(rewardAccount, [
+        { pubkey: vestingEscrow, isWritable: true, isSigner: false },
+      ]);
+      await releaseVested(vestingEscrow, rewardAccount);
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+      assert.equal(
+        rewards.amount.toString(),
+        userAccount.vestingReleased.toString(),
+      );
+    });
+  });
+
+  describe('effective weight', () => {
+    it('keeps user weights summing to the pool total', async () => {
+      await initializePool(false);
+      await createUser();
+
+      const other = anchor.web3.Keypair.generate();
+      const otherTokenAccount = await stakingMint.createAccount(
+        other.publicKey,
+      );
+      await stakingMint.mintTo(
+        otherTokenAccount,
+        wallet.payer,
+        [],
+        10_000_000_000,
+      );
+      const [otherUser] = await anchor.web3.PublicKey.findProgramAddress(
+        [other.publicKey.toBuffer(), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createUser({
+        accounts: {
+          pool: pool.publicKey,
+          user: otherUser,
+          owner: other.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+        signers: [other],
+      });
+      const otherAccounts = {
+        pool: pool.publicKey,
+        stakingVault,
+        user: otherUser,
+        owner: other.publicKey,
+        stakeFromAccount: otherTokenAccount,
+        poolSigner,
+        ...(await dailyStatsAccounts()),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        stakingMint: stakingMint.publicKey,
+        priceFeed: pool.publicKey,
+        memoProgram: MEMO_PROGRAM_ID,
+        eventAuthority,
+        program: stakingProgram.programId,
+      };
+
+      await stake(new anchor.BN(6_000_000_000));
+      await stakingProgram.rpc.stake(
+        await today(),
+        new anchor.BN(3_000_000_000),
+        new anchor.BN(0),
+        new anchor.BN(0),
+        {
+          accounts: otherAccounts,
+          signers: [other],
+        },
+      );
+      await unstake(new anchor.BN(5_000_000_000));
+      await stakingProgram.rpc.unstake(
+        await today(),
+        new anchor.BN(1_000_000_000),
+        new anchor.BN(0),
+        '',
+        {
+          accounts: otherAccounts,
+          signers: [other],
+        },
+      );
+      await stake(new anchor.BN(20_000_000_000));
+
+      const users = await stakingProgram.account.user.fetchMultiple([
+        user,
+        otherUser,
+      ]);
+      const sum = users.reduce(
+        (total, u: any) => total.add(u.effectiveWeight),
+        new anchor.BN(0),
+      );
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(sum.toString(), poolAccount.totalWeight.toString());
+      assert.equal(poolAccount.totalWeight.toString(), '23000000000');
+    });
+  });
+
+  describe('claim', () => {
+    it('records the claim time only when rewards are paid', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+
+      await claim(rewardAccount);
+      let userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.lastClaimTime.toString(), '0');
+
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await claim(rewardAccount);
+      userAccount = await stakingProgram.account.user.fetch(user);
+      assert.ok(userAccount.lastClaimTime.gtn(0));
+    });
+
+    it('tracks rewards owed to users on the pool', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await unstake(new anchor.BN(2_000_000_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      let poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.ok(userAccount.rewardPerTokenPending.gtn(0));
+      assert.equal(
+        poolAccount.aggregatePending.toString(),
+        userAccount.rewardPerTokenPending.toString(),
+      );
+
+      await claim(await rewardMint.createAccount(wallet.publicKey));
+      poolAccount = await stakingProgram.account.pool.fetch(pool.publicKey);
+      assert.equal(poolAccount.aggregatePending.toString(), '0');
+    });
+
+    it('rejects reward accounts of another mint or owner', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const wrongMint = await stakingMint.createAccount(wallet.publicKey);
+      await assert.rejects(claim(wrongMint));
+
+      const thirdParty = await rewardMint.createAccount(
+        anchor.web3.Keypair.generate().publicKey,
+      );
+      await assert.rejects(claim(thirdParty));
+    });
+
+    it('creates a missing associated reward account', async () => {
+      const mint = await createMint(provider, 4);
+      rewardVault = await mint.createAccount(poolSigner);
+      await initializePool(false, { rewardMint: mint });
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      const from = await mint.createAccount(wallet.publicKey);
+      await mint.mintTo(from, wallet.payer, [], 604_800_000_000);
+      await stakingProgram.rpc.fund(new anchor.BN(604_800_000_000), {
+        accounts: {
+          pool: pool.publicKey,
+          rewardVault,
+          funder: wallet.publicKey,
+          from,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: mint.publicKey,
+        },
+      });
+      await sleep(2000);
+
+      const rewardAccount = await Token.getAssociatedTokenAddress(
+        ASSOCIATED_TOKEN_PROGRAM_ID,
+        TOKEN_PROGRAM_ID,
+        mint.publicKey,
+        wallet.publicKey,
+      );
+      assert.equal(
+        await provider.connection.getAccountInfo(rewardAccount),
+        null,
+      );
+      await claim(rewardAccount);
+      const rewards = await mint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+
+      await sleep(1000);
+      await claim(rewardAccount);
+      const more = await mint.getAccountInfo(rewardAccount);
+      assert.ok(more.amount.gt(rewards.amount));
+    });
+
+    it('creates no reward account other than the associated one', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(
+        claim(anchor.web3.Keypair.generate().publicKey),
+        (err: any) =>
+          err.msg ===
+          "The reward account must be the owner's account of the reward mint.",
+      );
+    });
+  });
+
+  describe('claim via cpi', () => {
+    it('lets an approved program claim to the owner', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+
+      const [claimAuthority] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('claim_authority'), user.toBuffer()],
+        mockClaimer.programId,
+      );
+      const rewardAccount = (
+        await rewardMint.getOrCreateAssociatedAccountInfo(wallet.publicKey)
+      ).address;
+      const claimViaMock = async () => {
+        await mockClaimer.rpc.claim({
+          accounts: {
+            pool: pool.publicKey,
+            stakingVault,
+            rewardVault,
+            user,
+            owner: wallet.publicKey,
+            claimAuthority,
+            rewardAccount,
+            poolSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            rewardMint: rewardMint.publicKey,
+            stakingProgram: stakingProgram.programId,
+          },
+        });
+      };
+      const approveClaimProgram = async (
+        programId: anchor.web3.PublicKey,
+      ) => {
+        await stakingProgram.rpc.approveClaimProgram(programId, {
+          accounts: {
+            pool: pool.publicKey,
+            user,
+            owner: wallet.publicKey,
+          },
+        });
+      };
+
+      await assert.rejects(claimViaMock());
+
+      await approveClaimProgram(mockClaimer.programId);
+      await claimViaMock();
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+
+      await approveClaimProgram(anchor.web3.PublicKey.default);
+      await assert.rejects(claimViaMock());
+    });
+  });
+
+  describe('cpi staker', () => {
+    it('stakes, unstakes, claims and closes with a PDA owner', async () => {
+      await initializePool(false);
+      const [vaultAuthority] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('vault_authority'), wallet.publicKey.toBuffer()],
+        cpiStaker.programId,
+      );
+      const [position] = await anchor.web3.PublicKey.findProgramAddress(
+        [vaultAuthority.toBuffer(), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      const vaultTokenAccount = await stakingMint.createAccount(vaultAuthority);
+      await stakingMint.mintTo(
+        vaultTokenAccount,
+        wallet.payer,
+        [],
+        5_000_000_000,
+      );
+      // Created by the claim at the depositor's expense; the vault authority
+      // holds no lamports.
+      const vaultRewardAccount = await Token.getAssociatedTokenAddress(
+        ASSOCIATED_TOKEN_PROGRAM_ID,
+        TOKEN_PROGRAM_ID,
+        rewardMint.publicKey,
+        vaultAuthority,
+        true,
+      );
+
+      await cpiStaker.rpc.createPosition({
+        accounts: {
+          pool: pool.publicKey,
+          user: position,
+          vaultAuthority,
+          depositor: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          stakingProgram: stakingProgram.programId,
+        },
+      });
+      const moveAccounts = async () => ({
+        pool: pool.publicKey,
+        stakingVault,
+        user: position,
+        vaultAuthority,
+        vaultTokenAccount,
+        poolSigner,
+        dailyStats: await dailyStatsAddress(
+          provider,
+          stakingProgram.programId,
+          pool.publicKey,
+        ),
+        depositor: wallet.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        stakingProgram: stakingProgram.programId,
+        stakingMint: stakingMint.publicKey,
+        priceFeed: pool.publicKey,
+        memoProgram: MEMO_PROGRAM_ID,
+        eventAuthority,
+      });
+
+      await cpiStaker.rpc.stake(await today(), new anchor.BN(2_000_000_000), {
+        accounts: await moveAccounts(),
+      });
+      let positionAccount = await stakingProgram.account.user.fetch(position);
+      assert.equal(positionAccount.owner.toString(), vaultAuthority.toString());
+      assert.equal(positionAccount.balanceStaked.toString(), '2000000000');
+
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await cpiStaker.rpc.unstake(await today(), new anchor.BN(2_000_000_000), {
+        accounts: await moveAccounts(),
+      });
+      positionAccount = await stakingProgram.account.user.fetch(position);
+      assert.equal(positionAccount.balanceStaked.toString(), '0');
+      const vault = await stakingMint.getAccountInfo(vaultTokenAccount);
+      assert.equal(vault.amount.toString(), '5000000000');
+
+      await cpiStaker.rpc.claim(await today(), {
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          rewardVault,
+          user: position,
+          vaultAuthority,
+          rewardAccount: vaultRewardAccount,
+          poolSigner,
+          dailyStats: await dailyStatsAddress(
+            provider,
+            stakingProgram.programId,
+            pool.publicKey,
+          ),
+          depositor: wallet.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          stakingProgram: stakingProgram.programId,
+          rewardMint: rewardMint.publicKey,
+          memoProgram: MEMO_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          eventAuthority,
+        },
+      });
+      const rewards = await rewardMint.getAccountInfo(vaultRewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+
+      const rent = await provider.connection.getBalance(position);
+      const before = await provider.connection.getBalance(wallet.publicKey);
+      await cpiStaker.rpc.closePosition({
+        accounts: {
+          pool: pool.publicKey,
+          user: position,
+          vaultAuthority,
+          depositor: wallet.publicKey,
+          stakingProgram: stakingProgram.programId,
+        },
+      });
+      assert.equal(await provider.connection.getAccountInfo(position), null);
+      const after = await provider.connection.getBalance(wallet.publicKey);
+      // The refund covers the rent less the transaction fee.
+      assert.ok(after > before + rent - 10_000);
+    });
+  });
+
+  describe('recovery', () => {
+    it('waits out the delay and lets the owner cancel', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const newOwner = anchor.web3.Keypair.generate();
+      await stakingProgram.rpc.requestRecovery(newOwner.publicKey, {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          user,
+        },
+      });
+      let userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(
+        userAccount.recoveryNewOwner.toString(),
+        newOwner.publicKey.toString(),
+      );
+
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: wallet.publicKey,
+            toPubkey: newOwner.publicKey,
+            lamports: anchor.web3.LAMPORTS_PER_SOL,
+          }),
+        ),
+      );
+      const [newUser] = await anchor.web3.PublicKey.findProgramAddress(
+        [newOwner.publicKey.toBuffer(), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      const executeRecovery = (rentPayer: anchor.web3.PublicKey) =>
+        stakingProgram.rpc.executeRecovery({
+          accounts: {
+            pool: pool.publicKey,
+            authority: wallet.publicKey,
+            oldUser: user,
+            newUser,
+            newOwner: newOwner.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            rentPayer,
+          },
+          signers: [newOwner],
+        });
+      // The old account's rent goes back to the wallet that paid it.
+      await assert.rejects(
+        executeRecovery(newOwner.publicKey),
+        (err: any) => err.msg === 'A raw constraint was violated',
+      );
+      await assert.rejects(
+        executeRecovery(wallet.publicKey),
+        (err: any) => err.msg === 'Recovery delay has not passed yet.',
+      );
+
+      await stakingProgram.rpc.cancelRecovery({
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          owner: wallet.publicKey,
+        },
+      });
+      userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.recoveryRequestedAt.toString(), '0');
+    });
+  });
+
+  describe('attestation', () => {
+    it('requires an attestor-countersigned commitment to stake', async () => {
+      await initializePool(false);
+      await createUser();
+
+      const attestor = anchor.web3.Keypair.generate();
+      await stakingProgram.rpc.setAttestationPolicy(attestor.publicKey, true, {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+        },
+      });
+      await assert.rejects(stake(new anchor.BN(2_000_000_000)));
+
+      const commitment = Array(32).fill(7);
+      const accounts = {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
+      };
+      const countersignature = [
+        { pubkey: attestor.publicKey, isWritable: false, isSigner: true },
+      ];
+      await assert.rejects(
+        stakingProgram.rpc.setAttestation(commitment, { accounts }),
+      );
+      await stakingProgram.rpc.setAttestation(commitment, {
+        accounts,
+        remainingAccounts: countersignature,
+        signers: [attestor],
+      });
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(
+        stakingProgram.rpc.setAttestation(Array(32).fill(0), { accounts }),
+      );
+      await stakingProgram.rpc.setAttestation(Array(32).fill(0), {
+        accounts,
+        remainingAccounts: countersignature,
+        signers: [attestor],
+      });
+      await assert.rejects(stake(new anchor.BN(2_000_000_000)));
+    });
+  });
+
+  describe('tier override', () => {
+    const setTierOverride = async (tier: number, expiresAt: number) => {
+      await stakingProgram.rpc.setTierOverride(tier, new anchor.BN(expiresAt), {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          user,
+        },
+      });
+    };
+
+    const userTier = async () =>
+      (await stakingProgram.account.user.fetch(user)).tier;
+
+    it('raises the tier until the override expires', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      assert.equal(await userTier(), 1);
+
+      const expiresAt = Math.floor(Date.now() / 1000) + 4;
+      await setTierOverride(5, expiresAt);
+      assert.equal(await userTier(), 5);
+      await stake(new anchor.BN(1_000_000_000));
+      assert.equal(await userTier(), 5);
+
+      await sleep(6000);
+      await stake(new anchor.BN(1_000_000_000));
+      assert.equal(await userTier(), 1);
+
+      await setTierOverride(5, expiresAt + 86_400);
+      assert.equal(await userTier(), 5);
+      await setTierOverride(0, 0);
+      assert.equal(await userTier(), 1);
+    });
+
+    it('leaves pools without tiers untouched', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      await setTierOverride(5, Math.floor(Date.now() / 1000) + 86_400);
+      assert.equal(await userTier(), 0);
+    });
+  });
+
+  describe('leaderboard', () => {
+    it('ranks users by their live balance', async () => {
+      await initializePool(true);
+      await createUser();
+
+      const other = anchor.web3.Keypair.generate();
+      const otherTokenAccount = await stakingMint.createAccount(
+        other.publicKey,
+      );
+      await stakingMint.mintTo(
+        otherTokenAccount,
+        wallet.payer,
+        [],
+        10_000_000_000,
+      );
+      const [otherUser] = await anchor.web3.PublicKey.findProgramAddress(
+        [other.publicKey.toBuffer(), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createUser({
+        accounts: {
+          pool: pool.publicKey,
+          user: otherUser,
+          owner: other.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+        signers: [other],
+      });
+      const otherAccounts = {
+        pool: pool.publicKey,
+        stakingVault,
+        user: otherUser,
+        owner: other.publicKey,
+        stakeFromAccount: otherTokenAccount,
+        poolSigner,
+        ...(await dailyStatsAccounts()),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        stakingMint: stakingMint.publicKey,
+        priceFeed: pool.publicKey,
+        memoProgram: MEMO_PROGRAM_ID,
+        eventAuthority,
+        program: stakingProgram.programId,
+      };
+
+      const [leaderboard] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('leaderboard'), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createLeaderboard({
+        accounts: {
+          pool: pool.publicKey,
+          leaderboard,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      const updateLeaderboard = async (candidate: anchor.web3.PublicKey) => {
+        await stakingProgram.rpc.updateLeaderboard({
+          accounts: { pool: pool.publicKey, leaderboard, user: candidate },
+        });
+      };
+      const ranking = async () => {
+        const board = await stakingProgram.account.leaderboard.fetch(
+          leaderboard,
+        );
+        return board.entries
+          .filter((e: any) => e.balance.gtn(0))
+          .map((e: any) => [e.owner.toString(), e.balance.toString()]);
+      };
+
+      await stake(new anchor.BN(3_000_000_000));
+      await stakingProgram.rpc.stake(
+        await today(),
+        new anchor.BN(5_000_000_000),
+        new anchor.BN(0),
+        new anchor.BN(0),
+        {
+          accounts: otherAccounts,
+          signers: [other],
+        },
+      );
+      await updateLeaderboard(user);
+      await updateLeaderboard(otherUser);
+      assert.deepEqual(await ranking(), [
+        [other.publicKey.toString(), '5000000000'],
+        [wallet.publicKey.toString(), '3000000000'],
+      ]);
+
+      await stakingProgram.rpc.unstake(
+        await today(),
+        new anchor.BN(4_000_000_000),
+        new anchor.BN(0),
+        '',
+        {
+          accounts: otherAccounts,
+          signers: [other],
+        },
+      );
+      await updateLeaderboard(otherUser);
+      assert.deepEqual(await ranking(), [
+        [wallet.publicKey.toString(), '3000000000'],
+        [other.publicKey.toString(), '1000000000'],
+      ]);
+
+      await unstake(new anchor.BN(3_000_000_000));
+      await updateLeaderboard(user);
+      assert.deepEqual(await ranking(), [
+        [other.publicKey.toString(), '1000000000'],
+      ]);
+    });
+  });
+
+  describe('lottery', () => {
+    let leaderboard: anchor.web3.PublicKey;
+    let lottery: anchor.web3.PublicKey;
+    let lotteryVault: anchor.web3.PublicKey;
+    let randomness: anchor.web3.Keypair;
+
+    beforeEach(async () => {
+      await initializePool(false);
+      await createUser();
+      const poolAddress = async (seed: string) =>
+        (
+          await anchor.web3.PublicKey.findProgramAddress(
+            [Buffer.from(seed), pool.publicKey.toBuffer()],
+            stakingProgram.programId,
+          )
+        )[0];
+      leaderboard = await poolAddress('leaderboard');
+      lottery = await poolAddress('lottery');
+      lotteryVault = await poolAddress('lottery_vault');
+
+      await stakingProgram.rpc.createLeaderboard({
+        accounts: {
+          pool: pool.publicKey,
+          leaderboard,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      await stakingProgram.rpc.createLottery(new anchor.BN(1), {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          lottery,
+          lotteryVault,
+          rewardMint: rewardMint.publicKey,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        },
+      });
+      const from = await rewardMint.createAccount(wallet.publicKey);
+      await rewardMint.mintTo(from, wallet.payer, [], 1_000_000);
+      await stakingProgram.rpc.fundLottery(new anchor.BN(1_000_000), {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          lottery,
+          lotteryVault,
+          from,
+          rewardMint: rewardMint.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+      });
+
+      randomness = anchor.web3.Keypair.generate();
+      const space = 408;
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: wallet.publicKey,
+            newAccountPubkey: randomness.publicKey,
+            lamports:
+              await provider.connection.getMinimumBalanceForRentExemption(
+                space,
+              ),
+            space,
+            programId: stakingProgram.programId,
+          }),
+        ),
+        [randomness],
+      );
+      // Let the first draw come due.
+      await sleep(1500);
+    });
+
+    const writeRandomness = (
+      seedSlot: number,
+      revealSlot: number,
+      value: number[],
+    ) =>
+      stakingProgram.rpc.writeRandomnessForTesting(
+        new anchor.BN(seedSlot),
+        new anchor.BN(revealSlot),
+        value,
+        {
+          accounts: {
+            randomness: randomness.publicKey,
+            authority: wallet.publicKey,
+          },
+        },
+      );
+    const requestDraw = () =>
+      stakingProgram.rpc.requestDraw({
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          lottery,
+          leaderboard,
+          randomness: randomness.publicKey,
+        },
+      });
+    const settleDraw = (
+      remainingAccounts: anchor.web3.AccountMeta[] = [],
+    ) =>
+      stakingProgram.rpc.settleDraw({
+        accounts: {
+          pool: pool.publicKey,
+          lottery,
+          lotteryVault,
+          randomness: randomness.publicKey,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+        },
+        remainingAccounts,
+      });
+
+    it('pays the drawn staker once the randomness is revealed', async () => {
+      await stake(new anchor.BN(2_000_000_000));
+      await stakingProgram.rpc.updateLeaderboard({
+        accounts: { pool: pool.publicKey, leaderboard, user },
+      });
+      const slot = await provider.connection.getSlot();
+      await writeRandomness(slot, 0, new Array(32).fill(0));
+      await requestDraw();
+
+      const rewardAccount = (
+        await rewardMint.getOrCreateAssociatedAccountInfo(wallet.publicKey)
+      ).address;
+      const winnerAccounts = [
+        { pubkey: user, isWritable: false, isSigner: false },
+        { pubkey: rewardAccount, isWritable: true, isSigner: false },
+      ];
+      await assert.rejects(
+        settleDraw(winnerAccounts),
+        (err: any) => err.msg === 'Randomness has not been revealed.',
+      );
+
+      await writeRandomness(slot, slot + 1, new Array(32).fill(7));
+      const before = (await rewardMint.getAccountInfo(rewardAccount)).amount;
+      await settleDraw(winnerAccounts);
+      const after = (await rewardMint.getAccountInfo(rewardAccount)).amount;
+      assert.equal(after.sub(before).toString(), '1000000');
+      const round = await stakingProgram.account.lotteryRound.fetch(lottery);
+      assert.equal(round.round.toString(), '2');
+      assert.equal(round.prize.toString(), '0');
+      assert.ok(round.lastWinner.equals(wallet.publicKey));
+
+      await assert.rejects(
+        settleDraw(winnerAccounts),
+        (err: any) => err.msg === 'No lottery draw is pending.',
+      );
+    });
+
+    it('rolls the prize over when the reward account is closed', async () => {
+      await stake(new anchor.BN(2_000_000_000));
+      await stakingProgram.rpc.updateLeaderboard({
+        accounts: { pool: pool.publicKey, leaderboard, user },
+      });
+      const slot = await provider.connection.getSlot();
+      await writeRandomness(slot, 0, new Array(32).fill(0));
+      await requestDraw();
+
+      const rewardAccount = await rewardMint.getOrCreateAssociatedAccountInfo(
+        wallet.publicKey,
+      );
+      const sink = await rewardMint.createAccount(wallet.publicKey);
+      await rewardMint.transfer(
+        rewardAccount.address,
+        sink,
+        wallet.payer,
+        [],
+        rewardAccount.amount,
+      );
+      await rewardMint.closeAccount(
+        rewardAccount.address,
+        wallet.publicKey,
+        wallet.payer,
+        [],
+      );
+
+      await writeRandomness(slot, slot + 1, new Array(32).fill(7));
+      await settleDraw([
+        { pubkey: user, isWritable: false, isSigner: false },
+        { pubkey: rewardAccount.address, isWritable: true, isSigner: false },
+      ]);
+      const round = await stakingProgram.account.lotteryRound.fetch(lottery);
+      assert.equal(round.round.toString(), '2');
+      assert.equal(round.prize.toString(), '1000000');
+      assert.ok(round.lastWinner.equals(anchor.web3.PublicKey.default));
+      const vault = await rewardMint.getAccountInfo(lotteryVault);
+      assert.equal(vault.amount.toString(), '1000000');
+    });
+
+    it('only writes test accounts claimed by the signer', async () => {
+      await writeRandomness(0, 0, new Array(32).fill(0));
+      const other = anchor.web3.Keypair.generate();
+      await assert.rejects(
+        stakingProgram.rpc.writeRandomnessForTesting(
+          new anchor.BN(0),
+          new anchor.BN(1),
+          new Array(32).fill(7),
+          {
+            accounts: {
+              randomness: randomness.publicKey,
+              authority: other.publicKey,
+            },
+            signers: [other],
+          },
+        ),
+        (err: any) =>
+          err.msg === 'Account is not a test account writable by this signer.',
+      );
+      await assert.rejects(
+        stakingProgram.rpc.writeRandomnessForTesting(
+          new anchor.BN(0),
+          new anchor.BN(1),
+          new Array(32).fill(7),
+          {
+            accounts: {
+              randomness: lottery,
+              authority: wallet.publicKey,
+            },
+          },
+        ),
+        (err: any) =>
+          err.msg === 'Account is not a test account writable by this signer.',
+      );
+    });
+
+    it('rolls the prize over when nobody is staked', async () => {
+      const slot = await provider.connection.getSlot();
+      await writeRandomness(slot, 0, new Array(32).fill(0));
+      await requestDraw();
+      await assert.rejects(
+        requestDraw(),
+        (err: any) => err.msg === 'A lottery draw is already pending.',
+      );
+
+      await writeRandomness(slot, slot + 1, new Array(32).fill(7));
+      await settleDraw();
+      const round = await stakingProgram.account.lotteryRound.fetch(lottery);
+      assert.equal(round.round.toString(), '2');
+      assert.equal(round.prize.toString(), '1000000');
+      assert.ok(round.lastWinner.equals(anchor.web3.PublicKey.default));
+    });
+  });
+
+  describe('token-2022 staking mint', () => {
+    let legacyMint: Token;
+    let mint2022: Token;
+
+    before(async () => {
+      legacyMint = stakingMint;
+      mint2022 = await createMint2022(provider, 4);
+      stakingMint = mint2022;
+    });
+
+    afterEach(() => {
+      stakingMint = mint2022;
+    });
+
+    after(() => {
+      stakingMint = legacyMint;
+    });
+
+    it('stakes, claims and unstakes', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      assert.equal(
+        (await tokenBalance(provider, stakingVault)).toString(),
+        '2000000000',
+      );
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      await claim(rewardAccount);
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gtn(0));
+
+      await unstake(new anchor.BN(2_000_000_000));
+      assert.equal(
+        (await tokenBalance(provider, stakingVault)).toString(),
+        '0',
+      );
+      assert.equal(
+        (await tokenBalance(provider, ownerTokenAccount)).toString(),
+        '100000000000000',
+      );
+    });
+
+    it('stakes only what reaches the vault under a transfer fee', async () => {
+      // 1%, so 20 of every 2,000 tokens moved stay withheld.
+      stakingMint = await createMint2022(provider, 4, [
+        transferFeeExtension(100, new anchor.BN(1_000_000_000_000)),
+      ]);
+      stakingVault = await stakingMint.createAccount(poolSigner);
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '1980000000');
+      assert.equal(
+        (await tokenBalance(provider, stakingVault)).toString(),
+        '1980000000',
+      );
+
+      // The withdrawal pays the fee again, out of what the owner receives.
+      await unstake(new anchor.BN(1_980_000_000));
+      assert.equal(
+        (await tokenBalance(provider, ownerTokenAccount)).toString(),
+        '99999960200000',
+      );
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.totalStaked.toString(), '0');
+    });
+
+    it('rejects mints with unsupported extensions', async () => {
+      stakingMint = await createMint2022(provider, 4, [
+        defaultAccountStateExtension(),
+      ]);
+      stakingVault = await stakingMint.createAccount(poolSigner);
+
+      await assert.rejects(
+        initializePool(false),
+        (err: any) =>
+          err.msg ===
+          "Staking mint has a Token-2022 extension pools don't support.",
+      );
+    });
+
+    it('rejects the legacy token program for a Token-2022 mint', async () => {
+      await initializePool(false);
+      await createUser();
+
+      await assert.rejects(
+        stakingProgram.rpc.stake(
+          await today(),
+          new anchor.BN(2_000_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          {
+            accounts: {
+              pool: pool.publicKey,
+              stakingVault,
+              user,
+              owner: wallet.publicKey,
+              stakeFromAccount: ownerTokenAccount,
+              poolSigner,
+              ...(await dailyStatsAccounts()),
+              tokenProgram: TOKEN_PROGRAM_ID,
+              stakingMint: stakingMint.publicKey,
+              priceFeed: pool.publicKey,
+              memoProgram: MEMO_PROGRAM_ID,
+              eventAuthority,
+              program: stakingProgram.programId,
+            },
+          },
+        ),
+        (err: any) =>
+          err.msg ===
+          "Token accounts must belong to the staking mint's token program.",
+      );
+    });
+
+    it('checks the invariants of the vault', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      assert.equal(await assertInvariants(), false);
+
+      await corruptPool(new anchor.BN(3_000_000_000));
+      assert.equal(await assertInvariants(), true);
+    });
+
+    it('auto-claims for opted-in users', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await stakingProgram.rpc.setAutoClaim(true, new anchor.BN(0), {
+        accounts: { pool: pool.publicKey, user, owner: wallet.publicKey },
+      });
+
+      const keeper = anchor.web3.Keypair.generate();
+      const rewardAccount = (
+        await rewardMint.getOrCreateAssociatedAccountInfo(wallet.publicKey)
+      ).address;
+      const before = (await rewardMint.getAccountInfo(rewardAccount)).amount;
+      await stakingProgram.rpc.autoClaim({
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          rewardVault,
+          user,
+          rewardAccount,
+          cranker: keeper.publicKey,
+          tipAccount: await rewardMint.createAccount(keeper.publicKey),
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+        },
+        signers: [keeper],
+      });
+
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.ok(rewards.amount.gt(before));
+    });
+
+    it('rejects the instructions still on the legacy program', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      // Every other account is a legacy one, so only the vault is wrong.
+      const destination = await legacyMint.createAccount(wallet.publicKey);
+      stakingMint = legacyMint;
+      await assert.rejects(
+        slash(new anchor.BN(1_000_000_000), 1, destination),
+        (err: any) =>
+          err.msg ===
+          "Token accounts must belong to the staking mint's token program.",
+      );
+      assert.equal(
+        (await tokenBalance(provider, stakingVault)).toString(),
+        '2000000000',
+      );
+    });
+  });
+
+  describe('events over CPI', () => {
+    it('emits the core events as inner instructions', async () => {
+      await initializePool(false);
+      await createUser();
+      const staked = await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      const unstaked = await unstake(new anchor.BN(2_000_000_000));
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      const claimed = await claim(rewardAccount);
+
+      const [stakeEvent] = await cpiEvents(provider, stakingProgram, staked);
+      assert.equal(stakeEvent.name, 'StakeEvent');
+      assert.equal(stakeEvent.data.user.toString(), user.toString());
+      assert.equal(stakeEvent.data.amount.toString(), '2000000000');
+      const [unstakeEvent] = await cpiEvents(
+        provider,
+        stakingProgram,
+        unstaked,
+      );
+      assert.equal(unstakeEvent.name, 'UnstakeEvent');
+      assert.equal(unstakeEvent.data.balanceStaked.toString(), '0');
+      const [claimEvent] = await cpiEvents(provider, stakingProgram, claimed);
+      const rewards = await rewardMint.getAccountInfo(rewardAccount);
+      assert.equal(claimEvent.name, 'ClaimEvent');
+      assert.equal(
+        claimEvent.data.amount.toString(),
+        rewards.amount.toString(),
+      );
+    });
+
+    it('only accepts log_event from the program itself', async () => {
+      const forger = anchor.web3.Keypair.generate();
+      await assert.rejects(
+        stakingProgram.rpc.logEvent(Buffer.from([]), {
+          accounts: { eventAuthority: forger.publicKey },
+          signers: [forger],
+        }),
+      );
+    });
+  });
+
+  describe('claim and swap', () => {
+    const jupiterProgram = new anchor.web3.PublicKey(
+      'JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4',
+    );
+    const routeData = Buffer.from([229, 23, 203, 151, 122, 227, 173, 42]);
+    let swapAccount: anchor.web3.PublicKey;
+    let swapAuthority: anchor.web3.PublicKey;
+    let destination: anchor.web3.PublicKey;
+
+    beforeEach(async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+
+      [swapAccount] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('swap'), user.toBuffer()],
+        stakingProgram.programId,
+      );
+      [swapAuthority] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('swap_authority'), user.toBuffer()],
+        stakingProgram.programId,
+      );
+      const outputMint = await createMint(provider, 6);
+      destination = await outputMint.createAssociatedTokenAccount(
+        wallet.publicKey,
+      );
+    });
+
+    const claimAndSwap = (
+      source: anchor.web3.PublicKey,
+      program = jupiterProgram,
+    ) =>
+      stakingProgram.rpc.claimAndSwap(new anchor.BN(1), routeData, {
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          rewardVault,
+          user,
+          owner: wallet.publicKey,
+          destination,
+          swapAccount,
+          swapAuthority,
+          poolSigner,
+          jupiterProgram: program,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          rewardMint: rewardMint.publicKey,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        },
+        remainingAccounts: [
+          { pubkey: TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+          { pubkey: swapAuthority, isWritable: false, isSigner: false },
+          { pubkey: source, isWritable: true, isSigner: false },
+          { pubkey: destination, isWritable: true, isSigner: false },
+        ],
+      });
+
+    it('refuses routes spending another input mint', async () => {
+      const source = await stakingMint.createAccount(wallet.publicKey);
+      await assert.rejects(
+        claimAndSwap(source),
+        (err: any) =>
+          err.msg ===
+          'Swap route must spend the claimed rewards from the swap account.',
+      );
+
+      // The claim reverted with the swap.
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.totalRewardsClaimed.toString(), '0');
+      assert.equal(await provider.connection.getAccountInfo(swapAccount), null);
+    });
+
+    it('only calls the Jupiter program', async () => {
+      await assert.rejects(claimAndSwap(swapAccount, MEMO_PROGRAM_ID));
+    });
+  });
+
+  describe('maturity extension', () => {
+    const extendUserMaturity = async (additionalSeconds: number) => {
+      await stakingProgram.rpc.extendUserMaturity(
+        new anchor.BN(additionalSeconds),
+        7,
+        {
+          accounts: {
+            pool: pool.publicKey,
+            authority: wallet.publicKey,
+            user,
+          },
+        },
+      );
+    };
+
+    it('is unavailable without maturity enforcement', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(extendUserMaturity(60));
+    });
+
+    it('extends maturity up to the pool maximum', async () => {
+      await initializePool(false, {
+        lockPeriod: new anchor.BN(86400),
+        maxMaturityExtension: new anchor.BN(100),
+      });
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      const before = (await stakingProgram.account.user.fetch(user))
+        .maturityTime;
+
+      await extendUserMaturity(60);
+      const after = (await stakingProgram.account.user.fetch(user))
+        .maturityTime;
+      assert.equal(after.sub(before).toNumber(), 60);
+
+      await assert.rejects(extendUserMaturity(41));
+      await extendUserMaturity(40);
+    });
+  });
+
+  describe('sync user', () => {
+    it('checkpoints pending rewards without the owner', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+
+      await stakingProgram.rpc.syncUser({
+        accounts: { pool: pool.publicKey, user },
+      });
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.ok(userAccount.rewardPerTokenPending.gtn(0));
+    });
+  });
+
+  describe('crank update', () => {
+    it('advances reward accounting for anyone', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      const before = await stakingProgram.account.pool.fetch(pool.publicKey);
+      await sleep(2000);
+
+      const events = await captureEvents(
+        stakingProgram,
+        ['FundingRequiredEvent'],
+        async () => {
+          await stakingProgram.rpc.crankUpdate({
+            accounts: { pool: pool.publicKey, rewardVault },
+          });
+        },
+      );
+
+      const after = await stakingProgram.account.pool.fetch(pool.publicKey);
+      assert.ok(after.rewardPerTokenStored.gt(before.rewardPerTokenStored));
+      assert.ok(after.lastUpdateTime.gt(before.lastUpdateTime));
+      assert.equal(after.underfunded, false);
+      assert.equal(events.length, 0);
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
+    });
+  });
+
+  describe('reward math', () => {
+    it('accrues a large reward rate against a tiny stake', async () => {
+      await initializePool(false, { rewardDuration: new anchor.BN(86400) });
+      await createUser();
+      await stake(new anchor.BN(1));
+      const funded = new anchor.BN(9_000_000_000_000_000);
+      await fund(funded);
+      await sleep(2000);
+
+      await stakingProgram.rpc.syncUser({
+        accounts: { pool: pool.publicKey, user },
+      });
+
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.ok(poolAccount.rewardPerTokenStored.bitLength() > 64);
+      assert.ok(userAccount.rewardPerTokenPending.gtn(0));
+      assert.ok(userAccount.rewardPerTokenPending.lte(funded));
+    });
+
+    it('accrues a gap that emits more than u64::MAX', async () => {
+      await initializePool(false, { rewardDuration: new anchor.BN(86400) });
+      await createUser();
+      await stake(new anchor.BN(1_000_000));
+      await fund(new anchor.BN(9_000_000_000_000_000));
+
+      // An empty user to sync, so no balance has to earn the whole emission.
+      const other = anchor.web3.Keypair.generate();
+      const [otherUser] = await anchor.web3.PublicKey.findProgramAddress(
+        [other.publicKey.toBuffer(), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createUser({
+        accounts: {
+          pool: pool.publicKey,
+          user: otherUser,
+          owner: other.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+        signers: [other],
+      });
+
+      await stakingProgram.rpc.rewindPoolForTesting(
+        new anchor.BN(200_000_000),
+        { accounts: { pool: pool.publicKey, authority: wallet.publicKey } },
+      );
+      const before = await stakingProgram.account.pool.fetch(pool.publicKey);
+      await stakingProgram.rpc.syncUser({
+        accounts: { pool: pool.publicKey, user: otherUser },
+      });
+      const after = await stakingProgram.account.pool.fetch(pool.publicKey);
+
+      const emitted = after.lastUpdateTime
+        .sub(before.lastUpdateTime)
+        .mul(before.rewardRate);
+      assert.ok(emitted.bitLength() > 64);
+      assert.ok(
+        after.rewardPerTokenStored.eq(
+          rewardPerToken(
+            before.totalStaked,
+            before.rewardPerTokenStored,
+            after.lastUpdateTime,
+            before.lastUpdateTime,
+            before.rewardRate,
+          ),
+        ),
+      );
+    });
+  });
+
+  describe('halt', () => {
+    const halt = async (seconds: number) => {
+      await stakingProgram.rpc.halt(new anchor.BN(seconds), {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+    };
+
+    it('caps the halt at 72 hours', async () => {
+      await initializePool(false);
+
+      await assert.rejects(halt(72 * 3600 + 1));
+      await halt(72 * 3600);
+    });
+
+    it('blocks stake, unstake and claim until it expires', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await halt(4);
+
+      await assert.rejects(stake(new anchor.BN(1_000_000_000)));
+      await assert.rejects(unstake(new anchor.BN(1_000_000_000)));
+      await assert.rejects(
+        claim(await rewardMint.createAccount(wallet.publicKey)),
+      );
+
+      await sleep(5000);
+      await stake(new anchor.BN(1_000_000_000));
+      await unstake(new anchor.BN(1_000_000_000));
+    });
+  });
+
+  describe('invariants', () => {
+    it('leaves a consistent pool running', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+
+      assert.equal(await assertInvariants(), false);
+    });
+
+    it('halts staking, funding and claims but not unstaking', async () => {
+      await initializePool(false);
+      await createUser();
+      const amount = new anchor.BN(2_000_000_000);
+      await stake(amount);
+
+      await corruptPool(new anchor.BN(3_000_000_000));
+      assert.equal(await assertInvariants(), true);
+      await corruptPool(amount);
+
+      await assert.rejects(stake(amount));
+      await assert.rejects(fund(new anchor.BN(604_800_000_000)));
+      await assert.rejects(
+        claim(await rewardMint.createAccount(wallet.publicKey)),
+      );
+      await unstake(new anchor.BN(1_000_000_000));
+
+      await stakingProgram.rpc.clearHalt({
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+      await stake(amount);
+    });
+  });
+
+  describe('sync total staked', () => {
+    const donate = async (amount: number) => {
+      await stakingMint.transfer(
+        ownerTokenAccount,
+        stakingVault,
+        wallet.publicKey,
+        [],
+        amount,
+      );
+    };
+
+    const syncTotalStaked = async (
+      creditUser: boolean,
+      remainingAccounts: anchor.web3.AccountMeta[],
+    ) => {
+      await stakingProgram.rpc.syncTotalStaked(creditUser, {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          stakingVault,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          stakingMint: stakingMint.publicKey,
+        },
+        remainingAccounts,
+      });
+    };
+
+    it('sends the surplus to a recovery account', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await donate(1_000_000_000);
+
+      const recovery = await stakingMint.createAccount(wallet.publicKey);
+      await syncTotalStaked(false, [
+        { pubkey: recovery, isWritable: true, isSigner: false },
+      ]);
+
+      const recovered = await stakingMint.getAccountInfo(recovery);
+      assert.equal(recovered.amount.toString(), '1000000000');
+      const vault = await stakingMint.getAccountInfo(stakingVault);
+      assert.equal(vault.amount.toString(), '2000000000');
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '2000000000');
+    });
+
+    it('credits the surplus to a consenting user', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await donate(1_000_000_000);
+
+      await syncTotalStaked(true, [
+        { pubkey: user, isWritable: true, isSigner: false },
+        { pubkey: wallet.publicKey, isWritable: false, isSigner: true },
+      ]);
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '3000000000');
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.totalStaked.toString(), '3000000000');
+    });
+
+    it('refuses to run when the vault is short', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await corruptPool(new anchor.BN(3_000_000_000));
+
+      const recovery = await stakingMint.createAccount(wallet.publicKey);
+      await assert.rejects(
+        syncTotalStaked(false, [
+          { pubkey: recovery, isWritable: true, isSigner: false },
+        ]),
+      );
+    });
+  });
+
+  describe('renounce funder', () => {
+    const renounceFunder = async (funder: anchor.web3.Keypair) => {
+      await stakingProgram.rpc.renounceFunder({
+        accounts: { pool: pool.publicKey, funder: funder.publicKey },
+        signers: [funder],
+      });
+    };
+
+    it('removes the signing funder', async () => {
+      await initializePool(false);
+      const funder = anchor.web3.Keypair.generate();
+      await stakingProgram.rpc.authorizeFunder(funder.publicKey, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+      await renounceFunder(funder);
+
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.ok(
+        poolAccount.funders.every(
+          (key: anchor.web3.PublicKey) => !key.equals(funder.publicKey),
+        ),
+      );
+      await assert.rejects(renounceFunder(funder));
+    });
+
+    it('rejects a signer that is not a funder', async () => {
+      await initializePool(false);
+
+      await assert.rejects(renounceFunder(anchor.web3.Keypair.generate()));
+      await assert.rejects(
+        stakingProgram.rpc.renounceFunder({
+          accounts: { pool: pool.publicKey, funder: wallet.publicKey },
+        }),
+      );
+    });
+  });
+
+  describe('closing', () => {
+    const beginClose = () =>
+      stakingProgram.rpc.beginClose({
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+    const cancelClose = () =>
+      stakingProgram.rpc.cancelClose({
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+    it('rejects new users until cancelled', async () => {
+      await initializePool(false);
+      await beginClose();
+      await assert.rejects(beginClose());
+
+      await assert.rejects(createUser());
+      await cancelClose();
+      await assert.rejects(cancelClose());
+      await createUser();
+    });
+
+    it('rejects stakes but keeps unstake and claim open', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await beginClose();
+
+      await assert.rejects(stake(new anchor.BN(1_000_000_000)));
+      await unstake(new anchor.BN(1_000_000_000));
+      await sleep(1000);
+      await claim(await rewardMint.createAccount(wallet.publicKey));
+
+      await cancelClose();
+      await stake(new anchor.BN(1_000_000_000));
+    });
+
+    const closePool = async () =>
+      stakingProgram.rpc.closePool({
+        accounts: {
+          refundee: wallet.publicKey,
+          stakingRefundee: await stakingMint.createAccount(wallet.publicKey),
+          rewardRefundee: await rewardMint.createAccount(wallet.publicKey),
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          stakingVault,
+          rewardVault,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          stakingMint: stakingMint.publicKey,
+          rewardMint: rewardMint.publicKey,
+          stakingTokenProgram: stakingMint.programId,
+        },
+      });
+
+    it('closes a pool that was never funded', async () => {
+      await initializePool(false);
+      await beginClose();
+      await stakingProgram.rpc.pause({
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+      await closePool();
+
+      const accounts = await provider.connection.getMultipleAccountsInfo([
+        pool.publicKey,
+        stakingVault,
+        rewardVault,
+      ]);
+      assert.ok(accounts.every((account) => account === null));
+    });
+
+    it('closes a pool of a Token-2022 staking mint', async () => {
+      const legacyMint = stakingMint;
+      stakingMint = await createMint2022(provider, 4, [
+        transferFeeExtension(100, new anchor.BN(1_000_000_000)),
+      ]);
+      try {
+        stakingVault = await stakingMint.createAccount(poolSigner);
+        await initializePool(false);
+        await createUser();
+        // Leaves withheld fees in the vault, harvested before closing it.
+        await stake(new anchor.BN(2_000_000_000));
+        await unstake(new anchor.BN(1_980_000_000));
+        await closeUser();
+        await beginClose();
+        await stakingProgram.rpc.pause({
+          accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+        });
+
+        await closePool();
+      } finally {
+        stakingMint = legacyMint;
+      }
+
+      const accounts = await provider.connection.getMultipleAccountsInfo([
+        pool.publicKey,
+        stakingVault,
+        rewardVault,
+      ]);
+      assert.ok(accounts.every((account) => account === null));
+    });
+
+    it('keeps a funded pool open during its reward period', async () => {
+      await initializePool(false);
+      await fund(new anchor.BN(604_800_000_000));
+      await beginClose();
+
+      await assert.rejects(
+        stakingProgram.rpc.pause({
+          accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+        }),
+      );
+      await assert.rejects(closePool());
+    });
+  });
+
+  describe('events', () => {
+    it('emits stake, unstake, fund and claim events', async () => {
+      await initializePool(false);
+      const amount = new anchor.BN(2_000_000_000);
+
+      const events = await captureEvents(
+        stakingProgram,
+        ['UserCreatedEvent', 'StakeEvent', 'FundEvent', 'UnstakeEvent'],
+        async () => {
+          await createUser();
+          await stake(amount);
+          await fund(new anchor.BN(604_800_000_000));
+          await unstake(amount);
+        },
+      );
+
+      assert.deepEqual(
+        events.map((event) => event.name),
+        ['UserCreatedEvent', 'StakeEvent', 'FundEvent', 'UnstakeEvent'],
+      );
+      const [created, staked, funded, unstaked] = events.map((e) => e.data);
+      assert.equal(created.pool.toString(), pool.publicKey.toString());
+      assert.equal(created.user.toString(), user.toString());
+      assert.equal(created.owner.toString(), wallet.publicKey.toString());
+      assert.equal(staked.amount.toString(), amount.toString());
+      assert.equal(staked.balanceStaked.toString(), amount.toString());
+      assert.equal(funded.funder.toString(), wallet.publicKey.toString());
+      assert.equal(funded.amount.toString(), '604800000000');
+      assert.equal(funded.rewardRate.toString(), '1000000');
+      assert.equal(
+        funded.rewardDurationEnd.toString(),
+        funded.fundedAt.add(rewardDuration).toString(),
+      );
+      assert.equal(unstaked.amount.toString(), amount.toString());
+      assert.equal(unstaked.balanceStaked.toString(), '0');
+
+      const claimed = await captureEvents(
+        stakingProgram,
+        ['ClaimEvent'],
+        async () => {
+          await claim(await rewardMint.createAccount(wallet.publicKey));
+        },
+      );
+      assert.equal(claimed.length, 1);
+      assert.equal(claimed[0].data.user.toString(), user.toString());
+      assert.equal(
+        claimed[0].data.totalRewardsClaimed.toString(),
+        claimed[0].data.amount.toString(),
+      );
+    });
+
+    it('echoes client_ref in stake, unstake and claim events', async () => {
+      await initializePool(false);
+      await createUser();
+      const amount = new anchor.BN(2_000_000_000);
+      const maxRef = new anchor.BN('18446744073709551615');
+
+      const events = await captureEvents(
+        stakingProgram,
+        ['StakeEvent', 'UnstakeEvent', 'ClaimEvent'],
+        async () => {
+          await stake(amount, new anchor.BN(0), new anchor.BN(42));
+          await unstake(amount, [], [], maxRef);
+          await claim(
+            await rewardMint.createAccount(wallet.publicKey),
+            [],
+            new anchor.BN(7),
+          );
+          await stake(amount);
+        },
+      );
+
+      assert.deepEqual(
+        events.map((event) => event.name),
+        ['StakeEvent', 'UnstakeEvent', 'ClaimEvent', 'StakeEvent'],
+      );
+      assert.deepEqual(
+        events.map((event) => event.data.clientRef.toString()),
+        ['42', maxRef.toString(), '7', '0'],
+      );
+    });
+
+    it('attaches memos to unstake and claim transfers', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(1000);
+
+      const memosOf = async (signature: string) => {
+        await provider.connection.confirmTransaction(signature, 'confirmed');
+        const tx = await provider.connection.getTransaction(signature, {
+          commitment: 'confirmed',
+        });
+        const keys = tx.transaction.message.accountKeys;
+        return tx.meta.innerInstructions
+          .flatMap((inner) => inner.instructions)
+          .filter((ix) => keys[ix.programIdIndex].equals(MEMO_PROGRAM_ID))
+          .map((ix) => Buffer.from(anchor.utils.bytes.bs58.decode(ix.data)));
+      };
+
+      const unstakeMemo = 'deposit 4417-0092';
+      const unstaked = await unstake(
+        new anchor.BN(1_000_000_000),
+        [],
+        [],
+        new anchor.BN(0),
+        unstakeMemo,
+      );
+      assert.deepEqual(await memosOf(unstaked), [Buffer.from(unstakeMemo)]);
+
+      const claimMemo = 'sub-account ✓';
+      const claimed = await claim(
+        await rewardMint.createAccount(wallet.publicKey),
+        [],
+        new anchor.BN(0),
+        claimMemo,
+      );
+      assert.deepEqual(await memosOf(claimed), [Buffer.from(claimMemo)]);
+
+      const plain = await unstake(new anchor.BN(1_000_000_000));
+      assert.deepEqual(await memosOf(plain), []);
+    });
+
+    it('rejects memos over the length limit', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(
+        unstake(
+          new anchor.BN(1_000_000_000),
+          [],
+          [],
+          new anchor.BN(0),
+          'x'.repeat(257),
+        ),
+        (err: any) => err.msg === 'Memo is too long.',
+      );
+    });
+
+    it('emits tier changes only when the tier moves', async () => {
+      await initializePool(false);
+      await createUser();
+      const amount = new anchor.BN(2_000_000_000);
+
+      const events = await captureEvents(
+        stakingProgram,
+        ['TierChangedEvent'],
+        async () => {
+          await stake(amount);
+          await stake(amount);
+          await stake(amount);
+        },
+      );
+
+      assert.deepEqual(
+        events.map(({ data }) => [data.oldTier, data.newTier]),
+        [
+          [0, 1],
+          [1, 2],
+        ],
+      );
+      assert.equal(events[0].data.user.toString(), user.toString());
+      assert.equal(events[0].data.balance.toString(), '2000000000');
+      assert.equal(events[1].data.balance.toString(), '6000000000');
+    });
+
+    it('emits maturity changes only when maturity moves', async () => {
+      await initializePool(false, { lockPeriod: new anchor.BN(86400 * 90) });
+      await createUser();
+      await setTopupGrace(new anchor.BN(0), 100);
+
+      const first = await captureEvents(
+        stakingProgram,
+        ['MaturitySetEvent'],
+        async () => {
+          await stake(new anchor.BN(10_000_000_000));
+        },
+      );
+      assert.equal(first.length, 1);
+      assert.equal(first[0].data.oldMaturity.toString(), '0');
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(
+        first[0].data.newMaturity.toString(),
+        userAccount.maturityTime.toString(),
+      );
+
+      await sleep(2000);
+      const topup = await captureEvents(
+        stakingProgram,
+        ['MaturitySetEvent'],
+        async () => {
+          await stake(new anchor.BN(100_000_000));
+        },
+      );
+      assert.equal(topup.length, 0);
+    });
+
+    it('emits pause and user closed events', async () => {
+      await initializePool(false);
+      await createUser();
+
+      const events = await captureEvents(
+        stakingProgram,
+        ['PauseEvent', 'UserClosedEvent'],
+        async () => {
+          await stakingProgram.rpc.pause({
+            accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+          });
+          await stakingProgram.rpc.unpause({
+            accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+          });
+          await closeUser();
+        },
+      );
+
+      assert.deepEqual(
+        events.map((event) => event.name),
+        ['PauseEvent', 'PauseEvent', 'UserClosedEvent'],
+      );
+      assert.equal(events[0].data.paused, true);
+      assert.equal(events[1].data.paused, false);
+      const closed = events[2].data;
+      assert.equal(closed.user.toString(), user.toString());
+      assert.equal(closed.owner.toString(), wallet.publicKey.toString());
+      assert.equal(closed.forgivenDust.toString(), '0');
+      assert.equal(closed.userStakeCount, 0);
+    });
+  });
+
+  describe('vault accounting', () => {
+    it('tolerates direct donations into the staking vault', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      await stakingMint.transfer(
+        ownerTokenAccount,
+        stakingVault,
+        wallet.payer,
+        [],
+        500_000_000,
+      );
+      await stake(new anchor.BN(1_000_000_000));
+      await unstake(new anchor.BN(3_000_000_000));
+
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.totalStaked.toString(), '0');
+      const vault = await stakingMint.getAccountInfo(stakingVault);
+      assert.equal(vault.amount.toString(), '500000000');
+    });
+  });
+
+  describe('token account validation', () => {
+    it('rejects a stake account of another mint', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const otherMintAccount = await rewardMint.createAccount(
+        wallet.publicKey,
+      );
+      await rewardMint.mintTo(
+        otherMintAccount,
+        wallet.payer,
+        [],
+        2_000_000_000,
+      );
+      const accounts = {
+        pool: pool.publicKey,
+        stakingVault,
+        user,
+        owner: wallet.publicKey,
+        stakeFromAccount: otherMintAccount,
+        poolSigner,
+        ...(await dailyStatsAccounts()),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        stakingMint: stakingMint.publicKey,
+        priceFeed: pool.publicKey,
+        memoProgram: MEMO_PROGRAM_ID,
+        eventAuthority,
+        program: stakingProgram.programId,
+      };
+      await assert.rejects(
+        stakingProgram.rpc.stake(
+          await today(),
+          new anchor.BN(1_000_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          { accounts },
+        ),
+      );
+      await assert.rejects(
+        stakingProgram.rpc.unstake(
+          await today(),
+          new anchor.BN(1_000_000_000),
+          new anchor.BN(0),
+          '',
+          { accounts },
+        ),
+      );
+    });
+
+    it('rejects a mint other than the pool staking mint', async () => {
+      await initializePool(false);
+      await createUser();
+      const accounts = {
+        pool: pool.publicKey,
+        stakingVault,
+        user,
+        owner: wallet.publicKey,
+        stakeFromAccount: ownerTokenAccount,
+        poolSigner,
+        ...(await dailyStatsAccounts()),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        stakingMint: rewardMint.publicKey,
+        priceFeed: pool.publicKey,
+        memoProgram: MEMO_PROGRAM_ID,
+        eventAuthority,
+        program: stakingProgram.programId,
+      };
+      await assert.rejects(
+        stakingProgram.rpc.stake(
+          await today(),
+          new anchor.BN(1_000_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          { accounts },
+        ),
+        (err: any) => err.msg === 'The token account has the wrong mint.',
+      );
+    });
+
+    it('rejects withdrawals to an account of someone else', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      const attackerAccount = await stakingMint.createAccount(
+        anchor.web3.Keypair.generate().publicKey,
+      );
+      await assert.rejects(
+        stakingProgram.rpc.unstake(
+          await today(),
+          new anchor.BN(1_000_000_000),
+          new anchor.BN(0),
+          '',
+          {
+            accounts: {
+              pool: pool.publicKey,
+              stakingVault,
+              user,
+              owner: wallet.publicKey,
+              stakeFromAccount: attackerAccount,
+              poolSigner,
+              ...(await dailyStatsAccounts()),
+              tokenProgram: TOKEN_PROGRAM_ID,
+              stakingMint: stakingMint.publicKey,
+              priceFeed: pool.publicKey,
+              memoProgram: MEMO_PROGRAM_ID,
+              eventAuthority,
+              program: stakingProgram.programId,
+            },
+          },
+        ),
+      );
+      const vault = await stakingMint.getAccountInfo(attackerAccount);
+      assert.equal(vault.amount.toString(), '0');
+    });
+  });
+
+  describe('fund', () => {
+    const fundFrom = (from: anchor.web3.PublicKey) =>
+      stakingProgram.rpc.fund(new anchor.BN(604_800_000), {
+        accounts: {
+          pool: pool.publicKey,
+          rewardVault,
+          funder: wallet.publicKey,
+          from,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+        },
+      });
+
+    it('rejects a funding account of another mint', async () => {
+      await initializePool(false);
+      const from = await stakingMint.createAccount(wallet.publicKey);
+      await stakingMint.mintTo(from, wallet.payer, [], 604_800_000);
+
+      await assert.rejects(fundFrom(from));
+    });
+
+    it('rejects a funding account the funder does not control', async () => {
+      await initializePool(false);
+      const from = await rewardMint.createAccount(
+        anchor.web3.Keypair.generate().publicKey,
+      );
+      await rewardMint.mintTo(from, wallet.payer, [], 604_800_000);
+
+      await assert.rejects(fundFrom(from));
+    });
+
+    describe('through a multisig', () => {
+      let multisigSigners: anchor.web3.Keypair[];
+      let multisig: anchor.web3.PublicKey;
+      let from: anchor.web3.PublicKey;
+
+      const fundAsMultisig = (signers: anchor.web3.Keypair[]) =>
+        stakingProgram.rpc.fund(new anchor.BN(604_800_000), {
+          accounts: {
+            pool: pool.publicKey,
+            rewardVault,
+            funder: multisig,
+            from,
+            poolSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            rewardMint: rewardMint.publicKey,
+          },
+          remainingAccounts: signers.map((signer) => ({
+            pubkey: signer.publicKey,
+            isWritable: false,
+            isSigner: true,
+          })),
+          signers,
+        });
+
+      beforeEach(async () => {
+        await initializePool(false);
+        multisigSigners = [0, 1, 2].map(() => anchor.web3.Keypair.generate());
+        multisig = await rewardMint.createMultisig(
+          2,
+          multisigSigners.map((signer) => signer.publicKey),
+        );
+        from = await rewardMint.createAccount(multisig);
+        await rewardMint.mintTo(from, wallet.payer, [], 604_800_000);
+        await stakingProgram.rpc.authorizeFunder(multisig, {
+          accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+        });
+      });
+
+      it('funds with enough multisig signers', async () => {
+        await fundAsMultisig(multisigSigners.slice(1));
+
+        const vault = await rewardMint.getAccountInfo(rewardVault);
+        assert.equal(vault.amount.toString(), '604800000');
+        const poolAccount = await stakingProgram.account.pool.fetch(
+          pool.publicKey,
+        );
+        assert.equal(
+          poolAccount.funderContributions[0].toString(),
+          '604800000',
+        );
+      });
+
+      it('rejects too few multisig signers', async () => {
+        const signer = multisigSigners[0];
+        await assert.rejects(
+          fundAsMultisig([signer, signer]),
+          (err: any) =>
+            err.msg ===
+            'Funder must sign, directly or through its multisig signers.',
+        );
+      });
+    });
+
+    describe('by another funder', () => {
+      let funder: anchor.web3.Keypair;
+
+      const fundAs = async (amount: number) => {
+        const from = await rewardMint.createAccount(funder.publicKey);
+        await rewardMint.mintTo(from, wallet.payer, [], amount);
+        await stakingProgram.rpc.fund(new anchor.BN(amount), {
+          accounts: {
+            pool: pool.publicKey,
+            rewardVault,
+            funder: funder.publicKey,
+            from,
+            poolSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            rewardMint: rewardMint.publicKey,
+          },
+          signers: [funder],
+        });
+      };
+
+      beforeEach(async () => {
+        await initializePool(false);
+        funder = anchor.web3.Keypair.generate();
+        await stakingProgram.rpc.authorizeFunder(funder.publicKey, {
+          accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+        });
+        // A reward rate of one token per second.
+        await fund(new anchor.BN(604_800));
+        await sleep(2500);
+      });
+
+      it('may keep the reward rate unchanged', async () => {
+        await assert.rejects(fundAs(1));
+        await fundAs(604_800);
+
+        const poolAccount = await stakingProgram.account.pool.fetch(
+          pool.publicKey,
+        );
+        assert.equal(poolAccount.rewardRate.toString(), '1');
+      });
+
+      it('may lower the rate with the minimum funding', async () => {
+        await stakingProgram.rpc.setMinFundAmount(new anchor.BN(1_000), {
+          accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+        });
+
+        await assert.rejects(fundAs(999));
+        await fundAs(1_000);
+      });
+
+      it('leaves the authority unrestricted', async () => {
+        await fund(new anchor.BN(1));
+      });
+
+      it('tracks contributions per funder', async () => {
+        await fundAs(1_209_600);
+
+        let poolAccount = await stakingProgram.account.pool.fetch(
+          pool.publicKey,
+        );
+        assert.deepEqual(
+          poolAccount.funderContributions.map((x) => x.toString()),
+          ['1209600', '0', '0', '0', '0', '604800'],
+        );
+
+        await stakingProgram.rpc.deauthorizeFunder(funder.publicKey, {
+          accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+        });
+        poolAccount = await stakingProgram.account.pool.fetch(pool.publicKey);
+        assert.equal(poolAccount.funderContributions[0].toString(), '0');
+        assert.equal(
+          poolAccount.removedFunderContributions.toString(),
+          '1209600',
+        );
+      });
+    });
+  });
+
+  describe('withdraw policy', () => {
+    it('requires the cosigner for unstakes above the threshold', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(4_000_000_000));
+
+      const cosigner = anchor.web3.Keypair.generate();
+      const accounts = {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
+      };
+      const cosignature = [
+        { pubkey: cosigner.publicKey, isWritable: false, isSigner: true },
+      ];
+      await stakingProgram.rpc.setWithdrawPolicy(
+        new anchor.BN(2_000_000_000),
+        cosigner.publicKey,
+        { accounts },
+      );
+
+      await unstake(new anchor.BN(1_000_000_000));
+      await assert.rejects(unstake(new anchor.BN(2_000_000_000)));
+      await unstake(new anchor.BN(2_000_000_000), cosignature, [cosigner]);
+
+      await assert.rejects(
+        stakingProgram.rpc.setWithdrawPolicy(
+          new anchor.BN(0),
+          anchor.web3.PublicKey.default,
+          { accounts },
+        ),
+      );
+      await stakingProgram.rpc.setWithdrawPolicy(
+        new anchor.BN(0),
+        anchor.web3.PublicKey.default,
+        { accounts, remainingAccounts: cosignature, signers: [cosigner] },
+      );
+      await unstake(new anchor.BN(1_000_000_000));
+    });
+  });
+
+  describe('unstake delay', () => {
+    it('rejects unstaking right after staking', async () => {
+      await initializePool(false);
+      await createUser();
+      await setUnstakeDelay(new anchor.BN(1000));
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(unstake(new anchor.BN(1_000_000_000)));
+    });
+
+    it('skips the check for pools without tiers', async () => {
+      await initializePool(true);
+      await createUser();
+      await setUnstakeDelay(new anchor.BN(1000));
+      await stake(new anchor.BN(2_000_000_000));
+
+      await unstake(new anchor.BN(1_000_000_000));
+    });
+  });
+
+  describe('require active rewards', () => {
+    const setRequireActiveRewards = async (required: boolean) => {
+      await stakingProgram.rpc.setRequireActiveRewards(required, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+    };
+
+    it('rejects stakes into a never funded pool', async () => {
+      await initializePool(false);
+      await createUser();
+      await setRequireActiveRewards(true);
+
+      await assert.rejects(stake(new anchor.BN(2_000_000_000)));
+
+      await setRequireActiveRewards(false);
+      await stake(new anchor.BN(2_000_000_000));
+    });
+
+    it('accepts stakes during the reward period', async () => {
+      await initializePool(false);
+      await createUser();
+      await setRequireActiveRewards(true);
+      await fund(new anchor.BN(604_800_000_000));
+
+      await stake(new anchor.BN(2_000_000_000));
+    });
+  });
+
+  describe('deposit limit', () => {
+    it('rejects deposits above the window limit', async () => {
+      await initializePool(false);
+      await createUser();
+      await setDepositLimit(new anchor.BN(3_000_000_000));
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(stake(new anchor.BN(2_000_000_000)));
+      await stake(new anchor.BN(1_000_000_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.windowDeposited.toString(), '3000000000');
+    });
+  });
+
+  describe('migrate user', () => {
+    it('migrates a v1 user account and then stakes', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(1_000_000_000));
+      await downgradeUser();
+
+      const legacy = await provider.connection.getAccountInfo(user);
+      assert.equal(legacy.data.length, 114);
+      await assert.rejects(stake(new anchor.BN(1_000_000_000)));
+
+      await migrateUser();
+      await stake(new anchor.BN(1_000_000_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.version, 23);
+      assert.equal(userAccount.balanceStaked.toString(), '2000000000');
+      await assert.rejects(migrateUser());
+    });
+
+    it('leaves a pool with the current layout unchanged', async () => {
+      await initializePool(false);
+      const before = await provider.connection.getAccountInfo(pool.publicKey);
+
+      await stakingProgram.rpc.migratePool({
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+
+      const after = await provider.connection.getAccountInfo(pool.publicKey);
+      assert.equal(after.data.length, before.data.length);
+      assert.equal(after.lamports, before.lamports);
+    });
+  });
+
+  describe('create user and stake', () => {
+    it('creates the user and stakes in one instruction', async () => {
+      await initializePool(false);
+      ownerTokenAccount = await stakingMint.createAccount(wallet.publicKey);
+      await stakingMint.mintTo(
+        ownerTokenAccount,
+        wallet.payer,
+        [],
+        2_000_000_000,
+      );
+      [user] = await anchor.web3.PublicKey.findProgramAddress(
+        [wallet.publicKey.toBuffer(), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+
+      await stakingProgram.rpc.createUserAndStake(
+        await today(),
+        new anchor.BN(2_000_000_000),
+        new anchor.BN(0),
+        new anchor.BN(0),
+        {
+          accounts: {
+            pool: pool.publicKey,
+            stakingVault,
+            user,
+            owner: wallet.publicKey,
+            stakeFromAccount: ownerTokenAccount,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            ...(await dailyStatsAccounts()),
+            stakingMint: stakingMint.publicKey,
+            priceFeed: pool.publicKey,
+          },
+        },
+      );
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.owner.toString(), wallet.publicKey.toString());
+      assert.equal(userAccount.balanceStaked.toString(), '2000000000');
+      assert.equal(userAccount.tier.toString(), '1');
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.userStakeCount.toString(), '1');
+      assert.equal(poolAccount.totalStaked.toString(), '2000000000');
+      const stats = await stakingProgram.account.dailyStats.fetch(
+        (await dailyStatsAccounts()).dailyStats,
+      );
+      assert.equal(stats.stakes.toString(), '1');
+      assert.equal(stats.stakeVolume.toString(), '2000000000');
+    });
+  });
+
+  describe('unstake', () => {
+    it('update tier', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+
+      await unstake(new anchor.BN(5_000_000_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.pool.toString(), pool.publicKey.toString());
+      assert.equal(userAccount.owner.toString(), wallet.publicKey.toString());
+      assert.equal(userAccount.rewardPerTokenComplete.toString(), '0');
+      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
+      assert.equal(userAccount.tier.toString(), '0');
+    });
+
+    it('do not update tier if no allocation', async () => {
+      await initializePool(true);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+
+      await unstake(new anchor.BN(1_000_000_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier.toString(), '0');
+    });
+
+    it('rejects unstakes before maturity', async () => {
+      await initializePool(false, { lockPeriod: new anchor.BN(86400) });
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+
+      await assert.rejects(
+        unstake(new anchor.BN(1_000_000_000)),
+        (err: any) => err.msg === 'Position has not matured yet.',
+      );
+    });
+
+    it('rejects unstakes above the staked balance', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await assert.rejects(
+        unstake(new anchor.BN(3_000_000_000)),
+        (err: any) => err.msg === 'Amount exceeds the staked balance.',
+      );
+    });
+
+    it('records position timestamps', async () => {
+      await initializePool(false);
+      await createUser();
+
+      let userAccount = await stakingProgram.account.user.fetch(user);
+      assert.ok(userAccount.createdAt.gtn(0));
+      assert.equal(userAccount.lastStakeTime.toString(), '0');
+
+      await stake(new anchor.BN(2_000_000_000));
+      await unstake(new anchor.BN(1_000_000_000));
+
+      userAccount = await stakingProgram.account.user.fetch(user);
+      assert.ok(userAccount.lastStakeTime.gte(userAccount.createdAt));
+      assert.ok(userAccount.lastUnstakeTime.gte(userAccount.lastStakeTime));
+    });
+
+    it('keeps a ring buffer of recent actions', async () => {
+      await initializePool(false);
+      await createUser();
+
+      for (let i = 1; i <= 9; i++) {
+        await stake(new anchor.BN(i * 1_000_000));
+      }
+      await unstake(new anchor.BN(500_000));
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.historyHead, 2);
+      assert.equal(userAccount.history[0].action, 1);
+      assert.equal(userAccount.history[0].amount.toString(), '9000000');
+      assert.equal(userAccount.history[1].action, 2);
+      assert.equal(userAccount.history[1].amount.toString(), '500000');
+      assert.equal(userAccount.history[2].amount.toString(), '3000000');
+    });
+  });
+
+  describe('top-up grace', () => {
+    const longLock = new anchor.BN(86400 * 90);
+
+    it('small top-up keeps maturity', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setTopupGrace(new anchor.BN(0), 100);
+      await stake(new anchor.BN(10_000_000_000));
+
+      const before = await stakingProgram.account.user.fetch(user);
+      await sleep(2000);
+      await stake(new anchor.BN(100_000_000));
+
+      const after = await stakingProgram.account.user.fetch(user);
+      assert.equal(
+        after.maturityTime.toString(),
+        before.maturityTime.toString(),
+      );
+    });
+
+    it('large top-up resets maturity', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setTopupGrace(new anchor.BN(0), 100);
+      await stake(new anchor.BN(10_000_000_000));
+
+      const before = await stakingProgram.account.user.fetch(user);
+      await sleep(2000);
+      await stake(new anchor.BN(20_000_000_000));
+
+      const after = await stakingProgram.account.user.fetch(user);
+      assert.ok(after.maturityTime.gt(before.maturityTime));
+    });
+  });
+
+  describe('early unstake penalty', () => {
+    const longLock = new anchor.BN(86400 * 90);
+    let penaltyVault: anchor.web3.PublicKey;
+
+    beforeEach(async () => {
+      penaltyVault = await stakingMint.createAccount(wallet.publicKey);
+    });
+
+    it('rejects early unstake without a penalty configured', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await stake(new anchor.BN(10_000_000_000));
+
+      await assert.rejects(unstake(new anchor.BN(1_000_000_000)));
+    });
+
+    it('charges the full penalty right after staking', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stake(new anchor.BN(10_000_000_000));
+
+      await unstake(new anchor.BN(1_000_000_000), [
+        { pubkey: penaltyVault, isWritable: true, isSigner: false },
+      ]);
+
+      const vault = await stakingMint.getAccountInfo(penaltyVault);
+      assert.equal(vault.amount.toString(), '100000000');
+    });
+
+    it('totals penalties and emits FeeCollectedEvent', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stake(new anchor.BN(10_000_000_000));
+      const remainingAccounts = [
+        { pubkey: penaltyVault, isWritable: true, isSigner: false },
+      ];
+
+      const events = await captureEvents(
+        stakingProgram,
+        ['FeeCollectedEvent'],
+        async () => {
+          await unstake(new anchor.BN(1_000_000_000), remainingAccounts);
+          await unstake(new anchor.BN(2_000_000_000), remainingAccounts);
+        },
+      );
+
+      assert.equal(events.length, 2);
+      for (const event of events) {
+        assert.equal(event.data.pool.toString(), pool.publicKey.toString());
+        assert.equal(event.data.kind, 2);
+        assert.equal(event.data.payer.toString(), wallet.publicKey.toString());
+      }
+      const total = events[0].data.amount.add(events[1].data.amount);
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.totalPenalties.toString(), total.toString());
+      assert.equal(poolAccount.totalDepositFees.toString(), '0');
+      assert.equal(poolAccount.totalClaimFees.toString(), '0');
+      const vault = await stakingMint.getAccountInfo(penaltyVault);
+      assert.equal(vault.amount.toString(), total.toString());
+    });
+
+    it('charges the penalty when the waiver proof is invalid', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stakingProgram.rpc.setPenaltyWaiverCollection(
+        anchor.web3.Keypair.generate().publicKey,
+        {
+          accounts: {
+            pool: pool.publicKey,
+            authority: wallet.publicKey,
+          },
+        },
+      );
+      await stake(new anchor.BN(10_000_000_000));
+
+      await unstake(new anchor.BN(1_000_000_000), [
+        { pubkey: penaltyVault, isWritable: true, isSigner: false },
+        { pubkey: ownerTokenAccount, isWritable: false, isSigner: false },
+        {
+          pubkey: anchor.web3.Keypair.generate().publicKey,
+          isWritable: false,
+          isSigner: false,
+        },
+      ]);
+
+      const vault = await stakingMint.getAccountInfo(penaltyVault);
+      assert.equal(vault.amount.toString(), '100000000');
+    });
+
+    it('skips the penalty for exempt users', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stake(new anchor.BN(10_000_000_000));
+      await stakingProgram.rpc.setPenaltyExempt(true, {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          user,
+        },
+      });
+
+      const data = await simulateReturnData(
+        provider,
+        stakingProgram.instruction.simulateUnstake(
+          new anchor.BN(1_000_000_000),
+          { accounts: { pool: pool.publicKey, user } },
+        ),
+      );
+      assert.equal(readU64(data, 8).toString(), '0');
+      assert.equal(data[35], 1);
+
+      await unstake(new anchor.BN(1_000_000_000), [
+        { pubkey: penaltyVault, isWritable: true, isSigner: false },
+      ]);
+      const vault = await stakingMint.getAccountInfo(penaltyVault);
+      assert.equal(vault.amount.toString(), '0');
+    });
+
+    it('requires the penalty vault', async () => {
+      await initializePool(false, { lockPeriod: longLock });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stake(new anchor.BN(10_000_000_000));
+
+      await assert.rejects(unstake(new anchor.BN(1_000_000_000)));
+    });
+  });
+
+  describe('stake split', () => {
+    it('splits a deposit across two pools', async () => {
+      await initializePool(false);
+      await createUser();
+      const second = await createSecondPool();
+
+      await stakeSplit(second, new anchor.BN(10_000_000_000), 7000);
+
+      const firstUser = await stakingProgram.account.user.fetch(user);
+      assert.equal(firstUser.balanceStaked.toString(), '7000000000');
+      const secondUser = await stakingProgram.account.user.fetch(second.user);
+      assert.equal(secondUser.balanceStaked.toString(), '3000000000');
+      const { dailyStats } = await dailyStatsAccounts(second.pool);
+      const stats = await stakingProgram.account.dailyStats.fetch(dailyStats);
+      assert.equal(stats.stakeVolume.toString(), '3000000000');
+    });
+
+    it('fails entirely when one pool is paused', async () => {
+      await initializePool(false);
+      await createUser();
+      const second = await createSecondPool();
+      await stakingProgram.rpc.pause({
+        accounts: { pool: second.pool, authority: wallet.publicKey },
+      });
+
+      await assert.rejects(
+        stakeSplit(second, new anchor.BN(10_000_000_000), 7000),
+      );
+
+      const firstUser = await stakingProgram.account.user.fetch(user);
+      assert.equal(firstUser.balanceStaked.toString(), '0');
+    });
+  });
+
+  describe('migrate stake', () => {
+    const createMigration = async (
+      second: SecondPool,
+      payPendingRewards: boolean,
+    ) => {
+      const [migration] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('migration'), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createMigration(payPendingRewards, {
+        accounts: {
+          migration,
+          oldPool: pool.publicKey,
+          newPool: second.pool,
+          oldAuthority: wallet.publicKey,
+          newAuthority: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      return migration;
+    };
+
+    const migrateStake = (
+      second: SecondPool,
+      migration: anchor.web3.PublicKey,
+      rewardAccount: anchor.web3.PublicKey,
+      signers: anchor.web3.Keypair[] = [],
+      rentPayer = wallet.publicKey,
+    ) =>
+      stakingProgram.rpc.migrateStake({
+        accounts: {
+          migration,
+          oldPool: pool.publicKey,
+          oldStakingVault: stakingVault,
+          oldRewardVault: rewardVault,
+          oldUser: user,
+          oldPoolSigner: poolSigner,
+          rewardAccount,
+          newPool: second.pool,
+          newStakingVault: second.stakingVault,
+          newUser: second.user,
+          owner: wallet.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          stakingMint: stakingMint.publicKey,
+          rewardMint: rewardMint.publicKey,
+          rentPayer,
+        },
+        remainingAccounts: signers.map((signer) => ({
+          pubkey: signer.publicKey,
+          isWritable: false,
+          isSigner: true,
+        })),
+        signers,
+      });
+
+    it('moves the position to the linked pool', async () => {
+      await initializePool(false);
+      await createUser();
+      const second = await createSecondPool();
+      await stake(new anchor.BN(6_000_000_000));
+      const migration = await createMigration(second, true);
+
+      const { address } = await rewardMint.getOrCreateAssociatedAccountInfo(
+        wallet.publicKey,
+      );
+      await migrateStake(second, migration, address);
+
+      const oldPool = await stakingProgram.account.pool.fetch(pool.publicKey);
+      assert.equal(oldPool.totalStaked.toString(), '0');
+      assert.equal(oldPool.userStakeCount.toString(), '0');
+      const newPool = await stakingProgram.account.pool.fetch(second.pool);
+      assert.equal(newPool.totalStaked.toString(), '6000000000');
+      const newUser = await stakingProgram.account.user.fetch(second.user);
+      assert.equal(newUser.balanceStaked.toString(), '6000000000');
+      assert.equal(newUser.tier.toString(), '2');
+      assert.equal(await provider.connection.getAccountInfo(user), null);
+    });
+
+    it('refunds the old account rent to the sponsor that paid it', async () => {
+      const sponsor = anchor.web3.Keypair.generate();
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: wallet.publicKey,
+            toPubkey: sponsor.publicKey,
+            lamports: anchor.web3.LAMPORTS_PER_SOL,
+          }),
+        ),
+      );
+      await initializePool(false);
+      await createUser(sponsor);
+      const second = await createSecondPool();
+      await stake(new anchor.BN(2_000_000_000));
+      const migration = await createMigration(second, false);
+      const { address } = await rewardMint.getOrCreateAssociatedAccountInfo(
+        wallet.publicKey,
+      );
+
+      await assert.rejects(
+        migrateStake(second, migration, address),
+        (err: any) => err.msg === 'A raw constraint was violated',
+      );
+      const rent = await provider.connection.getBalance(user);
+      const before = await provider.connection.getBalance(sponsor.publicKey);
+      await migrateStake(second, migration, address, [], sponsor.publicKey);
+      const after = await provider.connection.getBalance(sponsor.publicKey);
+      assert.equal(after - before, rent);
+    });
+
+    it("pays pending rewards only to the owner's reward account", async () => {
+      await initializePool(false);
+      await createUser();
+      const second = await createSecondPool();
+      await stake(new anchor.BN(6_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      const migration = await createMigration(second, true);
+
+      await assert.rejects(
+        migrateStake(
+          second,
+          migration,
+          await rewardMint.createAccount(wallet.publicKey),
+        ),
+        (err: any) =>
+          err.msg === "Rewards must be paid to the beneficiary's token account.",
+      );
+      const { address } = await rewardMint.getOrCreateAssociatedAccountInfo(
+        wallet.publicKey,
+      );
+      await migrateStake(second, migration, address);
+
+      const rewards = await rewardMint.getAccountInfo(address);
+      assert.ok(rewards.amount.gtn(0));
+    });
+
+    it('requires and carries over the withdraw cosigner', async () => {
+      await initializePool(false);
+      await createUser();
+      const second = await createSecondPool();
+      await stake(new anchor.BN(6_000_000_000));
+      const cosigner = anchor.web3.Keypair.generate();
+      await stakingProgram.rpc.setWithdrawPolicy(
+        new anchor.BN(1_000_000_000),
+        cosigner.publicKey,
+        { accounts: { pool: pool.publicKey, user, owner: wallet.publicKey } },
+      );
+      const migration = await createMigration(second, false);
+
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      await assert.rejects(
+        migrateStake(second, migration, rewardAccount),
+        (err: any) => err.msg === 'The withdraw policy cosigner must sign.',
+      );
+      await migrateStake(second, migration, rewardAccount, [cosigner]);
+
+      const newUser = await stakingProgram.account.user.fetch(second.user);
+      assert.equal(newUser.balanceStaked.toString(), '6000000000');
+      assert.ok(newUser.withdrawCosigner.equals(cosigner.publicKey));
+      assert.equal(newUser.withdrawThreshold.toString(), '1000000000');
+    });
+  });
+
+  describe('migrate pool vaults', () => {
+    // Links the global pool to a fresh second pool and pauses both.
+    const linkPools = async () => {
+      const second = await createSecondPool(false);
+      const [migration] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from('migration'), pool.publicKey.toBuffer()],
+        stakingProgram.programId,
+      );
+      await stakingProgram.rpc.createMigration(false, {
+        accounts: {
+          migration,
+          oldPool: pool.publicKey,
+          newPool: second.pool,
+          oldAuthority: wallet.publicKey,
+          newAuthority: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      for (const paused of [pool.publicKey, second.pool]) {
+        await stakingProgram.rpc.pause({
+          accounts: { pool: paused, authority: wallet.publicKey },
+        });
+      }
+
+      const migratePoolVaults = () =>
+        stakingProgram.rpc.migratePoolVaults({
+          accounts: {
+            migration,
+            oldPool: pool.publicKey,
+            oldStakingVault: stakingVault,
+            oldRewardVault: rewardVault,
+            oldPoolSigner: poolSigner,
+            oldAuthority: wallet.publicKey,
+            newPool: second.pool,
+            newStakingVault: second.stakingVault,
+            newRewardVault: second.rewardVault,
+            newAuthority: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            stakingMint: stakingMint.publicKey,
+            rewardMint: rewardMint.publicKey,
+          },
+        });
+      const migrateStake = async () =>
+        stakingProgram.rpc.migrateStake({
+          accounts: {
+            migration,
+            oldPool: pool.publicKey,
+            oldStakingVault: stakingVault,
+            oldRewardVault: rewardVault,
+            oldUser: user,
+            oldPoolSigner: poolSigner,
+            rewardAccount: await rewardMint.createAccount(wallet.publicKey),
+            newPool: second.pool,
+            newStakingVault: second.stakingVault,
+            newUser: second.user,
+            owner: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            stakingMint: stakingMint.publicKey,
+            rewardMint: rewardMint.publicKey,
+            rentPayer: wallet.publicKey,
+          },
+        });
+      const unpauseNewPool = () =>
+        stakingProgram.rpc.unpause({
+          accounts: { pool: second.pool, authority: wallet.publicKey },
+        });
+
+      return {
+        second,
+        migration,
+        migratePoolVaults,
+        migrateStake,
+        unpauseNewPool,
+      };
+    };
+
+    it('hands both vaults to the new pool and re-homes users', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+      await rewardMint.mintTo(rewardVault, wallet.payer, [], 1_000_000);
+      const { second, migratePoolVaults, migrateStake, unpauseNewPool } =
+        await linkPools();
+
+      await migratePoolVaults();
+      await assert.rejects(migratePoolVaults());
+
+      let newPool = await stakingProgram.account.pool.fetch(second.pool);
+      assert.equal(newPool.totalStaked.toString(), '6000000000');
+      assert.equal(newPool.userStakeCount.toString(), '1');
+      const staked = await stakingMint.getAccountInfo(second.stakingVault);
+      assert.equal(staked.amount.toString(), '6000000000');
+      const rewards = await rewardMint.getAccountInfo(second.rewardVault);
+      assert.equal(rewards.amount.toString(), '1000000');
+
+      await unpauseNewPool();
+      await migrateStake();
+
+      newPool = await stakingProgram.account.pool.fetch(second.pool);
+      assert.equal(newPool.totalStaked.toString(), '6000000000');
+      assert.equal(newPool.userStakeCount.toString(), '1');
+      const newUser = await stakingProgram.account.user.fetch(second.user);
+      assert.equal(newUser.balanceStaked.toString(), '6000000000');
+    });
+
+    it('blocks claims on the old pool and carries pending over', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      const { second, migratePoolVaults, migrateStake, unpauseNewPool } =
+        await linkPools();
+
+      await migratePoolVaults();
+      const oldPool = await stakingProgram.account.pool.fetch(pool.publicKey);
+      assert.ok(oldPool.rewardDurationEnd.eq(oldPool.lastUpdateTime));
+      const oldUser = await stakingProgram.account.user.fetch(user);
+      const pending = earned(
+        oldUser.balanceStaked,
+        oldPool.rewardPerTokenStored,
+        oldUser.rewardPerTokenComplete,
+        oldUser.rewardPerTokenPending,
+      );
+      assert.ok(pending.gtn(0));
+
+      await assert.rejects(
+        claim(await rewardMint.createAccount(wallet.publicKey)),
+        (err: any) => err.msg === 'Pool vaults were already migrated.',
+      );
+
+      await unpauseNewPool();
+      await migrateStake();
+      const newUser = await stakingProgram.account.user.fetch(second.user);
+      assert.equal(
+        newUser.rewardPerTokenPending.toString(),
+        pending.toString(),
+      );
+    });
+
+    it('credits rewards accrued between the two steps', async () => {
+      await initializePool(false);
+      await createUser();
+      const amount = new anchor.BN(6_000_000_000);
+      await stake(amount);
+      const {
+        second,
+        migration,
+        migratePoolVaults,
+        migrateStake,
+        unpauseNewPool,
+      } = await linkPools();
+
+      await migratePoolVaults();
+      await unpauseNewPool();
+      const funding = new anchor.BN(604_800_000_000);
+      const from = await rewardMint.createAccount(wallet.publicKey);
+      await rewardMint.mintTo(from, wallet.payer, [], funding.toNumber());
+      await stakingProgram.rpc.fund(funding, {
+        accounts: {
+          pool: second.pool,
+          rewardVault: second.rewardVault,
+          funder: wallet.publicKey,
+          from,
+          poolSigner: second.poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rewardMint: rewardMint.publicKey,
+        },
+      });
+      await sleep(2000);
+
+      await migrateStake();
+      const { rewardPerTokenStart } =
+        await stakingProgram.account.migrationConfig.fetch(migration);
+      const newPool = await stakingProgram.account.pool.fetch(second.pool);
+      const newUser = await stakingProgram.account.user.fetch(second.user);
+      const accrued = earned(
+        amount,
+        newPool.rewardPerTokenStored,
+        rewardPerTokenStart,
+        new anchor.BN(0),
+      );
+      assert.ok(accrued.gtn(0));
+      assert.equal(
+        newUser.rewardPerTokenPending.toString(),
+        accrued.toString(),
+      );
+      assert.equal(newPool.aggregatePending.toString(), accrued.toString());
+    });
+  });
+
+  describe('batch unstake', () => {
+    const maxBatchPools = 5;
+
+    const batchAccounts = (
+      entries: SecondPool[],
+      destination = ownerTokenAccount,
+    ) =>
+      entries.flatMap((entry) => [
+        { pubkey: entry.pool, isWritable: true, isSigner: false },
+        { pubkey: entry.stakingVault, isWritable: true, isSigner: false },
+        { pubkey: entry.user, isWritable: true, isSigner: false },
+        { pubkey: destination, isWritable: true, isSigner: false },
+        { pubkey: entry.poolSigner, isWritable: false, isSigner: false },
+        { pubkey: stakingMint.publicKey, isWritable: false, isSigner: false },
+        { pubkey: entry.pool, isWritable: false, isSigner: false },
+      ]);
+
+    it('unstakes from the maximum number of pools', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(1_000_000_000));
+
+      const entries: SecondPool[] = [
+        { pool: pool.publicKey, stakingVault, user, poolSigner },
+      ];
+      for (let i = 1; i < maxBatchPools; i++) {
+        const second = await createSecondPool();
+        await stakeInto(second, new anchor.BN(1_000_000_000));
+        entries.push(second);
+      }
+
+      await stakingProgram.rpc.batchUnstake(
+        entries.map(() => new anchor.BN(1_000_000_000)),
+        {
+          accounts: {
+            owner: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          },
+          remainingAccounts: batchAccounts(entries),
+        },
+      );
+
+      for (const entry of entries) {
+        const userAccount = await stakingProgram.account.user.fetch(
+          entry.user,
+        );
+        assert.equal(userAccount.balanceStaked.toString(), '0');
+        const poolAccount = await stakingProgram.account.pool.fetch(
+          entry.pool,
+        );
+        assert.equal(poolAccount.totalStaked.toString(), '0');
+      }
+    });
+
+    it('reports progress per position within the compute budget', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(1_000_000_000));
+
+      const entries: SecondPool[] = [
+        { pool: pool.publicKey, stakingVault, user, poolSigner },
+      ];
+      for (let i = 1; i < maxBatchPools; i++) {
+        const second = await createSecondPool();
+        await stakeInto(second, new anchor.BN(1_000_000_000));
+        entries.push(second);
+      }
+      const simulateBatch = (batch: SecondPool[]) =>
+        stakingProgram.simulate.batchUnstake(
+          batch.map(() => new anchor.BN(1_000_000_000)),
+          {
+            accounts: {
+              owner: wallet.publicKey,
+              tokenProgram: TOKEN_PROGRAM_ID,
+            },
+            remainingAccounts: batchAccounts(batch),
+          },
+        );
+
+      const single = await simulateBatch(entries.slice(0, 1));
+      const full = await simulateBatch(entries);
+
+      assert.deepEqual(
+        full.events.map((event) => event.name),
+        [...entries.map(() => 'CrankProgressEvent'), 'CrankCompletedEvent'],
+      );
+      entries.forEach((entry, i) => {
+        const progress = full.events[i].data as any;
+        assert.equal(progress.user.toString(), entry.user.toString());
+        assert.equal(progress.action, 2);
+        assert.equal(progress.amount.toString(), '1000000000');
+      });
+      const completed = full.events[maxBatchPools].data as any;
+      assert.equal(completed.processed, maxBatchPools);
+      assert.equal(completed.skipped, 0);
+
+      // Every extra position, its CrankProgressEvent included, costs
+      // `perPosition` units. The budget would fit `ceiling` positions, so the
+      // account limit behind MAX_BATCH_UNSTAKE_POOLS is what caps a batch.
+      const perPosition =
+        (unitsConsumed(full.raw, stakingProgram.programId) -
+          unitsConsumed(single.raw, stakingProgram.programId)) /
+        (maxBatchPools - 1);
+      const ceiling = Math.floor(200_000 / perPosition);
+      assert.ok(perPosition > 0);
+      assert.ok(ceiling >= maxBatchPools);
+    });
+
+    it('reverts the whole batch when one pool fails', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(1_000_000_000));
+      const second = await createSecondPool();
+      await stakeInto(second, new anchor.BN(1_000_000_000));
+
+      await assert.rejects(
+        stakingProgram.rpc.batchUnstake(
+          [new anchor.BN(1_000_000_000), new anchor.BN(2_000_000_000)],
+          {
+            accounts: {
+              owner: wallet.publicKey,
+              tokenProgram: TOKEN_PROGRAM_ID,
+            },
+            remainingAccounts: batchAccounts([
+              { pool: pool.publicKey, stakingVault, user, poolSigner },
+              second,
+            ]),
+          },
+        ),
+      );
+
+      const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '1000000000');
+    });
+
+    it("rejects a destination the owner doesn't hold", async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(1_000_000_000));
+      const stranger = anchor.web3.Keypair.generate();
+      const destination = await stakingMint.createAccount(stranger.publicKey);
+
+      await assert.rejects(
+        stakingProgram.rpc.batchUnstake([new anchor.BN(1_000_000_000)], {
+          accounts: {
+            owner: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          },
+          remainingAccounts: batchAccounts(
+            [{ pool: pool.publicKey, stakingVault, user, poolSigner }],
+            destination,
+          ),
+        }),
+        (err: any) =>
+          err.msg === 'Withdrawals must go to a token account of the owner.',
+      );
+    });
+  });
+
+  describe('simulate unstake', () => {
+    it('previews the penalty and net payout', async () => {
+      const penaltyVault = await stakingMint.createAccount(wallet.publicKey);
+      await initializePool(false, { lockPeriod: new anchor.BN(86400 * 90) });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stake(new anchor.BN(10_000_000_000));
+
+      const data = await simulateReturnData(
+        provider,
+        stakingProgram.instruction.simulateUnstake(
+          new anchor.BN(1_000_000_000),
+          { accounts: { pool: pool.publicKey, user } },
+        ),
+      );
+
+      assert.equal(readU64(data, 0).toString(), '1000000000');
+      assert.equal(readU64(data, 8).toString(), '100000000');
+      assert.equal(data.readUInt16LE(16), 1000);
+      assert.equal(readU64(data, 18).toString(), '900000000');
+      assert.equal(data[34], 0);
+      assert.equal(data[35], 0);
+    });
+
+    it('reads the waiver proof from the accounts of unstake', async () => {
+      const penaltyVault = await stakingMint.createAccount(wallet.publicKey);
+      await initializePool(false, { lockPeriod: new anchor.BN(86400 * 90) });
+      await createUser();
+      await setUnstakePenalty(1000, penaltyVault);
+      await stakingProgram.rpc.setPenaltyWaiverCollection(
+        anchor.web3.Keypair.generate().publicKey,
+        {
+          accounts: {
+            pool: pool.publicKey,
+            authority: wallet.publicKey,
+          },
+        },
+      );
+      await stake(new anchor.BN(10_000_000_000));
+      const remainingAccounts = [
+        { pubkey: penaltyVault, isWritable: true, isSigner: false },
+        { pubkey: ownerTokenAccount, isWritable: false, isSigner: false },
+        {
+          pubkey: anchor.web3.Keypair.generate().publicKey,
+          isWritable: false,
+          isSigner: false,
+        },
+      ];
+
+      // The proof is invalid, so both charge the penalty.
+      const data = await simulateReturnData(
+        provider,
+        stakingProgram.instruction.simulateUnstake(
+          new anchor.BN(1_000_000_000),
+          { accounts: { pool: pool.publicKey, user }, remainingAccounts },
+        ),
+      );
+      assert.equal(readU64(data, 8).toString(), '100000000');
+      await unstake(new anchor.BN(1_000_000_000), remainingAccounts);
+      const vault = await stakingMint.getAccountInfo(penaltyVault);
+      assert.equal(vault.amount.toString(), readU64(data, 8).toString());
+    });
+
+    it('fails where unstake would', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(4_000_000_000));
+      const simulate = () =>
+        simulateReturnData(
+          provider,
+          stakingProgram.instruction.simulateUnstake(
+            new anchor.BN(2_000_000_000),
+            { accounts: { pool: pool.publicKey, user } },
+          ),
+        );
+      const accounts = {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
+      };
+
+      const guardian = anchor.web3.Keypair.generate();
+      await stakingProgram.rpc.setGuardian(guardian.publicKey, { accounts });
+      await stakingProgram.rpc.freezeAccount({ accounts });
+      // PositionFrozen.
+      await assert.rejects(simulate(), (err: Error) =>
+        err.message.includes('"Custom":6028'),
+      );
+      await stakingProgram.rpc.unfreezeAccount({
+        accounts: { user, guardian: guardian.publicKey },
+        signers: [guardian],
+      });
+
+      await stakingProgram.rpc.setWithdrawPolicy(
+        new anchor.BN(2_000_000_000),
+        anchor.web3.Keypair.generate().publicKey,
+        { accounts },
+      );
+      // CosignerRequired.
+      await assert.rejects(simulate(), (err: Error) =>
+        err.message.includes('"Custom":6040'),
+      );
+    });
+  });
+
+  describe('user summary', () => {
+    it('sums the given positions', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(3_000_000_000));
+
+      const data = await simulateReturnData(
+        provider,
+        stakingProgram.instruction.getUserSummary(wallet.publicKey, {
+          accounts: { pool: pool.publicKey },
+          remainingAccounts: [
+            { pubkey: user, isWritable: false, isSigner: false },
+          ],
+        }),
+      );
+
+      assert.equal(
+        new anchor.web3.PublicKey(data.slice(0, 32)).toString(),
+        wallet.publicKey.toString(),
+      );
+      assert.equal(data[32], 1);
+      assert.equal(readU64(data, 33).toString(), '3000000000');
+    });
+
+    it('rejects positions of another owner', async () => {
+      await initializePool(false);
+      await createUser();
+
+      await assert.rejects(
+        simulateReturnData(
+          provider,
+          stakingProgram.instruction.getUserSummary(
+            anchor.web3.Keypair.generate().publicKey,
+            {
+              accounts: { pool: pool.publicKey },
+              remainingAccounts: [
+                { pubkey: user, isWritable: false, isSigner: false },
+              ],
+            },
+          ),
+        ),
+      );
+    });
+  });
+
+  describe('config', () => {
+    it('returns the pool settings in one call', async () => {
+      await initializePool(true, { lockPeriod: new anchor.BN(86400) });
+      const funder = anchor.web3.Keypair.generate().publicKey;
+      await stakingProgram.rpc.authorizeFunder(funder, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+      const data = await simulateReturnData(
+        provider,
+        stakingProgram.instruction.getConfig({
+          accounts: { pool: pool.publicKey },
+        }),
+      );
+
+      assert.equal(data[0], 1);
+      assert.equal(
+        new anchor.web3.PublicKey(data.slice(1, 33)).toString(),
+        wallet.publicKey.toString(),
+      );
+      assert.equal(data.readUInt32LE(33), 1);
+      assert.equal(
+        new anchor.web3.PublicKey(data.slice(37, 69)).toString(),
+        funder.toString(),
+      );
+      assert.equal(readU64(data, 69).toString(), rewardDuration.toString());
+      assert.equal(readU64(data, 77).toString(), '86400');
+      // no_tier, paused, halted
+      assert.deepEqual([...data.slice(85, 88)], [1, 0, 0]);
+    });
+  });
+
+  describe('version', () => {
+    it('reports the crate version and feature bits', async () => {
+      const data = await simulateReturnData(
+        provider,
+        stakingProgram.instruction.getVersion({ accounts: {} }),
+      );
+
+      assert.deepEqual(
+        [data.readUInt16LE(0), data.readUInt16LE(2), data.readUInt16LE(4)],
+        [0, 1, 0],
+      );
+      assert.equal(readU64(data, 6).toString(), '131071');
+    });
+  });
+
+  describe('user stats', () => {
+    const getUserStats = () =>
+      simulateReturnData(
+        provider,
+        stakingProgram.instruction.getUserStats({
+          accounts: { pool: pool.publicKey, user },
+        }),
+      );
+
+    it('reports balance, tier and time to maturity', async () => {
+      await initializePool(false, { lockPeriod: new anchor.BN(86400) });
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+
+      const data = await getUserStats();
+      assert.equal(readU64(data, 0).toString(), '0');
+      assert.ok(readU64(data, 8).lten(86400));
+      assert.ok(readU64(data, 8).gtn(86400 - 60));
+      assert.equal(data[16], 2);
+      assert.equal(readU64(data, 17).toString(), '6000000000');
+    });
+
+    it('matches the amount a claim pays', async () => {
+      await initializePool(false);
+      await createUser();
+      const amount = new anchor.BN(2_000_000_000);
+      await stake(amount);
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      // Unstaking stops accrual, so the claim below pays exactly the
+      // simulated amount.
+      await unstake(amount);
+
+      const pending = readU64(await getUserStats(), 0);
+      assert.ok(pending.gtn(0));
+
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      await claim(rewardAccount);
+      const claimed = await rewardMint.getAccountInfo(rewardAccount);
+      assert.equal(claimed.amount.toString(), pending.toString());
+    });
+  });
+
+  describe('daily stats', () => {
+    it('counts the day of stakes, unstakes and claims', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await stake(new anchor.BN(1_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      await sleep(2000);
+      await unstake(new anchor.BN(3_000_000_000));
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
+      await claim(rewardAccount);
+
+      const { dailyStats } = await dailyStatsAccounts();
+      const stats = await stakingProgram.account.dailyStats.fetch(dailyStats);
+      const claimed = await rewardMint.getAccountInfo(rewardAccount);
+      assert.equal(stats.pool.toString(), pool.publicKey.toString());
+      assert.equal(stats.dayIndex.toString(), (await today()).toString());
+      assert.equal(stats.stakes.toString(), '2');
+      assert.equal(stats.stakeVolume.toString(), '3000000000');
+      assert.equal(stats.unstakes.toString(), '1');
+      assert.equal(stats.unstakeVolume.toString(), '3000000000');
+      assert.equal(stats.claims.toString(), '1');
+      assert.equal(stats.claimVolume.toString(), claimed.amount.toString());
+    });
+
+    it('keeps the days on either side of midnight apart', async () => {
+      await initializePool(false);
+      await createUser();
+      const dayIndex = await today();
+      const yesterday = dayIndex.subn(1);
+      const before = await dailyStatsAccounts(pool.publicKey, yesterday);
+      const after = await dailyStatsAccounts(pool.publicKey, dayIndex);
+      assert.notEqual(
+        before.dailyStats.toString(),
+        after.dailyStats.toString(),
+      );
+
+      // A stake that landed in the last second before midnight.
+      await stakingProgram.rpc.recordDailyStatsForTesting(
+        yesterday,
+        new anchor.BN(1_000_000_000),
+        {
+          accounts: {
+            pool: pool.publicKey,
+            authority: wallet.publicKey,
+            dailyStats: before.dailyStats,
+            systemProgram: before.systemProgram,
+          },
+        },
+      );
+      // One built before midnight that lands after it.
+      await assert.rejects(
+        stakingProgram.rpc.stake(
+          yesterday,
+          new anchor.BN(2_000_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          {
+            accounts: {
+              pool: pool.publicKey,
+              stakingVault,
+              user,
+              owner: wallet.publicKey,
+              stakeFromAccount: ownerTokenAccount,
+              poolSigner,
+              ...before,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              stakingMint: stakingMint.publicKey,
+              priceFeed: pool.publicKey,
+              memoProgram: MEMO_PROGRAM_ID,
+              eventAuthority,
+              program: stakingProgram.programId,
+            },
+          },
+        ),
+        (err: any) =>
+          err.msg ===
+          "The day index isn't the current day of the clock; rebuild the transaction.",
+      );
+      await stake(new anchor.BN(2_000_000_000));
+
+      const previous = await stakingProgram.account.dailyStats.fetch(
+        before.dailyStats,
+      );
+      assert.equal(previous.dayIndex.toString(), yesterday.toString());
+      assert.equal(previous.stakes.toString(), '1');
+      assert.equal(previous.stakeVolume.toString(), '1000000000');
+      const current = await stakingProgram.account.dailyStats.fetch(
+        after.dailyStats,
+      );
+      assert.equal(current.dayIndex.toString(), dayIndex.toString());
+      assert.equal(current.stakes.toString(), '1');
+      assert.equal(current.stakeVolume.toString(), '2000000000');
+    });
+  });
+
+  describe('voter weight', () => {
+    it('derives the weight from the staked balance and tier', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+
+      const realm = anchor.web3.Keypair.generate().publicKey;
+      const [voterWeightRecord] =
+        await anchor.web3.PublicKey.findProgramAddress(
+          [
+            Buffer.from('voter-weight-record'),
+            realm.toBuffer(),
+            stakingMint.publicKey.toBuffer(),
+            wallet.publicKey.toBuffer(),
+          ],
+          stakingProgram.programId,
+        );
+      await stakingProgram.rpc.createVoterWeightRecord(realm, {
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          voterWeightRecord,
+          owner: wallet.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      const updateVoterWeight = () =>
+        stakingProgram.rpc.updateVoterWeightRecord({
+          accounts: { pool: pool.publicKey, user, voterWeightRecord },
+        });
+
+      await updateVoterWeight();
+      let record = await stakingProgram.account.voterWeightRecord.fetch(
+        voterWeightRecord,
+      );
+      assert.equal(record.realm.toString(), realm.toString());
+      assert.equal(
+        record.governingTokenMint.toString(),
+        stakingMint.publicKey.toString(),
+      );
+      assert.equal(
+        record.governingTokenOwner.toString(),
+        wallet.publicKey.toString(),
+      );
+      assert.equal(record.voterWeight.toString(), '6000000000');
+      assert.ok(record.voterWeightExpiry.gtn(0));
+      assert.equal(record.weightAction, null);
+
+      await stakingProgram.rpc.setVoterWeightTierBoost(true, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+      await updateVoterWeight();
+      record = await stakingProgram.account.voterWeightRecord.fetch(
+        voterWeightRecord,
+      );
+      const { tier } = await stakingProgram.account.user.fetch(user);
+      const multiplierBps = 10_000 + 1_000 * tier;
+      assert.equal(
+        record.voterWeight.toString(),
+        new anchor.BN(6_000_000_000)
+          .muln(multiplierBps)
+          .divn(10_000)
+          .toString(),
+      );
+    });
+
+    it('casts a governance vote with the derived weight', async () => {
+      await initializePool(false);
+      await createUser();
+      await stake(new anchor.BN(6_000_000_000));
+
+      const programVersion = await getGovernanceProgramVersion(
+        provider.connection,
+        GOVERNANCE_PROGRAM_ID,
+      );
+      const send = (instructions: anchor.web3.TransactionInstruction[]) =>
+        provider.send(new anchor.web3.Transaction().add(...instructions));
+
+      // A realm of the staking mint with this program as its voter weight
+      // addin; realm names are unique, so it's named after the pool.
+      let instructions: anchor.web3.TransactionInstruction[] = [];
+      const realm = await withCreateRealm(
+        instructions,
+        GOVERNANCE_PROGRAM_ID,
+        programVersion,
+        pool.publicKey.toBase58(),
+        wallet.publicKey,
+        stakingMint.publicKey,
+        wallet.publicKey,
+        undefined,
+        MintMaxVoteWeightSource.FULL_SUPPLY_FRACTION,
+        new anchor.BN(1),
+        new GoverningTokenConfigAccountArgs({
+          voterWeightAddin: stakingProgram.programId,
+          maxVoterWeightAddin: undefined,
+          tokenType: GoverningTokenType.Liquid,
+        }),
+      );
+      const tokenOwnerRecord = await withCreateTokenOwnerRecord(
+        instructions,
+        GOVERNANCE_PROGRAM_ID,
+        programVersion,
+        realm,
+        wallet.publicKey,
+        stakingMint.publicKey,
+        wallet.publicKey,
+      );
+      await send(instructions);
+
+      const [voterWeightRecord] =
+        await anchor.web3.PublicKey.findProgramAddress(
+          [
+            Buffer.from('voter-weight-record'),
+            realm.toBuffer(),
+            stakingMint.publicKey.toBuffer(),
+            wallet.publicKey.toBuffer(),
+          ],
+          stakingProgram.programId,
+        );
+      await stakingProgram.rpc.createVoterWeightRecord(realm, {
+        accounts: {
+          pool: pool.publicKey,
+          user,
+          voterWeightRecord,
+          owner: wallet.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+      // The weight expires with the slot, so each governance instruction
+      // reading it follows an update in the same transaction.
+      const updateVoterWeight =
+        stakingProgram.instruction.updateVoterWeightRecord({
+          accounts: { pool: pool.publicKey, user, voterWeightRecord },
+        });
+
+      instructions = [updateVoterWeight];
+      const governance = await withCreateGovernance(
+        instructions,
+        GOVERNANCE_PROGRAM_ID,
+        programVersion,
+        realm,
+        undefined,
+        new GovernanceConfig({
+          communityVoteThreshold: new VoteThreshold({
+            type: VoteThresholdType.YesVotePercentage,
+            value: 60,
+          }),
+          minCommunityTokensToCreateProposal: new anchor.BN(1),
+          minInstructionHoldUpTime: 0,
+          baseVotingTime: 3600,
+          communityVoteTipping: VoteTipping.Disabled,
+          minCouncilTokensToCreateProposal: new anchor.BN(1),
+          councilVoteThreshold: new VoteThreshold({
+            type: VoteThresholdType.Disabled,
+          }),
+          councilVetoVoteThreshold: new VoteThreshold({
+            type: VoteThresholdType.Disabled,
+          }),
+          communityVetoVoteThreshold: new VoteThreshold({
+            type: VoteThresholdType.Disabled,
+          }),
+          councilVoteTipping: VoteTipping.Disabled,
+          votingCoolOffTime: 0,
+          depositExemptProposalCount: 10,
+        }),
+        tokenOwnerRecord,
+        wallet.publicKey,
+        wallet.publicKey,
+        voterWeightRecord,
+      );
+      await send(instructions);
+
+      instructions = [updateVoterWeight];
+      const proposal = await withCreateProposal(
+        instructions,
+        GOVERNANCE_PROGRAM_ID,
+        programVersion,
+        realm,
+        governance,
+        tokenOwnerRecord,
+        'Staked vote',
+        '',
+        stakingMint.publicKey,
+        wallet.publicKey,
+        0,
+        VoteType.SINGLE_CHOICE,
+        ['Approve'],
+        true,
+        wallet.publicKey,
+        voterWeightRecord,
+      );
+      await withSignOffProposal(
+        instructions,
+        GOVERNANCE_PROGRAM_ID,
+        programVersion,
+        realm,
+        governance,
+        proposal,
+        wallet.publicKey,
+        undefined,
+        tokenOwnerRecord,
+      );
+      await send(instructions);
+
+      instructions = [updateVoterWeight];
+      await withCastVote(
+        instructions,
+        GOVERNANCE_PROGRAM_ID,
+        programVersion,
+        realm,
+        governance,
+        proposal,
+        tokenOwnerRecord,
+        tokenOwnerRecord,
+        wallet.publicKey,
+        stakingMint.publicKey,
+        Vote.fromYesNoVote(YesNoVote.Yes),
+        wallet.publicKey,
+        voterWeightRecord,
+      );
+      await send(instructions);
+
+      const voteRecord = await getGovernanceAccount(
+        provider.connection,
+        await getVoteRecordAddress(
+          GOVERNANCE_PROGRAM_ID,
+          proposal,
+          tokenOwnerRecord,
+        ),
+        VoteRecord,
+      );
+      assert.equal(voteRecord.account.voterWeight.toString(), '6000000000');
+    });
+  });
+
+  describe('nft staking', () => {
+    const collection = anchor.web3.Keypair.generate().publicKey;
+
+    it('rejects fungible stakes into NFT pools', async () => {
+      await initializePool(true, { nftCollection: collection });
+      await createUser();
+
+      await assert.rejects(
+        stake(new anchor.BN(1_000_000_000)),
+        (err: any) => err.msg === 'Pool only stakes NFTs.',
+      );
+    });
+
+    it('rejects an NFT without verified collection metadata', async () => {
+      await initializePool(true, { nftCollection: collection });
+      await createUser();
+      const nftMint = await createMint(provider, 0);
+      const nftAccount = await nftMint.createAccount(wallet.publicKey);
+      await nftMint.mintTo(nftAccount, wallet.payer, [], 1);
+      const [nftEscrow] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+          Buffer.from('nft_escrow'),
+          user.toBuffer(),
+          nftMint.publicKey.toBuffer(),
+        ],
+        stakingProgram.programId,
+      );
+
+      await assert.rejects(
+        stakingProgram.rpc.stakeNft({
+          accounts: {
+            pool: pool.publicKey,
+            user,
+            owner: wallet.publicKey,
+            nftMint: nftMint.publicKey,
+            nftFromAccount: nftAccount,
+            nftMetadata: anchor.web3.Keypair.generate().publicKey,
+            nftEscrow,
+            poolSigner,
+            payer: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          },
+        }),
+        (err: any) =>
+          err.msg === "Token is not an NFT of the pool's collection.",
+      );
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.nftCollection.toString(), collection.toString());
+      assert.equal(poolAccount.nftWeight.toString(), '1');
+      assert.equal(poolAccount.totalStaked.toString(), '0');
+    });
+  });
+
+  describe('usd tiers', () => {
+    const usdThresholds = [100, 200, 500, 1_000, 2_000, 5_000, 10_000].map(
+      (usd) => new anchor.BN(usd * 1_000_000),
+    );
+
+    it('tiers by the USD value of the staked balance', async () => {
+      await initializePool(false);
+      await createUser();
+
+      const feed = anchor.web3.Keypair.generate();
+      const space = 3312;
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: wallet.publicKey,
+            newAccountPubkey: feed.publicKey,
+            lamports:
+              await provider.connection.getMinimumBalanceForRentExemption(
+                space,
+              ),
+            space,
+            programId: stakingProgram.programId,
+          }),
+        ),
+        [feed],
+      );
+      // 0.001 USD per token.
+      const writePrice = (price: number, timestamp: number) =>
+        stakingProgram.rpc.writePriceFeedForTesting(
+          new anchor.BN(price),
+          new anchor.BN(1_000),
+          -8,
+          new anchor.BN(timestamp),
+          {
+            accounts: {
+              priceFeed: feed.publicKey,
+              authority: wallet.publicKey,
+            },
+          },
+        );
+      await writePrice(100_000, Math.floor(Date.now() / 1000));
+      // Program accounts can't be rewritten as price feeds.
+      await assert.rejects(
+        stakingProgram.rpc.writePriceFeedForTesting(
+          new anchor.BN(1),
+          new anchor.BN(0),
+          -8,
+          new anchor.BN(0),
+          {
+            accounts: {
+              priceFeed: pool.publicKey,
+              authority: wallet.publicKey,
+            },
+          },
+        ),
+        (err: any) =>
+          err.msg === 'Account is not a test account writable by this signer.',
+      );
+      await stakingProgram.rpc.setPriceFeed(feed.publicKey, usdThresholds, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+      const stakeWithFeed = async (amount: anchor.BN) =>
+        stakingProgram.rpc.stake(
+          await today(),
+          amount,
+          new anchor.BN(0),
+          new anchor.BN(0),
+          {
+            accounts: {
+              pool: pool.publicKey,
+              stakingVault,
+              user,
+              owner: wallet.publicKey,
+              stakeFromAccount: ownerTokenAccount,
+              poolSigner,
+              ...(await dailyStatsAccounts()),
+              tokenProgram: TOKEN_PROGRAM_ID,
+              stakingMint: stakingMint.publicKey,
+              priceFeed: feed.publicKey,
+              memoProgram: MEMO_PROGRAM_ID,
+              eventAuthority,
+              program: stakingProgram.programId,
+            },
+          },
+        );
+
+      // 600,000 tokens at 0.001 USD are worth 600 USD.
+      await stakeWithFeed(new anchor.BN(6_000_000_000));
+      let userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier, 3);
+
+      // A stale price leaves the tier alone, however high it is.
+      await writePrice(1_000_000, Math.floor(Date.now() / 1000) - 600);
+      await stakeWithFeed(new anchor.BN(1_000_000_000));
+      userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier, 3);
+    });
+
+    it('rejects a price feed other than the configured one', async () => {
+      await initializePool(false);
+      await createUser();
+      const feed = anchor.web3.Keypair.generate().publicKey;
+      await stakingProgram.rpc.setPriceFeed(feed, usdThresholds, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+      await assert.rejects(
+        stake(new anchor.BN(1_000_000_000)),
+        (err: any) => err.msg === 'Price feed does not match the pool.',
+      );
+    });
+
+    it('requires increasing thresholds', async () => {
+      await initializePool(false);
+      await assert.rejects(
+        stakingProgram.rpc.setPriceFeed(
+          anchor.web3.Keypair.generate().publicKey,
+          [...usdThresholds.slice(0, 6), new anchor.BN(0)],
+          { accounts: { pool: pool.publicKey, authority: wallet.publicKey } },
+        ),
+        (err: any) => err.msg === 'Tier thresholds must be increasing.',
+      );
+    });
+  });
+
+  describe('exchange rate tiers', () => {
+    const PYTH = 1;
+
+    it('tiers by the underlying value of the staked balance', async () => {
+      await initializePool(false);
+      await createUser();
+
+      const oracle = anchor.web3.Keypair.generate();
+      const space = 3312;
+      await provider.send(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: wallet.publicKey,
+            newAccountPubkey: oracle.publicKey,
+            lamports:
+              await provider.connection.getMinimumBalanceForRentExemption(
+                space,
+              ),
+            space,
+            programId: stakingProgram.programId,
+          }),
+        ),
+        [oracle],
+      );
+      // Each staking token is worth 2 underlying tokens.
+      const writeRate = (timestamp: number) =>
+        stakingProgram.rpc.writePriceFeedForTesting(
+          new anchor.BN(200_000_000),
+          new anchor.BN(1_000),
+          -8,
+          new anchor.BN(timestamp),
+          {
+            accounts: {
+              priceFeed: oracle.publicKey,
+              authority: wallet.publicKey,
+            },
+          },
+        );
+      await writeRate(Math.floor(Date.now() / 1000));
+      await stakingProgram.rpc.setExchangeRateOracle(oracle.publicKey, PYTH, {
+        accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+      });
+
+      const stakeWithOracle = async (amount: anchor.BN) =>
+        stakingProgram.rpc.stake(
+          await today(),
+          amount,
+          new anchor.BN(0),
+          new anchor.BN(0),
+          {
+            accounts: {
+              pool: pool.publicKey,
+              stakingVault,
+              user,
+              owner: wallet.publicKey,
+              stakeFromAccount: ownerTokenAccount,
+              poolSigner,
+              ...(await dailyStatsAccounts()),
+              tokenProgram: TOKEN_PROGRAM_ID,
+              stakingMint: stakingMint.publicKey,
+              priceFeed: oracle.publicKey,
+              memoProgram: MEMO_PROGRAM_ID,
+              eventAuthority,
+              program: stakingProgram.programId,
+            },
+          },
+        );
+
+      // 3,000 tokens alone are tier 1, but are worth 6,000 underlying.
+      await stakeWithOracle(new anchor.BN(3_000_000_000));
+      let userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier, 2);
+
+      // A stale rate leaves the tier alone.
+      await writeRate(Math.floor(Date.now() / 1000) - 600);
+      await stakeWithOracle(new anchor.BN(3_000_000_000));
+      userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.tier, 2);
+    });
+
+    it('rejects an oracle that does not match its source', async () => {
+      await initializePool(false);
+      await assert.rejects(
+        stakingProgram.rpc.setExchangeRateOracle(
+          anchor.web3.PublicKey.default,
+          PYTH,
+          { accounts: { pool: pool.publicKey, authority: wallet.publicKey } },
+        ),
+        (err: any) =>
+          err.msg === 'Exchange-rate oracle does not match its source.',
+      );
+    });
+
+    it('rejects a price feed alongside an exchange-rate oracle', async () => {
+      await initializePool(false);
+      await stakingProgram.rpc.setExchangeRateOracle(
+        anchor.web3.Keypair.generate().publicKey,
+        PYTH,
+        { accounts: { pool: pool.publicKey, authority: wallet.publicKey } },
+      );
 
-      const userAccount = await stakingProgram.account.user.fetch(user);
-      assert.equal(userAccount.pool.toString(), pool.publicKey.toString());
-      assert.equal(userAccount.owner.toString(), wallet.publicKey.toString());
-      assert.equal(userAccount.rewardPerTokenComplete.toString(), '0');
-      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
-      assert.equal(userAccount.balanceStaked.toString(), amount.toString());
-      assert.equal(userAccount.tier.toString(), '1');
+      await assert.rejects(
+        stakingProgram.rpc.setPriceFeed(
+          anchor.web3.Keypair.generate().publicKey,
+          [100, 200, 500, 1_000, 2_000, 5_000, 10_000].map(
+            (usd) => new anchor.BN(usd * 1_000_000),
+          ),
+          { accounts: { pool: pool.publicKey, authority: wallet.publicKey } },
+        ),
+        (err: any) =>
+          err.msg === 'Pool already values tiers through another oracle.',
+      );
     });
+  });
 
-    it('increase tier', async () => {
+  describe('reward math parity', () => {
+    it('predicts rewards over random stakes and claims', async () => {
       await initializePool(false);
       await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
+      const rewardAccount = await rewardMint.createAccount(wallet.publicKey);
 
-      const amount = new anchor.BN(2_000_000_000);
+      for (let step = 0; step < 6; step++) {
+        await sleep(Math.floor(Math.random() * 2000));
+        const poolBefore = await stakingProgram.account.pool.fetch(
+          pool.publicKey,
+        );
+        const userBefore = await stakingProgram.account.user.fetch(user);
 
-      await stake(amount);
-      await stake(amount);
+        const claiming = Math.random() < 0.5;
+        if (claiming) {
+          await claim(rewardAccount);
+        } else {
+          await stake(
+            new anchor.BN(1 + Math.floor(Math.random() * 1_000)).muln(
+              1_000_000,
+            ),
+          );
+        }
 
-      let userAccount = await stakingProgram.account.user.fetch(user);
-      assert.equal(userAccount.tier.toString(), '1');
+        const poolAfter = await stakingProgram.account.pool.fetch(
+          pool.publicKey,
+        );
+        const userAfter = await stakingProgram.account.user.fetch(user);
+        const expectedRewardPerToken = rewardPerToken(
+          poolBefore.totalStaked,
+          poolBefore.rewardPerTokenStored,
+          poolAfter.lastUpdateTime,
+          poolBefore.lastUpdateTime,
+          poolBefore.rewardRate,
+        );
+        assert.equal(
+          poolAfter.rewardPerTokenStored.toString(),
+          expectedRewardPerToken.toString(),
+        );
+        const expectedPending = earned(
+          userBefore.balanceStaked,
+          expectedRewardPerToken,
+          userBefore.rewardPerTokenComplete,
+          userBefore.rewardPerTokenPending,
+        );
+        if (claiming) {
+          assert.equal(
+            userAfter.totalRewardsClaimed
+              .sub(userBefore.totalRewardsClaimed)
+              .toString(),
+            expectedPending.toString(),
+          );
+        } else {
+          assert.equal(
+            userAfter.rewardPerTokenPending.toString(),
+            expectedPending.toString(),
+          );
+        }
+      }
+    });
+  });
 
-      await stake(amount);
+  describe('pool stats', () => {
+    const getPoolStats = () =>
+      simulateReturnData(
+        provider,
+        stakingProgram.instruction.getPoolStats({
+          accounts: { pool: pool.publicKey },
+        }),
+      );
 
-      userAccount = await stakingProgram.account.user.fetch(user);
-      assert.equal(userAccount.tier.toString(), '2');
+    it('reports an empty pool', async () => {
+      await initializePool(false);
+      await fund(new anchor.BN(604_800_000_000));
+
+      const data = await getPoolStats();
+      assert.equal(readU64(data, 0).toString(), '0');
+      assert.equal(readU64(data, 8).toString(), '1000000');
+      assert.equal(readU64(data, 32).toString(), '0');
+      assert.equal(data.readUInt32LE(40), 0);
     });
 
-    it('do not update tier if no allocation', async () => {
-      await initializePool(true);
+    it('reports rate, time remaining and apr', async () => {
+      await initializePool(false);
       await createUser();
+      await stake(new anchor.BN(2_000_000_000));
+      await fund(new anchor.BN(604_800_000_000));
 
-      const amount = new anchor.BN(2_000_000_000);
+      const data = await getPoolStats();
+      const secondsRemaining = readU64(data, 16);
+      assert.equal(readU64(data, 0).toString(), '2000000000');
+      assert.equal(readU64(data, 8).toString(), '1000000');
+      assert.ok(secondsRemaining.lte(rewardDuration));
+      assert.ok(secondsRemaining.gt(rewardDuration.subn(60)));
+      assert.equal(
+        readU64(data, 24).toString(),
+        secondsRemaining.muln(1_000_000).toString(),
+      );
+      assert.equal(readU64(data, 32).toString(), '157680000');
+      assert.equal(data.readUInt32LE(40), 1);
+      assert.deepEqual([...data.slice(44, 47)], [0, 0, 0]);
+    });
+  });
 
-      await stake(amount);
+  describe('slash', () => {
+    let slashDestination: anchor.web3.PublicKey;
 
-      const userAccount = await stakingProgram.account.user.fetch(user);
-      assert.equal(userAccount.tier.toString(), '0');
+    beforeEach(async () => {
+      slashDestination = await stakingMint.createAccount(wallet.publicKey);
     });
-  });
 
-  describe('unstake', () => {
-    it('update tier', async () => {
+    it('rejects slashing when disabled', async () => {
       await initializePool(false);
       await createUser();
       await stake(new anchor.BN(6_000_000_000));
 
-      await unstake(new anchor.BN(5_000_000_000));
-
-      const userAccount = await stakingProgram.account.user.fetch(user);
-      assert.equal(userAccount.pool.toString(), pool.publicKey.toString());
-      assert.equal(userAccount.owner.toString(), wallet.publicKey.toString());
-      assert.equal(userAccount.rewardPerTokenComplete.toString(), '0');
-      assert.equal(userAccount.rewardPerTokenPending.toString(), '0');
-      assert.equal(userAccount.tier.toString(), '0');
+      await assert.rejects(
+        slash(new anchor.BN(1_000_000_000), 1, slashDestination),
+      );
     });
 
-    it('do not update tier if no allocation', async () => {
-      await initializePool(true);
+    it('reduces the balance and recomputes the tier', async () => {
+      await initializePool(false, { slashingEnabled: true });
       await createUser();
       await stake(new anchor.BN(6_000_000_000));
 
-      await unstake(new anchor.BN(1_000_000_000));
+      await slash(new anchor.BN(5_000_000_000), 7, slashDestination);
 
       const userAccount = await stakingProgram.account.user.fetch(user);
+      assert.equal(userAccount.balanceStaked.toString(), '1000000000');
       assert.equal(userAccount.tier.toString(), '0');
+      const poolAccount = await stakingProgram.account.pool.fetch(
+        pool.publicKey,
+      );
+      assert.equal(poolAccount.totalStaked.toString(), '1000000000');
+      const destination = await stakingMint.getAccountInfo(slashDestination);
+      assert.equal(destination.amount.toString(), '5000000000');
     });
   });
 
-  const initializePool = async (noTier: boolean) => {
+  type PoolOptions = {
+    lockPeriod?: anchor.BN;
+    slashingEnabled?: boolean;
+    rewardMint?: Token;
+    maxMaturityExtension?: anchor.BN;
+    rewardDuration?: anchor.BN;
+    stakingMint?: Token;
+    strictMint?: boolean;
+    authority?: anchor.web3.PublicKey;
+    nftCollection?: anchor.web3.PublicKey;
+  };
+
+  const initializePool = async (noTier: boolean, options: PoolOptions = {}) => {
     await stakingProgram.rpc.initializePool(
-      nonce,
-      rewardDuration,
-      lockPeriod,
+      options.rewardDuration ?? rewardDuration,
+      options.lockPeriod ?? lockPeriod,
       noTier,
+      options.slashingEnabled ?? false,
+      options.maxMaturityExtension !== undefined,
+      options.maxMaturityExtension ?? new anchor.BN(0),
+      options.strictMint ?? false,
+      options.nftCollection ?? anchor.web3.PublicKey.default,
+      new anchor.BN(options.nftCollection ? 1 : 0),
       {
         accounts: {
-          authority: wallet.publicKey,
-          stakingMint: stakingMint.publicKey,
+          authority: options.authority ?? wallet.publicKey,
+          stakingMint: (options.stakingMint ?? stakingMint).publicKey,
           stakingVault,
-          rewardMint: rewardMint.publicKey,
+          rewardMint: (options.rewardMint ?? rewardMint).publicKey,
           rewardVault,
           poolSigner: poolSigner,
           pool: pool.publicKey,
@@ -176,7 +5326,7 @@ describe('staking', () => {
     );
   };
 
-  const createUser = async () => {
+  const createUser = async (payer?: anchor.web3.Keypair) => {
     ownerTokenAccount = await stakingMint.createAccount(wallet.publicKey);
     await stakingMint.mintTo(
       ownerTokenAccount,
@@ -196,13 +5346,67 @@ describe('staking', () => {
         pool: pool.publicKey,
         user,
         owner: wallet.publicKey,
+        payer: (payer ?? wallet.payer).publicKey,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
+      signers: payer ? [payer] : [],
     });
   };
 
-  const stake = async (amount: anchor.BN) => {
-    await stakingProgram.rpc.stake(amount, {
+  const today = () => clockDayIndex(provider);
+
+  const dailyStatsAccounts = async (
+    poolKey = pool.publicKey,
+    dayIndex?: anchor.BN,
+  ) => ({
+    dailyStats: await dailyStatsAddress(
+      provider,
+      stakingProgram.programId,
+      poolKey,
+      dayIndex,
+    ),
+    payer: wallet.publicKey,
+    systemProgram: anchor.web3.SystemProgram.programId,
+  });
+
+  const stake = async (
+    amount: anchor.BN,
+    maxMaturity: anchor.BN = new anchor.BN(0),
+    clientRef: anchor.BN = new anchor.BN(0),
+  ) => {
+    return stakingProgram.rpc.stake(
+      await today(),
+      amount,
+      maxMaturity,
+      clientRef,
+      {
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          user: user,
+          owner: wallet.publicKey,
+          stakeFromAccount: ownerTokenAccount,
+          poolSigner: poolSigner,
+          ...(await dailyStatsAccounts()),
+          tokenProgram: stakingMint.programId,
+          stakingMint: stakingMint.publicKey,
+          priceFeed: pool.publicKey,
+          memoProgram: MEMO_PROGRAM_ID,
+          eventAuthority,
+          program: stakingProgram.programId,
+        },
+      },
+    );
+  };
+
+  const unstake = async (
+    amount: anchor.BN,
+    remainingAccounts: anchor.web3.AccountMeta[] = [],
+    signers: anchor.web3.Keypair[] = [],
+    clientRef: anchor.BN = new anchor.BN(0),
+    memo = '',
+  ) => {
+    return stakingProgram.rpc.unstake(await today(), amount, clientRef, memo, {
       accounts: {
         pool: pool.publicKey,
         stakingVault,
@@ -210,21 +5414,425 @@ describe('staking', () => {
         owner: wallet.publicKey,
         stakeFromAccount: ownerTokenAccount,
         poolSigner: poolSigner,
+        ...(await dailyStatsAccounts()),
+        tokenProgram: stakingMint.programId,
+        stakingMint: stakingMint.publicKey,
+        priceFeed: pool.publicKey,
+        memoProgram: MEMO_PROGRAM_ID,
+        eventAuthority,
+        program: stakingProgram.programId,
+      },
+      remainingAccounts,
+      signers,
+    });
+  };
+
+  const setTopupGrace = async (graceSeconds: anchor.BN, graceBps: number) => {
+    await stakingProgram.rpc.setTopupGrace(graceSeconds, graceBps, {
+      accounts: {
+        pool: pool.publicKey,
+        authority: wallet.publicKey,
+      },
+    });
+  };
+
+  const setUnstakePenalty = async (
+    maxPenaltyBps: number,
+    penaltyVault: anchor.web3.PublicKey,
+  ) => {
+    await stakingProgram.rpc.setUnstakePenalty(maxPenaltyBps, {
+      accounts: {
+        pool: pool.publicKey,
+        authority: wallet.publicKey,
+        penaltyVault,
+      },
+    });
+  };
+
+  const slash = async (
+    amount: anchor.BN,
+    reasonCode: number,
+    slashDestination: anchor.web3.PublicKey,
+  ) => {
+    await stakingProgram.rpc.slash(amount, reasonCode, {
+      accounts: {
+        pool: pool.publicKey,
+        authority: wallet.publicKey,
+        stakingVault,
+        user,
+        slashDestination,
+        poolSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        stakingMint: stakingMint.publicKey,
+      },
+    });
+  };
+
+  type SecondPool = {
+    pool: anchor.web3.PublicKey;
+    stakingVault: anchor.web3.PublicKey;
+    rewardVault: anchor.web3.PublicKey;
+    user: anchor.web3.PublicKey;
+    poolSigner: anchor.web3.PublicKey;
+  };
+
+  const createSecondPool = async (withUser = true): Promise<SecondPool> => {
+    const secondPool = anchor.web3.Keypair.generate();
+    const [secondSigner] = await anchor.web3.PublicKey.findProgramAddress(
+      [secondPool.publicKey.toBuffer()],
+      stakingProgram.programId,
+    );
+    const secondStakingVault = await stakingMint.createAccount(secondSigner);
+    const secondRewardVault = await rewardMint.createAccount(secondSigner);
+
+    await stakingProgram.rpc.initializePool(
+      rewardDuration,
+      lockPeriod,
+      false,
+      false,
+      false,
+      new anchor.BN(0),
+      false,
+      anchor.web3.PublicKey.default,
+      new anchor.BN(0),
+      {
+        accounts: {
+          authority: wallet.publicKey,
+          stakingMint: stakingMint.publicKey,
+          stakingVault: secondStakingVault,
+          rewardMint: rewardMint.publicKey,
+          rewardVault: secondRewardVault,
+          poolSigner: secondSigner,
+          pool: secondPool.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+        signers: [secondPool],
+        instructions: [
+          await stakingProgram.account.pool.createInstruction(secondPool),
+        ],
+      },
+    );
+
+    const [secondUser] = await anchor.web3.PublicKey.findProgramAddress(
+      [wallet.publicKey.toBuffer(), secondPool.publicKey.toBuffer()],
+      stakingProgram.programId,
+    );
+    if (withUser) {
+      await stakingProgram.rpc.createUser({
+        accounts: {
+          pool: secondPool.publicKey,
+          user: secondUser,
+          owner: wallet.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+    }
+
+    return {
+      pool: secondPool.publicKey,
+      stakingVault: secondStakingVault,
+      rewardVault: secondRewardVault,
+      user: secondUser,
+      poolSigner: secondSigner,
+    };
+  };
+
+  const stakeInto = async (second: SecondPool, amount: anchor.BN) => {
+    await stakingProgram.rpc.stake(
+      await today(),
+      amount,
+      new anchor.BN(0),
+      new anchor.BN(0),
+      {
+        accounts: {
+          pool: second.pool,
+          stakingVault: second.stakingVault,
+          user: second.user,
+          owner: wallet.publicKey,
+          stakeFromAccount: ownerTokenAccount,
+          poolSigner: second.poolSigner,
+          ...(await dailyStatsAccounts(second.pool)),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          stakingMint: stakingMint.publicKey,
+          priceFeed: second.pool,
+          memoProgram: MEMO_PROGRAM_ID,
+          eventAuthority,
+          program: stakingProgram.programId,
+        },
+      },
+    );
+  };
+
+  const stakeSplit = async (
+    second: SecondPool,
+    totalAmount: anchor.BN,
+    firstPoolBps: number,
+  ) => {
+    const first = await dailyStatsAccounts();
+    const { dailyStats: secondDailyStats } = await dailyStatsAccounts(
+      second.pool,
+    );
+    await stakingProgram.rpc.stakeSplit(
+      await today(),
+      totalAmount,
+      firstPoolBps,
+      new anchor.BN(0),
+      new anchor.BN(0),
+      {
+        accounts: {
+          firstPool: pool.publicKey,
+          firstStakingVault: stakingVault,
+          firstUser: user,
+          secondPool: second.pool,
+          secondStakingVault: second.stakingVault,
+          secondUser: second.user,
+          owner: wallet.publicKey,
+          stakeFromAccount: ownerTokenAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          firstDailyStats: first.dailyStats,
+          secondDailyStats,
+          payer: first.payer,
+          systemProgram: first.systemProgram,
+          stakingMint: stakingMint.publicKey,
+          firstPriceFeed: pool.publicKey,
+          secondPriceFeed: second.pool,
+        },
+      },
+    );
+  };
+
+  const setUnstakeDelay = async (minUnstakeDelaySlots: anchor.BN) => {
+    await stakingProgram.rpc.setUnstakeDelay(minUnstakeDelaySlots, {
+      accounts: {
+        pool: pool.publicKey,
+        authority: wallet.publicKey,
+      },
+    });
+  };
+
+  const setDepositLimit = async (maxPerWindow: anchor.BN) => {
+    await stakingProgram.rpc.setDepositLimit(maxPerWindow, {
+      accounts: {
+        pool: pool.publicKey,
+        authority: wallet.publicKey,
+      },
+    });
+  };
+
+  const fund = async (amount: anchor.BN) => {
+    const from = await rewardMint.createAccount(wallet.publicKey);
+    await rewardMint.mintTo(from, wallet.payer, [], amount.toNumber());
+
+    await stakingProgram.rpc.fund(amount, {
+      accounts: {
+        pool: pool.publicKey,
+        rewardVault,
+        funder: wallet.publicKey,
+        from,
+        poolSigner,
         tokenProgram: TOKEN_PROGRAM_ID,
+        rewardMint: rewardMint.publicKey,
+      },
+    });
+  };
+
+  const setInsurance = async (
+    insuranceVault: anchor.web3.PublicKey,
+    insuranceShareBps: number,
+  ) => {
+    await stakingProgram.rpc.setInsurance(insuranceShareBps, {
+      accounts: {
+        pool: pool.publicKey,
+        authority: wallet.publicKey,
+        insuranceVault,
+        poolSigner,
+      },
+    });
+  };
+
+  const migrateUser = async () => {
+    await stakingProgram.rpc.migrateUser({
+      accounts: {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      },
+    });
+  };
+
+  const downgradeUser = async () => {
+    await stakingProgram.rpc.downgradeUserForTesting({
+      accounts: {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
       },
     });
   };
 
-  const unstake = async (amount: anchor.BN) => {
-    await stakingProgram.rpc.unstake(amount, {
+  const unstakeBySpender = async (
+    spender: anchor.web3.Keypair,
+    destination: anchor.web3.PublicKey,
+    amount: number,
+  ) => {
+    await stakingProgram.rpc.unstakeBySpender(new anchor.BN(amount), {
       accounts: {
         pool: pool.publicKey,
         stakingVault,
-        user: user,
+        user,
+        spender: spender.publicKey,
+        destination,
+        poolSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        stakingMint: stakingMint.publicKey,
+      },
+      signers: [spender],
+    });
+  };
+
+  const closeUser = async (
+    rentPayer = wallet.publicKey,
+    remainingAccounts: anchor.web3.AccountMeta[] = [],
+    signers: anchor.web3.Keypair[] = [],
+  ) => {
+    await stakingProgram.rpc.closeUser({
+      accounts: {
+        pool: pool.publicKey,
+        user,
         owner: wallet.publicKey,
-        stakeFromAccount: ownerTokenAccount,
-        poolSigner: poolSigner,
+        rentPayer,
+      },
+      remainingAccounts,
+      signers,
+    });
+  };
+
+  const crankCompound = async () => {
+    await stakingProgram.rpc.crankCompound({
+      accounts: {
+        pool: pool.publicKey,
+        stakingVault,
+        rewardVault,
+        user,
+        cranker: wallet.publicKey,
+        tipAccount: ownerTokenAccount,
+        poolSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        // Compounding pools reward in their staking mint.
+        rewardMint: stakingMint.publicKey,
+      },
+    });
+  };
+
+  const delegateUnstake = async (
+    delegate: anchor.web3.Keypair,
+    destination: anchor.web3.PublicKey,
+    amount: number,
+  ) => {
+    await stakingProgram.rpc.delegateUnstake(
+      await today(),
+      new anchor.BN(amount),
+      {
+        accounts: {
+          pool: pool.publicKey,
+          stakingVault,
+          user,
+          delegate: delegate.publicKey,
+          destination,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          stakingMint: stakingMint.publicKey,
+          priceFeed: pool.publicKey,
+          ...(await dailyStatsAccounts()),
+        },
+        signers: [delegate],
+      },
+    );
+  };
+
+  const assertInvariants = async () => {
+    await stakingProgram.rpc.assertInvariants({
+      accounts: { pool: pool.publicKey, stakingVault },
+    });
+    return (await stakingProgram.account.pool.fetch(pool.publicKey)).halted;
+  };
+
+  const corruptPool = async (totalStaked: anchor.BN) => {
+    await stakingProgram.rpc.corruptPoolForTesting(totalStaked, {
+      accounts: { pool: pool.publicKey, authority: wallet.publicKey },
+    });
+  };
+
+  const claim = async (
+    rewardAccount: anchor.web3.PublicKey,
+    remainingAccounts: anchor.web3.AccountMeta[] = [],
+    clientRef: anchor.BN = new anchor.BN(0),
+    memo = '',
+  ) => {
+    // Shared-mint pools pay rewards in the staking mint.
+    const poolAccount = await stakingProgram.account.pool.fetch(pool.publicKey);
+    return stakingProgram.rpc.claim(await today(), clientRef, memo, {
+      accounts: {
+        pool: pool.publicKey,
+        stakingVault,
+        rewardVault,
+        user,
+        owner: wallet.publicKey,
+        rewardAccount,
+        poolSigner,
+        ...(await dailyStatsAccounts()),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rewardMint: poolAccount.rewardMint,
+        memoProgram: MEMO_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        eventAuthority,
+        program: stakingProgram.programId,
+      },
+      remainingAccounts,
+    });
+  };
+
+  const setUserVesting = async (cliffTimestamp: number, duration: number) => {
+    const [vestingEscrow] = await anchor.web3.PublicKey.findProgramAddress(
+      [Buffer.from('vesting'), user.toBuffer()],
+      stakingProgram.programId,
+    );
+    await stakingProgram.rpc.setUserVesting(
+      new anchor.BN(cliffTimestamp),
+      new anchor.BN(duration),
+      {
+        accounts: {
+          pool: pool.publicKey,
+          authority: wallet.publicKey,
+          user,
+          rewardMint: rewardMint.publicKey,
+          vestingEscrow,
+          poolSigner,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        },
+      },
+    );
+  };
+
+  const releaseVested = async (
+    vestingEscrow: anchor.web3.PublicKey,
+    rewardAccount: anchor.web3.PublicKey,
+  ) => {
+    await stakingProgram.rpc.releaseVested({
+      accounts: {
+        pool: pool.publicKey,
+        user,
+        owner: wallet.publicKey,
+        vestingEscrow,
+        rewardAccount,
+        poolSigner,
         tokenProgram: TOKEN_PROGRAM_ID,
+        rewardMint: rewardMint.publicKey,
       },
     });
   };
@@ -1,17 +1,328 @@
 import * as anchor from '@project-serum/anchor';
 import { TOKEN_PROGRAM_ID, Token } from '@solana/spl-token';
 
+export const MEMO_PROGRAM_ID = new anchor.web3.PublicKey(
+  'MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr',
+);
+
 export const createMint = async (
   provider: anchor.Provider,
   decimals: number,
+  freezeAuthority: anchor.web3.PublicKey | null = null,
 ): Promise<Token> => {
   const mint = await Token.createMint(
     provider.connection,
     (provider.wallet as anchor.Wallet).payer,
     provider.wallet.publicKey,
-    null,
+    freezeAuthority,
     decimals,
     TOKEN_PROGRAM_ID,
   );
   return mint;
 };
+
+export const TOKEN_2022_PROGRAM_ID = new anchor.web3.PublicKey(
+  'TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb',
+);
+
+export const GOVERNANCE_PROGRAM_ID = new anchor.web3.PublicKey(
+  'GovER5Lthms3bLBqWub97yVrMmEogzX7xNjdXpPPCVZw',
+);
+
+// A Token-2022 mint extension: its size in the mint and in each token
+// account, and the data of the instruction initializing it.
+export type MintExtension = {
+  mintLength: number;
+  accountLength: number;
+  data: Buffer;
+};
+
+export const transferFeeExtension = (
+  feeBps: number,
+  maxFee: anchor.BN,
+): MintExtension => {
+  // InitializeTransferFeeConfig without config or withdraw authorities.
+  const data = Buffer.alloc(14);
+  data.set([26, 0, 0, 0]);
+  data.writeUInt16LE(feeBps, 4);
+  data.set(maxFee.toArrayLike(Buffer, 'le', 8), 6);
+  return { mintLength: 108, accountLength: 8, data };
+};
+
+export const defaultAccountStateExtension = (): MintExtension => ({
+  mintLength: 1,
+  accountLength: 0,
+  // Initialize, with new accounts starting unfrozen.
+  data: Buffer.from([28, 0, 1]),
+});
+
+// Base size of extended accounts and mints, plus the account type byte.
+const EXTENDED_BASE_LENGTH = 166;
+
+const extendedLength = (baseLength: number, lengths: number[]): number =>
+  lengths.length === 0
+    ? baseLength
+    : lengths.reduce(
+        (total, length) => total + 4 + length,
+        EXTENDED_BASE_LENGTH,
+      );
+
+// Token-2022 mint with `extensions`, built by hand as @solana/spl-token 0.1.8
+// predates them. The `Token` sends its instructions to Token-2022, and its
+// `createAccount` sizes accounts for the mint's extensions.
+export const createMint2022 = async (
+  provider: anchor.Provider,
+  decimals: number,
+  extensions: MintExtension[] = [],
+): Promise<Token> => {
+  const payer = (provider.wallet as anchor.Wallet).payer;
+  const mint = anchor.web3.Keypair.generate();
+  const space = extendedLength(
+    82,
+    extensions.map((extension) => extension.mintLength),
+  );
+  const initializeMint = Buffer.alloc(35);
+  initializeMint.writeUInt8(decimals, 1);
+  initializeMint.set(provider.wallet.publicKey.toBuffer(), 2);
+
+  const tx = new anchor.web3.Transaction().add(
+    anchor.web3.SystemProgram.createAccount({
+      fromPubkey: provider.wallet.publicKey,
+      newAccountPubkey: mint.publicKey,
+      space,
+      lamports: await provider.connection.getMinimumBalanceForRentExemption(
+        space,
+      ),
+      programId: TOKEN_2022_PROGRAM_ID,
+    }),
+    ...extensions.map(
+      (extension) =>
+        new anchor.web3.TransactionInstruction({
+          keys: [{ pubkey: mint.publicKey, isSigner: false, isWritable: true }],
+          programId: TOKEN_2022_PROGRAM_ID,
+          data: extension.data,
+        }),
+    ),
+    new anchor.web3.TransactionInstruction({
+      keys: [
+        { pubkey: mint.publicKey, isSigner: false, isWritable: true },
+        {
+          pubkey: anchor.web3.SYSVAR_RENT_PUBKEY,
+          isSigner: false,
+          isWritable: false,
+        },
+      ],
+      programId: TOKEN_2022_PROGRAM_ID,
+      data: initializeMint,
+    }),
+  );
+  await provider.send(tx, [mint]);
+
+  const token = new Token(
+    provider.connection,
+    mint.publicKey,
+    TOKEN_2022_PROGRAM_ID,
+    payer,
+  );
+  const accountSpace = extendedLength(
+    165,
+    extensions
+      .map((extension) => extension.accountLength)
+      .filter((length) => length > 0),
+  );
+  token.createAccount = async (owner: anchor.web3.PublicKey) => {
+    const account = anchor.web3.Keypair.generate();
+    const tx = new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.createAccount({
+        fromPubkey: provider.wallet.publicKey,
+        newAccountPubkey: account.publicKey,
+        space: accountSpace,
+        lamports: await provider.connection.getMinimumBalanceForRentExemption(
+          accountSpace,
+        ),
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      Token.createInitAccountInstruction(
+        TOKEN_2022_PROGRAM_ID,
+        mint.publicKey,
+        account.publicKey,
+        owner,
+      ),
+    );
+    await provider.send(tx, [account]);
+    return account.publicKey;
+  };
+  return token;
+};
+
+// Token balance read from the account data, for accounts of either token
+// program; `Token.getAccountInfo` only parses unextended ones.
+export const tokenBalance = async (
+  provider: anchor.Provider,
+  account: anchor.web3.PublicKey,
+): Promise<anchor.BN> => {
+  const info = await provider.connection.getAccountInfo(account);
+  return readU64(info.data, 64);
+};
+
+export const sleep = (ms: number): Promise<void> =>
+  new Promise((resolve) => setTimeout(resolve, ms));
+
+export const simulateReturnData = async (
+  provider: anchor.Provider,
+  instruction: anchor.web3.TransactionInstruction,
+): Promise<Buffer> => {
+  const tx = new anchor.web3.Transaction().add(instruction);
+  tx.feePayer = provider.wallet.publicKey;
+  tx.recentBlockhash = (
+    await provider.connection.getRecentBlockhash()
+  ).blockhash;
+  const signed = await provider.wallet.signTransaction(tx);
+
+  const response = await (provider.connection as any)._rpcRequest(
+    'simulateTransaction',
+    [signed.serialize().toString('base64'), { encoding: 'base64' }],
+  );
+  if (response.error) {
+    throw new Error(response.error.message);
+  }
+  if (response.result.value.err) {
+    throw new Error(JSON.stringify(response.result.value.err));
+  }
+
+  const [data] = response.result.value.returnData.data;
+  return Buffer.from(data, 'base64');
+};
+
+export const readU64 = (data: Buffer, offset: number): anchor.BN =>
+  new anchor.BN(data.slice(offset, offset + 8), 'le');
+
+// Compute units the program's top-level invocation reported in `logs`.
+export const unitsConsumed = (
+  logs: string[],
+  programId: anchor.web3.PublicKey,
+): number => {
+  const prefix = `Program ${programId.toString()} consumed `;
+  const line = logs.find((log) => log.startsWith(prefix));
+  return parseInt(line.slice(prefix.length).split(' ')[0], 10);
+};
+
+export const captureEvents = async (
+  program: anchor.Program<any>,
+  names: string[],
+  action: () => Promise<void>,
+): Promise<{ name: string; data: any }[]> => {
+  const events: { name: string; data: any }[] = [];
+  const listeners = names.map((name) =>
+    program.addEventListener(name, (data) => events.push({ name, data })),
+  );
+  try {
+    await action();
+    await sleep(1000);
+  } finally {
+    for (const listener of listeners) {
+      await program.removeEventListener(listener);
+    }
+  }
+  return events;
+};
+
+// Day of the validator clock, which may differ from the local one.
+export const clockDayIndex = async (
+  provider: anchor.Provider,
+): Promise<anchor.BN> => {
+  const clock = await provider.connection.getAccountInfo(
+    anchor.web3.SYSVAR_CLOCK_PUBKEY,
+  );
+  const unixTimestamp = new anchor.BN(clock.data.slice(32, 40), 'le');
+  return unixTimestamp.divn(86400);
+};
+
+export const dailyStatsAddress = async (
+  provider: anchor.Provider,
+  programId: anchor.web3.PublicKey,
+  pool: anchor.web3.PublicKey,
+  dayIndex?: anchor.BN,
+): Promise<anchor.web3.PublicKey> => {
+  const day = dayIndex ?? (await clockDayIndex(provider));
+  const [address] = await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from('daily_stats'),
+      pool.toBuffer(),
+      day.toArrayLike(Buffer, 'le', 8),
+    ],
+    programId,
+  );
+  return address;
+};
+
+export const eventAuthorityAddress = async (
+  programId: anchor.web3.PublicKey,
+): Promise<anchor.web3.PublicKey> => {
+  const [address] = await anchor.web3.PublicKey.findProgramAddress(
+    [Buffer.from('__event_authority')],
+    programId,
+  );
+  return address;
+};
+
+// Events a transaction emitted through `log_event` self-CPIs, decoded from
+// the inner instructions alone. Their data is the instruction discriminator,
+// then the event bytes with a u32 length prefix.
+export const cpiEvents = async (
+  provider: anchor.Provider,
+  program: anchor.Program<any>,
+  signature: string,
+): Promise<{ name: string; data: any }[]> => {
+  await provider.connection.confirmTransaction(signature, 'confirmed');
+  const tx = await provider.connection.getTransaction(signature, {
+    commitment: 'confirmed',
+  });
+  const keys = tx.transaction.message.accountKeys;
+  const discriminator = Buffer.from(
+    anchor.utils.sha256.hash('global:log_event'),
+    'hex',
+  ).slice(0, 8);
+  return tx.meta.innerInstructions
+    .flatMap((inner) => inner.instructions)
+    .filter((ix) => keys[ix.programIdIndex].equals(program.programId))
+    .map((ix) => Buffer.from(anchor.utils.bytes.bs58.decode(ix.data)))
+    .filter((data) => data.slice(0, 8).equals(discriminator))
+    .map((data) =>
+      program.coder.events.decode(data.slice(12).toString('base64')),
+    );
+};
+
+// Ports of the program's `math` module, for predicting rewards off-chain.
+export const PRECISION = new anchor.BN(2).pow(new anchor.BN(64)).subn(1);
+
+export const rewardPerToken = (
+  totalStaked: anchor.BN,
+  rewardPerTokenStored: anchor.BN,
+  lastTimeRewardApplicable: anchor.BN,
+  lastUpdateTime: anchor.BN,
+  rewardRate: anchor.BN,
+): anchor.BN => {
+  if (totalStaked.isZero()) {
+    return rewardPerTokenStored;
+  }
+  const elapsed = anchor.BN.max(
+    lastTimeRewardApplicable.sub(lastUpdateTime),
+    new anchor.BN(0),
+  );
+  return rewardPerTokenStored.add(
+    elapsed.mul(rewardRate).mul(PRECISION).div(totalStaked),
+  );
+};
+
+export const earned = (
+  balanceStaked: anchor.BN,
+  rewardPerToken: anchor.BN,
+  userRewardPerTokenPaid: anchor.BN,
+  userRewardPending: anchor.BN,
+): anchor.BN =>
+  rewardPerToken
+    .sub(userRewardPerTokenPaid)
+    .mul(balanceStaked)
+    .div(PRECISION)
+    .add(userRewardPending);